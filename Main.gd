@@ -17,12 +17,17 @@ func _process(delta):
 func _input(event):
 	if InputMap.event_is_action(event, "throttle"):
 		throttle_value = event.get_axis_value()
-	throttle_on =  Input.is_action_pressed("throttle_on")
+	var pressed = Input.is_action_pressed("throttle_on")
 
 	if connected:
-		var result = sensor.send_throttle(throttle_on, throttle_value)
+		if pressed != throttle_on:
+			var arming = sensor.arm() if pressed else sensor.disarm()
+			if not arming.has("Ok"):
+				print(arming.get("Err"))
+		var result = sensor.send_throttle(throttle_value)
 		if not result.has("Ok"):
 			print(result.get("Err"))
+	throttle_on = pressed
 
 func _on_Button_button_up():
 	var sensor_mac = $Control/GridContainer/HBoxContainer/TextEdit.text
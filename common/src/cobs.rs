@@ -0,0 +1,281 @@
+//! Consistent overhead byte stuffing. A frame comes out without a single [EOT](crate::EOT)
+//! in it, for one byte of overhead per 253, so the delimiter always marks a frame boundary
+//! and a receiver that lost track picks up again at the next one. The delimiter is 0xFF
+//! rather than the usual zero, group codes stop at 0xFE to stay clear of it.
+
+use crate::{FrameError, EOT};
+
+/// Code of a group of 253 bytes without a delimiter after it
+const FULL: u8 = EOT - 1;
+
+/// Largest encoding of `len` bytes, without the delimiter
+pub const fn max_encoded_len(len: usize) -> usize {
+    len + len / (FULL as usize - 1) + 1
+}
+
+/// Encodes `src` into `dst` and gives the length, not counting the delimiter the caller
+/// writes after it. [FrameError::Length] when `dst` is too short.
+pub fn encode(src: &[u8], dst: &mut [u8]) -> Result<usize, FrameError> {
+    if dst.len() < max_encoded_len(src.len()) {
+        return Err(FrameError::Length);
+    }
+
+    let mut code_at = 0;
+    let mut out = 1;
+    let mut code = 1u8;
+    for byte in src {
+        if *byte == EOT {
+            dst[code_at] = code;
+            code_at = out;
+            out += 1;
+            code = 1;
+        } else {
+            dst[out] = *byte;
+            out += 1;
+            code += 1;
+            if code == FULL {
+                dst[code_at] = code;
+                code_at = out;
+                out += 1;
+                code = 1;
+            }
+        }
+    }
+    dst[code_at] = code;
+
+    Ok(out)
+}
+
+/// Decodes one frame without its delimiter into `dst` and gives the length.
+/// [FrameError::Encoding] for a delimiter inside the frame or one cut short.
+pub fn decode(src: &[u8], dst: &mut [u8]) -> Result<usize, FrameError> {
+    let mut at = 0;
+    let mut out = 0;
+    while at < src.len() {
+        let code = src[at] as usize;
+        if code == 0 || code > FULL as usize || at + code > src.len() {
+            return Err(FrameError::Encoding);
+        }
+        let run = &src[at + 1..at + code];
+        if run.contains(&EOT) {
+            return Err(FrameError::Encoding);
+        }
+        if out + run.len() > dst.len() {
+            return Err(FrameError::Length);
+        }
+        dst[out..out + run.len()].copy_from_slice(run);
+        out += run.len();
+        at += code;
+
+        // every group but a full one stands for a delimiter byte, the last one doesn't
+        if code < FULL as usize && at < src.len() {
+            if out == dst.len() {
+                return Err(FrameError::Length);
+            }
+            dst[out] = EOT;
+            out += 1;
+        }
+    }
+
+    Ok(out)
+}
+
+/// Collects bytes up to the next delimiter and decodes them, `N` bytes of encoded frame at
+/// most. Anything longer is dropped whole, the frame after it comes through.
+#[derive(Debug)]
+pub struct Decoder<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    overflow: bool,
+}
+
+impl<const N: usize> Decoder<N> {
+    pub const fn new() -> Self {
+        Decoder { buf: [0; N], len: 0, overflow: false }
+    }
+
+    /// A decoded frame in `dst` once `byte` is the delimiter closing it, None meanwhile and
+    /// for the empty frames of delimiters back to back
+    pub fn push(&mut self, byte: u8, dst: &mut [u8]) -> Option<Result<usize, FrameError>> {
+        if byte != EOT {
+            if self.len < N {
+                self.buf[self.len] = byte;
+                self.len += 1;
+            } else {
+                self.overflow = true;
+            }
+            return None;
+        }
+
+        let (len, overflow) = (self.len, self.overflow);
+        self.len = 0;
+        self.overflow = false;
+        match (len, overflow) {
+            (_, true) => Some(Err(FrameError::Length)),
+            (0, false) => None,
+            (len, false) => Some(decode(&self.buf[..len], dst)),
+        }
+    }
+}
+
+impl<const N: usize> Default for Decoder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONGEST: usize = 1024;
+
+    fn roundtrip(payload: &[u8]) {
+        let mut encoded = [0u8; max_encoded_len(LONGEST)];
+        let len = encode(payload, &mut encoded).unwrap();
+        let encoded = &encoded[..len];
+        assert!(len <= max_encoded_len(payload.len()), "{} bytes took {}", payload.len(), len);
+        assert!(!encoded.contains(&EOT), "{:x?}", payload);
+
+        let mut decoded = [0u8; LONGEST];
+        assert_eq!(decode(encoded, &mut decoded), Ok(payload.len()), "{:x?}", payload);
+        assert_eq!(&decoded[..payload.len()], payload);
+    }
+
+    #[test]
+    fn every_short_payload_roundtrips() {
+        // the bytes whose handling differs, every arrangement of up to four of them
+        let alphabet = [EOT, 0x00, 0x01, FULL, 0x7f];
+        let mut payload = [0u8; 4];
+        for len in 0..=payload.len() {
+            for mut n in 0..alphabet.len().pow(len as u32) {
+                for byte in payload[..len].iter_mut() {
+                    *byte = alphabet[n % alphabet.len()];
+                    n /= alphabet.len();
+                }
+                roundtrip(&payload[..len]);
+            }
+        }
+    }
+
+    #[test]
+    fn payloads_around_a_full_group_roundtrip() {
+        let mut payload = [0x55u8; LONGEST];
+        for len in 0..LONGEST {
+            roundtrip(&payload[..len]);
+        }
+
+        // a delimiter at every position of the first two groups
+        for at in 0..2 * FULL as usize + 2 {
+            payload = [0x55; LONGEST];
+            payload[at] = EOT;
+            roundtrip(&payload[..2 * FULL as usize + 3]);
+        }
+
+        roundtrip(&[EOT; LONGEST]);
+    }
+
+    #[test]
+    fn random_payloads_roundtrip() {
+        let mut seed = 0x2545_f491u32;
+        let mut payload = [0u8; LONGEST];
+        for _ in 0..2000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let len = seed as usize % LONGEST;
+            for byte in payload[..len].iter_mut() {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                // delimiters often, they are what the encoding is about
+                *byte = if seed.is_multiple_of(8) { EOT } else { (seed >> 8) as u8 };
+            }
+            roundtrip(&payload[..len]);
+        }
+    }
+
+    #[test]
+    fn encode_refuses_a_short_buffer() {
+        let mut dst = [0u8; 4];
+
+        assert_eq!(encode(&[1, 2, 3, 4], &mut dst), Err(FrameError::Length));
+        assert_eq!(encode(&[1, 2, 3], &mut dst), Ok(4));
+    }
+
+    #[test]
+    fn truncated_frames_never_decode_as_the_frame() {
+        let payload = [0x10, EOT, 0x20, 0x30, EOT, EOT, 0x40];
+        let mut encoded = [0u8; max_encoded_len(7)];
+        let len = encode(&payload, &mut encoded).unwrap();
+        let mut decoded = [0u8; 16];
+
+        for cut in 0..len {
+            match decode(&encoded[..cut], &mut decoded) {
+                Ok(n) => assert!(n < payload.len(), "{} bytes of {} decoded to {}", cut, len, n),
+                Err(e) => assert_eq!(e, FrameError::Encoding),
+            }
+        }
+        // cut inside a group, its code runs past the end
+        assert_eq!(decode(&encoded[..4], &mut decoded), Err(FrameError::Encoding));
+    }
+
+    #[test]
+    fn malformed_frames_are_refused() {
+        let mut decoded = [0u8; 16];
+
+        assert_eq!(decode(&[0x00, 0x01], &mut decoded), Err(FrameError::Encoding));
+        assert_eq!(decode(&[0x03, 0x01, EOT], &mut decoded), Err(FrameError::Encoding));
+        assert_eq!(decode(&[EOT], &mut decoded), Err(FrameError::Encoding));
+        assert_eq!(decode(&[0x04, 0x01, 0x02, 0x03], &mut decoded[..2]), Err(FrameError::Length));
+        assert_eq!(decode(&[0x02, 0x01, 0x01], &mut decoded[..1]), Err(FrameError::Length));
+    }
+
+    /// Frames the decoder gives for `bytes`, lengths or errors
+    fn feed<const N: usize>(decoder: &mut Decoder<N>, bytes: &[u8], out: &mut [Result<usize, FrameError>; 8]) -> usize {
+        let mut dst = [0u8; 64];
+        let mut n = 0;
+        for byte in bytes {
+            if let Some(frame) = decoder.push(*byte, &mut dst) {
+                out[n] = frame;
+                n += 1;
+            }
+        }
+        n
+    }
+
+    #[test]
+    fn decoder_picks_up_again_after_garbage() {
+        let mut decoder = Decoder::<16>::default();
+        let mut out = [Ok(0); 8];
+        let mut stream = [0u8; 32];
+
+        // the tail of a frame, joined mid-way, then a whole one
+        stream[..3].copy_from_slice(&[0x09, 0x22, 0x33]);
+        stream[3] = EOT;
+        let len = encode(&[0x01, EOT, 0x02], &mut stream[4..]).unwrap();
+        stream[4 + len] = EOT;
+
+        let n = feed(&mut decoder, &stream[..5 + len], &mut out);
+        assert_eq!(&out[..n], &[Err(FrameError::Encoding), Ok(3)]);
+    }
+
+    #[test]
+    fn decoder_drops_an_overlong_frame_whole() {
+        let mut decoder = Decoder::<4>::new();
+        let mut out = [Ok(0); 8];
+
+        let n = feed(&mut decoder, &[0x06, 1, 2, 3, 4, 5, EOT, 0x02, 0x07, EOT], &mut out);
+        assert_eq!(&out[..n], &[Err(FrameError::Length), Ok(1)]);
+    }
+
+    #[test]
+    fn back_to_back_delimiters_are_no_frames() {
+        let mut decoder = Decoder::<4>::new();
+        let mut out = [Ok(0); 8];
+
+        assert_eq!(feed(&mut decoder, &[EOT, EOT, EOT], &mut out), 0);
+        let n = feed(&mut decoder, &[EOT, 0x01, EOT, EOT], &mut out);
+        assert_eq!(&out[..n], &[Ok(0)]);
+    }
+}
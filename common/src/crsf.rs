@@ -0,0 +1,230 @@
+//! Crossfire serial protocol, what ExpressLRS and TBS receivers speak to a flight controller.
+//! A frame is the address it goes to, the length of the rest, the frame type, its payload
+//! and a CRC-8 over type and payload. Fields are big-endian, unlike everything else here.
+
+use core::ops::Deref;
+
+use crate::{FrameError, SpatialOrientation, RC_CHANNELS};
+
+pub const BAUD: u32 = 420_000;
+/// Address of the flight controller, frames in both directions lead with it
+pub const ADDRESS: u8 = 0xc8;
+
+pub const BATTERY_SENSOR: u8 = 0x08;
+pub const LINK_STATISTICS: u8 = 0x14;
+pub const RC_CHANNELS_PACKED: u8 = 0x16;
+pub const ATTITUDE: u8 = 0x1e;
+
+/// Address, length, type, payload and CRC
+pub const MAX_FRAME: usize = 64;
+const MAX_PAYLOAD: usize = MAX_FRAME - 4;
+
+/// CRC-8 with the DVB-S2 polynomial
+pub fn crc8(buf: &[u8]) -> u8 {
+    buf.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| if crc & 0x80 != 0 { (crc << 1) ^ 0xd5 } else { crc << 1 })
+    })
+}
+
+/// A frame that came through whole, addressed to [ADDRESS]
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub kind: u8,
+    payload: [u8; MAX_PAYLOAD],
+    len: usize,
+}
+
+impl Frame {
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.len]
+    }
+}
+
+/// Picks frames for [ADDRESS] out of a byte stream however it was chunked
+#[derive(Debug)]
+pub struct Parser {
+    buf: [u8; MAX_FRAME],
+    len: usize,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Parser { buf: [0; MAX_FRAME], len: 0 }
+    }
+
+    /// A frame once `byte` is its CRC, None meanwhile and for anything before an [ADDRESS].
+    /// [FrameError::Length] for a length that can't be one, the parser looks for the next
+    /// address right after it. [FrameError::Crc] for a frame that doesn't check out.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Frame, FrameError>> {
+        match self.len {
+            0 if byte != ADDRESS => return None,
+            // type and CRC at least, and no more than fits
+            1 if !(2..=MAX_FRAME as u8 - 2).contains(&byte) => {
+                self.len = 0;
+                return Some(Err(FrameError::Length));
+            }
+            _ => {}
+        }
+        self.buf[self.len] = byte;
+        self.len += 1;
+
+        if self.len < 2 || self.len < 2 + self.buf[1] as usize {
+            return None;
+        }
+        let frame = &self.buf[2..self.len];
+        self.len = 0;
+        let (body, crc) = frame.split_at(frame.len() - 1);
+        if crc8(body) != crc[0] {
+            return Some(Err(FrameError::Crc));
+        }
+
+        let mut payload = [0; MAX_PAYLOAD];
+        payload[..body.len() - 1].copy_from_slice(&body[1..]);
+        Some(Ok(Frame { kind: body[0], payload, len: body.len() - 1 }))
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The channels of an [RC_CHANNELS_PACKED], 11 bits each starting at the lowest. SBUS packs
+/// them the same way. [RcMap](crate::RcMap) makes controls of them.
+#[derive(Debug, Clone, Copy)]
+pub struct Channels(pub [u16; RC_CHANNELS]);
+
+impl Channels {
+    /// None for a payload of another length
+    pub fn from_payload(payload: &[u8]) -> Option<Channels> {
+        if payload.len() != 22 {
+            return None;
+        }
+        let mut channels = [0; RC_CHANNELS];
+        for (i, c) in channels.iter_mut().enumerate() {
+            let bit = i * 11;
+            let bits = payload[bit / 8] as u32 | (payload[bit / 8 + 1] as u32) << 8 | (*payload.get(bit / 8 + 2).unwrap_or(&0) as u32) << 16;
+            *c = ((bits >> (bit % 8)) & 0x7ff) as u16;
+        }
+        Some(Channels(channels))
+    }
+}
+
+/// Encoded telemetry frame for the receiver to send down, derefs to the bytes to write
+#[derive(Debug, Clone, Copy)]
+pub struct Response {
+    buf: [u8; MAX_FRAME],
+    len: usize,
+}
+
+impl Response {
+    fn new(kind: u8, payload: &[u8]) -> Self {
+        let mut buf = [0; MAX_FRAME];
+        buf[0] = ADDRESS;
+        buf[1] = payload.len() as u8 + 2;
+        buf[2] = kind;
+        buf[3..3 + payload.len()].copy_from_slice(payload);
+        let end = 3 + payload.len();
+        buf[end] = crc8(&buf[2..end]);
+
+        Response { buf, len: end + 1 }
+    }
+
+    /// Pitch, roll and yaw in 100 microradian steps
+    pub fn attitude(orientation: &SpatialOrientation) -> Self {
+        let angle = |v: f32| ((v * 10_000.0) as i16).to_be_bytes();
+        let [p, r, y] = [angle(orientation.pitch), angle(orientation.roll), angle(orientation.yaw)];
+        Response::new(ATTITUDE, &[p[0], p[1], r[0], r[1], y[0], y[1]])
+    }
+
+    /// Volts and amps in tenths, mAh drawn since boot and the percentage left
+    pub fn battery(volts: f32, amps: f32, drawn_mah: u32, remaining_percent: u8) -> Self {
+        let [v0, v1] = ((volts * 10.0) as u16).to_be_bytes();
+        let [a0, a1] = ((amps * 10.0) as u16).to_be_bytes();
+        let [_, c0, c1, c2] = drawn_mah.min(0xff_ffff).to_be_bytes();
+        Response::new(BATTERY_SENSOR, &[v0, v1, a0, a1, c0, c1, c2, remaining_percent])
+    }
+}
+
+impl Deref for Response {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RcMap, Setpoint, MAX_SETPOINT_ANGLE};
+
+    /// Frames in `bytes`, fed one at a time
+    fn parse(bytes: &[u8]) -> Vec<Result<Frame, FrameError>> {
+        let mut parser = Parser::default();
+        bytes.iter().filter_map(|b| parser.push(*b)).collect()
+    }
+
+    /// An RC_CHANNELS_PACKED with roll full left, pitch full forward, throttle down, yaw
+    /// centred and channel 5 up, the other channels centred
+    const RC_FRAME: [u8; 26] = [
+        0xc8, 0x18, 0x16, 0xac, 0x98, 0x38, 0x2b, 0xc0, 0x37, 0x71, 0xf0, 0x81, 0x0f, 0x7c, 0xe0, 0x03, 0x1f, 0xf8, 0xc0, 0x07, 0x3e, 0xf0,
+        0x81, 0x0f, 0x7c, 0x8c,
+    ];
+
+    #[test]
+    fn crc8_is_dvb_s2() {
+        assert_eq!(crc8(b"123456789"), 0xbc);
+        assert_eq!(crc8(&RC_FRAME[2..25]), RC_FRAME[25]);
+    }
+
+    #[test]
+    fn sticks_and_switch_of_an_rc_frame() {
+        let frames = parse(&RC_FRAME);
+        let frame = frames[0].as_ref().unwrap();
+        assert_eq!((frames.len(), frame.kind), (1, RC_CHANNELS_PACKED));
+
+        let channels = Channels::from_payload(frame.payload()).unwrap();
+        assert_eq!(channels.0, [172, 1811, 172, 992, 1811, 992, 992, 992, 992, 992, 992, 992, 992, 992, 992, 992]);
+        let input = RcMap::DEFAULT.input(&channels.0);
+        assert_eq!(input.setpoint, Setpoint { roll: -MAX_SETPOINT_ANGLE, pitch: MAX_SETPOINT_ANGLE, yaw_rate: 0.0 });
+        assert_eq!((input.throttle, input.arm), (0.0, true));
+        assert!(Channels::from_payload(&frame.payload()[..21]).is_none());
+    }
+
+    #[test]
+    fn a_broken_frame_costs_only_itself() {
+        let mut corrupted = RC_FRAME;
+        corrupted[10] ^= 0x04;
+        let mut stream = vec![0x00, 0x55];
+        stream.extend(corrupted);
+        // an address with a length no frame has
+        stream.extend([ADDRESS, 0x01]);
+        stream.extend(RC_FRAME);
+
+        let frames = parse(&stream);
+        assert!(matches!(frames[..], [Err(FrameError::Crc), Err(FrameError::Length), Ok(_)]));
+        assert_eq!(frames[2].unwrap().payload(), &RC_FRAME[3..25]);
+    }
+
+    #[test]
+    fn frames_to_another_address_are_skipped() {
+        let mut other = RC_FRAME;
+        other[0] = 0xea;
+        assert!(parse(&other).is_empty());
+    }
+
+    #[test]
+    fn telemetry_parses_back() {
+        let orientation = SpatialOrientation { pitch: 0.5, roll: -0.25, yaw: 3.0 };
+        let attitude = Response::attitude(&orientation);
+        assert_eq!(attitude.len(), 4 + 6);
+        let frame = parse(&attitude)[0].unwrap();
+        assert_eq!((frame.kind, frame.payload()), (ATTITUDE, &[0x13, 0x88, 0xf6, 0x3c, 0x75, 0x30][..]));
+
+        let battery = Response::battery(11.7, 2.5, 0x0102_0304, 80);
+        let frame = parse(&battery)[0].unwrap();
+        assert_eq!((frame.kind, frame.payload()), (BATTERY_SENSOR, &[0x00, 0x75, 0x00, 0x19, 0xff, 0xff, 0xff, 80][..]));
+    }
+}
@@ -1,52 +1,3324 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
+
+pub mod cobs;
+pub mod crsf;
+pub mod mavlink;
+pub mod msp;
+pub mod ppm;
+pub mod sbus;
+pub mod telemetry;
+mod vector;
+mod wire;
+
+pub use vector::Vec3;
+pub use wire::{decode, Frame};
+
+use postcard::experimental::max_size::MaxSize;
+use serde::{Deserialize, Serialize};
 
 pub const EOT: u8 = 0b11111111;
-pub const COMMAND_SIZE: usize = 5;
-pub const BUFF_SIZE: usize = 8;
+/// Leads every command, a device on another version answers [AckStatus::Version] and
+/// announces its own in a [ProtocolInfo] without reading any further
+pub const PROTOCOL_VERSION: u8 = 6;
+/// Longest command, protocol version, postcard encoded sequence and [Msg] and their CRC-16.
+/// The largest variant decides, the receive ring holds a few of these once encoded.
+pub const COMMAND_SIZE: usize = 32;
+const _: () = assert!(1 + Command::POSTCARD_MAX_SIZE + 2 <= COMMAND_SIZE);
+/// A [cobs] encoded command and its [EOT]
+pub const COMMAND_FRAME_SIZE: usize = cobs::max_encoded_len(COMMAND_SIZE) + 1;
+/// Longest frame either side sends, id and payload before [telemetry] frames it
+pub const MAX_FRAME_SIZE: usize = 64;
+pub const SPATIAL_FRAME_SIZE: usize = 1 + SpatialOrientation::POSTCARD_MAX_SIZE;
+pub const TEMPERATURE_SIZE: usize = 1 + Temperature::POSTCARD_MAX_SIZE;
+pub const ALTITUDE_SIZE: usize = 1 + Altitude::POSTCARD_MAX_SIZE;
+pub const STATUS_SIZE: usize = 1 + Status::POSTCARD_MAX_SIZE;
+pub const SELF_TEST_SIZE: usize = 1 + SelfTest::POSTCARD_MAX_SIZE;
+pub const GYRO_DEBUG_SIZE: usize = 1 + GyroDebug::POSTCARD_MAX_SIZE;
+pub const BUS_SCAN_SIZE: usize = 1 + BusScan::POSTCARD_MAX_SIZE;
+pub const RAW_SAMPLE_SIZE: usize = 1 + RawSample::POSTCARD_MAX_SIZE;
+pub const SAMPLE_STATS_SIZE: usize = 1 + SampleStats::POSTCARD_MAX_SIZE;
+pub const MAG_CALIBRATION_SIZE: usize = 1 + MagCalibrationProgress::POSTCARD_MAX_SIZE;
+pub const FILTER_CONFIG_SIZE: usize = 1 + FilterConfig::POSTCARD_MAX_SIZE;
+pub const QUATERNION_SIZE: usize = 1 + AttitudeQuaternion::POSTCARD_MAX_SIZE;
+pub const LINEAR_ACCEL_SIZE: usize = 1 + LinearAcceleration::POSTCARD_MAX_SIZE;
+pub const VERTICAL_SIZE: usize = 1 + Vertical::POSTCARD_MAX_SIZE;
+pub const RATES_SIZE: usize = 1 + Rates::POSTCARD_MAX_SIZE;
+pub const COMPACT_ORIENTATION_SIZE: usize = 8;
+pub const HEADING_SIZE: usize = 1 + Heading::POSTCARD_MAX_SIZE;
+pub const CYCLE_STATS_SIZE: usize = 1 + CycleStats::POSTCARD_MAX_SIZE;
+pub const LINK_STATS_SIZE: usize = 1 + LinkStats::POSTCARD_MAX_SIZE;
+pub const ACK_SIZE: usize = 1 + Ack::POSTCARD_MAX_SIZE;
+pub const PROTOCOL_INFO_SIZE: usize = 1 + ProtocolInfo::POSTCARD_MAX_SIZE;
+pub const SETPOINT_SIZE: usize = 1 + Setpoint::POSTCARD_MAX_SIZE;
+pub const MOTOR_OUTPUTS_SIZE: usize = 1 + MotorOutputs::POSTCARD_MAX_SIZE;
+pub const APPLIED_STATE_SIZE: usize = 1 + AppliedState::POSTCARD_MAX_SIZE;
+pub const THROTTLE_OUTPUT_SIZE: usize = 1 + ThrottleOutput::POSTCARD_MAX_SIZE;
+pub const PID_GAINS_SIZE: usize = 1 + AxisPidGains::POSTCARD_MAX_SIZE;
+pub const TELEMETRY_CONFIG_SIZE: usize = 1 + TelemetryConfig::POSTCARD_MAX_SIZE;
+pub const ARM_STATE_SIZE: usize = 1 + ArmState::POSTCARD_MAX_SIZE;
+pub const LOCK_STATE_SIZE: usize = 1 + LockState::POSTCARD_MAX_SIZE;
+pub const FAILSAFE_CONFIG_SIZE: usize = 1 + FailsafeConfig::POSTCARD_MAX_SIZE;
+pub const TRIM_SIZE: usize = 1 + Trim::POSTCARD_MAX_SIZE;
+pub const PONG_SIZE: usize = 1 + Pong::POSTCARD_MAX_SIZE;
+pub const PARAM_VALUE_SIZE: usize = 1 + ParamValue::POSTCARD_MAX_SIZE;
+pub const PARAM_INFO_SIZE: usize = 1 + ParamInfo::POSTCARD_MAX_SIZE;
+pub const FLIGHT_MODE_SIZE: usize = 1 + FlightMode::POSTCARD_MAX_SIZE;
+pub const COMMAND_LOG_ENTRY_SIZE: usize = 1 + CommandLogEntry::POSTCARD_MAX_SIZE;
+pub const BUILD_INFO_SIZE: usize = 1 + BuildInfo::POSTCARD_MAX_SIZE;
+pub const ANNOUNCE_SIZE: usize = 1 + Announce::POSTCARD_MAX_SIZE;
+pub const LINK_RATE_SIZE: usize = 1 + LinkRate::POSTCARD_MAX_SIZE;
+pub const BATTERY_SIZE: usize = 1 + Battery::POSTCARD_MAX_SIZE;
+pub const EXTENDED_STATUS_SIZE: usize = 1 + ExtendedStatus::POSTCARD_MAX_SIZE;
 
-#[derive(Debug)]
+pub const ORIENTATION_ID: u8 = 0x4f;
+pub const TEMPERATURE_ID: u8 = 0x54;
+pub const ALTITUDE_ID: u8 = 0x41;
+pub const STATUS_ID: u8 = 0x53;
+pub const SELF_TEST_ID: u8 = 0x42;
+pub const GYRO_DEBUG_ID: u8 = 0x47;
+pub const BUS_SCAN_ID: u8 = 0x44;
+pub const RAW_SAMPLE_ID: u8 = 0x52;
+pub const SAMPLE_STATS_ID: u8 = 0x4d;
+pub const MAG_CALIBRATION_ID: u8 = 0x43;
+pub const FILTER_CONFIG_ID: u8 = 0x46;
+pub const QUATERNION_ID: u8 = 0x51;
+pub const LINEAR_ACCEL_ID: u8 = 0x4c;
+pub const VERTICAL_ID: u8 = 0x56;
+pub const RATES_ID: u8 = 0x72;
+pub const COMPACT_ORIENTATION_ID: u8 = 0x6f;
+pub const HEADING_ID: u8 = 0x48;
+pub const CYCLE_STATS_ID: u8 = 0x63;
+pub const LINK_STATS_ID: u8 = 0x6c;
+pub const ACK_ID: u8 = 0x61;
+pub const PROTOCOL_INFO_ID: u8 = 0x76;
+pub const SETPOINT_ID: u8 = 0x73;
+pub const MOTOR_OUTPUTS_ID: u8 = 0x6d;
+pub const APPLIED_STATE_ID: u8 = 0x64;
+pub const THROTTLE_OUTPUT_ID: u8 = 0x68;
+pub const PID_GAINS_ID: u8 = 0x70;
+pub const TELEMETRY_CONFIG_ID: u8 = 0x65;
+pub const ARM_STATE_ID: u8 = 0x45;
+pub const LOCK_STATE_ID: u8 = 0x55;
+pub const FAILSAFE_CONFIG_ID: u8 = 0x66;
+pub const TRIM_ID: u8 = 0x74;
+pub const PONG_ID: u8 = 0x50;
+pub const PARAM_VALUE_ID: u8 = 0x6e;
+pub const PARAM_INFO_ID: u8 = 0x49;
+pub const FLIGHT_MODE_ID: u8 = 0x4e;
+pub const COMMAND_LOG_ENTRY_ID: u8 = 0x67;
+pub const BUILD_INFO_ID: u8 = 0x62;
+pub const ANNOUNCE_ID: u8 = 0x6b;
+pub const LINK_RATE_ID: u8 = 0x6a;
+pub const BATTERY_ID: u8 = 0x69;
+pub const EXTENDED_STATUS_ID: u8 = 0x57;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, MaxSize)]
 pub struct SpatialOrientation {
     pub pitch: f32,
     pub roll: f32,
+    /// Counter clockwise seen from above, -pi to pi. Drifts without a magnetometer,
+    /// see [Msg::ZeroYaw].
+    pub yaw: f32,
 }
 
+/// Wire format of an orientation frame, [SPATIAL_FRAME_SIZE] bytes:
+///
+/// | bytes  | field | encoding |
+/// |--------|-------|----------|
+/// | 0      | [ORIENTATION_ID] | |
+/// | 1..5   | pitch | f32 little-endian, radians |
+/// | 5..9   | roll  | f32 little-endian, radians |
+/// | 9..13  | yaw   | f32 little-endian, radians |
+///
+/// Any of the bytes can be [EOT], 0xFF is a common low byte of an ordinary angle, which is
+/// why every frame goes over the link [cobs] encoded, see [telemetry].
+///
+/// This is the postcard encoding, floats go out as they are. The frame used to go without
+/// the ID, told apart by its length, which the postcard frames with their varints don't keep
+/// to.
 impl SpatialOrientation {
-    pub fn to_byte_array(&self) -> [u8; 8] {
-        let mut result: [u8; 8] = [0; 8];
-        let (one, two) = result.split_at_mut(4);
-        one.copy_from_slice(&self.pitch.to_le_bytes());
-        two.copy_from_slice(&self.roll.to_le_bytes());
+    pub fn to_byte_array(&self) -> Frame<SPATIAL_FRAME_SIZE> {
+        Frame::encode(ORIENTATION_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<SpatialOrientation> {
+        decode(ORIENTATION_ID, buf)
+    }
+}
+
+/// [SpatialOrientation] in centi-degrees, leading [COMPACT_ORIENTATION_ID] and closed by an
+/// XOR of every byte before it. Replaces orientation frames while [Streams::compact] is set,
+/// 8 bytes against 13 and a corrupted frame gets caught.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompactOrientation {
+    /// Pitch, roll and yaw, 0.01° steps. The angles stay within half a turn, saturation only
+    /// matters for a broken estimate.
+    pub centi_degrees: [i16; 3],
+}
+
+impl CompactOrientation {
+    /// Saturates at the i16 range, NaN ends up at zero
+    pub fn from_orientation(o: &SpatialOrientation) -> CompactOrientation {
+        let centi = |rad: f32| (rad * (18000.0 / core::f32::consts::PI)) as i16;
+        CompactOrientation { centi_degrees: [centi(o.pitch), centi(o.roll), centi(o.yaw)] }
+    }
+
+    pub fn to_orientation(&self) -> SpatialOrientation {
+        let rad = |centi: i16| centi as f32 * (core::f32::consts::PI / 18000.0);
+        let [pitch, roll, yaw] = self.centi_degrees;
+        SpatialOrientation { pitch: rad(pitch), roll: rad(roll), yaw: rad(yaw) }
+    }
+
+    pub fn to_byte_array(&self) -> [u8; COMPACT_ORIENTATION_SIZE] {
+        let mut result: [u8; COMPACT_ORIENTATION_SIZE] = [0; COMPACT_ORIENTATION_SIZE];
+        result[0] = COMPACT_ORIENTATION_ID;
+        for (chunk, v) in result[1..7].chunks_exact_mut(2).zip(self.centi_degrees.iter()) {
+            chunk.copy_from_slice(&v.to_le_bytes());
+        }
+        result[7] = xor(&result[..7]);
         result
     }
 
-    pub fn from_byte_slice(buf: &[u8]) -> SpatialOrientation {
-        let (one, two) = buf.split_at(4);
+    /// None for a checksum mismatch as well
+    pub fn from_byte_slice(buf: &[u8]) -> Option<CompactOrientation> {
+        if buf.len() != COMPACT_ORIENTATION_SIZE || buf[0] != COMPACT_ORIENTATION_ID || buf[7] != xor(&buf[..7]) {
+            return None;
+        }
+        let word = |i: usize| i16::from_le_bytes([buf[1 + i * 2], buf[2 + i * 2]]);
 
-        let pitch = f32::from_le_bytes(one.try_into().unwrap());
-        let roll = f32::from_le_bytes(two.try_into().unwrap());
+        Some(CompactOrientation { centi_degrees: [word(0), word(1), word(2)] })
+    }
+}
+
+fn xor(buf: &[u8]) -> u8 {
+    buf.iter().fold(0, |acc, b| acc ^ b)
+}
+
+/// Attitude as a unit quaternion, leading [QUATERNION_ID]. Replaces orientation frames while
+/// [Streams::quaternion] is set, same rotation as the [SpatialOrientation] angles without
+/// their gimbal lock.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct AttitudeQuaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl AttitudeQuaternion {
+    pub fn to_byte_array(&self) -> Frame<QUATERNION_SIZE> {
+        Frame::encode(QUATERNION_ID, self).unwrap()
+    }
 
-        SpatialOrientation { pitch, roll }
+    pub fn from_byte_slice(buf: &[u8]) -> Option<AttitudeQuaternion> {
+        decode(QUATERNION_ID, buf)
     }
 }
 
-#[derive(Debug)]
-pub struct Command {
-    pub throttle_on: bool,
-    pub throttle: f32,
+/// Tilt compensated magnetic heading and how far it is off the one [Msg::HoldHeading]
+/// captured, leading [HEADING_ID]. Every other orientation frame while a reference is held.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct Heading {
+    /// Counter clockwise like yaw, 0.01° steps
+    pub centi_degrees: i16,
+    /// Heading less the reference, -180° to 180°
+    pub error_centi_degrees: i16,
 }
 
-impl Command {
-    pub fn to_byte_array(&self) -> [u8; 5] {
-        let mut result: [u8; 5] = [0; 5];
-        result[0] = self.throttle_on as u8;
-        result[1..].copy_from_slice(&self.throttle.to_le_bytes());
-        result
+impl Heading {
+    pub fn to_byte_array(&self) -> Frame<HEADING_SIZE> {
+        Frame::encode(HEADING_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<Heading> {
+        decode(HEADING_ID, buf)
+    }
+}
+
+/// Gyroscope rates in frame axes as fusion sees them, offset taken out and notch filtered,
+/// leading [RATES_ID]. Sent with every orientation frame while [Streams::rates] is set.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct Rates {
+    /// About X, Y and Z like pitch, roll and yaw, up to 32 rad/s
+    pub milli_rad_s: [i16; 3],
+}
+
+impl Rates {
+    pub fn to_byte_array(&self) -> Frame<RATES_SIZE> {
+        Frame::encode(RATES_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<Rates> {
+        decode(RATES_ID, buf)
+    }
+}
+
+/// Acceleration with gravity taken out, in earth axes with Z up, leading [LINEAR_ACCEL_ID].
+/// Sent with every other orientation frame while [Streams::linear_accel] is set, not while
+/// the DMP fuses.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct LinearAcceleration {
+    pub milli_g: [i16; 3],
+}
+
+impl LinearAcceleration {
+    pub fn to_byte_array(&self) -> Frame<LINEAR_ACCEL_SIZE> {
+        Frame::encode(LINEAR_ACCEL_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<LinearAcceleration> {
+        decode(LINEAR_ACCEL_ID, buf)
+    }
+}
+
+/// MPU6050 die temperature, sent on its own with a leading [TEMPERATURE_ID]
+#[derive(Debug, Serialize, Deserialize, MaxSize)]
+pub struct Temperature {
+    pub celsius: f32,
+}
+
+impl Temperature {
+    pub fn to_byte_array(&self) -> Frame<TEMPERATURE_SIZE> {
+        Frame::encode(TEMPERATURE_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<Temperature> {
+        decode(TEMPERATURE_ID, buf)
+    }
+}
+
+/// Barometric altitude above the point the device booted at, leading [ALTITUDE_ID]
+#[derive(Debug, Serialize, Deserialize, MaxSize)]
+pub struct Altitude {
+    pub centimeters: i32,
+}
+
+impl Altitude {
+    pub fn to_byte_array(&self) -> Frame<ALTITUDE_SIZE> {
+        Frame::encode(ALTITUDE_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<Altitude> {
+        decode(ALTITUDE_ID, buf)
+    }
+}
+
+/// Barometer altitude fused with the accelerometer, leading [VERTICAL_ID]. Replaces
+/// [Altitude] with every barometer reading while the software fusion runs.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct Vertical {
+    /// Above the point the device booted at
+    pub centimeters: i32,
+    /// Climb rate, negative while descending
+    pub centimeters_per_s: i16,
+}
+
+impl Vertical {
+    pub fn to_byte_array(&self) -> Frame<VERTICAL_SIZE> {
+        Frame::encode(VERTICAL_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<Vertical> {
+        decode(VERTICAL_ID, buf)
+    }
+}
+
+/// Device state changes, leading [STATUS_ID]
+#[derive(Debug, Serialize, Deserialize, MaxSize)]
+pub struct Status {
+    /// Gyro offset capture is running, the device has to be kept still
+    pub calibrating: bool,
+    /// MPU6050 stopped answering, motors were cut and stay disarmed until commanded again
+    pub imu_lost: bool,
+    /// Gyroscope hit its full scale, attitude is off until the accelerometer corrected it
+    pub gyro_saturated: bool,
+    /// The craft was moving when armed, it flies on the attitude it had instead of one
+    /// re-seeded from the accelerometer
+    pub reset_skipped: bool,
+    /// Tipped over or hit the ground, the motor was cut and arming is refused until a
+    /// command disarms
+    pub crashed: bool,
+}
+
+impl Status {
+    pub fn to_byte_array(&self) -> Frame<STATUS_SIZE> {
+        Frame::encode(STATUS_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<Status> {
+        decode(STATUS_ID, buf)
+    }
+}
+
+/// MPU6050 power-on self-test per axis, leading [SELF_TEST_ID].
+/// Arming is refused unless every axis passed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct SelfTest {
+    pub gyro: [bool; 3],
+    pub accel: [bool; 3],
+}
+
+impl SelfTest {
+    pub fn passed(&self) -> bool {
+        self.gyro.iter().chain(self.accel.iter()).all(|p| *p)
+    }
+
+    pub fn to_byte_array(&self) -> Frame<SELF_TEST_SIZE> {
+        Frame::encode(SELF_TEST_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<SelfTest> {
+        decode(SELF_TEST_ID, buf)
+    }
+}
+
+/// Gyroscope before and after the notch filter in sensor counts less the offset, leading
+/// [GYRO_DEBUG_ID]. Only sent while [Streams::gyro_debug] is set.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct GyroDebug {
+    pub raw: [i16; 3],
+    pub filtered: [i16; 3],
+}
+
+impl GyroDebug {
+    pub fn to_byte_array(&self) -> Frame<GYRO_DEBUG_SIZE> {
+        Frame::encode(GYRO_DEBUG_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<GyroDebug> {
+        decode(GYRO_DEBUG_ID, buf)
+    }
+}
+
+/// Sensor words as read, before any calibration or filtering, leading [RAW_SAMPLE_ID].
+/// Replaces orientation frames while [Streams::raw_stream] is set.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct RawSample {
+    /// Counts every sample, streamed or not, so decimation and drops show up as gaps
+    pub counter: u16,
+    pub acc: [i16; 3],
+    pub gyro: [i16; 3],
+}
+
+impl RawSample {
+    pub fn to_byte_array(&self) -> Frame<RAW_SAMPLE_SIZE> {
+        Frame::encode(RAW_SAMPLE_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<RawSample> {
+        decode(RAW_SAMPLE_ID, buf)
+    }
+}
+
+/// How well sampling keeps up with the IMU, once per second with a leading [SAMPLE_STATS_ID]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct SampleStats {
+    /// Samples processed since boot
+    pub read: u32,
+    /// Samples the IMU produced that never made it, since boot
+    pub missed: u32,
+    /// Longest time between two batches of samples since the previous report
+    pub max_gap_us: u32,
+    /// FIFO overflows since boot
+    pub overflows: u16,
+}
+
+impl SampleStats {
+    pub fn to_byte_array(&self) -> Frame<SAMPLE_STATS_SIZE> {
+        Frame::encode(SAMPLE_STATS_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<SampleStats> {
+        decode(SAMPLE_STATS_ID, buf)
+    }
+}
+
+/// Magnetometer calibration in progress, leading [MAG_CALIBRATION_ID]. Sent every second
+/// while the craft is turned around and once more with `remaining_s` at zero when it ended.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct MagCalibrationProgress {
+    /// Share of the sphere of field directions seen so far
+    pub coverage_percent: u8,
+    pub remaining_s: u8,
+    /// Covered enough to solve, turning further still improves it
+    pub enough: bool,
+}
+
+impl MagCalibrationProgress {
+    pub fn to_byte_array(&self) -> Frame<MAG_CALIBRATION_SIZE> {
+        Frame::encode(MAG_CALIBRATION_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<MagCalibrationProgress> {
+        decode(MAG_CALIBRATION_ID, buf)
+    }
+}
+
+/// Attitude estimator tuning in use, leading [FILTER_CONFIG_ID]. Sent once per second, a
+/// [Param] changing it is answered with a [ParamValue].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct FilterConfig {
+    /// Pull of the accelerometer, the complementary weight, Madgwick's beta, Mahony's Kp or the
+    /// Kalman angle noise
+    pub gain: f32,
+    /// Accelerometer low-pass, zero for estimators without one
+    pub acc_cutoff_hz: f32,
+}
+
+impl FilterConfig {
+    pub fn to_byte_array(&self) -> Frame<FILTER_CONFIG_SIZE> {
+        Frame::encode(FILTER_CONFIG_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<FilterConfig> {
+        decode(FILTER_CONFIG_ID, buf)
+    }
+}
+
+/// Fewest, mean and most DWT cycles one stage took, all zero for a stage that didn't run
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, MaxSize)]
+pub struct Cycles {
+    pub min: u32,
+    pub mean: u32,
+    pub max: u32,
+}
+
+/// Where the sampling task spends its time since the previous report, once per second with a
+/// leading [CYCLE_STATS_ID]. Cycles at the 72 MHz system clock.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct CycleStats {
+    /// From asking for a FIFO batch to the transfer handing it over
+    pub i2c: Cycles,
+    /// One estimator update
+    pub fusion: Cycles,
+    /// One round of the telemetry task, encoding and queueing the frames for the DMA
+    pub telemetry: Cycles,
+    /// One run of the task for a FIFO batch
+    pub total: Cycles,
+    /// One run of the receive interrupt, the commands apply outside of it
+    pub receive: Cycles,
+}
+
+impl CycleStats {
+    pub fn to_byte_array(&self) -> Frame<CYCLE_STATS_SIZE> {
+        Frame::encode(CYCLE_STATS_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<CycleStats> {
+        decode(CYCLE_STATS_ID, buf)
+    }
+}
+
+/// 7-bit addresses the bus scan probes, the rest are reserved
+pub const BUS_SCAN_FIRST: u8 = 0x08;
+pub const BUS_SCAN_LAST: u8 = 0x77;
+/// One bit per address from [BUS_SCAN_FIRST] to [BUS_SCAN_LAST]
+const BUS_SCAN_BYTES: usize = (BUS_SCAN_LAST - BUS_SCAN_FIRST) as usize / 8 + 1;
+
+/// Addresses that acknowledged on the sensor bus at boot, leading [BUS_SCAN_ID]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct BusScan {
+    /// One bit per address from [BUS_SCAN_FIRST]
+    found: [u8; BUS_SCAN_BYTES],
+    /// Scan gave up on a stuck or timed out bus, only part of it was probed
+    pub dead: bool,
+}
+
+impl BusScan {
+    pub fn new() -> Self {
+        BusScan { found: [0; BUS_SCAN_BYTES], dead: false }
+    }
+
+    pub fn set(&mut self, address: u8) {
+        if let Some(i) = Self::index(address) {
+            self.found[i / 8] |= 1 << (i % 8);
+        }
+    }
+
+    pub fn found(&self, address: u8) -> bool {
+        Self::index(address).map(|i| self.found[i / 8] & (1 << (i % 8)) != 0).unwrap_or(false)
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = u8> + '_ {
+        (BUS_SCAN_FIRST..=BUS_SCAN_LAST).filter(move |a| self.found(*a))
+    }
+
+    fn index(address: u8) -> Option<usize> {
+        if (BUS_SCAN_FIRST..=BUS_SCAN_LAST).contains(&address) {
+            Some((address - BUS_SCAN_FIRST) as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn to_byte_array(&self) -> Frame<BUS_SCAN_SIZE> {
+        Frame::encode(BUS_SCAN_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<BusScan> {
+        decode(BUS_SCAN_ID, buf)
+    }
+}
+
+impl Default for BusScan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Setting a [Msg::SetParam] changes by its id, the discriminant. The device applies it
+/// right away and stores it on [Msg::SaveParams], its [ParamInfo] has the type and range.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub enum Param {
+    /// [FilterConfig::gain], f32
+    FilterGain = 1,
+    /// [FilterConfig::acc_cutoff_hz], f32, ignored by estimators without the low-pass
+    AccCutoff = 2,
+    /// Tilt in degrees past which the craft counts as crashed, f32
+    CrashTilt = 3,
+    /// [FailsafeConfig::timeout_ms], u32
+    LinkTimeout = 4,
+    /// [FailsafeConfig::ramp_ms], u32
+    LinkRamp = 5,
+    /// [TelemetryConfig::divisor], u32
+    TelemetryDivisor = 6,
+    /// [TelemetryConfig::streams], u32
+    TelemetryStreams = 7,
+    /// [Trim::roll] in degrees, f32
+    TrimRoll = 8,
+    /// [Trim::pitch] in degrees, f32
+    TrimPitch = 9,
+    /// [Streams::gyro_debug], bool, not stored
+    GyroDebug = 10,
+    /// [Streams::rates], bool, not stored
+    Rates = 11,
+    /// [Streams::compact], bool, not stored
+    Compact = 12,
+    /// [SlewConfig::up_per_s], f32
+    ThrottleSlewUp = 13,
+    /// [SlewConfig::down_per_s], f32
+    ThrottleSlewDown = 14,
+    /// [RcMap::roll] counting from one, u32
+    RcRoll = 15,
+    /// [RcMap::pitch] counting from one, u32
+    RcPitch = 16,
+    /// [RcMap::throttle] counting from one, u32
+    RcThrottle = 17,
+    /// [RcMap::yaw] counting from one, u32
+    RcYaw = 18,
+    /// [RcMap::arm] counting from one, u32
+    RcArm = 19,
+    /// [BatteryConfig::divider], f32
+    BatteryDivider = 20,
+    /// [BatteryConfig::cells], u32
+    BatteryCells = 21,
+    /// [BatteryConfig::warning_cell_volts], f32
+    BatteryWarning = 22,
+    /// [BatteryConfig::critical_cell_volts], f32
+    BatteryCritical = 23,
+    /// [BatteryConfig::failsafe], bool
+    BatteryFailsafe = 24,
+    /// [CurrentConfig::mv_per_amp], f32
+    CurrentScale = 25,
+    /// [CurrentConfig::offset_mv], f32
+    CurrentOffset = 26,
+    /// [CurrentConfig::max_amps], f32
+    CurrentLimit = 27,
+    /// Milliseconds between two [ExtendedStatus] frames, u32
+    StatusPeriod = 28,
+}
+
+impl Param {
+    pub const ALL: [Param; 28] = [
+        Param::FilterGain,
+        Param::AccCutoff,
+        Param::CrashTilt,
+        Param::LinkTimeout,
+        Param::LinkRamp,
+        Param::TelemetryDivisor,
+        Param::TelemetryStreams,
+        Param::TrimRoll,
+        Param::TrimPitch,
+        Param::GyroDebug,
+        Param::Rates,
+        Param::Compact,
+        Param::ThrottleSlewUp,
+        Param::ThrottleSlewDown,
+        Param::RcRoll,
+        Param::RcPitch,
+        Param::RcThrottle,
+        Param::RcYaw,
+        Param::RcArm,
+        Param::BatteryDivider,
+        Param::BatteryCells,
+        Param::BatteryWarning,
+        Param::BatteryCritical,
+        Param::BatteryFailsafe,
+        Param::CurrentScale,
+        Param::CurrentOffset,
+        Param::CurrentLimit,
+        Param::StatusPeriod,
+    ];
+
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    /// None for an id this side doesn't know of
+    pub fn from_id(id: u8) -> Option<Param> {
+        Param::ALL.iter().copied().find(|p| p.id() == id)
+    }
+}
+
+/// What a [Param] is set to, the variant is the type it takes
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub enum Value {
+    F32(f32),
+    U32(u32),
+    Bool(bool),
+}
+
+impl Value {
+    /// As a number for a range check, a bool is zero or one
+    pub fn as_f32(self) -> f32 {
+        match self {
+            Value::F32(v) => v,
+            Value::U32(v) => v as f32,
+            Value::Bool(v) => v as u8 as f32,
+        }
+    }
+
+    /// Same type as `other`
+    pub fn same_kind(self, other: Value) -> bool {
+        core::mem::discriminant(&self) == core::mem::discriminant(&other)
+    }
+}
+
+/// A parameter as the device has it, leading [PARAM_VALUE_ID]. Sent for [Msg::GetParam], for
+/// every [Msg::SetParam] whether it applied or not, and along with [ParamInfo].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct ParamValue {
+    /// [Param::id]
+    pub id: u8,
+    pub value: Value,
+}
+
+impl ParamValue {
+    pub fn to_byte_array(&self) -> Frame<PARAM_VALUE_SIZE> {
+        Frame::encode(PARAM_VALUE_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<ParamValue> {
+        decode(PARAM_VALUE_ID, buf)
+    }
+}
+
+/// Type, default and range of a parameter, leading [PARAM_INFO_ID]. Sent one at a time
+/// for [Msg::ListParams], each followed by its [ParamValue].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct ParamInfo {
+    /// [Param::id]
+    pub id: u8,
+    /// Also the type every value takes
+    pub default: Value,
+    /// Inclusive, zero and one for a bool
+    pub min: f32,
+    pub max: f32,
+}
+
+impl ParamInfo {
+    /// Of the same type as the default and within the range, false for NaN
+    pub fn accepts(&self, value: Value) -> bool {
+        let v = value.as_f32();
+        value.same_kind(self.default) && (self.min..=self.max).contains(&v)
+    }
+
+    pub fn to_byte_array(&self) -> Frame<PARAM_INFO_SIZE> {
+        Frame::encode(PARAM_INFO_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<ParamInfo> {
+        decode(PARAM_INFO_ID, buf)
+    }
+}
+
+/// Why a received frame was dropped
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameError {
+    /// More or fewer bytes than the frame takes
+    Length,
+    /// Bytes were corrupted on the way
+    Crc,
+    /// Not a valid [cobs] encoding, a delimiter went missing or came in early
+    Encoding,
+    /// Intact, but led by another [PROTOCOL_VERSION] than this one, which the payload
+    /// can't be read without
+    Version(u8),
+    /// Intact and on this version, yet the fields don't decode
+    Payload,
+    /// Intact and on this version, with a [Msg] variant this side doesn't know of. A newer
+    /// sender, everything else it sends still comes through.
+    Unknown(u8),
+    /// Decodes, but a float in it is NaN or infinite
+    NotFinite,
+    /// Decodes, but a value is past what its field takes, see [Msg::check]
+    Range,
+}
+
+/// What went wrong with a sensor
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SensorError {
+    /// A transfer failed or never completed
+    Bus,
+    /// Something else answered at the address, with this id
+    Identity(u8),
+    /// Outside of the tolerance of its factory self-test
+    SelfTest,
+    /// Stopped answering while streaming
+    Lost,
+    /// Held still too little, tilted or not turned through enough orientations
+    Calibration,
+}
+
+/// What went wrong with the settings in flash
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StorageError {
+    /// Nothing stored, an erased page or something that isn't settings
+    Blank,
+    /// Stored by a firmware with another layout, of this version
+    Layout(u16),
+    /// A write that didn't complete or a page gone bad
+    Crc,
+    /// The flash didn't take the read, erase or write
+    Flash,
+}
+
+/// Any failure the firmware tells apart, to match on and to log as one
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    Frame(FrameError),
+    Sensor(SensorError),
+    Storage(StorageError),
+}
+
+impl Error {
+    /// Kind in the high byte and variant in the low one, what a value a variant carries
+    /// doesn't show. 0x0102 is [FrameError::Crc].
+    pub fn code(&self) -> u16 {
+        let (kind, variant) = match self {
+            Error::Frame(e) => (1, match e {
+                FrameError::Length => 1,
+                FrameError::Crc => 2,
+                FrameError::Encoding => 3,
+                FrameError::Version(_) => 4,
+                FrameError::Payload => 5,
+                FrameError::Unknown(_) => 6,
+                FrameError::NotFinite => 7,
+                FrameError::Range => 8,
+            }),
+            Error::Sensor(e) => (2, match e {
+                SensorError::Bus => 1,
+                SensorError::Identity(_) => 2,
+                SensorError::SelfTest => 3,
+                SensorError::Lost => 4,
+                SensorError::Calibration => 5,
+            }),
+            Error::Storage(e) => (3, match e {
+                StorageError::Blank => 1,
+                StorageError::Layout(_) => 2,
+                StorageError::Crc => 3,
+                StorageError::Flash => 4,
+            }),
+        };
+        (kind as u16) << 8 | variant
+    }
+}
+
+impl From<FrameError> for Error {
+    fn from(e: FrameError) -> Self {
+        Error::Frame(e)
     }
+}
+
+impl From<SensorError> for Error {
+    fn from(e: SensorError) -> Self {
+        Error::Sensor(e)
+    }
+}
+
+impl From<StorageError> for Error {
+    fn from(e: StorageError) -> Self {
+        Error::Storage(e)
+    }
+}
+
+/// CRC-16/CCITT-FALSE, bitwise, commands are too short and rare for a table
+pub fn crc16(buf: &[u8]) -> u16 {
+    let mut crc = 0xffffu16;
+    for byte in buf {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Writes the [crc16] of everything before the last two bytes into them, little-endian
+pub fn append_crc16(frame: &mut [u8]) {
+    let end = frame.len() - 2;
+    let crc = crc16(&frame[..end]);
+    frame[end..].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// Whether the last two bytes are the [crc16] of the rest, false for anything that short
+pub fn check_crc16(frame: &[u8]) -> bool {
+    match frame.len().checked_sub(2) {
+        Some(end) => crc16(&frame[..end]).to_le_bytes() == frame[end..],
+        None => false,
+    }
+}
+
+/// Where a circular DMA transfer over `size` bytes writes next, and how many bytes it wrote
+/// since `read_at`, from `remaining`, what its counter says it has left before it starts over.
+/// Those bytes run from `read_at` to the end of the buffer into the start of it when they
+/// wrapped. The counter reads `size` as it reloads, which is the start as well.
+pub fn ring_pending(read_at: usize, remaining: usize, size: usize) -> (usize, usize) {
+    let write_at = (size - remaining) % size;
+    (write_at, (write_at + size - read_at) % size)
+}
+
+/// How the command link fared since boot or the last [Msg::ResetLinkStats], leading
+/// [LINK_STATS_ID]. Sent whenever a command is dropped, with the once a second statistics
+/// and for [Msg::RequestStatus]. Every count wraps around past `u32::MAX`, the difference
+/// between two frames holds as long as they are less than a wrap apart.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct LinkStats {
+    /// Applied, or at least taken as the latest
+    pub accepted: u32,
+    /// Dropped for a [FrameError], [LinkStats::crc] among them
+    pub rejected: u32,
+    /// Dropped by the [SequenceTracker] as one already seen or older
+    pub stale: u32,
+    /// Rejected for [FrameError::Crc]
+    pub crc: u32,
+    /// Sequences skipped between accepted commands, see [SequenceTracker::skipped]
+    pub gaps: u32,
+    /// Times the link failsafe cut or ramped down the motors
+    pub failsafes: u32,
+    pub bytes_received: u32,
+    pub bytes_sent: u32,
+    /// Bursts the UART lost a byte of, the DMA fell behind. Counted once per idle line like
+    /// the two below.
+    pub overruns: u32,
+    /// Bursts with a byte missing its stop bit, a wrong baud rate or a line pulled low
+    pub framing: u32,
+    /// Bursts the UART sampled noise in
+    pub noise: u32,
+    /// Frames the device had no room for in its transmit buffer, the link too slow for the
+    /// telemetry rate
+    pub skipped: u32,
+}
+
+impl LinkStats {
+    pub const fn new() -> Self {
+        LinkStats { accepted: 0, rejected: 0, stale: 0, crc: 0, gaps: 0, failsafes: 0, bytes_received: 0, bytes_sent: 0, overruns: 0, framing: 0, noise: 0, skipped: 0 }
+    }
+
+    pub fn to_byte_array(&self) -> Frame<LINK_STATS_SIZE> {
+        Frame::encode(LINK_STATS_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<LinkStats> {
+        decode(LINK_STATS_ID, buf)
+    }
+}
+
+impl Default for LinkStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How far behind the last accepted command a replayed one can be. A sequence further back
+/// than that is taken for a restarted ground app and accepted.
+pub const SEQUENCE_WINDOW: u16 = 64;
+
+/// What became of a command, see [Ack]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub enum AckStatus {
+    Applied = 0,
+    /// Corrupted on the way, [FrameError::Crc]
+    Crc = 1,
+    /// Throttle outside of 0..1, a [Setpoint], [PidGains] or [Trim] that isn't valid or a
+    /// float that isn't finite, [FrameError::Range] and [FrameError::NotFinite]. Nothing
+    /// was applied.
+    Range = 2,
+    /// [Arm::Arm] while arming isn't allowed, with a throttle over [MAX_ARM_THROTTLE] or
+    /// without [ARM_MAGIC], the device stays disarmed
+    NotArmed = 3,
+    /// Calibration or [Msg::RebootToBootloader] asked for while armed, the rest was applied
+    Armed = 4,
+    /// Cut short, too long or badly encoded, the echoed sequence means nothing. Fields that
+    /// don't decode, [FrameError::Payload], come here too.
+    Malformed = 5,
+    /// Seen already, see [SequenceTracker]
+    Stale = 6,
+    /// Another [PROTOCOL_VERSION], nothing was read past it
+    Version = 7,
+    /// Applied, but a [TelemetryConfig] divisor out of range was clamped into it. The
+    /// [TelemetryConfig] frame that follows has what the device went with.
+    Clamped = 8,
+    /// A [Msg] this device doesn't know of, [FrameError::Unknown], or a [Param] id. Skipped,
+    /// commands after it apply as usual.
+    Unknown = 9,
+    /// A [FlightMode] the firmware has no controller for, the mode stays as it was
+    Unsupported = 10,
+    /// Throttle or [Arm::Arm] while [LockState::Locked], ignored until [Msg::Unlock]
+    Locked = 11,
+}
 
-    pub fn from_byte_slice(buf: &[u8]) -> Command {
-        let throttle_on = buf[0] != 0;
-        let throttle = f32::from_le_bytes(buf[1..].try_into().unwrap());
+/// Answer to a [Msg::Ping], leading [PONG_ID]. Goes out right after its [Ack] rather than
+/// with the next telemetry frame.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct Pong {
+    /// As the ping carried it
+    pub nonce: u32,
+    /// Device time since boot when it was answered, in the 10 ms steps of its scheduler
+    pub uptime_ms: u32,
+    /// From the receive interrupt that picked the ping up to the answer being queued
+    pub processing_us: u32,
+}
+
+impl Pong {
+    pub fn to_byte_array(&self) -> Frame<PONG_SIZE> {
+        Frame::encode(PONG_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<Pong> {
+        decode(PONG_ID, buf)
+    }
+}
+
+/// A command as the device took it in, leading [COMMAND_LOG_ENTRY_ID]. The device keeps the
+/// last few in RAM and sends them oldest first on [Msg::DumpCommandLog].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct CommandLogEntry {
+    /// Device time since boot it came in at, in the 10 ms steps of its scheduler
+    pub at_ms: u32,
+    /// Zero for a frame too broken to tell
+    pub sequence: u16,
+    /// None for a frame that didn't decode, the status says why
+    pub msg: Option<Msg>,
+    /// What it was acked with
+    pub status: AckStatus,
+}
+
+const _: () = assert!(COMMAND_LOG_ENTRY_SIZE <= MAX_FRAME_SIZE);
+
+impl CommandLogEntry {
+    pub fn to_byte_array(&self) -> Frame<COMMAND_LOG_ENTRY_SIZE> {
+        Frame::encode(COMMAND_LOG_ENTRY_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<CommandLogEntry> {
+        decode(COMMAND_LOG_ENTRY_ID, buf)
+    }
+}
+
+/// Reply to every command frame, leading [ACK_ID], with the [Command::sequence] it came
+/// with. A corrupted command echoes whatever its sequence bytes turned into.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct Ack {
+    pub sequence: u16,
+    pub status: AckStatus,
+}
+
+impl Ack {
+    pub fn to_byte_array(&self) -> Frame<ACK_SIZE> {
+        Frame::encode(ACK_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<Ack> {
+        decode(ACK_ID, buf)
+    }
+}
+
+/// [PROTOCOL_VERSION] of the device, leading [PROTOCOL_INFO_ID]. Sent once, the first time a
+/// command came in on another version.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct ProtocolInfo {
+    pub version: u8,
+}
+
+impl ProtocolInfo {
+    pub fn to_byte_array(&self) -> Frame<PROTOCOL_INFO_SIZE> {
+        Frame::encode(PROTOCOL_INFO_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<ProtocolInfo> {
+        decode(PROTOCOL_INFO_ID, buf)
+    }
+}
+
+/// Baud rate the device's command link runs at, leading [LINK_RATE_ID]. Reported once a
+/// second with the status.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct LinkRate {
+    pub baud: u32,
+}
+
+impl LinkRate {
+    pub fn to_byte_array(&self) -> Frame<LINK_RATE_SIZE> {
+        Frame::encode(LINK_RATE_ID, self).unwrap()
+    }
 
-        Command { throttle_on, throttle }
+    pub fn from_byte_slice(buf: &[u8]) -> Option<LinkRate> {
+        decode(LINK_RATE_ID, buf)
     }
-}
\ No newline at end of file
+}
+
+/// How low the battery is, see [BatteryConfig::alarm]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, MaxSize)]
+pub enum BatteryAlarm {
+    Ok = 0,
+    /// Below [BatteryConfig::warning_cell_volts], time to land
+    Warning = 1,
+    /// Below [BatteryConfig::critical_cell_volts], the craft fails safe with
+    /// [BatteryConfig::failsafe]
+    Critical = 2,
+}
+
+/// Pack voltage and current and the alarm on them, leading [BATTERY_ID]. Sent once a second
+/// with the status stream and whenever the alarm changes, nothing while no battery is
+/// connected.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct Battery {
+    pub millivolts: u16,
+    /// [Battery::millivolts] over [BatteryConfig::cells]
+    pub cell_millivolts: u16,
+    pub alarm: BatteryAlarm,
+    /// In 10 mA steps, none without a current sensor, see [CurrentConfig::mv_per_amp]
+    pub centiamps: Option<u16>,
+    /// Drawn since boot or the last [Msg::ResetConsumed]
+    pub consumed_mah: u32,
+    /// Drawn since the craft last armed
+    pub armed_mah: u32,
+    /// The current went past [CurrentConfig::max_amps] once, held until [Msg::ResetConsumed]
+    pub over_current: bool,
+}
+
+impl Battery {
+    pub fn to_byte_array(&self) -> Frame<BATTERY_SIZE> {
+        Frame::encode(BATTERY_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<Battery> {
+        decode(BATTERY_ID, buf)
+    }
+}
+
+/// Characters of the commit hash in a [BuildInfo]
+pub const COMMIT_LEN: usize = 7;
+
+/// The firmware a device runs, leading [BUILD_INFO_ID]. Sent at boot, with the once a second
+/// report and for [Msg::RequestVersion].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct BuildInfo {
+    /// Major, minor and patch of the crate
+    pub version: [u8; 3],
+    /// Short hash in ASCII, zeros for a build from outside a git checkout
+    pub commit: [u8; COMMIT_LEN],
+    /// Built with changes to tracked files on top of [BuildInfo::commit]
+    pub dirty: bool,
+    /// UTC day of the build as YYYYMMDD
+    pub date: u32,
+    pub protocol: u8,
+}
+
+impl BuildInfo {
+    pub fn to_byte_array(&self) -> Frame<BUILD_INFO_SIZE> {
+        Frame::encode(BUILD_INFO_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<BuildInfo> {
+        decode(BUILD_INFO_ID, buf)
+    }
+}
+
+/// `0.1.0 1a2b3c4-dirty 2024-05-01 protocol 5`, without the commit when there is none
+impl core::fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let [major, minor, patch] = self.version;
+        write!(f, "{}.{}.{}", major, minor, patch)?;
+        let len = self.commit.iter().position(|b| *b == 0).unwrap_or(COMMIT_LEN);
+        if let (true, Ok(commit)) = (len > 0, core::str::from_utf8(&self.commit[..len])) {
+            write!(f, " {}{}", commit, if self.dirty { "-dirty" } else { "" })?;
+        }
+        let (year, month, day) = (self.date / 10_000, self.date / 100 % 100, self.date % 100);
+        write!(f, " {:04}-{:02}-{:02} protocol {}", year, month, day, self.protocol)
+    }
+}
+
+/// What a device can do, leading [ANNOUNCE_ID], for the ground to set itself up from. Sent
+/// for [Msg::Hello] and once a few seconds after boot.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct Announce {
+    pub protocol: u8,
+    /// `FEATURE_` bits, what the firmware was built with
+    pub features: u32,
+    pub motors: u8,
+    /// `SENSOR_` bits, what answered at boot
+    pub sensors: u8,
+    /// Parameters [Msg::ListParams] goes through, see [Param::ALL]
+    pub params: u8,
+}
+
+impl Announce {
+    /// [Msg::Throttle] and the rest come over MSP instead, see [msp]
+    pub const FEATURE_MSP: u32 = 1 << 0;
+    /// Throttle, setpoint and arming come from a CRSF receiver
+    pub const FEATURE_CRSF: u32 = 1 << 1;
+    /// ... from an SBUS receiver
+    pub const FEATURE_SBUS: u32 = 1 << 2;
+    /// ... from a PPM receiver
+    pub const FEATURE_PPM: u32 = 1 << 3;
+    /// Telemetry goes out as MAVLink, see [mavlink]
+    pub const FEATURE_MAVLINK: u32 = 1 << 4;
+    /// ICM-20602 rather than MPU6050
+    pub const FEATURE_ICM20602: u32 = 1 << 5;
+    /// Fusion on the MPU6050 DMP when it loads
+    pub const FEATURE_DMP: u32 = 1 << 6;
+    /// Sampling on a timer, no INT line
+    pub const FEATURE_POLLED: u32 = 1 << 7;
+    /// A second command link on USART2, see the wired-link feature
+    pub const FEATURE_WIRED_LINK: u32 = 1 << 8;
+    /// Commands on USART1 an interrupt a byte, no DMA
+    pub const FEATURE_RX_IRQ: u32 = 1 << 9;
+
+    pub const SENSOR_IMU: u8 = 1 << 0;
+    pub const SENSOR_MAG: u8 = 1 << 1;
+    pub const SENSOR_BARO: u8 = 1 << 2;
+
+    pub fn feature(&self, feature: u32) -> bool {
+        self.features & feature != 0
+    }
+
+    pub fn sensor(&self, sensor: u8) -> bool {
+        self.sensors & sensor != 0
+    }
+
+    pub fn to_byte_array(&self) -> Frame<ANNOUNCE_SIZE> {
+        Frame::encode(ANNOUNCE_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<Announce> {
+        decode(ANNOUNCE_ID, buf)
+    }
+}
+
+/// Drops commands the receive DMA handed over twice or late, by [Command::sequence]
+#[derive(Debug, Clone, Copy)]
+pub struct SequenceTracker {
+    last: Option<u16>,
+}
+
+impl SequenceTracker {
+    pub const fn new() -> Self {
+        SequenceTracker { last: None }
+    }
+
+    /// True for a sequence past the last accepted one, which it then becomes. Wraps around,
+    /// anything up to [SEQUENCE_WINDOW] back counts as already seen.
+    pub fn accept(&mut self, sequence: u16) -> bool {
+        if let Some(last) = self.last {
+            if last.wrapping_sub(sequence) < SEQUENCE_WINDOW {
+                return false;
+            }
+        }
+        self.last = Some(sequence);
+        true
+    }
+
+    /// Commands missing between the last accepted one and `sequence`, asked before
+    /// [SequenceTracker::accept]. A jump further ahead than [SEQUENCE_WINDOW] is taken for
+    /// a restarted ground app and counts none.
+    pub fn skipped(&self, sequence: u16) -> u32 {
+        match self.last.map(|last| sequence.wrapping_sub(last)) {
+            Some(ahead) if (1..=SEQUENCE_WINDOW).contains(&ahead) => ahead as u32 - 1,
+            _ => 0,
+        }
+    }
+}
+
+impl Default for SequenceTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Roll and pitch a [Setpoint] can ask for at most, radians
+pub const MAX_SETPOINT_ANGLE: f32 = core::f32::consts::FRAC_PI_4;
+/// Yaw rate a [Setpoint] can ask for at most, rad/s
+pub const MAX_SETPOINT_YAW_RATE: f32 = 2.0 * core::f32::consts::PI;
+
+/// Attitude for the controller to hold, level and not turning by default. The device
+/// echoes the one it holds once per second, leading [SETPOINT_ID].
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, MaxSize)]
+pub struct Setpoint {
+    /// Radians, same sense as [SpatialOrientation::roll]
+    pub roll: f32,
+    /// Radians, same sense as [SpatialOrientation::pitch]
+    pub pitch: f32,
+    /// rad/s, counter clockwise seen from above
+    pub yaw_rate: f32,
+}
+
+impl Setpoint {
+    /// Within [MAX_SETPOINT_ANGLE] and [MAX_SETPOINT_YAW_RATE], false for NaN
+    pub fn is_valid(&self) -> bool {
+        let within = |v: f32, max: f32| (-max..=max).contains(&v);
+        within(self.roll, MAX_SETPOINT_ANGLE) && within(self.pitch, MAX_SETPOINT_ANGLE) && within(self.yaw_rate, MAX_SETPOINT_YAW_RATE)
+    }
+
+    /// What the controller holds for this setpoint on a frame trimmed by `trim`
+    pub fn trimmed(&self, trim: &Trim) -> Setpoint {
+        Setpoint { roll: self.roll + trim.roll, pitch: self.pitch + trim.pitch, ..*self }
+    }
+
+    pub fn to_byte_array(&self) -> Frame<SETPOINT_SIZE> {
+        Frame::encode(SETPOINT_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<Setpoint> {
+        decode(SETPOINT_ID, buf)
+    }
+}
+
+/// Sticks and arm switch of an RC link, whatever protocol brought them. Takes the place of
+/// [Msg::Throttle], [Msg::Setpoint] and [Msg::Arm] for a device flown by a transmitter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RcInput {
+    /// 0..1
+    pub throttle: f32,
+    /// [valid](Setpoint::is_valid) always
+    pub setpoint: Setpoint,
+    /// Switch position, the device arms on it going up and disarms on it going down
+    pub arm: bool,
+}
+
+impl RcInput {
+    /// Roll, pitch and yaw sticks at -1..1 and throttle at 0..1, anything past that is
+    /// clamped. Full stick is [MAX_SETPOINT_ANGLE] and [MAX_SETPOINT_YAW_RATE].
+    pub fn from_sticks(roll: f32, pitch: f32, yaw: f32, throttle: f32, arm: bool) -> Self {
+        let stick = |v: f32| v.clamp(-1.0, 1.0);
+        RcInput {
+            throttle: throttle.clamp(0.0, 1.0),
+            setpoint: Setpoint {
+                roll: stick(roll) * MAX_SETPOINT_ANGLE,
+                pitch: stick(pitch) * MAX_SETPOINT_ANGLE,
+                yaw_rate: stick(yaw) * MAX_SETPOINT_YAW_RATE,
+            },
+            arm,
+        }
+    }
+}
+
+/// Roll or pitch a [Trim] can offset at most, radians
+pub const MAX_TRIM_ANGLE: f32 = 10.0 * core::f32::consts::PI / 180.0;
+
+/// Attitude that counts as level for the frame, added to the roll and pitch of the
+/// [Setpoint]. Leading [TRIM_ID], sent once per second and whenever it changed.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, MaxSize)]
+pub struct Trim {
+    /// Radians, same sense as [Setpoint::roll]
+    pub roll: f32,
+    /// Radians, same sense as [Setpoint::pitch]
+    pub pitch: f32,
+}
+
+impl Trim {
+    /// Within [MAX_TRIM_ANGLE] either way, false for NaN
+    pub fn is_valid(&self) -> bool {
+        let within = |v: f32| (-MAX_TRIM_ANGLE..=MAX_TRIM_ANGLE).contains(&v);
+        within(self.roll) && within(self.pitch)
+    }
+
+    pub fn to_byte_array(&self) -> Frame<TRIM_SIZE> {
+        Frame::encode(TRIM_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<Trim> {
+        decode(TRIM_ID, buf)
+    }
+}
+
+/// PWM duty of the four motors as set, leading [MOTOR_OUTPUTS_ID]. Sent once per second.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct MotorOutputs {
+    /// Motor 1 to 4 on TIM4 channel 1 to 4, zero while disarmed
+    pub duty: [u16; 4],
+    /// Duty at full throttle
+    pub max_duty: u16,
+}
+
+impl MotorOutputs {
+    pub fn to_byte_array(&self) -> Frame<MOTOR_OUTPUTS_SIZE> {
+        Frame::encode(MOTOR_OUTPUTS_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<MotorOutputs> {
+        decode(MOTOR_OUTPUTS_ID, buf)
+    }
+}
+
+/// Range of the [ExtendedStatus] period, [Param::StatusPeriod]
+pub const MIN_STATUS_PERIOD_MS: u16 = 100;
+pub const MAX_STATUS_PERIOD_MS: u16 = 10_000;
+pub const DEFAULT_STATUS_PERIOD_MS: u16 = 1000;
+
+/// The health of the craft in one frame, leading [EXTENDED_STATUS_ID]. Sent with
+/// [TelemetryConfig::EXTENDED] every [Param::StatusPeriod], once a second by default.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct ExtendedStatus {
+    pub arm_state: ArmState,
+    pub outputs: MotorOutputs,
+    /// Since the last command of the link in control, none before the first one
+    pub command_age_ms: Option<u32>,
+    /// I2C transfers that failed since boot
+    pub i2c_errors: u32,
+    /// Shortest and longest gyro task run since the frame before, in us, zero without one
+    pub loop_min_us: u32,
+    pub loop_max_us: u32,
+    /// [ExtendedStatus::WARNING_IMU_LOST] and the rest that apply
+    pub warnings: u8,
+}
+
+impl ExtendedStatus {
+    /// No IMU at all or it stopped answering
+    pub const WARNING_IMU_LOST: u8 = 1 << 0;
+    /// [BatteryAlarm::Warning] or worse
+    pub const WARNING_LOW_BATTERY: u8 = 1 << 1;
+    pub const WARNING_GYRO_SATURATED: u8 = 1 << 2;
+    /// An accelerometer or magnetometer calibration is running
+    pub const WARNING_CALIBRATING: u8 = 1 << 3;
+    /// [Battery::over_current]
+    pub const WARNING_OVER_CURRENT: u8 = 1 << 4;
+
+    pub fn warning(&self, warning: u8) -> bool {
+        self.warnings & warning != 0
+    }
+
+    pub fn to_byte_array(&self) -> Frame<EXTENDED_STATUS_SIZE> {
+        Frame::encode(EXTENDED_STATUS_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<ExtendedStatus> {
+        decode(EXTENDED_STATUS_ID, buf)
+    }
+}
+
+/// What the device ended up with after a command, leading [APPLIED_STATE_ID]. Follows the
+/// [Ack] of every command that wasn't rejected, so the ground sees what it did rather than
+/// working it out.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct AppliedState {
+    /// Of the command, as its [Ack] has it
+    pub sequence: u16,
+    pub outputs: MotorOutputs,
+    pub arm_state: ArmState,
+    /// Motor enable line, only high once [ArmState::Armed]
+    pub enabled: bool,
+    pub mode: FlightMode,
+}
+
+impl AppliedState {
+    pub fn to_byte_array(&self) -> Frame<APPLIED_STATE_SIZE> {
+        Frame::encode(APPLIED_STATE_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<AppliedState> {
+        decode(APPLIED_STATE_ID, buf)
+    }
+}
+
+/// Largest P, I, D or feed forward gain a [PidGains] can carry
+pub const MAX_PID_GAIN: f32 = 10.0;
+
+/// Axis a [PidGains] applies to, also its index in the device's gain table
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub enum Axis {
+    Roll = 0,
+    Pitch = 1,
+    Yaw = 2,
+}
+
+impl Axis {
+    pub const ALL: [Axis; 3] = [Axis::Roll, Axis::Pitch, Axis::Yaw];
+}
+
+/// Attitude controller gains of one axis, throttle fraction per radian of error for roll
+/// and pitch and per rad/s for yaw. Zero until tuned.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, MaxSize)]
+pub struct PidGains {
+    pub p: f32,
+    pub i: f32,
+    pub d: f32,
+    /// On the setpoint itself rather than the error
+    pub ff: f32,
+}
+
+impl PidGains {
+    /// Every gain within 0..[MAX_PID_GAIN], false for NaN
+    pub fn is_valid(&self) -> bool {
+        [self.p, self.i, self.d, self.ff].iter().all(|g| (0.0..=MAX_PID_GAIN).contains(g))
+    }
+}
+
+/// Gains the device uses for one axis, leading [PID_GAINS_ID]. Sent for the axis a command
+/// set, valid or not, and for all three on [Msg::GetPidGains].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct AxisPidGains {
+    pub axis: Axis,
+    pub gains: PidGains,
+}
+
+impl AxisPidGains {
+    pub fn to_byte_array(&self) -> Frame<PID_GAINS_SIZE> {
+        Frame::encode(PID_GAINS_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<AxisPidGains> {
+        decode(PID_GAINS_ID, buf)
+    }
+}
+/// Samples between telemetry frames a [TelemetryConfig] can ask for at least, anything
+/// closer together doesn't fit through 9600 baud
+pub const MIN_TELEMETRY_DIVISOR: u16 = 5;
+/// Samples between telemetry frames a [TelemetryConfig] can ask for at most, 10 s at 500 Hz
+pub const MAX_TELEMETRY_DIVISOR: u16 = 5000;
+
+/// How often the device streams and what, leading [TELEMETRY_CONFIG_ID]. Echoed once a
+/// second and right after a command changed it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct TelemetryConfig {
+    /// Telemetry goes out every this many periods of the 500 Hz gyro, on a timer of its own
+    /// whatever the IMU samples at
+    pub divisor: u16,
+    /// [TelemetryConfig::ATTITUDE] and the rest, streams left out aren't sent at all
+    pub streams: u8,
+}
+
+impl TelemetryConfig {
+    /// Orientation, or its quaternion or compact form
+    pub const ATTITUDE: u8 = 1 << 0;
+    /// [Rates], as long as [Streams::rates] is set too
+    pub const RATES: u8 = 1 << 1;
+    /// The once a second [Temperature], [SelfTest], [FilterConfig], [Setpoint], [Trim] and
+    /// [MotorOutputs]. [Status] changes always go out.
+    pub const STATUS: u8 = 1 << 2;
+    /// [SampleStats] and [CycleStats]
+    pub const STATISTICS: u8 = 1 << 3;
+    /// [ExtendedStatus], on a period of its own
+    pub const EXTENDED: u8 = 1 << 4;
+
+    /// 50 Hz with every stream
+    pub const DEFAULT: TelemetryConfig = TelemetryConfig {
+        divisor: 10,
+        streams: TelemetryConfig::ATTITUDE | TelemetryConfig::RATES | TelemetryConfig::STATUS | TelemetryConfig::STATISTICS | TelemetryConfig::EXTENDED,
+    };
+
+    pub fn streams(&self, streams: u8) -> bool {
+        self.streams & streams == streams
+    }
+
+    /// The divisor within [MIN_TELEMETRY_DIVISOR] and [MAX_TELEMETRY_DIVISOR], true when it
+    /// wasn't
+    pub fn clamped(self) -> (TelemetryConfig, bool) {
+        let divisor = self.divisor.clamp(MIN_TELEMETRY_DIVISOR, MAX_TELEMETRY_DIVISOR);
+        (TelemetryConfig { divisor, ..self }, divisor != self.divisor)
+    }
+
+    pub fn to_byte_array(&self) -> Frame<TELEMETRY_CONFIG_SIZE> {
+        Frame::encode(TELEMETRY_CONFIG_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<TelemetryConfig> {
+        decode(TELEMETRY_CONFIG_ID, buf)
+    }
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig::DEFAULT
+    }
+}
+
+/// What the throttle drives, leading [FLIGHT_MODE_ID]. Sent on every change and once per
+/// second. A mode byte past the last one doesn't decode and is acked
+/// [AckStatus::Malformed].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub enum FlightMode {
+    /// Throttle straight to every motor, the [Setpoint] is only held
+    Passthrough = 0,
+    /// The [Setpoint] roll and pitch taken as rates
+    Rate = 1,
+    /// Self-levelling to the [Setpoint] attitude
+    Angle = 2,
+}
+
+impl FlightMode {
+    pub fn to_byte_array(&self) -> Frame<FLIGHT_MODE_SIZE> {
+        Frame::encode(FLIGHT_MODE_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<FlightMode> {
+        decode(FLIGHT_MODE_ID, buf)
+    }
+}
+
+/// Goes with [Arm::Arm], a corrupted frame that still decodes is unlikely to carry it
+pub const ARM_MAGIC: u32 = 0x4152_4d21;
+/// Throttle an [Arm::Arm] command may carry at most
+pub const MAX_ARM_THROTTLE: f32 = 0.05;
+
+/// Arming request of a [Msg::Arm]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub enum Arm {
+    /// With [ARM_MAGIC], see [ArmState::Arming]
+    Arm(u32),
+    /// Cuts the motors whatever the state, and the only way out of [ArmState::Failsafe]
+    Disarm,
+}
+
+/// Where the device is in arming, leading [ARM_STATE_ID]. Sent on every change and once
+/// per second.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub enum ArmState {
+    Disarmed = 0,
+    /// [Arm::Arm] was accepted, the motors are enabled once its [Ack] went out
+    Arming = 1,
+    /// Motors enabled, throttle applies
+    Armed = 2,
+    /// Motors cut by the device itself, the IMU was lost, the craft crashed or commands
+    /// stopped coming, see [FailsafeConfig]. Arming is refused until [Arm::Disarm].
+    Failsafe = 3,
+    /// Motors cut by an [EmergencyStop]. Nothing but a power cycle or
+    /// [Msg::ClearEmergencyStop] leaves it, not even [Arm::Disarm].
+    Killed = 4,
+}
+
+impl ArmState {
+    pub fn to_byte_array(&self) -> Frame<ARM_STATE_SIZE> {
+        Frame::encode(ARM_STATE_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<ArmState> {
+        decode(ARM_STATE_ID, buf)
+    }
+}
+/// Goes with [Msg::Unlock]. Only a whole command passing its CRC carries it, corrupted
+/// bytes don't unlock anything.
+pub const UNLOCK_CODE: u32 = 0x554e_4c4b;
+
+/// Whether the device takes throttle and arm commands, leading [LOCK_STATE_ID]. Sent on
+/// every change and once per second.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub enum LockState {
+    /// After boot and whenever commands stopped coming for [FailsafeConfig::timeout_ms], so
+    /// a throttle the ground still had queued when the link came back spins nothing.
+    /// [Arm::Disarm] still applies.
+    Locked = 0,
+    /// Since an [Msg::Unlock], or with an RC receiver flying the device since its arm
+    /// switch was seen down
+    Unlocked = 1,
+}
+
+impl LockState {
+    pub fn to_byte_array(&self) -> Frame<LOCK_STATE_SIZE> {
+        Frame::encode(LOCK_STATE_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<LockState> {
+        decode(LOCK_STATE_ID, buf)
+    }
+}
+
+/// Goes with [Msg::ClearEmergencyStop], nothing short of a whole command carrying it lets
+/// the motors run again after an [EmergencyStop]
+pub const CLEAR_STOP_MAGIC: u32 = 0x4b49_4c4c;
+
+/// Goes with [Msg::RebootToBootloader], the device stops flying until the next reset on it
+pub const BOOTLOADER_MAGIC: u32 = 0x424f_4f54;
+
+/// Rates [Msg::SetBaud] takes, the ones the HC-05 and USB serial adapters have in common
+pub const BAUD_RATES: [u32; 5] = [9600, 19_200, 38_400, 57_600, 115_200];
+
+/// Matches the raw bytes of [EmergencyStop::FRAME] as they come in, before and whatever
+/// the frame decoding. The device cuts the motors the moment the last byte is in, a decoder
+/// left half way through a broken frame or a full command queue don't hold it up.
+///
+/// The frame isn't a command. Between its two delimiters sits a [cobs] group of code 0xFE,
+/// which promises 253 bytes and stops after 4: no encoder ever writes that, and a command
+/// never gets near a full group anyway. So no run of valid frames contains it, only the
+/// ground sending it on purpose or line noise making up all 7 bytes.
+#[derive(Debug)]
+pub struct EmergencyStop {
+    matched: usize,
+}
+
+impl EmergencyStop {
+    pub const FRAME: [u8; 7] = [EOT, 0xFE, b'K', b'I', b'L', b'L', EOT];
+
+    pub const fn new() -> Self {
+        EmergencyStop { matched: 0 }
+    }
+
+    /// True once `byte` completes the frame, which may have come over several calls
+    pub fn push(&mut self, byte: u8) -> bool {
+        // only the delimiters repeat in the frame, a mismatch on one starts over from it
+        self.matched = if byte == Self::FRAME[self.matched] {
+            self.matched + 1
+        } else if byte == EOT {
+            1
+        } else {
+            0
+        };
+        if self.matched == Self::FRAME.len() {
+            // the closing delimiter opens the next one
+            self.matched = 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Default for EmergencyStop {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Shortest [FailsafeConfig::timeout_ms], a few commands lost in a row shouldn't cut
+pub const MIN_LINK_TIMEOUT_MS: u16 = 100;
+pub const MAX_LINK_TIMEOUT_MS: u16 = 5000;
+pub const MAX_LINK_RAMP_MS: u16 = 2000;
+
+/// What the device does once commands stop coming while armed, leading [FAILSAFE_CONFIG_ID].
+/// Sent once per second, a [Param] changing it is answered with a [ParamValue].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct FailsafeConfig {
+    /// Time without a command after which the device fails safe
+    pub timeout_ms: u16,
+    /// The motors go down to zero over this long, zero cuts them right away
+    pub ramp_ms: u16,
+}
+
+impl FailsafeConfig {
+    pub const DEFAULT: FailsafeConfig = FailsafeConfig { timeout_ms: 500, ramp_ms: 0 };
+
+    pub fn is_valid(&self) -> bool {
+        (MIN_LINK_TIMEOUT_MS..=MAX_LINK_TIMEOUT_MS).contains(&self.timeout_ms) && self.ramp_ms <= MAX_LINK_RAMP_MS
+    }
+
+    pub fn to_byte_array(&self) -> Frame<FAILSAFE_CONFIG_SIZE> {
+        Frame::encode(FAILSAFE_CONFIG_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<FailsafeConfig> {
+        decode(FAILSAFE_CONFIG_ID, buf)
+    }
+}
+
+impl Default for FailsafeConfig {
+    fn default() -> Self {
+        FailsafeConfig::DEFAULT
+    }
+}
+
+/// Fastest [SlewConfig] rate, anything quicker makes no difference at the output rate
+pub const MAX_THROTTLE_SLEW: f32 = 100.0;
+
+/// How fast the throttle the motors get follows the commanded one, in full throttle per
+/// second and zero for no limit. Going down is instant by default, cutting the throttle
+/// shouldn't wait.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SlewConfig {
+    pub up_per_s: f32,
+    pub down_per_s: f32,
+}
+
+impl SlewConfig {
+    /// Zero to full in half a second
+    pub const DEFAULT: SlewConfig = SlewConfig { up_per_s: 2.0, down_per_s: 0.0 };
+
+    pub fn is_valid(&self) -> bool {
+        let valid = |rate: f32| (0.0..=MAX_THROTTLE_SLEW).contains(&rate);
+        valid(self.up_per_s) && valid(self.down_per_s)
+    }
+
+    /// From `output` towards `commanded`, as far as `dt` seconds allow
+    pub fn step(&self, output: f32, commanded: f32, dt: f32) -> f32 {
+        let max_step = |rate: f32| if rate > 0.0 { rate * dt } else { f32::INFINITY };
+        if commanded > output {
+            commanded.min(output + max_step(self.up_per_s))
+        } else {
+            commanded.max(output - max_step(self.down_per_s))
+        }
+    }
+}
+
+impl Default for SlewConfig {
+    fn default() -> Self {
+        SlewConfig::DEFAULT
+    }
+}
+
+/// Channels a CRSF or SBUS frame carries
+pub const RC_CHANNELS: usize = 16;
+
+/// Receiver channel of every control, counting from zero. Roll, pitch, throttle and yaw
+/// on the first four and the arm switch on the fifth by default, what ExpressLRS and most
+/// transmitters start out with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RcMap {
+    pub roll: u8,
+    pub pitch: u8,
+    pub throttle: u8,
+    pub yaw: u8,
+    pub arm: u8,
+}
+
+impl RcMap {
+    pub const DEFAULT: RcMap = RcMap { roll: 0, pitch: 1, throttle: 2, yaw: 3, arm: 4 };
+
+    /// Every control on one of the [RC_CHANNELS], two may share one
+    pub fn is_valid(&self) -> bool {
+        [self.roll, self.pitch, self.throttle, self.yaw, self.arm].iter().all(|c| (*c as usize) < RC_CHANNELS)
+    }
+
+    /// From CRSF and SBUS channel values, 172 to 1811 for 988 to 2012 us. The arm switch
+    /// is up past 1700 us.
+    pub fn input(&self, channels: &[u16; RC_CHANNELS]) -> RcInput {
+        let (min, mid, max) = (172.0, 992.0, 1811.0);
+        let value = |channel: u8| channels[channel as usize % RC_CHANNELS] as f32;
+        let stick = |channel: u8| (value(channel) - mid) / (max - mid);
+        let throttle = (value(self.throttle) - min) / (max - min);
+        RcInput::from_sticks(stick(self.roll), stick(self.pitch), stick(self.yaw), throttle, stick(self.arm) > 0.4)
+    }
+}
+
+impl Default for RcMap {
+    fn default() -> Self {
+        RcMap::DEFAULT
+    }
+}
+
+/// Range of [BatteryConfig::divider]
+pub const MIN_BATTERY_DIVIDER: f32 = 1.0;
+pub const MAX_BATTERY_DIVIDER: f32 = 20.0;
+/// Most cells in series [BatteryConfig::cells] takes, a 6S pack
+pub const MAX_BATTERY_CELLS: u8 = 6;
+/// Range of the [BatteryConfig] thresholds, a LiPo cell empty to full
+pub const MIN_CELL_VOLTS: f32 = 2.8;
+pub const MAX_CELL_VOLTS: f32 = 4.2;
+/// How far past its threshold a cell has to come back up for an alarm to clear
+pub const BATTERY_HYSTERESIS_VOLTS: f32 = 0.1;
+
+/// Battery voltage sensing and its thresholds, per cell so they don't change with the pack
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatteryConfig {
+    /// Pack volts per volt on the pin, 11 for the usual 10k over 1k
+    pub divider: f32,
+    /// In series
+    pub cells: u8,
+    pub warning_cell_volts: f32,
+    pub critical_cell_volts: f32,
+    /// [BatteryAlarm::Critical] fails the craft safe, over the [FailsafeConfig::ramp_ms]
+    pub failsafe: bool,
+}
+
+impl BatteryConfig {
+    /// A 3S LiPo behind 10k over 1k, alarms only
+    pub const DEFAULT: BatteryConfig = BatteryConfig { divider: 11.0, cells: 3, warning_cell_volts: 3.5, critical_cell_volts: 3.3, failsafe: false };
+
+    /// A warning set below the critical threshold is valid, it never shows
+    pub fn is_valid(&self) -> bool {
+        let cell = |volts: f32| (MIN_CELL_VOLTS..=MAX_CELL_VOLTS).contains(&volts);
+        (MIN_BATTERY_DIVIDER..=MAX_BATTERY_DIVIDER).contains(&self.divider)
+            && (1..=MAX_BATTERY_CELLS).contains(&self.cells)
+            && cell(self.warning_cell_volts)
+            && cell(self.critical_cell_volts)
+    }
+
+    /// Alarm at `cell_volts` coming from `previous`. It goes up as soon as a threshold is
+    /// crossed and back down only [BATTERY_HYSTERESIS_VOLTS] above it, a pack sagging under
+    /// throttle doesn't flicker between two.
+    pub fn alarm(&self, cell_volts: f32, previous: BatteryAlarm) -> BatteryAlarm {
+        let level = |volts: f32| {
+            if volts < self.critical_cell_volts {
+                BatteryAlarm::Critical
+            } else if volts < self.warning_cell_volts {
+                BatteryAlarm::Warning
+            } else {
+                BatteryAlarm::Ok
+            }
+        };
+        level(cell_volts).max(previous.min(level(cell_volts - BATTERY_HYSTERESIS_VOLTS)))
+    }
+}
+
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        BatteryConfig::DEFAULT
+    }
+}
+
+/// Range of [CurrentConfig::mv_per_amp], zero being no sensor
+pub const MAX_CURRENT_SCALE: f32 = 500.0;
+/// Range of [CurrentConfig::offset_mv], the ADC input's
+pub const MAX_CURRENT_OFFSET_MV: f32 = 3300.0;
+/// Range of [CurrentConfig::max_amps], zero being no limit
+pub const MAX_CURRENT_LIMIT: f32 = 200.0;
+
+/// Current sense output of a power module, volts on the pin going up with the amps drawn
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CurrentConfig {
+    /// Zero for no sensor, the pin isn't read then
+    pub mv_per_amp: f32,
+    /// On the pin with nothing drawn, half the supply for a bidirectional Hall sensor
+    pub offset_mv: f32,
+    /// Past which [Battery::over_current] is set, zero for never
+    pub max_amps: f32,
+}
+
+impl CurrentConfig {
+    /// Nothing connected
+    pub const DEFAULT: CurrentConfig = CurrentConfig { mv_per_amp: 0.0, offset_mv: 0.0, max_amps: 0.0 };
+
+    pub fn is_valid(&self) -> bool {
+        (0.0..=MAX_CURRENT_SCALE).contains(&self.mv_per_amp)
+            && (0.0..=MAX_CURRENT_OFFSET_MV).contains(&self.offset_mv)
+            && (0.0..=MAX_CURRENT_LIMIT).contains(&self.max_amps)
+    }
+
+    /// Amps for `millivolts` on the pin, never below zero, none without a sensor
+    pub fn amps(&self, millivolts: f32) -> Option<f32> {
+        if self.mv_per_amp > 0.0 {
+            Some(((millivolts - self.offset_mv) / self.mv_per_amp).max(0.0))
+        } else {
+            None
+        }
+    }
+
+    pub fn over_current(&self, amps: f32) -> bool {
+        self.max_amps > 0.0 && amps > self.max_amps
+    }
+}
+
+impl Default for CurrentConfig {
+    fn default() -> Self {
+        CurrentConfig::DEFAULT
+    }
+}
+
+/// Throttle as commanded and as it goes to the motors after the [SlewConfig], leading
+/// [THROTTLE_OUTPUT_ID]. Sent with the telemetry frames while armed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub struct ThrottleOutput {
+    pub commanded: f32,
+    pub output: f32,
+}
+
+impl ThrottleOutput {
+    pub fn to_byte_array(&self) -> Frame<THROTTLE_OUTPUT_SIZE> {
+        Frame::encode(THROTTLE_OUTPUT_ID, self).unwrap()
+    }
+
+    pub fn from_byte_slice(buf: &[u8]) -> Option<ThrottleOutput> {
+        decode(THROTTLE_OUTPUT_ID, buf)
+    }
+}
+
+/// Extra frames the device streams, kept until the next [Msg::Streams]
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, MaxSize)]
+pub struct Streams {
+    /// [GyroDebug] frames
+    pub gyro_debug: bool,
+    /// [RawSample] frames instead of orientation, the device refuses to arm meanwhile
+    pub raw_stream: bool,
+    /// [AttitudeQuaternion] frames instead of orientation, they take 17 bytes against 13
+    pub quaternion: bool,
+    /// [LinearAcceleration] frames
+    pub linear_accel: bool,
+    /// [Rates] frames
+    pub rates: bool,
+    /// [CompactOrientation] frames instead of orientation, [Streams::quaternion] goes first
+    pub compact: bool,
+}
+
+/// What a [Command] asks for, one thing each. Postcard leads with the variant index, a
+/// new variant goes last so the ones before keep theirs.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, MaxSize)]
+pub enum Msg {
+    /// 0..1, the motors only run on it once [ArmState::Armed]
+    Throttle(f32),
+    /// Starts arming or disarms
+    Arm(Arm),
+    /// Replaces the one the device holds, a setpoint that isn't [valid](Setpoint::is_valid)
+    /// leaves it as it was
+    Setpoint(Setpoint),
+    Streams(Streams),
+    /// New value for the [Param] with this id, answered with a [ParamValue]. One the
+    /// device doesn't know of is acked [AckStatus::Unknown], one its [ParamInfo] doesn't
+    /// accept [AckStatus::Range].
+    SetParam(u8, Value),
+    /// New gains for an axis, in use right away but lost at reset until
+    /// [Msg::SavePidGains]. Ones that aren't [valid](PidGains::is_valid) are ignored.
+    SetPidGains(Axis, PidGains),
+    /// Report the gains of every axis
+    GetPidGains,
+    /// Store the gains in use to flash, ignored while armed
+    SavePidGains,
+    /// Replaces the one the device streams with, stored along with the calibration
+    Telemetry(TelemetryConfig),
+    /// Capture and store a new gyro offset, ignored while armed
+    Calibrate,
+    /// Capture the face the device rests on for the six position accelerometer calibration,
+    /// ignored while armed
+    CalibrateAccel,
+    /// Start the magnetometer calibration, the craft has to be turned through every
+    /// orientation until [MagCalibrationProgress] reports it ended. Ignored while armed.
+    CalibrateMag,
+    /// Take the current heading as yaw zero. A magnetometer pulls yaw back to magnetic
+    /// heading afterwards.
+    ZeroYaw,
+    /// Take the current magnetic heading as the reference [Heading] frames report against,
+    /// ignored without a magnetometer
+    HoldHeading,
+    /// Send the [ArmState], [LockState], [FlightMode], [TelemetryConfig], [FailsafeConfig],
+    /// [Setpoint], [Trim], [MotorOutputs] and [LinkStats] right away rather than with the
+    /// next once a second report
+    RequestStatus,
+    /// Replaces the trim in use, lost at reset until [Msg::SaveTrim]. One that isn't
+    /// [valid](Trim::is_valid) leaves it as it was.
+    SetTrim(Trim),
+    /// Take the current roll and pitch as the trim, with the craft resting on a level
+    /// surface. Ignored while armed or past [MAX_TRIM_ANGLE].
+    CaptureTrim,
+    /// Store the trim in use to flash, ignored while armed
+    SaveTrim,
+    /// Answered with a [Pong] carrying the same nonce, for the round trip time
+    Ping(u32),
+    /// Report the [ParamValue] of the [Param] with this id
+    GetParam(u8),
+    /// Report the [ParamInfo] and [ParamValue] of every parameter, a few per second
+    ListParams,
+    /// Store every parameter in use to flash along with the trim, ignored while armed
+    SaveParams,
+    /// Switch to another [FlightMode], armed or not
+    SetMode(FlightMode),
+    /// With [CLEAR_STOP_MAGIC], leaves [ArmState::Killed] for [ArmState::Disarmed]. Arming
+    /// takes an [Arm::Arm] after it as usual.
+    ClearEmergencyStop(u32),
+    /// Report the [CommandLogEntry] of every command the device still has, a few per second
+    DumpCommandLog,
+    /// With [UNLOCK_CODE], see [LockState::Locked]
+    Unlock(u32),
+    /// Report the [BuildInfo] right away
+    RequestVersion,
+    /// With [BOOTLOADER_MAGIC], cut the motors and jump into the ROM bootloader for
+    /// reflashing over the same serial link. Refused while armed with [AckStatus::Armed],
+    /// [AckStatus::Range] without the magic.
+    RebootToBootloader(u32),
+    /// Start every [LinkStats] count over from zero, answered with the [LinkStats] frame
+    ResetLinkStats,
+    /// Answered with an [Announce], for a ground that just connected
+    Hello,
+    /// Acked at the rate the link is at, then over to one of [BAUD_RATES]. Back to the old
+    /// rate when no command comes through at the new one. Refused while armed.
+    SetBaud(u32),
+    /// Start [Battery::consumed_mah] and [Battery::armed_mah] over from zero and clear
+    /// [Battery::over_current], answered with the [Battery] frame
+    ResetConsumed,
+}
+
+impl Msg {
+    /// Variant indices in use, anything from here on is [FrameError::Unknown]
+    pub const TAGS: u8 = 32;
+
+    /// [FrameError::NotFinite] for a NaN or infinite float, [FrameError::Range] for a
+    /// throttle, setpoint, gains or trim out of range. A [Msg::SetParam] value only has to
+    /// be finite, what range it takes is up to the device's [ParamInfo].
+    pub fn check(&self) -> Result<(), FrameError> {
+        let all_finite = |floats: &[f32]| floats.iter().all(|f| f.is_finite());
+        let (finite, valid) = match self {
+            Msg::Throttle(t) => (t.is_finite(), (0.0..=1.0).contains(t)),
+            Msg::Setpoint(s) => (all_finite(&[s.roll, s.pitch, s.yaw_rate]), s.is_valid()),
+            Msg::SetParam(_, value) => (value.as_f32().is_finite(), true),
+            Msg::SetPidGains(_, g) => (all_finite(&[g.p, g.i, g.d, g.ff]), g.is_valid()),
+            Msg::SetTrim(t) => (all_finite(&[t.roll, t.pitch]), t.is_valid()),
+            Msg::SetBaud(rate) => (true, BAUD_RATES.contains(rate)),
+            _ => (true, true),
+        };
+        match (finite, valid) {
+            (false, _) => Err(FrameError::NotFinite),
+            (true, false) => Err(FrameError::Range),
+            (true, true) => Ok(()),
+        }
+    }
+}
+
+/// On the wire the [PROTOCOL_VERSION], the sequence, the [Msg] variant index and its
+/// fields, and a [crc16] of all of them
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, MaxSize)]
+pub struct Command {
+    /// One more than the command before, wrapping, see [SequenceTracker]. First so that
+    /// [Command::sequence_of] finds it in a frame that doesn't decode.
+    pub sequence: u16,
+    pub msg: Msg,
+}
+
+impl Command {
+    pub fn to_byte_array(&self) -> Frame<COMMAND_SIZE> {
+        // sized for the longest encoding
+        Frame::encode(PROTOCOL_VERSION, self).and_then(Frame::with_crc16).unwrap()
+    }
+
+    /// Sequence of a command that didn't decode, None when even that is cut short or it's
+    /// on another version
+    pub fn sequence_of(buf: &[u8]) -> Option<u16> {
+        match buf.split_first() {
+            Some((&PROTOCOL_VERSION, rest)) => postcard::take_from_bytes(rest).ok().map(|(sequence, _)| sequence),
+            _ => None,
+        }
+    }
+
+    /// First byte of the variant index, past the version and sequence
+    fn tag_of(buf: &[u8]) -> Option<u8> {
+        match buf.split_first() {
+            Some((&PROTOCOL_VERSION, rest)) => postcard::take_from_bytes::<u16>(rest).ok().and_then(|(_, rest)| rest.first().copied()),
+            _ => None,
+        }
+    }
+
+    /// The version goes first, another one may come with other fields and length. Only a
+    /// command that passes [Msg::check] comes out.
+    pub fn from_byte_slice(buf: &[u8]) -> Result<Command, FrameError> {
+        if buf.len() < 3 || buf.len() > COMMAND_SIZE {
+            return Err(FrameError::Length);
+        }
+        if !check_crc16(buf) {
+            return Err(FrameError::Crc);
+        }
+        if buf[0] != PROTOCOL_VERSION {
+            return Err(FrameError::Version(buf[0]));
+        }
+
+        let payload = &buf[..buf.len() - 2];
+        let command: Command = decode(PROTOCOL_VERSION, payload).ok_or_else(|| match Command::tag_of(payload) {
+            Some(tag) if tag >= Msg::TAGS => FrameError::Unknown(tag),
+            _ => FrameError::Payload,
+        })?;
+        command.msg.check()?;
+        Ok(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quaternion_roundtrips() {
+        let q = AttitudeQuaternion { w: 0.9238795, x: 0.0, y: -0.3826834, z: 1e-7 };
+        let bytes = q.to_byte_array();
+
+        assert_eq!(bytes[0], QUATERNION_ID);
+        assert_eq!(AttitudeQuaternion::from_byte_slice(&bytes), Some(q));
+    }
+
+    #[test]
+    fn quaternion_refuses_other_frames() {
+        let bytes = AttitudeQuaternion { w: 1.0, x: 0.0, y: 0.0, z: 0.0 }.to_byte_array();
+
+        assert_eq!(AttitudeQuaternion::from_byte_slice(&bytes[..QUATERNION_SIZE - 1]), None);
+        let mut other = bytes.to_vec();
+        other[0] = FILTER_CONFIG_ID;
+        assert_eq!(AttitudeQuaternion::from_byte_slice(&other), None);
+    }
+
+    #[test]
+    fn crc16_is_ccitt_false() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+        assert_eq!(crc16(&[]), 0xffff);
+    }
+
+    /// One of every [Msg], in variant order
+    fn msgs() -> Vec<Msg> {
+        vec![
+            Msg::Throttle(0.5),
+            Msg::Arm(Arm::Arm(ARM_MAGIC)),
+            Msg::Setpoint(Setpoint { roll: 0.1, pitch: -0.2, yaw_rate: 1.0 }),
+            Msg::Streams(Streams { quaternion: true, rates: true, ..Streams::default() }),
+            Msg::SetParam(Param::FilterGain.id(), Value::F32(0.02)),
+            Msg::SetPidGains(Axis::Yaw, PidGains { p: 0.5, i: 0.0, d: 0.01, ff: 1.0 }),
+            Msg::GetPidGains,
+            Msg::SavePidGains,
+            Msg::Telemetry(TelemetryConfig { divisor: 25, streams: TelemetryConfig::ATTITUDE }),
+            Msg::Calibrate,
+            Msg::CalibrateAccel,
+            Msg::CalibrateMag,
+            Msg::ZeroYaw,
+            Msg::HoldHeading,
+            Msg::RequestStatus,
+            Msg::SetTrim(Trim { roll: -0.01, pitch: 0.02 }),
+            Msg::CaptureTrim,
+            Msg::SaveTrim,
+            Msg::Ping(0xdead_beef),
+            Msg::GetParam(Param::Compact.id()),
+            Msg::ListParams,
+            Msg::SaveParams,
+            Msg::SetMode(FlightMode::Angle),
+            Msg::ClearEmergencyStop(CLEAR_STOP_MAGIC),
+            Msg::DumpCommandLog,
+            Msg::Unlock(UNLOCK_CODE),
+            Msg::RequestVersion,
+            Msg::RebootToBootloader(BOOTLOADER_MAGIC),
+            Msg::ResetLinkStats,
+            Msg::Hello,
+            Msg::SetBaud(115_200),
+            Msg::ResetConsumed,
+        ]
+    }
+
+    /// Half throttle as command 0x1234
+    fn command() -> Command {
+        Command { sequence: 0x1234, msg: Msg::Throttle(0.5) }
+    }
+
+    #[test]
+    fn every_msg_roundtrips_under_its_variant_index() {
+        let msgs = msgs();
+        assert_eq!(msgs.len(), Msg::TAGS as usize);
+
+        for (tag, msg) in msgs.into_iter().enumerate() {
+            let frame = Command { sequence: 0xfffe, msg }.to_byte_array();
+            let decoded = Command::from_byte_slice(&frame).unwrap();
+
+            assert_eq!((decoded.sequence, decoded.msg), (0xfffe, msg));
+            assert_eq!(Command::tag_of(&frame), Some(tag as u8));
+            assert!(check_crc16(&frame) && frame.len() <= COMMAND_SIZE);
+        }
+    }
+
+    #[test]
+    fn the_largest_msg_fits_a_command() {
+        let largest = msgs().into_iter().map(|msg| Command { sequence: u16::MAX, msg }.to_byte_array().len()).max().unwrap();
+
+        assert!(largest <= COMMAND_SIZE);
+        assert!(cobs::max_encoded_len(largest) < COMMAND_FRAME_SIZE);
+    }
+
+    #[test]
+    fn unknown_variants_are_told_from_broken_ones() {
+        let frame = Command { sequence: 7, msg: Msg::Calibrate }.to_byte_array();
+        let body = &frame[..frame.len() - 2];
+        let tag = body.len() - 1;
+
+        for unknown in Msg::TAGS..0x80 {
+            // a newer variant, whatever fields it comes with
+            for fields in [&[][..], &[1, 2, 3]] {
+                let mut other = body.to_vec();
+                other[tag] = unknown;
+                other.extend_from_slice(fields);
+                assert_eq!(Command::from_byte_slice(&with_crc(&other)).err(), Some(FrameError::Unknown(unknown)));
+                assert_eq!(Command::sequence_of(&with_crc(&other)), Some(7));
+            }
+        }
+        // a known variant without its fields
+        let mut cut = body.to_vec();
+        cut[tag] = 0;
+        assert_eq!(Command::from_byte_slice(&with_crc(&cut)).err(), Some(FrameError::Payload));
+    }
+
+    #[test]
+    fn command_with_any_flipped_bit_is_rejected() {
+        for msg in msgs() {
+            let frame = Command { sequence: 0x1234, msg }.to_byte_array();
+            for i in 0..frame.len() * 8 {
+                let mut corrupted = frame.to_vec();
+                corrupted[i / 8] ^= 1 << (i % 8);
+                assert_eq!(Command::from_byte_slice(&corrupted).err(), Some(FrameError::Crc), "{:?} bit {}", msg, i);
+            }
+        }
+    }
+
+    #[test]
+    fn command_with_any_byte_replaced_is_rejected() {
+        for msg in msgs() {
+            let frame = Command { sequence: 0x1234, msg }.to_byte_array();
+            for i in 0..frame.len() {
+                for value in (0..=255u8).filter(|v| *v != frame[i]) {
+                    let mut corrupted = frame.to_vec();
+                    corrupted[i] = value;
+                    assert_eq!(Command::from_byte_slice(&corrupted).err(), Some(FrameError::Crc), "{:?} byte {} as {:#x}", msg, i, value);
+                }
+            }
+        }
+    }
+
+    /// `body` under the version byte it leads with and a fresh CRC
+    fn with_crc(body: &[u8]) -> Vec<u8> {
+        let mut frame = body.to_vec();
+        frame.extend_from_slice(&[0, 0]);
+        append_crc16(&mut frame);
+        frame
+    }
+
+    #[test]
+    fn command_of_the_wrong_length_is_rejected() {
+        let frame = command().to_byte_array();
+        let body = &frame[..frame.len() - 2];
+
+        // cut short the CRC is off, intact but cut or grown the fields don't decode
+        assert_eq!(Command::from_byte_slice(&frame[..frame.len() - 1]).err(), Some(FrameError::Crc));
+        assert_eq!(Command::from_byte_slice(&with_crc(&body[..body.len() - 1])).err(), Some(FrameError::Payload));
+        assert_eq!(Command::from_byte_slice(&with_crc(&[body, &[0]].concat())).err(), Some(FrameError::Payload));
+        assert_eq!(Command::from_byte_slice(&[]).err(), Some(FrameError::Length));
+        assert_eq!(Command::from_byte_slice(&with_crc(&[PROTOCOL_VERSION; COMMAND_SIZE - 1])).err(), Some(FrameError::Length));
+        assert!(!check_crc16(&[0x00]));
+    }
+
+    /// What a decoded command hands over holds up, a throttle in particular is a number
+    /// between 0 and 1
+    fn assert_checked(c: &Command) {
+        assert_eq!(c.msg.check(), Ok(()), "{:?}", c);
+        if let Msg::Throttle(t) = c.msg {
+            assert!((0.0..=1.0).contains(&t), "{:?}", c);
+        }
+    }
+
+    #[test]
+    fn commands_decode_or_fail_whatever_the_bytes() {
+        let mut seed = 0x2545_f491u32;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+
+        for _ in 0..20_000 {
+            let len = next() as usize % (COMMAND_SIZE + 8);
+            let mut body: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+            if let Ok(c) = Command::from_byte_slice(&body) {
+                assert_checked(&c);
+            }
+            let _ = Command::sequence_of(&body);
+
+            // past the CRC and the version, down to the fields
+            if let Some(first) = body.first_mut() {
+                *first = PROTOCOL_VERSION;
+            }
+            match Command::from_byte_slice(&with_crc(&body)) {
+                Ok(c) => {
+                    assert_checked(&c);
+                    assert!(c.to_byte_array().len() <= COMMAND_SIZE);
+                }
+                Err(e) => assert!(matches!(e, FrameError::Payload | FrameError::Length | FrameError::Unknown(_) | FrameError::NotFinite | FrameError::Range), "{:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn two_commands_in_one_frame_are_rejected() {
+        let msgs = msgs();
+        for (a, b) in msgs.iter().zip(msgs.iter().skip(1)) {
+            let (a, b) = (Command { sequence: 1, msg: *a }, Command { sequence: 2, msg: *b });
+            let both = [&a.to_byte_array()[..], &b.to_byte_array()[..]].concat();
+            let expected = if both.len() > COMMAND_SIZE { FrameError::Length } else { FrameError::Crc };
+            assert_eq!(Command::from_byte_slice(&both).unwrap_err(), expected, "{:?} then {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn every_whole_command_comes_through_a_stream_of_broken_ones() {
+        let mut seed = 0x8282_8282u32;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+
+        // each command cut short, with a flipped bit and after garbage, then whole
+        let mut stream = Vec::new();
+        let mut sent = Vec::new();
+        for (i, msg) in msgs().into_iter().enumerate() {
+            let command = Command { sequence: i as u16, msg };
+            let wire = on_the_wire(&command.to_byte_array());
+            stream.extend(&wire[..next() as usize % (wire.len() - 1)]);
+            let mut flipped = wire.clone();
+            let bit = next() as usize % ((wire.len() - 1) * 8);
+            flipped[bit / 8] ^= 1 << (bit % 8);
+            stream.extend(flipped);
+            stream.extend((0..next() % 40).map(|_| next() as u8));
+            stream.push(EOT);
+            stream.extend(wire);
+            sent.push(command);
+        }
+
+        let mut decoder = cobs::Decoder::<COMMAND_FRAME_SIZE>::new();
+        let mut decoded = [0; COMMAND_SIZE];
+        let mut received = Vec::new();
+        for byte in stream {
+            if let Some(Ok(n)) = decoder.push(byte, &mut decoded) {
+                if let Ok(c) = Command::from_byte_slice(&decoded[..n]) {
+                    assert_checked(&c);
+                    received.push(c);
+                }
+            }
+        }
+        assert_eq!(received.iter().map(|c| (c.sequence, c.msg)).collect::<Vec<_>>(), sent.iter().map(|c| (c.sequence, c.msg)).collect::<Vec<_>>());
+    }
+
+    /// Every msg as a command, numbered in order
+    fn commands() -> Vec<Command> {
+        msgs().into_iter().enumerate().map(|(i, msg)| Command { sequence: i as u16, msg }).collect()
+    }
+
+    /// `commands` as the ground sends them, one after the other
+    fn command_stream(commands: &[Command]) -> Vec<u8> {
+        commands.iter().flat_map(|c| on_the_wire(&c.to_byte_array())).collect()
+    }
+
+    /// What `decoder` closes while it's fed `bytes`, read the way the device reads commands
+    fn receive(decoder: &mut cobs::Decoder<COMMAND_FRAME_SIZE>, bytes: &[u8]) -> Vec<Result<Command, FrameError>> {
+        let mut decoded = [0; COMMAND_SIZE];
+        let mut received = Vec::new();
+        for byte in bytes {
+            match decoder.push(*byte, &mut decoded) {
+                Some(Ok(n)) => received.push(Command::from_byte_slice(&decoded[..n])),
+                Some(Err(e)) => received.push(Err(e)),
+                None => {}
+            }
+        }
+        received
+    }
+
+    /// (sequence, msg) of each command, for comparing what came through with what was sent
+    fn numbered<'a>(commands: impl IntoIterator<Item = &'a Command>) -> Vec<(u16, Msg)> {
+        commands.into_iter().map(|c| (c.sequence, c.msg)).collect()
+    }
+
+    #[test]
+    fn commands_come_through_a_stream_sliced_anywhere() {
+        let mut seed = 0x8787_0001u32;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as usize
+        };
+
+        let sent = [commands(), commands()].concat();
+        let stream = command_stream(&sent);
+        for _ in 0..200 {
+            let mut decoder = cobs::Decoder::new();
+            let mut received = Vec::new();
+            let mut rest = &stream[..];
+            while !rest.is_empty() {
+                let (chunk, after) = rest.split_at(next() % rest.len().min(2 * COMMAND_FRAME_SIZE) + 1);
+                received.extend(receive(&mut decoder, chunk));
+                rest = after;
+            }
+            let received: Vec<Command> = received.into_iter().map(Result::unwrap).collect();
+            assert_eq!(numbered(&received), numbered(&sent));
+        }
+    }
+
+    #[test]
+    fn garbage_between_commands_only_costs_itself() {
+        let mut seed = 0x8787_0002u32;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as usize
+        };
+
+        // noise closed by a delimiter, as an idle line after it leaves it
+        let sent = commands();
+        let mut stream = Vec::new();
+        for c in &sent {
+            let len = next() % (2 * COMMAND_FRAME_SIZE);
+            stream.extend((0..len).map(|_| next() as u8));
+            stream.push(EOT);
+            stream.extend(command_stream(&[*c]));
+        }
+        let received = receive(&mut cobs::Decoder::new(), &stream);
+        let taken: Vec<&Command> = received.iter().filter_map(|r| r.as_ref().ok()).collect();
+        assert_eq!(numbered(taken.iter().copied()), numbered(&sent));
+        for r in &received {
+            match r {
+                Ok(c) => assert_checked(c),
+                Err(e) => assert!(matches!(e, FrameError::Encoding | FrameError::Length | FrameError::Crc | FrameError::Version(_)), "{:?}", e),
+            }
+        }
+
+        // noise without one runs into the command after it, that one is lost and no other
+        let mut stream = command_stream(&sent[..2]);
+        stream.extend((0..5).map(|_| next() as u8).filter(|b| *b != EOT));
+        stream.extend(command_stream(&sent[2..]));
+        let received = receive(&mut cobs::Decoder::new(), &stream);
+        assert!(received[2].is_err());
+        let rest: Vec<Command> = received[..2].iter().chain(&received[3..]).map(|r| *r.as_ref().unwrap()).collect();
+        assert_eq!(numbered(&rest), numbered(sent[..2].iter().chain(&sent[3..])));
+    }
+
+    #[test]
+    fn commands_delivered_one_byte_at_a_time_come_out_on_their_delimiter() {
+        let mut decoder = cobs::Decoder::new();
+        for c in commands() {
+            let stream = command_stream(&[c]);
+            let (last, bytes) = stream.split_last().unwrap();
+            for byte in bytes {
+                assert!(receive(&mut decoder, &[*byte]).is_empty());
+            }
+            let received = receive(&mut decoder, &[*last]);
+            assert_eq!(received.len(), 1);
+            assert_eq!(numbered([received[0].as_ref().unwrap()]), numbered([&c]));
+        }
+    }
+
+    /// What becomes of `msg` sent as a command
+    fn sent(msg: Msg) -> Result<Msg, FrameError> {
+        Command::from_byte_slice(&Command { sequence: 1, msg }.to_byte_array()).map(|c| c.msg)
+    }
+
+    #[test]
+    fn non_finite_floats_are_refused_whatever_field() {
+        for f in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY, -f32::NAN] {
+            let setpoint = Setpoint { roll: 0.0, pitch: 0.0, yaw_rate: 0.0 };
+            let gains = PidGains { p: 1.0, i: 0.0, d: 0.0, ff: 0.0 };
+            let bad = [
+                Msg::Throttle(f),
+                Msg::Setpoint(Setpoint { roll: f, ..setpoint }),
+                Msg::Setpoint(Setpoint { pitch: f, ..setpoint }),
+                Msg::Setpoint(Setpoint { yaw_rate: f, ..setpoint }),
+                Msg::SetParam(Param::FilterGain.id(), Value::F32(f)),
+                Msg::SetPidGains(Axis::Roll, PidGains { p: f, ..gains }),
+                Msg::SetPidGains(Axis::Pitch, PidGains { i: f, ..gains }),
+                Msg::SetPidGains(Axis::Yaw, PidGains { d: f, ..gains }),
+                Msg::SetPidGains(Axis::Yaw, PidGains { ff: f, ..gains }),
+                Msg::SetTrim(Trim { roll: f, pitch: 0.0 }),
+                Msg::SetTrim(Trim { roll: 0.0, pitch: f }),
+            ];
+            for msg in bad {
+                assert_eq!(sent(msg), Err(FrameError::NotFinite), "{:?}", msg);
+            }
+        }
+    }
+
+    #[test]
+    fn out_of_range_values_are_refused() {
+        let past = |max: f32| max * 1.001;
+        let bad = [
+            Msg::Throttle(-0.001),
+            Msg::Throttle(1.001),
+            Msg::Setpoint(Setpoint { roll: past(MAX_SETPOINT_ANGLE), pitch: 0.0, yaw_rate: 0.0 }),
+            Msg::Setpoint(Setpoint { roll: 0.0, pitch: -past(MAX_SETPOINT_ANGLE), yaw_rate: 0.0 }),
+            Msg::Setpoint(Setpoint { roll: 0.0, pitch: 0.0, yaw_rate: past(MAX_SETPOINT_YAW_RATE) }),
+            Msg::SetPidGains(Axis::Roll, PidGains { p: -1.0, i: 0.0, d: 0.0, ff: 0.0 }),
+            Msg::SetPidGains(Axis::Roll, PidGains { p: 0.0, i: 0.0, d: past(MAX_PID_GAIN), ff: 0.0 }),
+            Msg::SetTrim(Trim { roll: past(MAX_TRIM_ANGLE), pitch: 0.0 }),
+            Msg::SetTrim(Trim { roll: 0.0, pitch: -past(MAX_TRIM_ANGLE) }),
+            Msg::SetBaud(0),
+            Msg::SetBaud(115_201),
+        ];
+        for msg in bad {
+            assert_eq!(sent(msg), Err(FrameError::Range), "{:?}", msg);
+        }
+
+        // at the edges, and a parameter the device judges for itself
+        for msg in [Msg::Throttle(0.0), Msg::Throttle(1.0), Msg::SetParam(Param::FilterGain.id(), Value::F32(1e9)), Msg::SetParam(0xff, Value::U32(u32::MAX)), Msg::SetTrim(Trim { roll: MAX_TRIM_ANGLE, pitch: -MAX_TRIM_ANGLE })] {
+            assert_eq!(sent(msg), Ok(msg));
+        }
+    }
+
+    #[test]
+    fn all_zero_and_all_ff_buffers_are_refused() {
+        for len in 0..COMMAND_SIZE + 4 {
+            for fill in [0x00, 0xff] {
+                let err = Command::from_byte_slice(&vec![fill; len]).err();
+                assert!(matches!(err, Some(FrameError::Length | FrameError::Crc)), "{} of {:#x}: {:?}", len, fill, err);
+            }
+        }
+    }
+
+    #[test]
+    fn link_stats_roundtrip() {
+        let stats = LinkStats { accepted: 0x0102_0304, rejected: 5, stale: 6, crc: 7, gaps: 8, failsafes: 9, bytes_received: u32::MAX, bytes_sent: 10, overruns: 11, framing: 12, noise: 13, skipped: 14 };
+        let bytes = stats.to_byte_array();
+
+        assert_eq!(bytes[0], LINK_STATS_ID);
+        assert_eq!(LinkStats::from_byte_slice(&bytes), Some(stats));
+        assert_eq!(LinkStats::from_byte_slice(&bytes[..bytes.len() - 1]), None);
+    }
+
+    const ACK_STATUSES: [AckStatus; 12] = [
+        AckStatus::Applied,
+        AckStatus::Crc,
+        AckStatus::Range,
+        AckStatus::NotArmed,
+        AckStatus::Armed,
+        AckStatus::Malformed,
+        AckStatus::Stale,
+        AckStatus::Version,
+        AckStatus::Clamped,
+        AckStatus::Unknown,
+        AckStatus::Unsupported,
+        AckStatus::Locked,
+    ];
+
+    #[test]
+    fn ack_roundtrips_every_status() {
+        for status in ACK_STATUSES {
+            let ack = Ack { sequence: 0xbeef, status };
+            let bytes = ack.to_byte_array();
+
+            assert_eq!(bytes[0], ACK_ID);
+            assert_eq!(Ack::from_byte_slice(&bytes), Some(ack));
+        }
+    }
+
+    #[test]
+    fn ack_refuses_unknown_statuses_and_other_frames() {
+        let bytes = Ack { sequence: 7, status: AckStatus::Applied }.to_byte_array();
+
+        let mut unknown = bytes.to_vec();
+        *unknown.last_mut().unwrap() = ACK_STATUSES.len() as u8;
+        assert_eq!(Ack::from_byte_slice(&unknown), None);
+        let mut other = bytes.to_vec();
+        other[0] = LINK_STATS_ID;
+        assert_eq!(Ack::from_byte_slice(&other), None);
+        assert_eq!(Ack::from_byte_slice(&bytes[..bytes.len() - 1]), None);
+    }
+
+    #[test]
+    fn error_codes_tell_every_variant_apart() {
+        let errors = [
+            Error::Frame(FrameError::Length),
+            Error::Frame(FrameError::Crc),
+            Error::Frame(FrameError::Encoding),
+            Error::Frame(FrameError::Version(7)),
+            Error::Frame(FrameError::Payload),
+            Error::Frame(FrameError::Unknown(200)),
+            Error::Frame(FrameError::NotFinite),
+            Error::Frame(FrameError::Range),
+            Error::Sensor(SensorError::Bus),
+            Error::Sensor(SensorError::Identity(0x70)),
+            Error::Sensor(SensorError::SelfTest),
+            Error::Sensor(SensorError::Lost),
+            Error::Sensor(SensorError::Calibration),
+            Error::Storage(StorageError::Blank),
+            Error::Storage(StorageError::Layout(3)),
+            Error::Storage(StorageError::Crc),
+            Error::Storage(StorageError::Flash),
+        ];
+        let mut codes: Vec<u16> = errors.iter().map(Error::code).collect();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        assert_eq!(Error::from(FrameError::Crc).code(), 0x0102);
+        // the value a variant carries doesn't change its code
+        assert_eq!(Error::Storage(StorageError::Layout(1)).code(), Error::Storage(StorageError::Layout(2)).code());
+    }
+
+    #[test]
+    fn a_corrupted_command_still_gives_its_sequence() {
+        let mut frame = command().to_byte_array().to_vec();
+        let last = frame.len() - 3;
+        frame[last] ^= 0x10;
+
+        assert_eq!(Command::from_byte_slice(&frame).err(), Some(FrameError::Crc));
+        assert_eq!(Command::sequence_of(&frame), Some(0x1234));
+        assert_eq!(Command::sequence_of(&frame[..2]), None);
+    }
+
+    #[test]
+    fn commands_on_a_higher_or_lower_version_are_refused_unread() {
+        let frame = command().to_byte_array();
+        let body = &frame[1..frame.len() - 2];
+
+        for version in [PROTOCOL_VERSION + 1, PROTOCOL_VERSION - 1, 0, 0xfe] {
+            // a later version may well have grown, or not decode at all
+            for len in [body.len(), body.len() - 1, body.len() + 4] {
+                let mut other = vec![version];
+                other.extend((0..len).map(|i| body.get(i).copied().unwrap_or(0xaa)));
+                let other = with_crc(&other);
+                assert_eq!(Command::from_byte_slice(&other).err(), Some(FrameError::Version(version)), "{} bytes", len);
+                assert_eq!(Command::sequence_of(&other), None);
+            }
+        }
+    }
+
+    #[test]
+    fn setpoints_are_valid_up_to_45_degrees_and_a_turn_a_second() {
+        let at = |roll: f32, pitch: f32, yaw_rate: f32| Setpoint { roll, pitch, yaw_rate }.is_valid();
+
+        assert!(Setpoint::default().is_valid());
+        assert!(at(MAX_SETPOINT_ANGLE, -MAX_SETPOINT_ANGLE, MAX_SETPOINT_YAW_RATE) && at(0.0, 0.0, -MAX_SETPOINT_YAW_RATE));
+        assert!(!at(46f32.to_radians(), 0.0, 0.0) && !at(0.0, -46f32.to_radians(), 0.0));
+        assert!(!at(0.0, 0.0, 361f32.to_radians()) && !at(0.0, 0.0, -361f32.to_radians()));
+        assert!(!at(f32::NAN, 0.0, 0.0) && !at(0.0, f32::NAN, 0.0) && !at(0.0, 0.0, f32::INFINITY));
+    }
+
+    #[test]
+    fn pid_gains_are_valid_from_zero_to_the_max() {
+        let gains = |g: f32| PidGains { p: g, i: g, d: g, ff: g };
+
+        assert!(PidGains::default().is_valid() && gains(MAX_PID_GAIN).is_valid());
+        assert!(!gains(-0.01).is_valid() && !gains(MAX_PID_GAIN + 0.01).is_valid() && !gains(f32::NAN).is_valid());
+        assert!(!PidGains { d: -1.0, ..PidGains::default() }.is_valid());
+        for axis in Axis::ALL {
+            let frame = AxisPidGains { axis, gains: gains(2.0) }.to_byte_array();
+            assert_eq!(AxisPidGains::from_byte_slice(&frame).map(|g| g.axis), Some(axis));
+        }
+    }
+
+    #[test]
+    fn telemetry_divisors_out_of_range_are_clamped_and_said_so() {
+        let at = |divisor: u16| TelemetryConfig { divisor, ..TelemetryConfig::DEFAULT }.clamped();
+
+        assert_eq!(at(10), (TelemetryConfig::DEFAULT, false));
+        assert_eq!(at(0).0.divisor, MIN_TELEMETRY_DIVISOR);
+        assert_eq!(at(u16::MAX).0.divisor, MAX_TELEMETRY_DIVISOR);
+        assert!(at(0).1 && at(MIN_TELEMETRY_DIVISOR - 1).1 && !at(MIN_TELEMETRY_DIVISOR).1 && !at(MAX_TELEMETRY_DIVISOR).1);
+        assert!(TelemetryConfig::DEFAULT.streams(TelemetryConfig::RATES | TelemetryConfig::STATUS));
+        assert!(!TelemetryConfig { streams: TelemetryConfig::RATES, ..TelemetryConfig::DEFAULT }.streams(TelemetryConfig::RATES | TelemetryConfig::STATUS));
+    }
+
+    #[test]
+    fn a_ping_is_answered_under_its_nonce() {
+        for nonce in [0, 1, 0x7f, 0x80, 0xdead_beef, u32::MAX] {
+            assert_eq!(sent(Msg::Ping(nonce)), Ok(Msg::Ping(nonce)));
+
+            let pong = Pong { nonce, uptime_ms: 86_400_000, processing_us: 180 };
+            let bytes = pong.to_byte_array();
+            assert!(bytes.len() <= PONG_SIZE);
+            assert_eq!(Pong::from_byte_slice(&bytes), Some(pong));
+            assert_eq!(Pong::from_byte_slice(&bytes[..bytes.len() - 1]), None);
+        }
+    }
+
+    #[test]
+    fn params_are_found_by_their_id() {
+        for (i, param) in Param::ALL.into_iter().enumerate() {
+            assert_eq!(Param::from_id(param.id()), Some(param));
+            assert!(Param::ALL[..i].iter().all(|p| p.id() != param.id()));
+        }
+        assert_eq!(Param::from_id(0), None);
+        assert_eq!(Param::from_id(0xff), None);
+    }
+
+    #[test]
+    fn param_info_accepts_its_type_within_its_range() {
+        let gain = ParamInfo { id: Param::FilterGain.id(), default: Value::F32(0.1), min: 0.0, max: 1.0 };
+        let flag = ParamInfo { id: Param::Rates.id(), default: Value::Bool(false), min: 0.0, max: 1.0 };
+        let timeout = ParamInfo { id: Param::LinkTimeout.id(), default: Value::U32(500), min: 100.0, max: 5000.0 };
+
+        assert!(gain.accepts(Value::F32(0.0)) && gain.accepts(Value::F32(1.0)));
+        assert!(!gain.accepts(Value::F32(1.01)) && !gain.accepts(Value::F32(f32::NAN)) && !gain.accepts(Value::U32(1)));
+        assert!(flag.accepts(Value::Bool(true)) && flag.accepts(Value::Bool(false)) && !flag.accepts(Value::U32(1)));
+        assert!(timeout.accepts(Value::U32(100)) && !timeout.accepts(Value::U32(99)) && !timeout.accepts(Value::U32(u32::MAX)));
+        assert!(!timeout.accepts(Value::F32(500.0)));
+    }
+
+    #[test]
+    fn flight_modes_past_the_last_dont_decode() {
+        for mode in [FlightMode::Passthrough, FlightMode::Rate, FlightMode::Angle] {
+            assert_eq!(FlightMode::from_byte_slice(&mode.to_byte_array()), Some(mode));
+            assert_eq!(sent(Msg::SetMode(mode)), Ok(Msg::SetMode(mode)));
+        }
+        assert_eq!(FlightMode::from_byte_slice(&[FLIGHT_MODE_ID, 3]), None);
+    }
+
+    /// A frame as it goes over the link, encoded and delimited
+    fn on_the_wire(frame: &[u8]) -> Vec<u8> {
+        let mut encoded = [0; cobs::max_encoded_len(MAX_FRAME_SIZE)];
+        let len = cobs::encode(frame, &mut encoded).unwrap();
+        [&encoded[..len], &[EOT]].concat()
+    }
+
+    /// Where in `bytes` an [EmergencyStop] fires
+    fn stops_in(bytes: &[u8]) -> Vec<usize> {
+        let mut stop = EmergencyStop::default();
+        bytes.iter().enumerate().filter(|(_, b)| stop.push(**b)).map(|(i, _)| i).collect()
+    }
+
+    #[test]
+    fn the_stop_frame_fires_on_its_last_byte() {
+        let frame = EmergencyStop::FRAME;
+        assert_eq!(stops_in(&frame), [frame.len() - 1]);
+
+        // after a frame, in the middle of a broken one and twice in a row
+        let command = on_the_wire(&command().to_byte_array());
+        let broken = &command[..command.len() / 2];
+        let stream = [&command[..], broken, &frame, &frame[1..], &[0x01, 0x02]].concat();
+        let first = command.len() + broken.len() + frame.len() - 1;
+        assert_eq!(stops_in(&stream), [first, first + frame.len() - 1]);
+
+        // one byte off anywhere and it doesn't
+        for i in 1..frame.len() - 1 {
+            let mut off = frame;
+            off[i] ^= 0x20;
+            assert_eq!(stops_in(&off), [], "byte {}", i);
+        }
+    }
+
+    #[test]
+    fn no_legitimate_frame_fires_the_stop() {
+        let mut stream = vec![EOT];
+        for msg in msgs() {
+            for sequence in [0, 0x7f, 0x80, 0x4b4c, u16::MAX] {
+                stream.extend(on_the_wire(&Command { sequence, msg }.to_byte_array()));
+            }
+        }
+        for frame in frames() {
+            stream.extend(on_the_wire(&frame));
+        }
+        assert_eq!(stops_in(&stream), []);
+    }
+
+    #[test]
+    fn no_random_frame_under_a_crc_fires_the_stop() {
+        let mut seed = 0x1b87_3593u32;
+        let mut stream = Vec::new();
+        for n in 0..20_000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let len = 3 + seed as usize % (MAX_FRAME_SIZE - 3);
+            // every other frame made of the bytes the stop frame is made of
+            let alphabet = [EOT, 0xfe, b'K', b'I', b'L', 0x00];
+            let mut frame: Vec<u8> = (0..len)
+                .map(|i| {
+                    let r = seed.rotate_left(i as u32 * 5) ^ i as u32;
+                    if n % 2 == 0 { r as u8 } else { alphabet[r as usize % alphabet.len()] }
+                })
+                .collect();
+            append_crc16(&mut frame);
+            stream.extend(on_the_wire(&frame));
+        }
+        assert_eq!(stops_in(&stream), []);
+    }
+
+    #[test]
+    fn command_log_entries_take_every_msg() {
+        for msg in msgs().into_iter().map(Some).chain([None]) {
+            let entry = CommandLogEntry { at_ms: u32::MAX, sequence: u16::MAX, msg, status: AckStatus::Unsupported };
+            let bytes = entry.to_byte_array();
+            assert_eq!(CommandLogEntry::from_byte_slice(&bytes), Some(entry));
+        }
+    }
+
+    #[test]
+    fn throttle_slews_up_at_its_rate_and_cuts_at_once() {
+        let slew = SlewConfig::DEFAULT;
+        let mut output = 0.0;
+        let mut steps = 0;
+        while output < 1.0 {
+            output = slew.step(output, 1.0, 0.01);
+            steps += 1;
+        }
+        // zero to full in half a second of 10 ms steps, give or take the rounding, never past
+        // what was commanded
+        assert!((50..=51).contains(&steps) && output == 1.0, "{} steps", steps);
+        assert_eq!(slew.step(1.0, 0.0, 0.01), 0.0);
+
+        let both = SlewConfig { up_per_s: 1.0, down_per_s: 4.0 };
+        assert!((both.step(0.5, 1.0, 0.1) - 0.6).abs() < 1e-6 && (both.step(0.5, 0.0, 0.1) - 0.1).abs() < 1e-6);
+        assert_eq!(both.step(0.5, 0.45, 0.1), 0.45);
+        let unlimited = SlewConfig { up_per_s: 0.0, down_per_s: 0.0 };
+        assert_eq!(unlimited.step(0.0, 1.0, 0.01), 1.0);
+        assert!(!SlewConfig { up_per_s: -1.0, ..slew }.is_valid() && !SlewConfig { down_per_s: f32::NAN, ..slew }.is_valid());
+    }
+
+    #[test]
+    fn protocol_info_roundtrips() {
+        let info = ProtocolInfo { version: PROTOCOL_VERSION };
+
+        assert_eq!(&*info.to_byte_array(), &[PROTOCOL_INFO_ID, PROTOCOL_VERSION]);
+        assert_eq!(ProtocolInfo::from_byte_slice(&info.to_byte_array()), Some(info));
+        assert_eq!(ProtocolInfo::from_byte_slice(&[ACK_ID, PROTOCOL_VERSION]), None);
+    }
+
+    /// One of every device frame with values in every field, none of them zero
+    fn frames() -> Vec<Vec<u8>> {
+        let cycles = |n: u32| Cycles { min: n, mean: 2 * n, max: 300_000 * n };
+        let mut bus = BusScan::new();
+        bus.set(0x68);
+        vec![
+            SpatialOrientation { pitch: 0.1, roll: -0.2, yaw: 3.0 }.to_byte_array().to_vec(),
+            CompactOrientation { centi_degrees: [100, -200, 17999] }.to_byte_array().to_vec(),
+            AttitudeQuaternion { w: 0.5, x: -0.5, y: 0.5, z: -0.5 }.to_byte_array().to_vec(),
+            Heading { centi_degrees: -9000, error_centi_degrees: 18000 }.to_byte_array().to_vec(),
+            Rates { milli_rad_s: [i16::MIN, 1, i16::MAX] }.to_byte_array().to_vec(),
+            LinearAcceleration { milli_g: [-1000, 20, 3] }.to_byte_array().to_vec(),
+            Temperature { celsius: 36.6 }.to_byte_array().to_vec(),
+            Altitude { centimeters: -i32::MAX }.to_byte_array().to_vec(),
+            Vertical { centimeters: 1234, centimeters_per_s: -56 }.to_byte_array().to_vec(),
+            Status { calibrating: true, imu_lost: false, gyro_saturated: true, reset_skipped: false, crashed: true }.to_byte_array().to_vec(),
+            SelfTest { gyro: [true, false, true], accel: [false, true, true] }.to_byte_array().to_vec(),
+            GyroDebug { raw: [1, -2, 3], filtered: [-4, 5, -6] }.to_byte_array().to_vec(),
+            bus.to_byte_array().to_vec(),
+            RawSample { counter: u16::MAX, acc: [1, 2, 3], gyro: [-1, -2, -3] }.to_byte_array().to_vec(),
+            SampleStats { read: u32::MAX, missed: 1, max_gap_us: 2000, overflows: 3 }.to_byte_array().to_vec(),
+            MagCalibrationProgress { coverage_percent: 80, remaining_s: 10, enough: true }.to_byte_array().to_vec(),
+            FilterConfig { gain: 0.02, acc_cutoff_hz: 5.0 }.to_byte_array().to_vec(),
+            CycleStats { i2c: cycles(1), fusion: cycles(2), telemetry: cycles(3), total: cycles(14_000), receive: cycles(4) }.to_byte_array().to_vec(),
+            LinkStats { accepted: u32::MAX, rejected: 2, stale: 3, ..LinkStats::default() }.to_byte_array().to_vec(),
+            Ack { sequence: u16::MAX, status: AckStatus::Version }.to_byte_array().to_vec(),
+            ProtocolInfo { version: PROTOCOL_VERSION }.to_byte_array().to_vec(),
+            Setpoint { roll: -0.5, pitch: 0.5, yaw_rate: -6.0 }.to_byte_array().to_vec(),
+            MotorOutputs { duty: [0, 1, 7200, u16::MAX], max_duty: 7200 }.to_byte_array().to_vec(),
+            TelemetryConfig { divisor: MAX_TELEMETRY_DIVISOR, streams: TelemetryConfig::RATES }.to_byte_array().to_vec(),
+            ArmState::Failsafe.to_byte_array().to_vec(),
+            FailsafeConfig { timeout_ms: MAX_LINK_TIMEOUT_MS, ramp_ms: 250 }.to_byte_array().to_vec(),
+            Trim { roll: MAX_TRIM_ANGLE, pitch: -MAX_TRIM_ANGLE }.to_byte_array().to_vec(),
+            Pong { nonce: u32::MAX, uptime_ms: 1, processing_us: 250 }.to_byte_array().to_vec(),
+            ParamValue { id: Param::LinkTimeout.id(), value: Value::U32(500) }.to_byte_array().to_vec(),
+            ParamInfo { id: Param::TrimRoll.id(), default: Value::F32(0.0), min: -10.0, max: 10.0 }.to_byte_array().to_vec(),
+            FlightMode::Rate.to_byte_array().to_vec(),
+            CommandLogEntry { at_ms: 1_000, sequence: 9, msg: Some(Msg::Throttle(0.25)), status: AckStatus::NotArmed }.to_byte_array().to_vec(),
+            LockState::Unlocked.to_byte_array().to_vec(),
+            AppliedState { sequence: 3, outputs: MotorOutputs { duty: [100; 4], max_duty: 7200 }, arm_state: ArmState::Armed, enabled: true, mode: FlightMode::Angle }.to_byte_array().to_vec(),
+            ThrottleOutput { commanded: 1.0, output: 0.25 }.to_byte_array().to_vec(),
+            AxisPidGains { axis: Axis::Pitch, gains: PidGains { p: 1.0, i: 0.1, d: 0.01, ff: 0.0 } }.to_byte_array().to_vec(),
+            build_info().to_byte_array().to_vec(),
+            announce().to_byte_array().to_vec(),
+            LinkRate { baud: 115_200 }.to_byte_array().to_vec(),
+            Battery { millivolts: 11_100, cell_millivolts: 3_700, alarm: BatteryAlarm::Warning, centiamps: Some(1_250), consumed_mah: 800, armed_mah: 120, over_current: true }.to_byte_array().to_vec(),
+            extended_status().to_byte_array().to_vec(),
+        ]
+    }
+
+    /// Decodes a frame with the decoder for each kind, true for the ones that took it
+    fn taken_by(frame: &[u8]) -> Vec<bool> {
+        vec![
+            SpatialOrientation::from_byte_slice(frame).is_some(),
+            CompactOrientation::from_byte_slice(frame).is_some(),
+            AttitudeQuaternion::from_byte_slice(frame).is_some(),
+            Heading::from_byte_slice(frame).is_some(),
+            Rates::from_byte_slice(frame).is_some(),
+            LinearAcceleration::from_byte_slice(frame).is_some(),
+            Temperature::from_byte_slice(frame).is_some(),
+            Altitude::from_byte_slice(frame).is_some(),
+            Vertical::from_byte_slice(frame).is_some(),
+            Status::from_byte_slice(frame).is_some(),
+            SelfTest::from_byte_slice(frame).is_some(),
+            GyroDebug::from_byte_slice(frame).is_some(),
+            BusScan::from_byte_slice(frame).is_some(),
+            RawSample::from_byte_slice(frame).is_some(),
+            SampleStats::from_byte_slice(frame).is_some(),
+            MagCalibrationProgress::from_byte_slice(frame).is_some(),
+            FilterConfig::from_byte_slice(frame).is_some(),
+            CycleStats::from_byte_slice(frame).is_some(),
+            LinkStats::from_byte_slice(frame).is_some(),
+            Ack::from_byte_slice(frame).is_some(),
+            ProtocolInfo::from_byte_slice(frame).is_some(),
+            Setpoint::from_byte_slice(frame).is_some(),
+            MotorOutputs::from_byte_slice(frame).is_some(),
+            TelemetryConfig::from_byte_slice(frame).is_some(),
+            ArmState::from_byte_slice(frame).is_some(),
+            FailsafeConfig::from_byte_slice(frame).is_some(),
+            Trim::from_byte_slice(frame).is_some(),
+            Pong::from_byte_slice(frame).is_some(),
+            ParamValue::from_byte_slice(frame).is_some(),
+            ParamInfo::from_byte_slice(frame).is_some(),
+            FlightMode::from_byte_slice(frame).is_some(),
+            CommandLogEntry::from_byte_slice(frame).is_some(),
+            LockState::from_byte_slice(frame).is_some(),
+            AppliedState::from_byte_slice(frame).is_some(),
+            ThrottleOutput::from_byte_slice(frame).is_some(),
+            AxisPidGains::from_byte_slice(frame).is_some(),
+            BuildInfo::from_byte_slice(frame).is_some(),
+            Announce::from_byte_slice(frame).is_some(),
+            LinkRate::from_byte_slice(frame).is_some(),
+            Battery::from_byte_slice(frame).is_some(),
+            ExtendedStatus::from_byte_slice(frame).is_some(),
+        ]
+    }
+
+    /// Two halves, as the receive DMA fills them
+    const RING: usize = 64;
+
+    #[test]
+    fn ring_pending_within_a_half() {
+        assert_eq!(ring_pending(0, RING - 10, RING), (10, 10));
+        assert_eq!(ring_pending(4, RING - 10, RING), (10, 6));
+        assert_eq!(ring_pending(10, RING - 10, RING), (10, 0));
+        assert_eq!(ring_pending(40, RING - 50, RING), (50, 10));
+    }
+
+    #[test]
+    fn ring_pending_across_the_halves() {
+        // first half into the second
+        assert_eq!(ring_pending(28, RING - 36, RING), (36, 8));
+        // second half around into the first
+        assert_eq!(ring_pending(60, RING - 4, RING), (4, 8));
+        assert_eq!(ring_pending(RING / 2 + 1, RING / 2, RING), (RING / 2, RING - 1));
+    }
+
+    #[test]
+    fn ring_pending_at_the_boundaries() {
+        assert_eq!(ring_pending(0, RING / 2, RING), (RING / 2, RING / 2));
+        assert_eq!(ring_pending(RING / 2, RING / 2, RING), (RING / 2, 0));
+        assert_eq!(ring_pending(RING / 2 - 1, RING / 2, RING), (RING / 2, 1));
+        assert_eq!(ring_pending(RING - 1, 1, RING), (RING - 1, 0));
+        // the counter reloading reads the full size
+        assert_eq!(ring_pending(RING - 1, RING, RING), (0, 1));
+        assert_eq!(ring_pending(0, 1, RING), (RING - 1, RING - 1));
+    }
+
+    #[test]
+    fn ring_pending_follows_a_stream_around_the_ring() {
+        let mut seed = 0x8585_8585u32;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+
+        let mut ring = [0u8; RING];
+        let (mut write_at, mut read_at) = (0, 0);
+        let (mut sent, mut received) = (Vec::new(), Vec::new());
+        for _ in 0..1_000 {
+            // less than a whole ring between two idle lines
+            let burst: Vec<u8> = (0..next() as usize % RING).map(|_| next() as u8).collect();
+            for byte in &burst {
+                ring[write_at] = *byte;
+                write_at = (write_at + 1) % RING;
+            }
+            sent.extend(&burst);
+
+            let (next_read, len) = ring_pending(read_at, RING - write_at, RING);
+            assert_eq!((next_read, len), (write_at, burst.len()));
+            received.extend((0..len).map(|i| ring[(read_at + i) % RING]));
+            read_at = next_read;
+        }
+        assert_eq!(received, sent);
+    }
+
+    #[test]
+    fn ring_pending_span_of_one_idle_line() {
+        // no wrap
+        assert_eq!(ring_pending(5, RING - 20, RING), (20, 15));
+        // up to the last byte exactly, the counter reloaded and the DMA writes at 0 next
+        assert_eq!(ring_pending(40, RING, RING), (0, RING - 40));
+        // wrapped, the write index is behind the read one
+        assert_eq!(ring_pending(50, RING - 10, RING), (10, 24));
+        // the counter at its full size with nothing new since the start
+        assert_eq!(ring_pending(0, RING, RING), (0, 0));
+        // a whole ring since the last read looks like nothing, the decoder drops what's torn
+        assert_eq!(ring_pending(17, RING - 17, RING), (17, 0));
+    }
+
+    #[test]
+    fn back_to_back_commands_come_out_of_one_span() {
+        let mut ring = [0u8; 4 * COMMAND_FRAME_SIZE];
+        let size = ring.len();
+        // as many as fit in the ring, in one burst
+        let all = commands();
+        let n = (1..=all.len()).take_while(|n| command_stream(&all[..*n]).len() < size).last().unwrap();
+        let (sent, stream) = (&all[..n], command_stream(&all[..n]));
+        assert!(n > 2);
+
+        // the burst wraps around for every start but 0
+        for read_at in [0, 1, size / 2, size - 7, size - 1] {
+            for (i, byte) in stream.iter().enumerate() {
+                ring[(read_at + i) % size] = *byte;
+            }
+            let remaining = size - (read_at + stream.len()) % size;
+
+            let (write_at, len) = ring_pending(read_at, remaining, size);
+            assert_eq!((write_at, len), ((read_at + stream.len()) % size, stream.len()));
+            let span: Vec<u8> = (0..len).map(|i| ring[(read_at + i) % size]).collect();
+            let received: Vec<Command> = receive(&mut cobs::Decoder::new(), &span).into_iter().map(Result::unwrap).collect();
+            assert_eq!(numbered(&received), numbered(sent));
+        }
+    }
+
+    #[test]
+    fn battery_alarm_goes_up_at_once_and_down_past_the_hysteresis() {
+        let config = BatteryConfig::DEFAULT;
+        let alarm = |volts: f32, previous| config.alarm(volts, previous);
+        assert_eq!(alarm(3.8, BatteryAlarm::Ok), BatteryAlarm::Ok);
+        assert_eq!(alarm(3.49, BatteryAlarm::Ok), BatteryAlarm::Warning);
+        assert_eq!(alarm(3.29, BatteryAlarm::Ok), BatteryAlarm::Critical);
+
+        // a pack recovering once the throttle is off stays where it was
+        assert_eq!(alarm(3.55, BatteryAlarm::Warning), BatteryAlarm::Warning);
+        assert_eq!(alarm(3.35, BatteryAlarm::Critical), BatteryAlarm::Critical);
+        assert_eq!(alarm(3.45, BatteryAlarm::Critical), BatteryAlarm::Warning);
+        assert_eq!(alarm(3.65, BatteryAlarm::Warning), BatteryAlarm::Ok);
+        assert_eq!(alarm(3.65, BatteryAlarm::Critical), BatteryAlarm::Ok);
+
+        assert!(config.is_valid());
+        assert!(!BatteryConfig { cells: 0, ..config }.is_valid());
+        assert!(!BatteryConfig { critical_cell_volts: 2.0, ..config }.is_valid());
+    }
+
+    #[test]
+    fn current_comes_off_the_offset_and_none_without_a_sensor() {
+        assert_eq!(CurrentConfig::DEFAULT.amps(1_000.0), None);
+        let sensor = CurrentConfig { mv_per_amp: 40.0, offset_mv: 500.0, max_amps: 30.0 };
+        assert_eq!(sensor.amps(900.0), Some(10.0));
+        // noise around the offset isn't current going back into the pack
+        assert_eq!(sensor.amps(480.0), Some(0.0));
+
+        assert!(!sensor.over_current(30.0) && sensor.over_current(30.5));
+        assert!(!CurrentConfig { max_amps: 0.0, ..sensor }.over_current(f32::MAX));
+        assert!(sensor.is_valid() && !CurrentConfig { mv_per_amp: -1.0, ..sensor }.is_valid());
+    }
+
+    fn announce() -> Announce {
+        Announce {
+            protocol: PROTOCOL_VERSION,
+            features: Announce::FEATURE_CRSF | Announce::FEATURE_DMP,
+            motors: 4,
+            sensors: Announce::SENSOR_IMU | Announce::SENSOR_BARO,
+            params: Param::ALL.len() as u8,
+        }
+    }
+
+    #[test]
+    fn announce_carries_its_feature_and_sensor_bits() {
+        let decoded = Announce::from_byte_slice(&announce().to_byte_array()).unwrap();
+        assert_eq!(decoded, announce());
+        assert!(decoded.feature(Announce::FEATURE_DMP) && !decoded.feature(Announce::FEATURE_MSP));
+        assert!(decoded.sensor(Announce::SENSOR_BARO) && !decoded.sensor(Announce::SENSOR_MAG));
+
+        // every bit on its own
+        let features = [Announce::FEATURE_MSP, Announce::FEATURE_CRSF, Announce::FEATURE_SBUS, Announce::FEATURE_PPM, Announce::FEATURE_MAVLINK, Announce::FEATURE_ICM20602, Announce::FEATURE_DMP, Announce::FEATURE_POLLED];
+        assert_eq!(features.iter().fold(0, |all, f| all | f).count_ones() as usize, features.len());
+        assert_eq!((Announce::SENSOR_IMU | Announce::SENSOR_MAG | Announce::SENSOR_BARO).count_ones(), 3);
+    }
+
+    fn extended_status() -> ExtendedStatus {
+        ExtendedStatus {
+            arm_state: ArmState::Armed,
+            outputs: MotorOutputs { duty: [3600; 4], max_duty: 7200 },
+            command_age_ms: Some(40),
+            i2c_errors: 2,
+            loop_min_us: 120,
+            loop_max_us: 850,
+            warnings: ExtendedStatus::WARNING_LOW_BATTERY | ExtendedStatus::WARNING_OVER_CURRENT,
+        }
+    }
+
+    #[test]
+    fn extended_status_carries_its_warning_bits() {
+        let decoded = ExtendedStatus::from_byte_slice(&extended_status().to_byte_array()).unwrap();
+        assert_eq!(decoded, extended_status());
+        assert!(decoded.warning(ExtendedStatus::WARNING_OVER_CURRENT) && !decoded.warning(ExtendedStatus::WARNING_IMU_LOST));
+        let before_a_command = ExtendedStatus { command_age_ms: None, ..extended_status() };
+        assert_eq!(ExtendedStatus::from_byte_slice(&before_a_command.to_byte_array()), Some(before_a_command));
+
+        let warnings = [ExtendedStatus::WARNING_IMU_LOST, ExtendedStatus::WARNING_LOW_BATTERY, ExtendedStatus::WARNING_GYRO_SATURATED, ExtendedStatus::WARNING_CALIBRATING, ExtendedStatus::WARNING_OVER_CURRENT];
+        assert_eq!(warnings.iter().fold(0, |all, w| all | w).count_ones() as usize, warnings.len());
+    }
+
+    fn build_info() -> BuildInfo {
+        BuildInfo { version: [0, 1, 0], commit: *b"1a2b3c4", dirty: true, date: 20240501, protocol: PROTOCOL_VERSION }
+    }
+
+    #[test]
+    fn build_info_reads_as_a_version_line() {
+        assert_eq!(build_info().to_string(), format!("0.1.0 1a2b3c4-dirty 2024-05-01 protocol {}", PROTOCOL_VERSION));
+
+        let outside_git = BuildInfo { commit: [0; COMMIT_LEN], dirty: false, ..build_info() };
+        assert_eq!(outside_git.to_string(), format!("0.1.0 2024-05-01 protocol {}", PROTOCOL_VERSION));
+    }
+
+    #[test]
+    fn every_frame_is_taken_by_its_own_decoder_alone() {
+        let frames = frames();
+        for (i, frame) in frames.iter().enumerate() {
+            let mut expected = vec![false; frames.len()];
+            expected[i] = true;
+            assert_eq!(taken_by(frame), expected, "frame {} {:x?}", i, frame);
+            assert!(frame.len() <= MAX_FRAME_SIZE);
+        }
+    }
+
+    #[test]
+    fn frames_roundtrip() {
+        let o = SpatialOrientation::from_byte_slice(&frames()[0]).unwrap();
+        assert_eq!((o.pitch, o.roll, o.yaw), (0.1, -0.2, 3.0));
+        assert_eq!(Heading::from_byte_slice(&frames()[3]), Some(Heading { centi_degrees: -9000, error_centi_degrees: 18000 }));
+        assert_eq!(Rates::from_byte_slice(&frames()[4]), Some(Rates { milli_rad_s: [i16::MIN, 1, i16::MAX] }));
+        assert_eq!(Temperature::from_byte_slice(&frames()[6]).map(|t| t.celsius), Some(36.6));
+        assert_eq!(Altitude::from_byte_slice(&frames()[7]).map(|a| a.centimeters), Some(-i32::MAX));
+        let s = Status::from_byte_slice(&frames()[9]).unwrap();
+        assert!(s.calibrating && !s.imu_lost && s.gyro_saturated && !s.reset_skipped && s.crashed);
+        assert_eq!(SelfTest::from_byte_slice(&frames()[10]), Some(SelfTest { gyro: [true, false, true], accel: [false, true, true] }));
+        let bus = BusScan::from_byte_slice(&frames()[12]).unwrap();
+        assert_eq!(bus.addresses().collect::<Vec<_>>(), [0x68]);
+        assert_eq!(RawSample::from_byte_slice(&frames()[13]), Some(RawSample { counter: u16::MAX, acc: [1, 2, 3], gyro: [-1, -2, -3] }));
+        assert_eq!(SampleStats::from_byte_slice(&frames()[14]).map(|s| s.read), Some(u32::MAX));
+        assert_eq!(CycleStats::from_byte_slice(&frames()[17]).map(|c| c.total.max), Some(300_000 * 14_000));
+    }
+
+    #[test]
+    fn cut_or_grown_frames_are_dropped() {
+        let frames = frames();
+        let none = vec![false; frames.len()];
+        for frame in &frames {
+            for len in 0..frame.len() {
+                assert_eq!(taken_by(&frame[..len]), none, "{:x?} cut to {}", frame, len);
+            }
+            assert_eq!(taken_by(&[frame.as_slice(), &[0]].concat()), none, "{:x?} grown", frame);
+        }
+    }
+
+    #[test]
+    fn arbitrary_bytes_never_panic_a_decoder() {
+        let frames = frames();
+        let mut seed = 0x9e37_79b9u32;
+        for _ in 0..20_000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let len = seed as usize % MAX_FRAME_SIZE;
+            let mut frame: Vec<u8> = (0..len).map(|i| (seed.rotate_left(i as u32 * 7) ^ i as u32) as u8).collect();
+            // often a real id, the decoders get past the first byte
+            if let (Some(first), Some(real)) = (frame.first_mut(), frames.get(seed as usize % frames.len())) {
+                *first = real[0];
+            }
+            taken_by(&frame);
+        }
+    }
+
+    #[test]
+    fn sequence_tracker_drops_repeats_and_replays_across_the_wrap() {
+        let mut tracker = SequenceTracker::default();
+        assert!(tracker.accept(u16::MAX - 1));
+        assert!(!tracker.accept(u16::MAX - 1));
+        assert!(tracker.accept(u16::MAX));
+        assert!(tracker.accept(0));
+        assert!(tracker.accept(2));
+
+        // the DMA handing over frames from before the wrap again
+        assert!(!tracker.accept(u16::MAX));
+        assert!(!tracker.accept(1));
+        assert!(!tracker.accept(2u16.wrapping_sub(SEQUENCE_WINDOW - 1)));
+        // a restarted ground app counts from wherever it is
+        assert!(tracker.accept(2u16.wrapping_sub(SEQUENCE_WINDOW)));
+    }
+
+    #[test]
+    fn gaps_count_the_commands_skipped_within_the_window() {
+        let mut tracker = SequenceTracker::new();
+        assert_eq!(tracker.skipped(5), 0);
+        tracker.accept(u16::MAX);
+
+        assert_eq!([0, 3, u16::MAX].map(|s| tracker.skipped(s)), [0, 3, 0]);
+        assert_eq!(tracker.skipped(u16::MAX.wrapping_add(SEQUENCE_WINDOW)), SEQUENCE_WINDOW as u32 - 1);
+        // a restarted ground app
+        assert_eq!(tracker.skipped(u16::MAX.wrapping_add(SEQUENCE_WINDOW + 1)), 0);
+    }
+
+    /// Finite f32 of random bit patterns, the same sequence every run
+    fn finite(seed: &mut u32) -> f32 {
+        loop {
+            *seed ^= *seed << 13;
+            *seed ^= *seed >> 17;
+            *seed ^= *seed << 5;
+            let v = f32::from_bits(*seed);
+            if v.is_finite() {
+                return v;
+            }
+        }
+    }
+
+    #[test]
+    fn orientation_roundtrips_any_finite_angles() {
+        let edges = [0.0, -0.0, f32::MIN_POSITIVE, f32::MAX, f32::MIN, f32::EPSILON, 1e-45, core::f32::consts::PI];
+        let mut seed = 0x9e37_79b9;
+
+        for i in 0..10_000 {
+            let (pitch, roll, yaw) = if i < edges.len() { (edges[i], -edges[i], edges[i]) } else { (finite(&mut seed), finite(&mut seed), finite(&mut seed)) };
+            let bytes = SpatialOrientation { pitch, roll, yaw }.to_byte_array();
+            let decoded = SpatialOrientation::from_byte_slice(&bytes).unwrap();
+
+            // bit for bit, so -0.0 stays negative
+            assert_eq!([decoded.pitch.to_bits(), decoded.roll.to_bits(), decoded.yaw.to_bits()], [pitch.to_bits(), roll.to_bits(), yaw.to_bits()]);
+        }
+    }
+
+    #[test]
+    fn orientation_layout_is_the_id_then_little_endian_pitch_roll_yaw() {
+        let bytes = SpatialOrientation { pitch: 1.0, roll: -2.0, yaw: 0.5 }.to_byte_array();
+
+        assert_eq!(&*bytes, &[ORIENTATION_ID, 0x00, 0x00, 0x80, 0x3f, 0x00, 0x00, 0x00, 0xc0, 0x00, 0x00, 0x00, 0x3f]);
+        assert_eq!(SpatialOrientation::from_byte_slice(&bytes[..SPATIAL_FRAME_SIZE - 1]).map(|o| o.pitch), None);
+    }
+
+    #[test]
+    fn orientation_with_an_eot_byte_is_dropped_not_misread() {
+        // an ordinary pitch of about 0.5 rad that encodes with EOT in its low byte
+        let pitch = f32::from_bits(0x3f00_00ff);
+        let bytes = SpatialOrientation { pitch, roll: 0.0, yaw: 0.0 }.to_byte_array();
+        assert!(bytes.contains(&EOT));
+
+        // until the framing escapes it, splitting on EOT leaves pieces no decoder takes for a frame
+        for piece in bytes.split(|b| *b == EOT) {
+            assert!(SpatialOrientation::from_byte_slice(piece).is_none());
+            assert!(AttitudeQuaternion::from_byte_slice(piece).is_none());
+        }
+    }
+
+    #[test]
+    fn compact_orientation_roundtrips_within_a_centi_degree() {
+        let step = 0.01f32.to_radians();
+
+        for i in -1800..=1800 {
+            let angle = (i as f32 * 0.1 + 0.037).to_radians();
+            let o = SpatialOrientation { pitch: angle, roll: -angle / 2.0, yaw: angle / 3.0 };
+            let bytes = CompactOrientation::from_orientation(&o).to_byte_array();
+            let decoded = CompactOrientation::from_byte_slice(&bytes).unwrap().to_orientation();
+
+            assert!((decoded.pitch - o.pitch).abs() <= step, "{} -> {}", o.pitch, decoded.pitch);
+            assert!((decoded.roll - o.roll).abs() <= step && (decoded.yaw - o.yaw).abs() <= step, "{:?} -> {:?}", o, decoded);
+        }
+    }
+
+    #[test]
+    fn compact_orientation_saturates() {
+        let o = SpatialOrientation { pitch: 1000.0, roll: -1000.0, yaw: f32::NAN };
+
+        assert_eq!(CompactOrientation::from_orientation(&o).centi_degrees, [i16::MAX, i16::MIN, 0]);
+    }
+
+    #[test]
+    fn compact_orientation_checksum_catches_a_flipped_bit() {
+        let bytes = CompactOrientation { centi_degrees: [1234, -5678, 17999] }.to_byte_array();
+        assert!(CompactOrientation::from_byte_slice(&bytes).is_some());
+
+        for i in 0..COMPACT_ORIENTATION_SIZE * 8 {
+            let mut corrupted = bytes;
+            corrupted[i / 8] ^= 1 << (i % 8);
+            assert_eq!(CompactOrientation::from_byte_slice(&corrupted), None, "bit {}", i);
+        }
+        assert_eq!(CompactOrientation::from_byte_slice(&bytes[..COMPACT_ORIENTATION_SIZE - 1]), None);
+    }
+}
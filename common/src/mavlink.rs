@@ -0,0 +1,218 @@
+//! MAVLink v1 output, enough for QGroundControl and friends to show a horizon. A frame is
+//! 0xfe, payload length, sequence, system and component, message id, the payload with its
+//! fields ordered by size, largest first, and a CRC-16/MCRF4XX over everything after the
+//! 0xfe. The CRC also takes a seed byte per message, the CRC_EXTRA of the generator, so two
+//! ends with different definitions of a message don't take each other's frames. Little-endian.
+
+use core::ops::Deref;
+
+use crate::SpatialOrientation;
+
+const STX: u8 = 0xfe;
+
+pub const HEARTBEAT: u8 = 0;
+pub const SYS_STATUS: u8 = 1;
+pub const ATTITUDE: u8 = 30;
+
+/// [SYS_STATUS] sensor bits
+pub const SENSOR_GYRO: u32 = 1 << 0;
+pub const SENSOR_ACCEL: u32 = 1 << 1;
+pub const SENSOR_MAG: u32 = 1 << 2;
+pub const SENSOR_BARO: u32 = 1 << 3;
+
+/// [SYS_STATUS], the longest message here
+const MAX_PAYLOAD: usize = 31;
+/// Header, payload and CRC
+pub const MAX_FRAME: usize = 6 + MAX_PAYLOAD + 2;
+
+const MAV_TYPE_QUADROTOR: u8 = 2;
+const MAV_AUTOPILOT_GENERIC: u8 = 0;
+const MAV_MODE_FLAG_SAFETY_ARMED: u8 = 0x80;
+const MAV_STATE_STANDBY: u8 = 3;
+const MAV_STATE_ACTIVE: u8 = 4;
+const MAV_STATE_CRITICAL: u8 = 5;
+const MAVLINK_VERSION: u8 = 3;
+
+/// Seed of every message above, from the message definitions
+fn crc_extra(id: u8) -> u8 {
+    match id {
+        HEARTBEAT => 50,
+        SYS_STATUS => 124,
+        ATTITUDE => 39,
+        _ => 0,
+    }
+}
+
+/// CRC-16/MCRF4XX, X.25 to MAVLink, carried on from `crc`
+fn crc_accumulate(crc: u16, buf: &[u8]) -> u16 {
+    buf.iter().fold(crc, |crc, byte| {
+        let tmp = byte ^ crc as u8;
+        let tmp = (tmp ^ (tmp << 4)) as u16;
+        (crc >> 8) ^ (tmp << 8) ^ (tmp << 3) ^ (tmp >> 4)
+    })
+}
+
+/// Numbers the frames it makes, one of these per link
+#[derive(Debug)]
+pub struct Encoder {
+    system: u8,
+    component: u8,
+    sequence: u8,
+}
+
+impl Encoder {
+    pub const fn new(system: u8, component: u8) -> Self {
+        Encoder { system, component, sequence: 0 }
+    }
+
+    /// Quadrotor with no particular autopilot, critical over `armed` for a crash or a lost IMU
+    pub fn heartbeat(&mut self, armed: bool, critical: bool) -> Frame {
+        let (base_mode, state) = match (armed, critical) {
+            (_, true) => (0, MAV_STATE_CRITICAL),
+            (true, false) => (MAV_MODE_FLAG_SAFETY_ARMED, MAV_STATE_ACTIVE),
+            (false, false) => (0, MAV_STATE_STANDBY),
+        };
+        let mut payload = [0; 9];
+        payload[4..].copy_from_slice(&[MAV_TYPE_QUADROTOR, MAV_AUTOPILOT_GENERIC, base_mode, state, MAVLINK_VERSION]);
+        self.frame(HEARTBEAT, &payload)
+    }
+
+    /// `sensors` present and healthy alike, battery voltage and current unknown without
+    /// `volts` and `amps`, what's left always is. `errors` for the communication errors.
+    pub fn sys_status(&mut self, sensors: u32, volts: Option<f32>, amps: Option<f32>, errors: u16) -> Frame {
+        let millivolts = volts.map_or(u16::MAX, |v| (v * 1000.0) as u16);
+        let centiamps = amps.map_or(-1, |a| (a * 100.0).min(i16::MAX as f32) as i16);
+        let mut payload = [0; 31];
+        for chunk in payload[..12].chunks_exact_mut(4) {
+            chunk.copy_from_slice(&sensors.to_le_bytes());
+        }
+        payload[14..16].copy_from_slice(&millivolts.to_le_bytes());
+        payload[16..18].copy_from_slice(&centiamps.to_le_bytes());
+        payload[20..22].copy_from_slice(&errors.to_le_bytes());
+        payload[30] = -1i8 as u8;
+        self.frame(SYS_STATUS, &payload)
+    }
+
+    /// Angles in radians and `rates` in rad/s, roll, pitch and yaw both
+    pub fn attitude(&mut self, time_boot_ms: u32, orientation: &SpatialOrientation, rates: [f32; 3]) -> Frame {
+        let mut payload = [0; 28];
+        payload[..4].copy_from_slice(&time_boot_ms.to_le_bytes());
+        let values = [orientation.roll, orientation.pitch, orientation.yaw].into_iter().chain(rates);
+        for (chunk, v) in payload[4..].chunks_exact_mut(4).zip(values) {
+            chunk.copy_from_slice(&v.to_le_bytes());
+        }
+        self.frame(ATTITUDE, &payload)
+    }
+
+    fn frame(&mut self, id: u8, payload: &[u8]) -> Frame {
+        let mut buf = [0; MAX_FRAME];
+        buf[..6].copy_from_slice(&[STX, payload.len() as u8, self.sequence, self.system, self.component, id]);
+        buf[6..6 + payload.len()].copy_from_slice(payload);
+        let end = 6 + payload.len();
+        let crc = crc_accumulate(crc_accumulate(0xffff, &buf[1..end]), &[crc_extra(id)]);
+        buf[end..end + 2].copy_from_slice(&crc.to_le_bytes());
+        self.sequence = self.sequence.wrapping_add(1);
+
+        Frame { buf, len: end + 2 }
+    }
+}
+
+/// Encoded message, derefs to the bytes to send
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    buf: [u8; MAX_FRAME],
+    len: usize,
+}
+
+impl Deref for Frame {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// CRC_EXTRA the way the generator makes it, over the message name and the type and name
+    /// of every field in wire order
+    fn seed(name: &str, fields: &[(&str, &str)]) -> u8 {
+        let crc = crc_accumulate(0xffff, format!("{} ", name).as_bytes());
+        let crc = fields.iter().fold(crc, |crc, (kind, field)| crc_accumulate(crc, format!("{} {} ", kind, field).as_bytes()));
+        (crc & 0xff) as u8 ^ (crc >> 8) as u8
+    }
+
+    #[test]
+    fn the_crc_is_mcrf4xx() {
+        assert_eq!(crc_accumulate(0xffff, b"123456789"), 0x6f91);
+    }
+
+    #[test]
+    fn crc_extra_follows_the_message_definitions() {
+        let heartbeat = [("uint32_t", "custom_mode"), ("uint8_t", "type"), ("uint8_t", "autopilot"), ("uint8_t", "base_mode"), ("uint8_t", "system_status"), ("uint8_t", "mavlink_version")];
+        assert_eq!(crc_extra(HEARTBEAT), seed("HEARTBEAT", &heartbeat));
+
+        let sys_status = [
+            ("uint32_t", "onboard_control_sensors_present"),
+            ("uint32_t", "onboard_control_sensors_enabled"),
+            ("uint32_t", "onboard_control_sensors_health"),
+            ("uint16_t", "load"),
+            ("uint16_t", "voltage_battery"),
+            ("int16_t", "current_battery"),
+            ("uint16_t", "drop_rate_comm"),
+            ("uint16_t", "errors_comm"),
+            ("uint16_t", "errors_count1"),
+            ("uint16_t", "errors_count2"),
+            ("uint16_t", "errors_count3"),
+            ("uint16_t", "errors_count4"),
+            ("int8_t", "battery_remaining"),
+        ];
+        assert_eq!(crc_extra(SYS_STATUS), seed("SYS_STATUS", &sys_status));
+
+        let attitude = [("uint32_t", "time_boot_ms"), ("float", "roll"), ("float", "pitch"), ("float", "yaw"), ("float", "rollspeed"), ("float", "pitchspeed"), ("float", "yawspeed")];
+        assert_eq!(crc_extra(ATTITUDE), seed("ATTITUDE", &attitude));
+    }
+
+    #[test]
+    fn heartbeat_and_attitude_match_their_bytes() {
+        let mut encoder = Encoder::new(1, 1);
+
+        let heartbeat = [0xfe, 0x09, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x80, 0x04, 0x03, 0x72, 0x24];
+        assert_eq!(&encoder.heartbeat(true, false)[..], &heartbeat[..]);
+
+        let orientation = SpatialOrientation { pitch: -0.25, roll: 0.5, yaw: 3.0 };
+        let attitude = [
+            0xfe, 0x1c, 0x01, 0x01, 0x01, 0x1e, 0xd2, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3f, 0x00, 0x00, 0x80, 0xbe, 0x00, 0x00, 0x40, 0x40,
+            0x00, 0x00, 0x00, 0x3e, 0x00, 0x00, 0x80, 0xbf, 0x00, 0x00, 0x00, 0x40, 0xd9, 0x8c,
+        ];
+        assert_eq!(&encoder.attitude(1234, &orientation, [0.125, -1.0, 2.0])[..], &attitude[..]);
+    }
+
+    #[test]
+    fn heartbeat_states() {
+        let mut encoder = Encoder::new(1, 1);
+        let modes = [(false, false), (true, false), (true, true)].map(|(armed, critical)| {
+            let frame = encoder.heartbeat(armed, critical);
+            (frame[12], frame[13])
+        });
+        assert_eq!(modes, [(0, MAV_STATE_STANDBY), (MAV_MODE_FLAG_SAFETY_ARMED, MAV_STATE_ACTIVE), (0, MAV_STATE_CRITICAL)]);
+    }
+
+    #[test]
+    fn sys_status_fits_and_sequences_wrap() {
+        let mut encoder = Encoder::new(1, 1);
+        let status = encoder.sys_status(SENSOR_GYRO | SENSOR_ACCEL, None, None, 7);
+        assert_eq!((status.len(), status[1], status[5]), (MAX_FRAME, 31, SYS_STATUS));
+        // no voltage, current or remaining charge to report
+        assert_eq!((&status[20..24], status[36]), (&[0xff; 4][..], 0xff));
+        assert_eq!(&status[26..28], &7u16.to_le_bytes());
+
+        let sequences: Vec<u8> = (0..256).map(|_| encoder.heartbeat(false, false)[2]).collect();
+        assert_eq!((sequences[0], sequences[254], sequences[255]), (1, 255, 0));
+
+        let status = Encoder::new(1, 1).sys_status(SENSOR_GYRO, Some(12.0), Some(1.5), 0);
+        assert_eq!((&status[20..22], &status[22..24]), (&12_000u16.to_le_bytes()[..], &150i16.to_le_bytes()[..]));
+    }
+}
@@ -0,0 +1,365 @@
+//! MultiWii Serial Protocol v1, for the configurators that speak it rather than the native
+//! frames. A request is `$M<`, payload length, command, payload and the XOR of everything
+//! from the length on. The answer has the same layout under `$M>`, or `$M!` with no payload
+//! for a command the device doesn't take. Every field is little-endian.
+
+use core::ops::Deref;
+
+use crate::{FrameError, PidGains, RcInput, SpatialOrientation};
+
+pub const STATUS: u8 = 101;
+pub const RAW_IMU: u8 = 102;
+pub const ATTITUDE: u8 = 108;
+pub const PID: u8 = 112;
+pub const SET_RAW_RC: u8 = 200;
+pub const SET_PID: u8 = 202;
+
+/// Sixteen RC channels, the longest request this side takes
+pub const MAX_PAYLOAD: usize = 32;
+/// Header, length, command, payload and checksum
+pub const MAX_FRAME: usize = 3 + 2 + MAX_PAYLOAD + 1;
+
+/// [STATUS] sensor bits
+pub const SENSOR_ACC: u16 = 1 << 0;
+pub const SENSOR_BARO: u16 = 1 << 1;
+pub const SENSOR_MAG: u16 = 1 << 2;
+
+/// [PID] rows before the ones past yaw nobody here has
+const PID_ROWS: usize = 10;
+/// Gains go out as tenths, a byte covers the whole [PidGains] range
+const PID_SCALE: f32 = 10.0;
+
+/// A request that came through whole
+#[derive(Debug, Clone, Copy)]
+pub struct Request {
+    pub command: u8,
+    payload: [u8; MAX_PAYLOAD],
+    len: usize,
+}
+
+impl Request {
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.len]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Dollar,
+    M,
+    Length,
+    Command,
+    Payload,
+    Checksum,
+}
+
+/// Picks requests out of a byte stream however it was chunked, anything between them is
+/// skipped until the next `$M<`
+#[derive(Debug)]
+pub struct Parser {
+    state: State,
+    request: Request,
+    /// Length the request announced, may be over [MAX_PAYLOAD]
+    expected: usize,
+    received: usize,
+    checksum: u8,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Parser {
+            state: State::Idle,
+            request: Request { command: 0, payload: [0; MAX_PAYLOAD], len: 0 },
+            expected: 0,
+            received: 0,
+            checksum: 0,
+        }
+    }
+
+    /// A request once `byte` is its checksum, None meanwhile. [FrameError::Crc] for a wrong
+    /// checksum, [FrameError::Length] for a payload over [MAX_PAYLOAD], which is read past
+    /// all the same so the next request comes through.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Request, FrameError>> {
+        self.state = match (self.state, byte) {
+            (State::Idle, b'$') => State::Dollar,
+            (State::Idle, _) => State::Idle,
+            (State::Dollar, b'M') => State::M,
+            (State::M, b'<') => State::Length,
+            // a `$` in the garbage before a request
+            (State::Dollar | State::M, b'$') => State::Dollar,
+            (State::Dollar | State::M, _) => State::Idle,
+            (State::Length, len) => {
+                self.expected = len as usize;
+                self.received = 0;
+                self.checksum = len;
+                State::Command
+            }
+            (State::Command, command) => {
+                self.request.command = command;
+                self.checksum ^= command;
+                if self.expected == 0 { State::Checksum } else { State::Payload }
+            }
+            (State::Payload, b) => {
+                if let Some(slot) = self.request.payload.get_mut(self.received) {
+                    *slot = b;
+                }
+                self.received += 1;
+                self.checksum ^= b;
+                if self.received == self.expected { State::Checksum } else { State::Payload }
+            }
+            (State::Checksum, checksum) => {
+                self.state = State::Idle;
+                self.request.len = self.expected.min(MAX_PAYLOAD);
+                return Some(match (checksum == self.checksum, self.expected <= MAX_PAYLOAD) {
+                    (false, _) => Err(FrameError::Crc),
+                    (true, false) => Err(FrameError::Length),
+                    (true, true) => Ok(self.request),
+                });
+            }
+        };
+        None
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Encoded answer, derefs to the bytes to send
+#[derive(Debug, Clone, Copy)]
+pub struct Response {
+    buf: [u8; MAX_FRAME],
+    len: usize,
+}
+
+impl Response {
+    /// `$M>` with `payload`, anything past [MAX_PAYLOAD] is cut
+    pub fn new(command: u8, payload: &[u8]) -> Self {
+        Response::encode(b'>', command, &payload[..payload.len().min(MAX_PAYLOAD)])
+    }
+
+    /// `$M!`, for a command the device doesn't take or a request it couldn't apply
+    pub fn error(command: u8) -> Self {
+        Response::encode(b'!', command, &[])
+    }
+
+    fn encode(direction: u8, command: u8, payload: &[u8]) -> Self {
+        let mut buf = [0; MAX_FRAME];
+        buf[..3].copy_from_slice(&[b'$', b'M', direction]);
+        buf[3] = payload.len() as u8;
+        buf[4] = command;
+        buf[5..5 + payload.len()].copy_from_slice(payload);
+        let len = 5 + payload.len();
+        buf[len] = buf[3..len].iter().fold(0, |x, b| x ^ b);
+
+        Response { buf, len: len + 1 }
+    }
+}
+
+impl Deref for Response {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// [STATUS] payload: cycle time, I2C errors, sensor bits, flight mode flags with bit 0 for
+/// armed, and the profile
+pub fn status(cycle_us: u16, i2c_errors: u16, sensors: u16, armed: bool) -> [u8; 11] {
+    let mut payload = [0; 11];
+    payload[0..2].copy_from_slice(&cycle_us.to_le_bytes());
+    payload[2..4].copy_from_slice(&i2c_errors.to_le_bytes());
+    payload[4..6].copy_from_slice(&sensors.to_le_bytes());
+    payload[6..10].copy_from_slice(&(armed as u32).to_le_bytes());
+    payload
+}
+
+/// [RAW_IMU] payload, sensor counts as they are
+pub fn raw_imu(acc: [i16; 3], gyro: [i16; 3], mag: [i16; 3]) -> [u8; 18] {
+    let mut payload = [0; 18];
+    let counts = acc.iter().chain(gyro.iter()).chain(mag.iter());
+    for (chunk, v) in payload.chunks_exact_mut(2).zip(counts) {
+        chunk.copy_from_slice(&v.to_le_bytes());
+    }
+    payload
+}
+
+/// [ATTITUDE] payload, roll and pitch in tenths of a degree and heading in degrees
+pub fn attitude(orientation: &SpatialOrientation) -> [u8; 6] {
+    let angles = [
+        (orientation.roll.to_degrees() * 10.0) as i16,
+        (orientation.pitch.to_degrees() * 10.0) as i16,
+        orientation.yaw.to_degrees() as i16,
+    ];
+    let mut payload = [0; 6];
+    for (chunk, v) in payload.chunks_exact_mut(2).zip(angles) {
+        chunk.copy_from_slice(&v.to_le_bytes());
+    }
+    payload
+}
+
+/// [PID] payload, P, I and D of roll, pitch and yaw in tenths and the rows after them zero.
+/// Feed forward has no place in it.
+pub fn pid(gains: &[PidGains; 3]) -> [u8; 3 * PID_ROWS] {
+    let byte = |gain: f32| (gain * PID_SCALE) as u8;
+    let mut payload = [0; 3 * PID_ROWS];
+    for (row, g) in payload.chunks_exact_mut(3).zip(gains) {
+        row.copy_from_slice(&[byte(g.p), byte(g.i), byte(g.d)]);
+    }
+    payload
+}
+
+/// Roll, pitch and yaw gains of a [SET_PID] payload, feed forward kept from `current`.
+/// None for one too short.
+pub fn set_pid(payload: &[u8], current: &[PidGains; 3]) -> Option<[PidGains; 3]> {
+    if payload.len() < 9 {
+        return None;
+    }
+    let gain = |byte: u8| byte as f32 / PID_SCALE;
+    let mut gains = *current;
+    for (g, row) in gains.iter_mut().zip(payload.chunks_exact(3)) {
+        *g = PidGains { p: gain(row[0]), i: gain(row[1]), d: gain(row[2]), ff: g.ff };
+    }
+    Some(gains)
+}
+
+/// Channels of a [SET_RAW_RC] in the MultiWii order, roll, pitch, yaw, throttle and the aux
+/// switches, 1000 to 2000 us each
+#[derive(Debug, Clone, Copy)]
+pub struct RawRc {
+    channels: [u16; 5],
+}
+
+impl RawRc {
+    /// None without the first five channels, more are ignored
+    pub fn from_payload(payload: &[u8]) -> Option<RawRc> {
+        if payload.len() < 10 {
+            return None;
+        }
+        let mut channels = [0; 5];
+        for (c, chunk) in channels.iter_mut().zip(payload.chunks_exact(2)) {
+            *c = u16::from_le_bytes([chunk[0], chunk[1]]);
+        }
+        Some(RawRc { channels })
+    }
+
+    /// AUX1 over 1700 us is the arm switch up
+    pub fn input(&self) -> RcInput {
+        let stick = |channel: usize| (self.channels[channel] as f32 - 1500.0) / 500.0;
+        let throttle = (self.channels[3] as f32 - 1000.0) / 1000.0;
+        RcInput::from_sticks(stick(0), stick(1), stick(2), throttle, self.channels[4] > 1700)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Setpoint, MAX_SETPOINT_ANGLE};
+
+    /// Requests in `bytes`, fed one at a time
+    fn parse(bytes: &[u8]) -> Vec<Result<Request, FrameError>> {
+        let mut parser = Parser::default();
+        bytes.iter().filter_map(|b| parser.push(*b)).collect()
+    }
+
+    /// What a configurator sends for a five channel [SET_RAW_RC]: roll full left, pitch
+    /// full forward, yaw centred, throttle at a tenth and AUX1 high
+    const RAW_RC_TRACE: [u8; 16] = [0x24, 0x4d, 0x3c, 0x0a, 0xc8, 0xe8, 0x03, 0xd0, 0x07, 0xdc, 0x05, 0x4c, 0x04, 0xd0, 0x07, 0xb8];
+
+    #[test]
+    fn an_attitude_request_comes_out_on_its_checksum() {
+        let trace = [0x24, 0x4d, 0x3c, 0x00, 0x6c, 0x6c];
+        let mut parser = Parser::new();
+
+        for b in &trace[..trace.len() - 1] {
+            assert!(parser.push(*b).is_none());
+        }
+        let request = parser.push(trace[trace.len() - 1]).unwrap().unwrap();
+        assert_eq!((request.command, request.payload()), (ATTITUDE, &[][..]));
+    }
+
+    #[test]
+    fn sticks_and_switch_of_a_raw_rc_trace() {
+        let requests = parse(&RAW_RC_TRACE);
+        let request = requests[0].as_ref().unwrap();
+        assert_eq!((requests.len(), request.command), (1, SET_RAW_RC));
+
+        let input = RawRc::from_payload(request.payload()).unwrap().input();
+        assert_eq!(input.setpoint, Setpoint { roll: -MAX_SETPOINT_ANGLE, pitch: MAX_SETPOINT_ANGLE, yaw_rate: 0.0 });
+        assert!((input.throttle - 0.1).abs() < 1e-6 && input.arm);
+        assert!(RawRc::from_payload(&request.payload()[..9]).is_none());
+    }
+
+    #[test]
+    fn sixteen_centred_channels_fit() {
+        let mut trace = vec![0x24, 0x4d, 0x3c, 0x10, 0xc8];
+        trace.extend([0xdc, 0x05].repeat(8));
+        trace.push(0xd8);
+
+        let request = parse(&trace)[0].unwrap();
+        assert_eq!(request.payload().len(), 16);
+        let input = RawRc::from_payload(request.payload()).unwrap().input();
+        assert_eq!(input, RcInput { throttle: 0.5, setpoint: Setpoint::default(), arm: false });
+    }
+
+    #[test]
+    fn answers_match_their_traces() {
+        let degrees = |d: f32| d.to_radians();
+        let orientation = SpatialOrientation { roll: degrees(12.35), pitch: degrees(-4.55), yaw: degrees(90.5) };
+        assert_eq!(&*Response::new(ATTITUDE, &attitude(&orientation)), &[0x24, 0x4d, 0x3e, 0x06, 0x6c, 0x7b, 0x00, 0xd3, 0xff, 0x5a, 0x00, 0x67]);
+
+        assert_eq!(status(2000, 3, SENSOR_ACC | SENSOR_MAG, true), [0xd0, 0x07, 0x03, 0x00, 0x05, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(&*Response::error(SET_PID), &[0x24, 0x4d, 0x21, 0x00, 0xca, 0xca]);
+        assert_eq!(&*Response::new(STATUS, &[0; 40]), &*Response::new(STATUS, &[0; MAX_PAYLOAD]));
+    }
+
+    #[test]
+    fn an_answer_parses_back_as_a_request_would() {
+        // the same layout under `$M<`, what a configurator's parser checks
+        let answer = Response::new(RAW_IMU, &raw_imu([1, -2, 4096], [-300, 0, 7], [0; 3]));
+        let mut request = answer.to_vec();
+        request[2] = b'<';
+
+        let parsed = parse(&request)[0].unwrap();
+        assert_eq!((parsed.command, parsed.payload()), (RAW_IMU, &answer[5..answer.len() - 1]));
+        assert_eq!(&parsed.payload()[..6], &[0x01, 0x00, 0xfe, 0xff, 0x00, 0x10]);
+    }
+
+    #[test]
+    fn garbage_and_broken_requests_cost_only_themselves() {
+        let overlong = [&[0x24, 0x4d, 0x3c, 40, PID][..], &[0x11; 40], &[40 ^ PID]].concat();
+        let mut bad_checksum = RAW_RC_TRACE;
+        bad_checksum[15] ^= 1;
+        let stream = [&b"$M$$Mx<\x00noise$"[..], &bad_checksum, &RAW_RC_TRACE, &overlong, b"$M", &RAW_RC_TRACE].concat();
+
+        let requests = parse(&stream);
+        assert_eq!(requests.len(), 4, "{:?}", requests);
+        assert_eq!(requests[0].err(), Some(FrameError::Crc));
+        assert_eq!(requests[1].map(|r| r.command), Ok(SET_RAW_RC));
+        assert_eq!(requests[2].err(), Some(FrameError::Length));
+        assert_eq!(requests[3].map(|r| r.command), Ok(SET_RAW_RC));
+    }
+
+    #[test]
+    fn pid_gains_go_out_and_back_in_tenths() {
+        let gains = [
+            PidGains { p: 4.5, i: 0.3, d: 2.0, ff: 0.7 },
+            PidGains { p: 4.6, i: 0.4, d: 2.1, ff: 0.0 },
+            PidGains { p: 8.5, i: 0.0, d: 0.0, ff: 1.0 },
+        ];
+        let payload = pid(&gains);
+
+        assert_eq!(&payload[..9], &[45, 3, 20, 46, 4, 21, 85, 0, 0]);
+        assert!(payload[9..].iter().all(|b| *b == 0));
+        let back = set_pid(&payload, &[PidGains::default(); 3]).unwrap();
+        for (b, g) in back.iter().zip(&gains) {
+            assert!((b.p - g.p).abs() < 0.05 && (b.i - g.i).abs() < 0.05 && (b.d - g.d).abs() < 0.05 && b.ff == 0.0);
+        }
+        assert_eq!(set_pid(&payload, &gains).unwrap()[0].ff, 0.7);
+        assert!(set_pid(&payload[..8], &gains).is_none());
+    }
+}
@@ -0,0 +1,173 @@
+//! PPM sum, every channel of the receiver as one pulse train on a single pin. The time from
+//! one rising edge to the next is a channel, 1000 to 2000 us give or take, and a longer gap
+//! ends the frame.
+
+use crate::{FrameError, RC_CHANNELS};
+
+pub const MAX_CHANNELS: usize = 8;
+/// Anything outside is a glitch and drops the frame it came in
+pub const MIN_PULSE_US: u32 = 800;
+pub const MAX_PULSE_US: u32 = 2200;
+/// Longer than any channel, shorter than the gap after a full frame of [MAX_CHANNELS]
+pub const SYNC_US: u32 = 2700;
+/// The capture timer runs out this long after the last edge
+pub const TIMEOUT_US: u32 = 50_000;
+/// No edge for this long and the receiver counts as lost
+pub const LOST_US: u32 = 100_000;
+
+/// Roll, pitch, throttle and yaw at least
+const MIN_CHANNELS: usize = 4;
+
+/// Turns the periods between rising edges into frames, nothing comes out before the first
+/// sync gap
+#[derive(Debug)]
+pub struct Decoder {
+    channels: [u32; MAX_CHANNELS],
+    count: usize,
+    synced: bool,
+    glitch: bool,
+    /// [TIMEOUT_US] in a row without an edge
+    timeouts: u32,
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Decoder { channels: [0; MAX_CHANNELS], count: 0, synced: false, glitch: false, timeouts: 0 }
+    }
+
+    /// Another [TIMEOUT_US] without an edge, true once that makes [LOST_US]. The decoder
+    /// waits for the next sync gap from then on.
+    pub fn timeout(&mut self) -> bool {
+        self.timeouts += 1;
+        let lost = self.timeouts == LOST_US / TIMEOUT_US;
+        if lost {
+            self.synced = false;
+        }
+        lost
+    }
+
+    /// The channels on the scale [RcMap](crate::RcMap) takes once `period_us` is a sync gap,
+    /// the ones past those the receiver sends at centre. [FrameError::Range] for a frame with
+    /// a glitch in it, [FrameError::Length] for too few or too many channels.
+    pub fn push(&mut self, period_us: u32) -> Option<Result<[u16; RC_CHANNELS], FrameError>> {
+        self.timeouts = 0;
+        if period_us < SYNC_US {
+            if self.synced {
+                let valid = (MIN_PULSE_US..=MAX_PULSE_US).contains(&period_us);
+                self.glitch |= !valid;
+                if let Some(c) = self.channels.get_mut(self.count) {
+                    *c = period_us;
+                }
+                self.count += 1;
+            }
+            return None;
+        }
+
+        let frame = (self.synced, self.glitch, self.count);
+        self.synced = true;
+        self.glitch = false;
+        self.count = 0;
+        match frame {
+            (false, _, _) => None,
+            (true, true, _) => Some(Err(FrameError::Range)),
+            (true, false, count) if !(MIN_CHANNELS..=MAX_CHANNELS).contains(&count) => Some(Err(FrameError::Length)),
+            (true, false, count) => {
+                // 988 to 2012 us onto 172 to 1811, CRSF and SBUS have them that way
+                let mut scaled = [992; RC_CHANNELS];
+                for (s, us) in scaled.iter_mut().zip(&self.channels[..count]) {
+                    *s = ((*us as i32 - 988) * 1639 / 1024 + 172).max(0) as u16;
+                }
+                Some(Ok(scaled))
+            }
+        }
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Periods of a frame of `channels` and the sync gap after it
+    fn framed(channels: &[u32]) -> Vec<u32> {
+        let mut periods = channels.to_vec();
+        periods.push(20_000 - channels.iter().sum::<u32>());
+        periods
+    }
+
+    /// What comes out of `periods`, run after a first sync gap
+    fn decode(decoder: &mut Decoder, periods: &[u32]) -> Vec<Result<[u16; RC_CHANNELS], FrameError>> {
+        periods.iter().filter_map(|p| decoder.push(*p)).collect()
+    }
+
+    fn synced() -> Decoder {
+        let mut decoder = Decoder::default();
+        assert!(decoder.push(SYNC_US).is_none());
+        decoder
+    }
+
+    #[test]
+    fn nothing_comes_out_before_the_first_sync_gap() {
+        let mut decoder = Decoder::new();
+        assert!(decode(&mut decoder, &[1500, 1500, 1500, 1500]).is_empty());
+        assert!(decoder.push(SYNC_US).is_none());
+        assert_eq!(decode(&mut decoder, &framed(&[1500; 4])).len(), 1);
+    }
+
+    #[test]
+    fn channels_come_out_on_the_crsf_scale() {
+        let frames = decode(&mut synced(), &framed(&[988, 2012, 1500, 1500, 1000, 2000]));
+        let channels = frames[0].unwrap();
+        assert_eq!(channels[..6], [172, 1811, 991, 991, 191, 1791]);
+        assert!(channels[6..].iter().all(|c| *c == 992));
+    }
+
+    #[test]
+    fn a_pulse_outside_800_to_2200_us_drops_its_frame() {
+        let mut decoder = synced();
+        for glitch in [799, 2201, 100] {
+            let frames = decode(&mut decoder, &framed(&[1500, glitch, 1500, 1500]));
+            assert!(matches!(frames[..], [Err(FrameError::Range)]), "{} us", glitch);
+        }
+        let edges = decode(&mut decoder, &framed(&[MIN_PULSE_US, MAX_PULSE_US, 1500, 1500]));
+        assert!(matches!(edges[..], [Ok(_)]));
+        // the frame after a glitch is whole again
+        assert!(matches!(decode(&mut decoder, &framed(&[1500; 4]))[..], [Ok(_)]));
+    }
+
+    #[test]
+    fn four_to_eight_channels_make_a_frame() {
+        let mut decoder = synced();
+        for count in 1..=10 {
+            let frames = decode(&mut decoder, &framed(&vec![1500; count]));
+            assert_eq!(frames[0].is_ok(), (MIN_CHANNELS..=MAX_CHANNELS).contains(&count), "{} channels", count);
+        }
+    }
+
+    #[test]
+    fn lost_after_100_ms_without_an_edge() {
+        let mut decoder = synced();
+        assert!(!decoder.timeout());
+        assert!(decoder.timeout());
+        // once, not on every update after
+        assert!(!decoder.timeout());
+
+        // waits for a sync gap again, the edges after the loss may be mid frame
+        assert!(decode(&mut decoder, &[1500, 1500, 1500, 1500, SYNC_US]).is_empty());
+        assert_eq!(decode(&mut decoder, &framed(&[1500; 4])).len(), 1);
+    }
+
+    #[test]
+    fn an_edge_starts_the_loss_over() {
+        let mut decoder = synced();
+        assert!(!decoder.timeout());
+        decoder.push(1500);
+        assert!(!decoder.timeout());
+        assert!(decoder.timeout());
+    }
+}
@@ -0,0 +1,154 @@
+//! Futaba SBUS, what most receivers without a serial protocol of their own put out. 100000
+//! baud, 8 data bits, even parity and two stop bits, and inverted: the line idles low. The
+//! F1 USART can't invert its input, the signal needs an inverter on the way to the RX pin,
+//! one transistor will do, unless the receiver has an uninverted pad.
+//!
+//! A frame is a header byte, the 16 channels packed the way CRSF has them, a flags byte
+//! and a zero, 25 bytes every 7 or 14 ms.
+
+use crate::{crsf, FrameError, RC_CHANNELS};
+
+pub const BAUD: u32 = 100_000;
+pub const FRAME_SIZE: usize = 25;
+
+const HEADER: u8 = 0x0f;
+/// Digital channels 17 and 18 in the low bits, not used here
+const FRAME_LOST: u8 = 1 << 2;
+const FAILSAFE: u8 = 1 << 3;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub channels: [u16; RC_CHANNELS],
+    /// The receiver missed the transmitter's last frame, the channels are the ones before
+    pub frame_lost: bool,
+    /// The receiver lost the transmitter altogether, the channels are its failsafe values
+    pub failsafe: bool,
+}
+
+/// Picks frames out of the bytes of an idle line. The header value turns up in channel data
+/// as well, [Parser::idle] starts the next frame over at the gap between two.
+#[derive(Debug)]
+pub struct Parser {
+    buf: [u8; FRAME_SIZE],
+    len: usize,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Parser { buf: [0; FRAME_SIZE], len: 0 }
+    }
+
+    /// Drops a frame cut short, for the idle line that ends every whole one
+    pub fn idle(&mut self) {
+        self.len = 0;
+    }
+
+    /// A frame once `byte` is its last one, None meanwhile and for anything before a header.
+    /// [FrameError::Encoding] for one without the end byte, the SBUS2 ones included.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Frame, FrameError>> {
+        if self.len == 0 && byte != HEADER {
+            return None;
+        }
+        self.buf[self.len] = byte;
+        self.len += 1;
+        if self.len < FRAME_SIZE {
+            return None;
+        }
+        self.len = 0;
+
+        let buf = &self.buf;
+        if buf[FRAME_SIZE - 1] != 0 {
+            return Some(Err(FrameError::Encoding));
+        }
+        let flags = buf[23];
+        crsf::Channels::from_payload(&buf[1..23]).map(|c| Ok(Frame { channels: c.0, frame_lost: flags & FRAME_LOST != 0, failsafe: flags & FAILSAFE != 0 }))
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RcMap, Setpoint, MAX_SETPOINT_ANGLE};
+
+    /// Frames in `bytes`, fed one at a time without an idle line
+    fn parse(bytes: &[u8]) -> Vec<Result<Frame, FrameError>> {
+        let mut parser = Parser::default();
+        bytes.iter().filter_map(|b| parser.push(*b)).collect()
+    }
+
+    /// Roll full left, pitch full forward, throttle and yaw centred, channels 5 and 16 up
+    /// and the others centred, with no flags set
+    const FRAME: [u8; FRAME_SIZE] = [
+        0x0f, 0xac, 0x98, 0x38, 0xf8, 0xc0, 0x37, 0x71, 0xf0, 0x81, 0x0f, 0x7c, 0xe0, 0x03, 0x1f, 0xf8, 0xc0, 0x07, 0x3e, 0xf0, 0x81, 0x6f,
+        0xe2, 0x00, 0x00,
+    ];
+
+    fn flagged(flags: u8) -> [u8; FRAME_SIZE] {
+        let mut frame = FRAME;
+        frame[23] = flags;
+        frame
+    }
+
+    #[test]
+    fn channels_of_a_frame() {
+        let frames = parse(&FRAME);
+        let frame = frames[0].unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frame.channels, [172, 1811, 992, 992, 1811, 992, 992, 992, 992, 992, 992, 992, 992, 992, 992, 1811]);
+        assert!(!frame.frame_lost && !frame.failsafe);
+
+        let input = RcMap::DEFAULT.input(&frame.channels);
+        assert_eq!(input.setpoint, Setpoint { roll: -MAX_SETPOINT_ANGLE, pitch: MAX_SETPOINT_ANGLE, yaw_rate: 0.0 });
+        assert!((input.throttle - 0.5).abs() < 1e-3 && input.arm);
+    }
+
+    #[test]
+    fn controls_go_by_the_map() {
+        let channels = parse(&FRAME)[0].unwrap().channels;
+        // throttle on the full forward stick and arming on a switch that's centred
+        let map = RcMap { throttle: 1, arm: 6, ..RcMap::DEFAULT };
+        let input = map.input(&channels);
+        assert_eq!((input.throttle, input.arm), (1.0, false));
+
+        assert!(RcMap { arm: 15, ..RcMap::DEFAULT }.is_valid());
+        assert!(!RcMap { arm: 16, ..RcMap::DEFAULT }.is_valid());
+    }
+
+    #[test]
+    fn flags_come_through() {
+        let lost = parse(&flagged(FRAME_LOST))[0].unwrap();
+        assert!(lost.frame_lost && !lost.failsafe);
+        let failsafe = parse(&flagged(FRAME_LOST | FAILSAFE))[0].unwrap();
+        assert!(failsafe.frame_lost && failsafe.failsafe);
+        // digital channels don't count as either
+        let digital = parse(&flagged(0x03))[0].unwrap();
+        assert!(!digital.frame_lost && !digital.failsafe);
+    }
+
+    #[test]
+    fn a_frame_without_its_end_byte_is_dropped() {
+        let mut sbus2 = FRAME;
+        sbus2[24] = 0x04;
+        assert!(matches!(parse(&sbus2)[..], [Err(FrameError::Encoding)]));
+    }
+
+    #[test]
+    fn the_idle_line_starts_over_after_a_cut_frame() {
+        let mut parser = Parser::new();
+        for b in &FRAME[..10] {
+            assert!(parser.push(*b).is_none());
+        }
+        parser.idle();
+        let frames: Vec<_> = FRAME.iter().filter_map(|b| parser.push(*b)).collect();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].unwrap().channels[1], 1811);
+        // bytes before a header are skipped
+        assert_eq!(parse(&[&[0x00, 0x42][..], &FRAME].concat()).len(), 1);
+    }
+}
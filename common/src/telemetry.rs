@@ -0,0 +1,249 @@
+//! Frames from the device as they go over the link. The id and payload a `to_byte_array`
+//! makes get the payload length after the id and a [crc16] of all three at the end,
+//! little-endian, and go out [cobs] encoded with an [EOT] after them:
+//!
+//! | bytes        | field   |
+//! |--------------|---------|
+//! | 0            | id      |
+//! | 1            | payload length, n |
+//! | 2..2+n       | payload |
+//! | 2+n..4+n     | CRC-16/CCITT-FALSE of the bytes before |
+//!
+//! The [EOT] is the sync, the encoding keeps it out of everything else. A receiver that
+//! came in half way or lost bytes drops what it has at the next one and starts over with
+//! the frame after it, one that got through garbled fails its length or CRC. Commands go
+//! the other way the same, with the [PROTOCOL_VERSION](crate::PROTOCOL_VERSION) in place of
+//! the id and length.
+
+use crate::{append_crc16, cobs, crc16, FrameError, EOT, MAX_FRAME_SIZE};
+
+/// Length byte and CRC around the id and payload
+const OVERHEAD: usize = 3;
+/// Longest frame [Decoder] takes, overhead included, before encoding
+const MAX_RAW_SIZE: usize = MAX_FRAME_SIZE + OVERHEAD;
+
+/// Bytes on the wire for a frame of `len` bytes of id and payload at most, delimiter
+/// included
+pub const fn max_wire_len(len: usize) -> usize {
+    cobs::max_encoded_len(len + OVERHEAD) + 1
+}
+
+/// Buffer [encode_frame] takes for any frame up to [MAX_FRAME_SIZE]
+pub const MAX_WIRE_SIZE: usize = max_wire_len(MAX_FRAME_SIZE);
+
+/// Frames `frame`, an id and its payload, into `dst` ready to send and gives the length.
+/// [FrameError::Length] for an empty frame, one past [MAX_FRAME_SIZE] or too short a `dst`.
+pub fn encode_frame(frame: &[u8], dst: &mut [u8]) -> Result<usize, FrameError> {
+    let (id, payload) = frame.split_first().ok_or(FrameError::Length)?;
+    if frame.len() > MAX_FRAME_SIZE || dst.len() < max_wire_len(frame.len()) {
+        return Err(FrameError::Length);
+    }
+
+    let mut raw = [0; MAX_RAW_SIZE];
+    let len = frame.len() + OVERHEAD;
+    raw[0] = *id;
+    raw[1] = payload.len() as u8;
+    raw[2..2 + payload.len()].copy_from_slice(payload);
+    append_crc16(&mut raw[..len]);
+
+    let encoded = cobs::encode(&raw[..len], dst)?;
+    dst[encoded] = EOT;
+    Ok(encoded + 1)
+}
+
+/// Takes the bytes off the link one at a time and hands back every frame that passed its
+/// checks, as `from_byte_slice` takes them
+#[derive(Debug)]
+pub struct Decoder {
+    cobs: cobs::Decoder<{ cobs::max_encoded_len(MAX_RAW_SIZE) }>,
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Decoder { cobs: cobs::Decoder::new() }
+    }
+
+    /// The id and payload of a frame in `dst` once `byte` is the [EOT] closing it, None
+    /// meanwhile. [FrameError::Crc] for a frame corrupted on the way, [FrameError::Length]
+    /// for one whose length byte doesn't match or that doesn't fit `dst`, and whatever
+    /// [cobs::Decoder::push] has for one that doesn't decode.
+    pub fn push(&mut self, byte: u8, dst: &mut [u8]) -> Option<Result<usize, FrameError>> {
+        let mut raw = [0; MAX_RAW_SIZE];
+        Some(match self.cobs.push(byte, &mut raw)? {
+            Ok(len) => unframe(&raw[..len], dst),
+            Err(e) => Err(e),
+        })
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Checks `raw` and copies its id and payload into `dst`
+fn unframe(raw: &[u8], dst: &mut [u8]) -> Result<usize, FrameError> {
+    if raw.len() < OVERHEAD + 1 {
+        return Err(FrameError::Length);
+    }
+    let (body, crc) = raw.split_at(raw.len() - 2);
+    if crc16(body).to_le_bytes() != crc {
+        return Err(FrameError::Crc);
+    }
+
+    let (id, len, payload) = (body[0], body[1] as usize, &body[2..]);
+    if payload.len() != len || dst.len() <= len {
+        return Err(FrameError::Length);
+    }
+    dst[0] = id;
+    dst[1..=len].copy_from_slice(payload);
+    Ok(len + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Status, STATUS_ID};
+
+    /// The next of a seeded xorshift, the same sequence every run
+    fn next(seed: &mut u32) -> usize {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 17;
+        *seed ^= *seed << 5;
+        *seed as usize
+    }
+
+    fn bytes(seed: &mut u32, len: usize) -> Vec<u8> {
+        (0..len).map(|_| next(seed) as u8).collect()
+    }
+
+    fn wire(frame: &[u8]) -> Vec<u8> {
+        let mut buf = [0; MAX_WIRE_SIZE];
+        let len = encode_frame(frame, &mut buf).unwrap();
+        assert!(len <= max_wire_len(frame.len()));
+        buf[..len].to_vec()
+    }
+
+    /// Every frame `decoder` closes while it's fed `bytes`
+    fn push_all(decoder: &mut Decoder, bytes: &[u8]) -> Vec<Result<Vec<u8>, FrameError>> {
+        let mut dst = [0; MAX_FRAME_SIZE];
+        let mut frames = Vec::new();
+        for byte in bytes {
+            if let Some(result) = decoder.push(*byte, &mut dst) {
+                frames.push(result.map(|n| dst[..n].to_vec()));
+            }
+        }
+        frames
+    }
+
+    #[test]
+    fn frames_of_every_length_roundtrip() {
+        let mut seed = 0x9999_0001;
+        let mut decoder = Decoder::new();
+        for len in 1..=MAX_FRAME_SIZE {
+            let frame = bytes(&mut seed, len);
+            let w = wire(&frame);
+            assert_eq!(w.iter().position(|b| *b == EOT), Some(w.len() - 1));
+            assert_eq!(push_all(&mut decoder, &w), [Ok(frame)]);
+        }
+
+        let status = Status { calibrating: false, imu_lost: true, gyro_saturated: false, reset_skipped: false, crashed: true };
+        let decoded = push_all(&mut decoder, &wire(&status.to_byte_array())).remove(0).unwrap();
+        assert_eq!(decoded[0], STATUS_ID);
+        let s = Status::from_byte_slice(&decoded).unwrap();
+        assert!(s.imu_lost && s.crashed && !s.calibrating);
+    }
+
+    #[test]
+    fn encode_refuses_what_doesnt_fit() {
+        let mut dst = [0; MAX_WIRE_SIZE];
+        assert_eq!(encode_frame(&[], &mut dst), Err(FrameError::Length));
+        assert_eq!(encode_frame(&[0; MAX_FRAME_SIZE + 1], &mut dst), Err(FrameError::Length));
+        assert_eq!(encode_frame(&[1, 2, 3], &mut dst[..max_wire_len(3) - 1]), Err(FrameError::Length));
+
+        // nor does the decoder hand over more than its caller has room for
+        let mut short = [0; 2];
+        let mut decoder = Decoder::new();
+        let results: Vec<_> = wire(&[1, 2, 3]).iter().filter_map(|b| decoder.push(*b, &mut short)).collect();
+        assert_eq!(results, [Err(FrameError::Length)]);
+    }
+
+    #[test]
+    fn back_to_back_frames_come_out_in_order() {
+        let mut seed = 0x9999_0002;
+        let frames: Vec<Vec<u8>> = (0..100).map(|_| {
+            let len = 1 + next(&mut seed) % MAX_FRAME_SIZE;
+            bytes(&mut seed, len)
+        }).collect();
+        let stream: Vec<u8> = frames.iter().flat_map(|f| wire(f)).collect();
+        assert_eq!(push_all(&mut Decoder::new(), &stream), frames.into_iter().map(Ok).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn a_flipped_bit_costs_the_frame_and_no_other() {
+        let mut seed = 0x9999_0003;
+        let after = bytes(&mut seed, 12);
+        for _ in 0..50 {
+            let len = 1 + next(&mut seed) % MAX_FRAME_SIZE;
+            let w = wire(&bytes(&mut seed, len));
+            for bit in 0..w.len() * 8 {
+                let mut flipped = w.clone();
+                flipped[bit / 8] ^= 1 << (bit % 8);
+                // a flipped delimiter runs the frame into the one after it, a second one follows
+                let stream = [&flipped, &wire(&after)[..], &wire(&after)].concat();
+                let decoded = push_all(&mut Decoder::new(), &stream);
+                for r in &decoded {
+                    match r {
+                        Ok(f) => assert_eq!(*f, after),
+                        Err(e) => assert!(matches!(e, FrameError::Crc | FrameError::Length | FrameError::Encoding), "{:?}", e),
+                    }
+                }
+                assert_eq!(decoded.last(), Some(&Ok(after.clone())));
+            }
+        }
+    }
+
+    #[test]
+    fn a_frame_cut_short_is_dropped() {
+        let mut seed = 0x9999_0004;
+        let (cut_short, after) = (bytes(&mut seed, 40), bytes(&mut seed, 9));
+        let w = wire(&cut_short);
+        for cut in 1..w.len() {
+            // the delimiter went with the rest, the frame after it is lost along
+            let stream = [&w[..cut], &wire(&after)[..], &wire(&after)].concat();
+            let decoded = push_all(&mut Decoder::new(), &stream);
+            assert_eq!(decoded.len(), 2, "cut at {}", cut);
+            assert!(decoded[0].is_err());
+            assert_eq!(decoded[1], Ok(after.clone()));
+        }
+    }
+
+    #[test]
+    fn a_length_that_doesnt_match_is_refused() {
+        let mut raw = vec![STATUS_ID, 4, 1, 2, 3, 0, 0];
+        append_crc16(&mut raw);
+        let mut w = vec![0; cobs::max_encoded_len(raw.len())];
+        let len = cobs::encode(&raw, &mut w).unwrap();
+        w.truncate(len);
+        w.push(EOT);
+        assert_eq!(push_all(&mut Decoder::new(), &w), [Err(FrameError::Length)]);
+    }
+
+    #[test]
+    fn arbitrary_bytes_fail_cleanly_and_the_next_frame_comes_through() {
+        let mut seed = 0x9999_0005;
+        let mut decoder = Decoder::new();
+        for _ in 0..5_000 {
+            let len = next(&mut seed) % 100;
+            for result in push_all(&mut decoder, &bytes(&mut seed, len)) {
+                match result {
+                    Ok(f) => assert!(!f.is_empty() && f.len() <= MAX_FRAME_SIZE),
+                    Err(e) => assert!(matches!(e, FrameError::Crc | FrameError::Length | FrameError::Encoding), "{:?}", e),
+                }
+            }
+        }
+        let frame = [STATUS_ID, 0, 0, 0, 0, 0];
+        assert_eq!(push_all(&mut decoder, &[&[EOT][..], &wire(&frame)].concat()).pop(), Some(Ok(frame.to_vec())));
+    }
+}
@@ -0,0 +1,144 @@
+//! Three component vector for sensor readings and rates, just the operations the firmware
+//! needs. Without an FPU the generic paths of a full linear algebra crate cost flash and
+//! cycles for nothing.
+
+use core::ops::{Add, AddAssign, Div, Index, IndexMut, Mul, Neg, Sub, SubAssign};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Vec3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Vec3 {
+    pub const fn new(x: f32, y: f32, z: f32) -> Self {
+        Vec3 { x, y, z }
+    }
+
+    pub const fn zeros() -> Self {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+
+    pub const fn repeat(v: f32) -> Self {
+        Vec3::new(v, v, v)
+    }
+
+    pub fn as_array(&self) -> &[f32; 3] {
+        // three f32 fields in declaration order under repr(C), the same layout as the array
+        unsafe { &*(self as *const Vec3 as *const [f32; 3]) }
+    }
+
+    pub fn as_mut_array(&mut self) -> &mut [f32; 3] {
+        unsafe { &mut *(self as *mut Vec3 as *mut [f32; 3]) }
+    }
+
+    pub fn iter(&self) -> core::slice::Iter<'_, f32> {
+        self.as_array().iter()
+    }
+
+    pub fn iter_mut(&mut self) -> core::slice::IterMut<'_, f32> {
+        self.as_mut_array().iter_mut()
+    }
+
+    pub fn map(self, f: impl Fn(f32) -> f32) -> Vec3 {
+        Vec3::new(f(self.x), f(self.y), f(self.z))
+    }
+
+    pub fn component_mul(&self, rhs: &Vec3) -> Vec3 {
+        Vec3::new(self.x * rhs.x, self.y * rhs.y, self.z * rhs.z)
+    }
+
+    pub fn component_div(&self, rhs: &Vec3) -> Vec3 {
+        Vec3::new(self.x / rhs.x, self.y / rhs.y, self.z / rhs.z)
+    }
+
+    pub fn dot(&self, rhs: &Vec3) -> f32 {
+        self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
+    }
+
+    pub fn cross(&self, rhs: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.y * rhs.z - self.z * rhs.y,
+            self.z * rhs.x - self.x * rhs.z,
+            self.x * rhs.y - self.y * rhs.x,
+        )
+    }
+
+    pub fn sum(&self) -> f32 {
+        self.x + self.y + self.z
+    }
+
+    /// Index of the component furthest from zero, the first one of a tie
+    pub fn iamax(&self) -> usize {
+        let abs = |v: f32| if v < 0.0 { -v } else { v };
+        let mut max = 0;
+        for i in 1..3 {
+            if abs(self[i]) > abs(self[max]) {
+                max = i;
+            }
+        }
+        max
+    }
+}
+
+impl Add for Vec3 {
+    type Output = Vec3;
+    fn add(self, rhs: Vec3) -> Vec3 {
+        Vec3::new(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
+    }
+}
+
+impl Sub for Vec3 {
+    type Output = Vec3;
+    fn sub(self, rhs: Vec3) -> Vec3 {
+        Vec3::new(self.x - rhs.x, self.y - rhs.y, self.z - rhs.z)
+    }
+}
+
+impl Neg for Vec3 {
+    type Output = Vec3;
+    fn neg(self) -> Vec3 {
+        Vec3::new(-self.x, -self.y, -self.z)
+    }
+}
+
+impl Mul<f32> for Vec3 {
+    type Output = Vec3;
+    fn mul(self, rhs: f32) -> Vec3 {
+        Vec3::new(self.x * rhs, self.y * rhs, self.z * rhs)
+    }
+}
+
+impl Div<f32> for Vec3 {
+    type Output = Vec3;
+    fn div(self, rhs: f32) -> Vec3 {
+        Vec3::new(self.x / rhs, self.y / rhs, self.z / rhs)
+    }
+}
+
+impl AddAssign for Vec3 {
+    fn add_assign(&mut self, rhs: Vec3) {
+        *self = *self + rhs;
+    }
+}
+
+impl SubAssign for Vec3 {
+    fn sub_assign(&mut self, rhs: Vec3) {
+        *self = *self - rhs;
+    }
+}
+
+impl Index<usize> for Vec3 {
+    type Output = f32;
+    fn index(&self, i: usize) -> &f32 {
+        &self.as_array()[i]
+    }
+}
+
+impl IndexMut<usize> for Vec3 {
+    fn index_mut(&mut self, i: usize) -> &mut f32 {
+        &mut self.as_mut_array()[i]
+    }
+}
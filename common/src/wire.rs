@@ -0,0 +1,59 @@
+//! Postcard encoding under a leading byte, for the frames that derive serde. Fields go out
+//! in declaration order, integers as varints, so a frame is only as long as its values need
+//! and the sizes are upper bounds from `MaxSize`.
+
+use core::ops::Deref;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{append_crc16, FrameError};
+
+/// Encoded frame of at most `N` bytes, derefs to the ones in use
+#[derive(Debug, Clone, Copy)]
+pub struct Frame<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Frame<N> {
+    /// `id` and `value` after it, [FrameError::Length] when that takes more than `N` bytes
+    pub fn encode<T: Serialize>(id: u8, value: &T) -> Result<Self, FrameError> {
+        let mut buf = [0; N];
+        let (first, rest) = buf.split_first_mut().ok_or(FrameError::Length)?;
+        *first = id;
+        let len = postcard::to_slice(value, rest).map_err(|_| FrameError::Length)?.len() + 1;
+
+        Ok(Frame { buf, len })
+    }
+
+    /// Closes the frame with the [crc16](crate::crc16) of what it holds so far
+    pub fn with_crc16(mut self) -> Result<Self, FrameError> {
+        if self.len + 2 > N {
+            return Err(FrameError::Length);
+        }
+        self.len += 2;
+        append_crc16(&mut self.buf[..self.len]);
+
+        Ok(self)
+    }
+}
+
+impl<const N: usize> Deref for Frame<N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// A `T` out of a frame led by `id`. None for another one, an encoding that doesn't decode
+/// and bytes left over after it, whatever `buf` holds.
+pub fn decode<'a, T: Deserialize<'a>>(id: u8, buf: &'a [u8]) -> Option<T> {
+    match buf.split_first() {
+        Some((first, rest)) if *first == id => match postcard::take_from_bytes(rest) {
+            Ok((value, [])) => Some(value),
+            _ => None,
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,401 @@
+//! Complementary filter in fixed point, for an M3 without an FPU where every f32 operation
+//! is a soft float call. Same steps as [Complementary](crate::Complementary), f32 only at
+//! the [AttitudeEstimator] boundary and for the tilt compensated magnetometer heading.
+//!
+//! Cost of `update` against the f32 filter, release build, in cycles (a Cortex-M3 instruction
+//! timing model run over the thumbv7m build, zero wait states as at the default 8 MHz):
+//!
+//! | `update`                  | f32    | fixed  |
+//! |---------------------------|--------|--------|
+//! | accelerometer             |  6 450 |  3 370 |
+//! | with the field            | 16 100 | 13 000 |
+//!
+//! About 0.4 ms saved per sample at 8 MHz, the field costs both the same f32 heading. The
+//! gyro task reports the fusion cycles it measures with DWT over RTT, compare against a
+//! build with the `complementary` feature.
+
+use core::ops::{Add, Div, Mul, Neg, Sub};
+
+use common::{SpatialOrientation, Vec3};
+
+use crate::{
+    quaternion_from_acc_angles, tilt_compensated_heading, AttitudeEstimator, ACC_LOWPASS_HZ, ACC_WEIGHT, MAG_WEIGHT,
+    VERTICAL_MAX_ROLL_RATE, VERTICAL_PITCH,
+};
+
+const FRAC_BITS: u32 = 27;
+
+/// Q4.27, up to 16 rad/s before a rate saturates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Fix(pub i32);
+
+impl Fix {
+    pub const ZERO: Fix = Fix(0);
+    pub const ONE: Fix = Fix(1 << FRAC_BITS);
+    pub const PI: Fix = Fix(421_657_428);
+    const TWO_PI: Fix = Fix(843_314_857);
+
+    /// Saturates outside of the range, NaN ends up at zero
+    pub fn from_f32(v: f32) -> Fix {
+        Fix((v * (1u32 << FRAC_BITS) as f32) as i32)
+    }
+
+    pub fn to_f32(self) -> f32 {
+        self.0 as f32 / (1u32 << FRAC_BITS) as f32
+    }
+
+    pub fn abs(self) -> Fix {
+        Fix(self.0.saturating_abs())
+    }
+
+    pub fn limit(self, limit: Fix) -> Fix {
+        self.max(-limit).min(limit)
+    }
+
+    /// Into -pi..pi, for an angle less than a turn outside of it
+    fn wrap(self) -> Fix {
+        if self > Fix::PI {
+            self - Fix::TWO_PI
+        } else if self < -Fix::PI {
+            self + Fix::TWO_PI
+        } else {
+            self
+        }
+    }
+}
+
+impl Add for Fix {
+    type Output = Fix;
+    fn add(self, rhs: Fix) -> Fix {
+        Fix(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl Sub for Fix {
+    type Output = Fix;
+    fn sub(self, rhs: Fix) -> Fix {
+        Fix(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl Neg for Fix {
+    type Output = Fix;
+    fn neg(self) -> Fix {
+        Fix(self.0.saturating_neg())
+    }
+}
+
+impl Mul for Fix {
+    type Output = Fix;
+    fn mul(self, rhs: Fix) -> Fix {
+        Fix(((self.0 as i64 * rhs.0 as i64) >> FRAC_BITS) as i32)
+    }
+}
+
+/// Zero for a zero divisor
+impl Div for Fix {
+    type Output = Fix;
+    fn div(self, rhs: Fix) -> Fix {
+        if rhs.0 == 0 {
+            return Fix::ZERO;
+        }
+        Fix((((self.0 as i64) << FRAC_BITS) / rhs.0 as i64) as i32)
+    }
+}
+
+/// atan(2^-i) for every CORDIC step
+const ATAN_TABLE: [i32; 16] = [
+    105_414_357, 62_229_729, 32_880_480, 16_690_645, 8_377_711, 4_192_939, 2_096_981, 1_048_555,
+    524_285, 262_144, 131_072, 65_536, 32_768, 16_384, 8_192, 4_096,
+];
+
+/// CORDIC in vectoring mode, any scale of `y` and `x` as long as both share it.
+/// Within 0.01° of `atan2f`.
+pub fn atan2(y: i32, x: i32) -> Fix {
+    if x == 0 && y == 0 {
+        return Fix::ZERO;
+    }
+
+    // the steps only cover a half turn, the left half is turned over first
+    let (mut x, mut y, mut z) = match (x < 0, y < 0) {
+        (false, _) => (x, y, Fix::ZERO),
+        (true, false) => (-x, -y, Fix::PI),
+        (true, true) => (-x, -y, -Fix::PI),
+    };
+
+    // largest component just below 2^29, the gain of 1.65 and the sums still fit
+    let shift = (x.unsigned_abs().max(y.unsigned_abs())).leading_zeros() as i32 - 3;
+    if shift > 0 {
+        x <<= shift;
+        y <<= shift;
+    } else {
+        x >>= -shift;
+        y >>= -shift;
+    }
+
+    for (i, atan) in ATAN_TABLE.iter().enumerate() {
+        let (dx, dy) = (y >> i, x >> i);
+        if y > 0 {
+            x += dx;
+            y -= dy;
+            z = z + Fix(*atan);
+        } else {
+            x -= dx;
+            y += dy;
+            z = z - Fix(*atan);
+        }
+    }
+
+    z
+}
+
+/// Integer square root, bit by bit
+fn isqrt(v: u64) -> u64 {
+    let mut rest = v;
+    let mut root = 0u64;
+    let mut bit = 1u64 << 62;
+    while bit > v {
+        bit >>= 2;
+    }
+
+    while bit != 0 {
+        if rest >= root + bit {
+            rest -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root
+}
+
+/// [acc_angles](crate::acc_angles) for whole counts
+pub fn acc_angles(acc: [i32; 3]) -> (Fix, Fix) {
+    let [x, y, z] = acc;
+    // 8 fractional bits on both sides of the ratio, whole counts lose too much at small angles
+    let hypot = |a: i32, b: i32| isqrt(((a as i64 * a as i64 + b as i64 * b as i64) as u64) << 16) as i32;
+
+    (atan2(y << 8, hypot(x, z)), -atan2(x << 8, hypot(y, z)))
+}
+
+/// Whole counts, readings never get over 16 bits
+fn counts(v: Vec3) -> [i32; 3] {
+    [v.x as i32, v.y as i32, v.z as i32]
+}
+
+/// [Complementary](crate::Complementary) on [Fix]. `acc` goes in as whole counts, which is
+/// what the gyro task passes; other units lose precision.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedComplementary {
+    pitch: Fix,
+    roll: Fix,
+    yaw: Fix,
+    /// Low-pass on the accelerometer angles, RC time constant in seconds
+    rc: Fix,
+    acc: Option<(Fix, Fix)>,
+    acc_cutoff_hz: f32,
+    acc_weight: Fix,
+    recovery_weight: Fix,
+    mag_weight: Fix,
+    vertical_pitch: Fix,
+    max_roll_rate: Fix,
+    recovering: bool,
+}
+
+impl FixedComplementary {
+    pub fn new(acc: [f32; 2], acc_cutoff_hz: f32, acc_weight: f32) -> Self {
+        FixedComplementary {
+            pitch: Fix::from_f32(acc[0]),
+            roll: Fix::from_f32(acc[1]),
+            yaw: Fix::ZERO,
+            rc: Fix::from_f32(time_constant(acc_cutoff_hz)),
+            acc: None,
+            acc_cutoff_hz,
+            acc_weight: Fix::from_f32(acc_weight),
+            recovery_weight: Fix::from_f32(crate::ACC_WEIGHT_RECOVERY),
+            mag_weight: Fix::from_f32(MAG_WEIGHT),
+            vertical_pitch: Fix::from_f32(VERTICAL_PITCH),
+            max_roll_rate: Fix::from_f32(VERTICAL_MAX_ROLL_RATE),
+            recovering: false,
+        }
+    }
+
+    fn low_pass(&mut self, x: (Fix, Fix), dt: Fix) -> (Fix, Fix) {
+        let alpha = dt / (self.rc + dt);
+        let y = match self.acc {
+            Some((p, r)) => (p + (x.0 - p) * alpha, r + (x.1 - r) * alpha),
+            None => x,
+        };
+        self.acc = Some(y);
+        y
+    }
+}
+
+fn time_constant(cutoff_hz: f32) -> f32 {
+    1.0 / (2.0 * core::f32::consts::PI * cutoff_hz)
+}
+
+impl AttitudeEstimator for FixedComplementary {
+    fn from_acc(acc: [f32; 2]) -> Self {
+        FixedComplementary::new(acc, ACC_LOWPASS_HZ, ACC_WEIGHT)
+    }
+
+    fn update(&mut self, gyro: Vec3, acc: Option<Vec3>, mag: Option<Vec3>, dt: f32) {
+        let dt = Fix::from_f32(dt);
+        let (rate_pitch, rate_roll, rate_yaw) = (Fix::from_f32(gyro.x), Fix::from_f32(gyro.y), Fix::from_f32(gyro.z));
+
+        let (acc, acc_weight) = match acc {
+            None => ((Fix::ZERO, Fix::ZERO), Fix::ZERO),
+            Some(a) => {
+                let filtered = self.low_pass(acc_angles(counts(a)), dt);
+                (filtered, if self.recovering { self.recovery_weight } else { self.acc_weight })
+            }
+        };
+
+        let vertical = self.pitch.abs() > self.vertical_pitch;
+        let (acc_weight, rate_roll) =
+            if vertical { (Fix::ZERO, rate_roll.limit(self.max_roll_rate)) } else { (acc_weight, rate_roll) };
+
+        let new_pitch = self.pitch + rate_pitch * dt;
+        let new_roll = self.roll + rate_roll * dt;
+        self.pitch = new_pitch + (acc.0 - new_pitch) * acc_weight;
+        self.roll = new_roll + (acc.1 - new_roll) * acc_weight;
+
+        let new_yaw = (self.yaw + rate_yaw * dt).wrap();
+        let mag_error = match mag {
+            Some(m) => {
+                // in f32, the field comes in at a fraction of the sample rate
+                let heading = Fix::from_f32(tilt_compensated_heading(m, self.pitch.to_f32(), self.roll.to_f32()));
+                (heading - new_yaw).wrap()
+            }
+            None => Fix::ZERO,
+        };
+        self.yaw = (new_yaw + mag_error * self.mag_weight).wrap();
+    }
+
+    fn reset(&mut self, acc: [f32; 2]) {
+        self.pitch = Fix::from_f32(acc[0]);
+        self.roll = Fix::from_f32(acc[1]);
+        self.yaw = Fix::ZERO;
+        self.acc = None;
+    }
+
+    fn zero_yaw(&mut self) {
+        self.yaw = Fix::ZERO;
+    }
+
+    fn orientation(&self) -> SpatialOrientation {
+        SpatialOrientation { pitch: self.pitch.to_f32(), roll: self.roll.to_f32(), yaw: self.yaw.to_f32() }
+    }
+
+    fn quaternion(&self) -> [f32; 4] {
+        quaternion_from_acc_angles(self.pitch.to_f32(), self.roll.to_f32(), self.yaw.to_f32())
+    }
+
+    fn set_recovering(&mut self, recovering: bool) {
+        self.recovering = recovering;
+    }
+
+    const KIND: u8 = 4;
+
+    fn gain(&self) -> f32 {
+        self.acc_weight.to_f32()
+    }
+
+    fn set_gain(&mut self, gain: f32) {
+        self.acc_weight = Fix::from_f32(gain);
+    }
+
+    fn acc_cutoff_hz(&self) -> Option<f32> {
+        Some(self.acc_cutoff_hz)
+    }
+
+    fn set_acc_cutoff_hz(&mut self, hz: f32) {
+        self.rc = Fix::from_f32(time_constant(hz));
+        self.acc = None;
+        self.acc_cutoff_hz = hz;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Complementary, ACCEL_RANGE, GYRO_DT, GYRO_FREQUENCY_HZ};
+
+    const TENTH_OF_A_DEGREE: f32 = 0.1 * core::f32::consts::PI / 180.0;
+
+    #[test]
+    fn atan2_within_a_hundredth_of_a_degree() {
+        let hundredth = 0.01f32.to_radians();
+
+        for scale in [1.0f32, 100.0, 8192.0, 1_000_000.0] {
+            for step in 0..720 {
+                let angle = (step as f32 * 0.5 - 180.0).to_radians();
+                let (y, x) = (libm::sinf(angle) * scale, libm::cosf(angle) * scale);
+                // whole numbers at the smallest scale, that's where rounding shows
+                let (y, x) = (libm::roundf(y) as i32, libm::roundf(x) as i32);
+                if x == 0 && y == 0 {
+                    continue;
+                }
+
+                let error = crate::wrap_angle(atan2(y, x).to_f32() - libm::atan2f(y as f32, x as f32));
+                assert!(error.abs() < hundredth, "{} {} off by {} degrees", y, x, error.to_degrees());
+            }
+        }
+    }
+
+    #[test]
+    fn accelerometer_angles_within_a_tenth_of_a_degree() {
+        for pitch in (-85..=85).step_by(5) {
+            for roll in (-85..=85).step_by(5) {
+                let (p, r) = ((pitch as f32).to_radians(), (roll as f32).to_radians());
+                let g = Vec3::new(-libm::sinf(r), libm::sinf(p), libm::cosf(p) * libm::cosf(r));
+                let acc = g / libm::sqrtf(g.dot(&g)) * ACCEL_RANGE.sensitivity();
+                let counts = counts(acc);
+
+                let expected = crate::acc_angles(Vec3::new(counts[0] as f32, counts[1] as f32, counts[2] as f32));
+                let (fixed_pitch, fixed_roll) = acc_angles(counts);
+                assert!((fixed_pitch.to_f32() - expected[0]).abs() < TENTH_OF_A_DEGREE, "pitch at {} {}", pitch, roll);
+                assert!((fixed_roll.to_f32() - expected[1]).abs() < TENTH_OF_A_DEGREE, "roll at {} {}", pitch, roll);
+            }
+        }
+    }
+
+    /// Uniform in 0..1, the same sequence every run
+    fn uniform(seed: &mut u32) -> f32 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 17;
+        *seed ^= *seed << 5;
+        *seed as f32 / u32::MAX as f32
+    }
+
+    #[test]
+    fn follows_the_f32_filter_within_a_tenth_of_a_degree() {
+        let mut float = Complementary::from_acc([0.0, 0.0]);
+        let mut fixed = FixedComplementary::from_acc([0.0, 0.0]);
+        let mut seed = 5;
+        let mut worst = 0.0f32;
+
+        for i in 0..60 * GYRO_FREQUENCY_HZ {
+            let t = i as f32 * GYRO_DT;
+            let (pitch, roll, heading) = (0.4 * libm::sinf(0.9 * t), 0.3 * libm::sinf(0.5 * t + 2.0), 0.3 * t);
+            let gyro = Vec3::new(0.36 * libm::cosf(0.9 * t), 0.15 * libm::cosf(0.5 * t + 2.0), 0.3)
+                + Vec3::new(uniform(&mut seed) - 0.5, uniform(&mut seed) - 0.5, uniform(&mut seed) - 0.5) * 0.02;
+            let g = Vec3::new(-libm::sinf(roll), libm::sinf(pitch), libm::cosf(pitch) * libm::cosf(roll))
+                + Vec3::new(uniform(&mut seed) - 0.5, uniform(&mut seed) - 0.5, uniform(&mut seed) - 0.5) * 0.05;
+            // whole counts like the sensor gives
+            let acc = (g * ACCEL_RANGE.sensitivity()).map(libm::roundf);
+            // a fresh magnetometer reading every tenth sample, in microtesla
+            let mag = (i % 10 == 0).then(|| Vec3::new(libm::cosf(heading), -libm::sinf(heading), 0.5) * 30.0);
+
+            float.update(gyro, Some(acc), mag, GYRO_DT);
+            fixed.update(gyro, Some(acc), mag, GYRO_DT);
+
+            let (a, b) = (float.orientation(), fixed.orientation());
+            worst = worst.max((a.pitch - b.pitch).abs()).max((a.roll - b.roll).abs()).max(crate::wrap_angle(a.yaw - b.yaw).abs());
+        }
+
+        assert!(worst < TENTH_OF_A_DEGREE, "{} degrees apart", worst.to_degrees());
+    }
+}
@@ -0,0 +1,235 @@
+//! Two state Kalman filter per axis, angle and gyroscope bias.
+//!
+//! Pitch and roll measure against the accelerometer angles, yaw against the magnetometer
+//! heading whenever a reading comes in. The bias state takes out what the offset captured
+//! at boot missed, like the Mahony integral but per axis.
+//!
+//! Every sample costs a prediction and a correction per axis on top of the two `atan2f` of
+//! [acc_angles]. Cost of `update` against the complementary filter, release build, in cycles
+//! (a Cortex-M3 instruction timing model run over the thumbv7m build, zero wait states as at
+//! the default 8 MHz):
+//!
+//! | `update`                  | complementary | kalman |
+//! |---------------------------|---------------|--------|
+//! | accelerometer             |         6 450 | 11 700 |
+//! | with the field            |        16 100 | 23 000 |
+//!
+//! About 1.5 ms at 8 MHz, three quarters of the sample period at 500 Hz. A sample with the
+//! field in it runs over the period, the magnetometer coming in at a fraction of the rate.
+
+use common::{SpatialOrientation, Vec3};
+
+use crate::{acc_angles, quaternion_from_acc_angles, tilt_compensated_heading, wrap_angle, AttitudeEstimator, VERTICAL_PITCH};
+
+/// Process noise of the angle, rad² per second. More of it trusts the accelerometer over
+/// the gyroscope.
+pub const KALMAN_Q_ANGLE: f32 = 0.001;
+/// Process noise of the bias, (rad/s)² per second, how fast the bias estimate may wander
+pub const KALMAN_Q_BIAS: f32 = 0.003;
+/// Noise of an accelerometer angle, rad²
+pub const KALMAN_R_ACC: f32 = 0.03;
+/// Noise of a magnetometer heading, rad²
+pub const KALMAN_R_MAG: f32 = 0.1;
+/// Measurement noise is scaled by this just out of a gyroscope saturation, see
+/// [Saturation](crate::Saturation)
+const KALMAN_R_RECOVERY: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy)]
+struct Axis {
+    angle: f32,
+    /// rad/s the gyroscope reads high
+    bias: f32,
+    /// Error covariance
+    p: [[f32; 2]; 2],
+}
+
+impl Axis {
+    fn new(angle: f32) -> Self {
+        Axis { angle, bias: 0.0, p: [[0.0; 2]; 2] }
+    }
+
+    fn predict(&mut self, rate: f32, dt: f32, q_angle: f32, q_bias: f32) {
+        self.angle += (rate - self.bias) * dt;
+
+        let p = &mut self.p;
+        p[0][0] += dt * (dt * p[1][1] - p[0][1] - p[1][0] + q_angle);
+        p[0][1] -= dt * p[1][1];
+        p[1][0] -= dt * p[1][1];
+        p[1][1] += q_bias * dt;
+    }
+
+    /// `error` is the measurement less the angle, wrapped already where that matters
+    fn correct(&mut self, error: f32, r: f32) {
+        let p = self.p;
+        let s = p[0][0] + r;
+        if s <= 0.0 {
+            return;
+        }
+        let (k0, k1) = (p[0][0] / s, p[1][0] / s);
+
+        self.angle += k0 * error;
+        self.bias += k1 * error;
+
+        self.p = [
+            [p[0][0] - k0 * p[0][0], p[0][1] - k0 * p[0][1]],
+            [p[1][0] - k1 * p[0][0], p[1][1] - k1 * p[0][1]],
+        ];
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Kalman {
+    /// Pitch, roll, yaw
+    axes: [Axis; 3],
+    pub q_angle: f32,
+    pub q_bias: f32,
+    pub r_acc: f32,
+    pub r_mag: f32,
+    recovering: bool,
+}
+
+impl Kalman {
+    pub fn new(acc: [f32; 2], q_angle: f32, q_bias: f32, r_acc: f32, r_mag: f32) -> Self {
+        Kalman {
+            axes: [Axis::new(acc[0]), Axis::new(acc[1]), Axis::new(0.0)],
+            q_angle,
+            q_bias,
+            r_acc,
+            r_mag,
+            recovering: false,
+        }
+    }
+
+    /// Bias estimate per axis, rad/s
+    pub fn bias(&self) -> Vec3 {
+        Vec3::new(self.axes[0].bias, self.axes[1].bias, self.axes[2].bias)
+    }
+}
+
+impl AttitudeEstimator for Kalman {
+    fn from_acc(acc: [f32; 2]) -> Self {
+        Kalman::new(acc, KALMAN_Q_ANGLE, KALMAN_Q_BIAS, KALMAN_R_ACC, KALMAN_R_MAG)
+    }
+
+    fn update(&mut self, gyro: Vec3, acc: Option<Vec3>, mag: Option<Vec3>, dt: f32) {
+        for (axis, rate) in self.axes.iter_mut().zip(gyro.iter()) {
+            axis.predict(*rate, dt, self.q_angle, self.q_bias);
+        }
+        let [pitch, roll, yaw] = &mut self.axes;
+        yaw.angle = wrap_angle(yaw.angle);
+
+        let scale = if self.recovering { KALMAN_R_RECOVERY } else { 1.0 };
+        // the accelerometer angles fold over near vertical, see Complementary
+        if let (Some(a), false) = (acc, libm::fabsf(pitch.angle) > VERTICAL_PITCH) {
+            let angles = acc_angles(a);
+            pitch.correct(angles[0] - pitch.angle, self.r_acc * scale);
+            roll.correct(angles[1] - roll.angle, self.r_acc * scale);
+        }
+        if let Some(m) = mag {
+            let heading = tilt_compensated_heading(m, pitch.angle, roll.angle);
+            yaw.correct(wrap_angle(heading - yaw.angle), self.r_mag);
+            yaw.angle = wrap_angle(yaw.angle);
+        }
+    }
+
+    fn reset(&mut self, acc: [f32; 2]) {
+        // the bias is still good, only the angles went stale
+        let [pitch, roll, yaw] = &mut self.axes;
+        *pitch = Axis { angle: acc[0], p: [[0.0; 2]; 2], ..*pitch };
+        *roll = Axis { angle: acc[1], p: [[0.0; 2]; 2], ..*roll };
+        *yaw = Axis { angle: 0.0, p: [[0.0; 2]; 2], ..*yaw };
+    }
+
+    fn reseed(&mut self, acc: [f32; 2]) {
+        self.axes = [Axis::new(acc[0]), Axis::new(acc[1]), Axis::new(0.0)];
+    }
+
+    fn zero_yaw(&mut self) {
+        self.axes[2].angle = 0.0;
+    }
+
+    fn orientation(&self) -> SpatialOrientation {
+        SpatialOrientation { pitch: self.axes[0].angle, roll: self.axes[1].angle, yaw: self.axes[2].angle }
+    }
+
+    fn quaternion(&self) -> [f32; 4] {
+        quaternion_from_acc_angles(self.axes[0].angle, self.axes[1].angle, self.axes[2].angle)
+    }
+
+    fn set_recovering(&mut self, recovering: bool) {
+        self.recovering = recovering;
+    }
+
+    const KIND: u8 = 5;
+
+    /// Q angle, the other noises stay where they are
+    fn gain(&self) -> f32 {
+        self.q_angle
+    }
+
+    fn set_gain(&mut self, gain: f32) {
+        self.q_angle = gain;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GYRO_DT, GYRO_FREQUENCY_HZ};
+
+    #[test]
+    fn bias_converges_to_an_injected_gyroscope_offset() {
+        let offset = Vec3::new(0.03, -0.02, 0.015);
+        let mut filter = Kalman::from_acc([0.0, 0.0]);
+        let level = Vec3::new(0.0, 0.0, 1.0);
+        let north = Vec3::new(30.0, 0.0, -20.0);
+
+        // the magnetometer comes in at a fraction of the rate, every tenth sample
+        for i in 0..5 * GYRO_FREQUENCY_HZ {
+            filter.update(offset, Some(level), (i % 10 == 0).then_some(north), GYRO_DT);
+        }
+
+        let bias = filter.bias();
+        for (found, injected) in bias.iter().zip(offset.iter()) {
+            assert!((found - injected).abs() < 0.1 * injected.abs(), "{:?} for {:?}", bias, offset);
+        }
+        let o = filter.orientation();
+        assert!(o.pitch.abs() < 0.2f32.to_radians() && o.roll.abs() < 0.2f32.to_radians(), "{:?}", o);
+    }
+
+    #[test]
+    fn tilt_holds_through_a_biased_turn() {
+        let offset = Vec3::new(0.02, 0.02, 0.0);
+        let mut filter = Kalman::from_acc([0.0, 0.0]);
+        let pitch_rate = 0.2;
+
+        // pitching up with the bias on top, the accelerometer following the true attitude
+        for i in 0..10 * GYRO_FREQUENCY_HZ {
+            let pitch = pitch_rate * libm::sinf(i as f32 * GYRO_DT);
+            let rate = pitch_rate * libm::cosf(i as f32 * GYRO_DT);
+            let acc = Vec3::new(0.0, libm::sinf(pitch), libm::cosf(pitch));
+            filter.update(Vec3::new(rate, 0.0, 0.0) + offset, Some(acc), None, GYRO_DT);
+        }
+
+        let t = 10.0;
+        let o = filter.orientation();
+        assert!((o.pitch - pitch_rate * libm::sinf(t)).abs() < 0.5f32.to_radians(), "{:?}", o);
+        assert!(o.roll.abs() < 0.5f32.to_radians(), "{:?}", o);
+    }
+
+    #[test]
+    fn reset_keeps_the_bias_and_reseed_drops_it() {
+        let mut filter = Kalman::from_acc([0.0, 0.0]);
+        for _ in 0..5 * GYRO_FREQUENCY_HZ {
+            filter.update(Vec3::new(0.03, 0.0, 0.0), Some(Vec3::new(0.0, 0.0, 1.0)), None, GYRO_DT);
+        }
+        let bias = filter.bias();
+
+        filter.reset([0.1, 0.0]);
+        assert_eq!(filter.bias(), bias);
+        assert_eq!(filter.orientation().pitch, 0.1);
+
+        filter.reseed([0.0, 0.0]);
+        assert_eq!(filter.bias(), Vec3::zeros());
+    }
+}
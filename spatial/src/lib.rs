@@ -0,0 +1,1294 @@
+//! Sensor ranges, filters and attitude estimation, without anything tied to the MCU.
+//! The firmware re-exports all of it as its `spatial` module.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use common::{SpatialOrientation, Vec3};
+
+pub mod fixed;
+pub mod kalman;
+pub mod madgwick;
+pub mod mahony;
+pub mod vertical;
+
+/// Sensor output rate, SMPLRT_DIV, the integration step and the filters all follow it
+pub const GYRO_FREQUENCY_HZ: u32 = 500;
+pub const GYRO_DT: f32 = 1.0 / GYRO_FREQUENCY_HZ as f32;
+
+/// Orientation frames per second, the link can't carry one per sample
+pub const TELEMETRY_FREQUENCY_HZ: u32 = 50;
+
+const _: () = assert!(GYRO_FREQUENCY_HZ.is_multiple_of(TELEMETRY_FREQUENCY_HZ));
+
+/// Measured sample intervals outside of these are a glitch in the timing, not a real step
+pub const MIN_DT: f32 = GYRO_DT / 4.0;
+pub const MAX_DT: f32 = GYRO_DT * 4.0;
+
+pub const GYRO_RANGE: GyroRange = GyroRange::Dps500;
+pub const ACCEL_RANGE: AccelRange = AccelRange::G8;
+
+/// How the sensor sits on the frame
+pub const MOUNTING: Mounting = Mounting::Cw0;
+
+/// Raw accelerometer reading above which an axis is considered clipped
+pub const ACCEL_SATURATION_LSB: f32 = 32000.0;
+/// Raw gyroscope reading within 1% of full scale
+pub const GYRO_SATURATION_LSB: f32 = 32440.0;
+/// Rates below this on every axis count as settled after a saturation
+pub const GYRO_SETTLED_DPS: f32 = 30.0;
+/// Rates below this on every axis count as still enough to re-seed the attitude on arming
+pub const REARM_STILL_DPS: f32 = 5.0;
+/// Settled samples the accelerometer gets the larger weight for after a saturation
+pub const RECOVERY_SAMPLES: u32 = GYRO_FREQUENCY_HZ / 2;
+
+/// Tilt of the frame Z axis from vertical past which the craft is down, the stored
+/// settings can override it
+pub const CRASH_TILT: f32 = 70.0 * core::f32::consts::PI / 180.0;
+/// Samples the tilt has to last, 20 ms. Leaves most of the 100 ms a flipped craft gets to
+/// stop its motor.
+pub const CRASH_TILT_SAMPLES: u32 = GYRO_FREQUENCY_HZ / 50;
+/// Acceleration magnitude of an impact in g, flying stays well clear of it
+pub const CRASH_IMPACT_G: f32 = 3.0;
+/// Rates below this on every axis after an impact mean the craft is lying on the ground
+pub const CRASH_STILL_DPS: f32 = 20.0;
+/// Still samples in a row it takes after an impact, 50 ms
+pub const CRASH_STILL_SAMPLES: u32 = GYRO_FREQUENCY_HZ / 20;
+/// Samples an impact has to come to rest in, 1 s, a bounce the craft flies out of doesn't
+pub const CRASH_SETTLE_SAMPLES: u32 = GYRO_FREQUENCY_HZ;
+
+/// Accelerometer share of every complementary filter step
+pub const ACC_WEIGHT: f32 = 0.04;
+/// Vibration on the accelerometer angles is cut above this, the lag it adds is why
+/// [ACC_WEIGHT] can't go much higher
+pub const ACC_LOWPASS_HZ: f32 = 5.0;
+/// Pulls out the integration error a saturation left behind within a fraction of a second
+const ACC_WEIGHT_RECOVERY: f32 = 0.2;
+
+/// Notch center with the motors idle and at full throttle, linear in between.
+/// Has to stay below half of [GYRO_FREQUENCY_HZ].
+pub const NOTCH_MIN_HZ: f32 = 40.0;
+pub const NOTCH_MAX_HZ: f32 = 110.0;
+pub const NOTCH_Q: f32 = 3.0;
+
+const _: () = assert!(NOTCH_MAX_HZ < GYRO_FREQUENCY_HZ as f32 / 2.0);
+/// Throttle change that moves the notch, sin and cos are too slow without an FPU to run per sample
+pub const NOTCH_RETUNE_THROTTLE: f32 = 0.05;
+
+/// Pitch past which the complementary filter stops trusting its roll: with gravity along Y the
+/// accelerometer roll is a ratio of two small readings, and its gyro roll rate no longer
+/// matches the sensor Y rate. The quaternion estimators don't need it.
+pub const VERTICAL_PITCH: f32 = 80.0 * core::f32::consts::PI / 180.0;
+/// Fastest roll the complementary filter integrates while past [VERTICAL_PITCH], rad/s
+pub const VERTICAL_MAX_ROLL_RATE: f32 = 1.0;
+
+/// Pull of a single magnetometer reading on yaw, mag samples arrive at a fraction of the gyro rate
+pub const MAG_WEIGHT: f32 = 0.02;
+
+/// Gyroscope full scale range, FS_SEL field of GYRO_CONFIG
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GyroRange {
+    Dps250 = 0,
+    Dps500 = 1,
+    Dps1000 = 2,
+    Dps2000 = 3,
+}
+
+impl GyroRange {
+    pub fn dps(&self) -> u32 {
+        match self {
+            GyroRange::Dps250 => 250,
+            GyroRange::Dps500 => 500,
+            GyroRange::Dps1000 => 1000,
+            GyroRange::Dps2000 => 2000,
+        }
+    }
+
+    /// LSB per degree per second
+    pub fn sensitivity(&self) -> f32 {
+        match self {
+            GyroRange::Dps250 => 131.0,
+            GyroRange::Dps500 => 65.5,
+            GyroRange::Dps1000 => 32.8,
+            GyroRange::Dps2000 => 16.4,
+        }
+    }
+
+    pub fn rad_per_lsb(&self) -> f32 {
+        core::f32::consts::PI / 180.0 / self.sensitivity()
+    }
+
+    /// Counts read at this range as they would read at `to`
+    pub fn rescale(&self, counts: Vec3, to: GyroRange) -> Vec3 {
+        counts * (to.sensitivity() / self.sensitivity())
+    }
+}
+
+/// Accelerometer full scale range, AFS_SEL field of ACCEL_CONFIG
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccelRange {
+    G2 = 0,
+    G4 = 1,
+    G8 = 2,
+    G16 = 3,
+}
+
+impl AccelRange {
+    pub fn g(&self) -> u32 {
+        match self {
+            AccelRange::G2 => 2,
+            AccelRange::G4 => 4,
+            AccelRange::G8 => 8,
+            AccelRange::G16 => 16,
+        }
+    }
+
+    /// LSB per g
+    pub fn sensitivity(&self) -> f32 {
+        match self {
+            AccelRange::G2 => 16384.0,
+            AccelRange::G4 => 8192.0,
+            AccelRange::G8 => 4096.0,
+            AccelRange::G16 => 2048.0,
+        }
+    }
+}
+
+/// Sensor orientation relative to the frame: optionally upside down (turned about its own X),
+/// then rotated clockwise seen from above. Fusion and telemetry only see frame axes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mounting {
+    Cw0,
+    Cw90,
+    Cw180,
+    Cw270,
+    Cw0Flip,
+    Cw90Flip,
+    Cw180Flip,
+    Cw270Flip,
+}
+
+impl Mounting {
+    /// Sensor axes into frame axes, a sign permutation so offsets can go through it as well
+    pub fn apply(&self, v: Vec3) -> Vec3 {
+        let (cw, flip) = match self {
+            Mounting::Cw0 => (0, false),
+            Mounting::Cw90 => (90, false),
+            Mounting::Cw180 => (180, false),
+            Mounting::Cw270 => (270, false),
+            Mounting::Cw0Flip => (0, true),
+            Mounting::Cw90Flip => (90, true),
+            Mounting::Cw180Flip => (180, true),
+            Mounting::Cw270Flip => (270, true),
+        };
+
+        let v = if flip { Vec3::new(v.x, -v.y, -v.z) } else { v };
+        match cw {
+            90 => Vec3::new(v.y, -v.x, v.z),
+            180 => Vec3::new(-v.x, -v.y, v.z),
+            270 => Vec3::new(-v.y, v.x, v.z),
+            _ => v,
+        }
+    }
+}
+
+/// Per axis accelerometer correction, see `calibration::SixPosition`
+#[derive(Debug, Clone, Copy)]
+pub struct AccelCalibration {
+    /// Counts read at zero g
+    pub offset: Vec3,
+    /// Measured over nominal sensitivity
+    pub scale: Vec3,
+}
+
+impl AccelCalibration {
+    pub fn identity() -> Self {
+        AccelCalibration { offset: Vec3::zeros(), scale: Vec3::new(1.0, 1.0, 1.0) }
+    }
+
+    /// Raw counts into counts at the nominal sensitivity
+    pub fn apply(&self, acc: Vec3) -> Vec3 {
+        (acc - self.offset).component_div(&self.scale)
+    }
+}
+
+/// Hard and soft iron correction, see `calibration::MagSweep`
+#[derive(Debug, Clone, Copy)]
+pub struct MagCalibration {
+    /// Field in microtesla the craft itself adds, center of the sphere readings lie on
+    pub offset: Vec3,
+    /// Radius along each axis over the mean radius
+    pub scale: Vec3,
+}
+
+impl MagCalibration {
+    pub fn identity() -> Self {
+        MagCalibration { offset: Vec3::zeros(), scale: Vec3::new(1.0, 1.0, 1.0) }
+    }
+
+    pub fn apply(&self, field: Vec3) -> Vec3 {
+        (field - self.offset).component_div(&self.scale)
+    }
+}
+
+/// Angles of the gravity vector, same order as `Mpu6050::get_acc_angles`.
+/// Only ratios between axes matter so `acc` can be raw counts of any range.
+pub fn acc_angles(acc: Vec3) -> [f32; 2] {
+    let a = libm::atan2f(acc.y, libm::sqrtf(acc.x * acc.x + acc.z * acc.z));
+    let b = -libm::atan2f(acc.x, libm::sqrtf(acc.y * acc.y + acc.z * acc.z));
+
+    [a, b]
+}
+
+/// `acc` in raw sensor counts
+pub fn acc_saturated(acc: Vec3) -> bool {
+    acc.iter().any(|a| libm::fabsf(*a) >= ACCEL_SATURATION_LSB)
+}
+
+/// `gyro` in raw sensor counts
+pub fn gyro_saturated(gyro: Vec3) -> bool {
+    gyro.iter().any(|g| libm::fabsf(*g) >= GYRO_SATURATION_LSB)
+}
+
+/// Follows a gyroscope saturation until the accelerometer had time to correct the attitude
+#[derive(Debug, Clone, Copy)]
+pub struct Saturation {
+    /// Saturations so far, a run of saturated samples counts once
+    pub count: u32,
+    /// Settled samples left until recovered
+    remaining: Option<u32>,
+    settled: bool,
+}
+
+impl Saturation {
+    pub fn new() -> Self {
+        Saturation { count: 0, remaining: None, settled: true }
+    }
+
+    /// Raw gyroscope counts at `range`, `offset` as used for fusion
+    pub fn update(&mut self, gyro: Vec3, offset: Vec3, range: GyroRange) {
+        if gyro_saturated(gyro) {
+            if self.remaining.is_none() {
+                self.count += 1;
+            }
+            self.remaining = Some(RECOVERY_SAMPLES);
+            self.settled = false;
+            return;
+        }
+
+        let limit = GYRO_SETTLED_DPS * range.sensitivity();
+        self.settled = (gyro - offset).iter().all(|g| libm::fabsf(*g) < limit);
+
+        if let (true, Some(remaining)) = (self.settled, self.remaining) {
+            self.remaining = if remaining > 1 { Some(remaining - 1) } else { None };
+        }
+    }
+
+    /// Saturated, or not recovered from it yet
+    pub fn saturated(&self) -> bool {
+        self.remaining.is_some()
+    }
+
+    /// Rates settled after a saturation, the accelerometer should take over
+    pub fn recovering(&self) -> bool {
+        self.remaining.is_some() && self.settled
+    }
+}
+
+impl Default for Saturation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Tells when the craft is on the ground: tipped past the crash tilt, or hit by an impact
+/// and still right after. Fires once per crash, the caller latches it.
+#[derive(Debug, Clone, Copy)]
+pub struct CrashDetector {
+    tilt: f32,
+    cos_tilt: f32,
+    /// Samples in a row past the tilt
+    tilted: u32,
+    /// Samples since the impact and still samples in a row among them
+    impact: Option<(u32, u32)>,
+}
+
+impl CrashDetector {
+    pub fn new(tilt: f32) -> Self {
+        CrashDetector { tilt, cos_tilt: libm::cosf(tilt), tilted: 0, impact: None }
+    }
+
+    /// Tilt from vertical in radians
+    pub fn tilt(&self) -> f32 {
+        self.tilt
+    }
+
+    pub fn set_tilt(&mut self, tilt: f32) {
+        *self = CrashDetector::new(tilt);
+    }
+
+    /// Once a sample, `acc` in g and `gyro` in rad/s
+    pub fn sample(&mut self, acc: Vec3, gyro: Vec3) -> bool {
+        if acc.dot(&acc) > CRASH_IMPACT_G * CRASH_IMPACT_G {
+            self.impact = Some((0, 0));
+            return false;
+        }
+
+        let limit = CRASH_STILL_DPS * core::f32::consts::PI / 180.0;
+        let still = gyro.iter().all(|g| libm::fabsf(*g) < limit);
+        self.impact = match self.impact {
+            Some((since, _)) if since >= CRASH_SETTLE_SAMPLES => None,
+            Some((since, run)) => Some((since + 1, if still { run + 1 } else { 0 })),
+            None => None,
+        };
+
+        let crashed = matches!(self.impact, Some((_, run)) if run >= CRASH_STILL_SAMPLES);
+        if crashed {
+            self.impact = None;
+        }
+        crashed
+    }
+
+    /// Once a batch of `samples` with the attitude at its end
+    pub fn attitude(&mut self, q: [f32; 4], samples: u32) -> bool {
+        let [_, x, y, _] = q;
+        // frame Z in earth axes, its vertical component
+        let up = 1.0 - 2.0 * (x * x + y * y);
+        self.tilted = if up < self.cos_tilt { self.tilted + samples } else { 0 };
+
+        let crashed = self.tilted >= CRASH_TILT_SAMPLES;
+        if crashed {
+            self.tilted = 0;
+        }
+        crashed
+    }
+}
+
+/// Heading of the horizontal field, counter clockwise like the gyroscope Z axis.
+/// Only valid while level, see [tilt_compensated_heading].
+pub fn mag_heading(field: Vec3) -> f32 {
+    libm::atan2f(-field.y, field.x)
+}
+
+/// [mag_heading] of the field turned back level by `pitch` and `roll` as [acc_angles] measures
+/// them. Tilting puts part of the vertical field on X and Y, at 30° of pitch and roll under
+/// a 60° dip [mag_heading] gets anything up to a half turn wrong depending on the heading.
+pub fn tilt_compensated_heading(field: Vec3, pitch: f32, roll: f32) -> f32 {
+    // the field less its vertical part and the horizontal direction across it, both in sensor
+    // axes: their X components are the heading's cosine and sine. No rotation to build.
+    let up = gravity_from_acc_angles(pitch, roll);
+    let along = field - up * field.dot(&up);
+    let across = up.cross(&along);
+    libm::atan2f(across.x, along.x)
+}
+
+/// Into -pi..pi
+pub fn wrap_angle(angle: f32) -> f32 {
+    use core::f32::consts::PI;
+
+    let wrapped = libm::remainderf(angle, 2.0 * PI);
+    if wrapped <= -PI { wrapped + 2.0 * PI } else { wrapped }
+}
+
+/// Time step for a measured sample interval in seconds, the nominal one until there is one.
+/// Also tells whether the interval had to be clamped.
+pub fn sample_dt(measured: Option<f32>) -> (f32, bool) {
+    match measured {
+        None => (GYRO_DT, false),
+        Some(dt) if (MIN_DT..=MAX_DT).contains(&dt) => (dt, false),
+        // NaN ends up at MIN_DT
+        Some(dt) => (if dt > MAX_DT { MAX_DT } else { MIN_DT }, true),
+    }
+}
+
+/// Biquad notch on each gyro axis (RBJ cookbook, direct form I), unity gain elsewhere
+/// so raw counts with the offset still in them pass through as they are
+#[derive(Debug, Clone, Copy)]
+pub struct Notch {
+    throttle: f32,
+    /// b2 is the same as b0
+    b0: f32,
+    b1: f32,
+    a1: f32,
+    a2: f32,
+    x1: Vec3,
+    x2: Vec3,
+    y1: Vec3,
+    y2: Vec3,
+    /// History holds real samples, starting from zeros would ring on the offset
+    primed: bool,
+}
+
+impl Notch {
+    pub fn new() -> Self {
+        let mut notch = Notch {
+            throttle: 0.0,
+            b0: 1.0,
+            b1: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+            x1: Vec3::zeros(),
+            x2: Vec3::zeros(),
+            y1: Vec3::zeros(),
+            y2: Vec3::zeros(),
+            primed: false,
+        };
+        notch.tune(0.0);
+        notch
+    }
+
+    /// Follows commanded `throttle` from 0 to 1 once it moved by [NOTCH_RETUNE_THROTTLE]
+    pub fn retune(&mut self, throttle: f32) {
+        if libm::fabsf(throttle - self.throttle) >= NOTCH_RETUNE_THROTTLE {
+            self.tune(throttle);
+        }
+    }
+
+    pub fn center_hz(&self) -> f32 {
+        NOTCH_MIN_HZ + (NOTCH_MAX_HZ - NOTCH_MIN_HZ) * self.throttle
+    }
+
+    /// Starts over from the next sample, after samples were lost
+    pub fn reset(&mut self) {
+        self.primed = false;
+    }
+
+    pub fn apply(&mut self, x: Vec3) -> Vec3 {
+        if !self.primed {
+            self.x1 = x;
+            self.x2 = x;
+            self.y1 = x;
+            self.y2 = x;
+            self.primed = true;
+        }
+
+        let y = (x + self.x2) * self.b0 + self.x1 * self.b1 - self.y1 * self.a1 - self.y2 * self.a2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+
+    fn tune(&mut self, throttle: f32) {
+        self.throttle = throttle.clamp(0.0, 1.0);
+
+        let w0 = 2.0 * core::f32::consts::PI * self.center_hz() / GYRO_FREQUENCY_HZ as f32;
+        let alpha = libm::sinf(w0) / (2.0 * NOTCH_Q);
+        let a0 = 1.0 + alpha;
+
+        self.b0 = 1.0 / a0;
+        self.b1 = -2.0 * libm::cosf(w0) / a0;
+        self.a1 = self.b1;
+        self.a2 = (1.0 - alpha) / a0;
+    }
+}
+
+impl Default for Notch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// First order low-pass on a pair of angles, follows the measured sample interval
+#[derive(Debug, Clone, Copy)]
+pub struct LowPass {
+    /// RC time constant in seconds
+    rc: f32,
+    state: Option<[f32; 2]>,
+}
+
+impl LowPass {
+    pub fn new(cutoff_hz: f32) -> Self {
+        LowPass { rc: 1.0 / (2.0 * core::f32::consts::PI * cutoff_hz), state: None }
+    }
+
+    pub fn apply(&mut self, x: [f32; 2], dt: f32) -> [f32; 2] {
+        let alpha = dt / (self.rc + dt);
+        let y = match self.state {
+            Some(y) => [y[0] + (x[0] - y[0]) * alpha, y[1] + (x[1] - y[1]) * alpha],
+            None => x,
+        };
+        self.state = Some(y);
+        y
+    }
+
+    /// Starts over from the next input
+    pub fn reset(&mut self) {
+        self.state = None;
+    }
+}
+
+/// Attitude from one sample at a time. The gyro task only sees [Estimator], which one
+/// it gets picked at build time by the `complementary`, `mahony`, `fixed` and `kalman`
+/// features.
+pub trait AttitudeEstimator {
+    /// Starting from the accelerometer angles of a resting craft, with the default tuning
+    fn from_acc(acc: [f32; 2]) -> Self
+    where
+        Self: Sized;
+
+    /// `gyro` in rad/s with the offset taken out, `acc` and `mag` in frame axes and any unit.
+    /// `acc` is None while saturated, `mag` only set for a fresh magnetometer reading.
+    /// `dt` is the time since the previous sample in seconds, see [sample_dt].
+    fn update(&mut self, gyro: Vec3, acc: Option<Vec3>, mag: Option<Vec3>, dt: f32);
+
+    /// Starts over from accelerometer angles after samples were lost, yaw from zero
+    fn reset(&mut self, acc: [f32; 2]);
+
+    /// [reset](AttitudeEstimator::reset) that also forgets what was learned along the way,
+    /// for arming a craft that may have been carried around
+    fn reseed(&mut self, acc: [f32; 2]) {
+        self.reset(acc);
+    }
+
+    /// Current heading becomes yaw zero, pitch and roll stay
+    fn zero_yaw(&mut self);
+
+    fn orientation(&self) -> SpatialOrientation;
+
+    /// Same rotation as [orientation](AttitudeEstimator::orientation), w, x, y, z
+    fn quaternion(&self) -> [f32; 4];
+
+    /// `acc` in g and frame axes into earth axes with Z up, less one g of gravity.
+    /// Near zero at rest whatever the tilt, as far as the attitude is right.
+    fn linear_acceleration(&self, acc: Vec3) -> Vec3 {
+        rotate_to_earth(self.quaternion(), acc) - Vec3::new(0.0, 0.0, 1.0)
+    }
+
+    /// Just out of a gyroscope saturation the accelerometer gets more weight, see [Saturation]
+    fn set_recovering(&mut self, recovering: bool);
+
+    /// Stored along with the tuning, so another estimator doesn't pick it up
+    const KIND: u8;
+
+    /// Pull of the accelerometer correction, see [valid_gain]
+    fn gain(&self) -> f32;
+    fn set_gain(&mut self, gain: f32);
+
+    /// Accelerometer low-pass, None for estimators without one. See [valid_acc_cutoff].
+    fn acc_cutoff_hz(&self) -> Option<f32> {
+        None
+    }
+    fn set_acc_cutoff_hz(&mut self, _hz: f32) {}
+}
+
+/// Gains are shares of the correction per sample or per rad/s of error, both unstable past 1
+pub fn valid_gain(gain: f32) -> bool {
+    (0.0..=1.0).contains(&gain)
+}
+
+/// Anything under 30° trips on hard manoeuvres, past 150° only upside down is left
+pub fn valid_crash_tilt(tilt: f32) -> bool {
+    (30.0 * core::f32::consts::PI / 180.0..=150.0 * core::f32::consts::PI / 180.0).contains(&tilt)
+}
+
+/// Cutoffs at or above half the sample rate do nothing useful
+pub fn valid_acc_cutoff(hz: f32) -> bool {
+    hz.is_finite() && hz > 0.0 && hz < GYRO_FREQUENCY_HZ as f32 / 2.0
+}
+
+#[cfg(any(
+    all(feature = "complementary", any(feature = "mahony", feature = "fixed", feature = "kalman")),
+    all(feature = "mahony", any(feature = "fixed", feature = "kalman")),
+    all(feature = "fixed", feature = "kalman"),
+))]
+compile_error!("pick one of the complementary, mahony, fixed and kalman features");
+
+#[cfg(not(any(feature = "complementary", feature = "mahony", feature = "fixed", feature = "kalman")))]
+pub type Estimator = crate::madgwick::Madgwick;
+#[cfg(feature = "complementary")]
+pub type Estimator = Complementary;
+#[cfg(feature = "mahony")]
+pub type Estimator = crate::mahony::Mahony;
+#[cfg(feature = "fixed")]
+pub type Estimator = crate::fixed::FixedComplementary;
+#[cfg(feature = "kalman")]
+pub type Estimator = crate::kalman::Kalman;
+
+/// Gyro integration pulled towards the accelerometer angles, yaw towards the magnetometer
+/// heading. Pitch and roll integrate independently so it only holds up for gentle motion,
+/// and roll is held close to where it was while the craft is near vertical, see [VERTICAL_PITCH].
+#[derive(Debug, Clone, Copy)]
+pub struct Complementary {
+    /// Pitch, roll, yaw
+    angles: Vec3,
+    acc: LowPass,
+    acc_cutoff_hz: f32,
+    pub acc_weight: f32,
+    recovering: bool,
+}
+
+impl Complementary {
+    pub fn new(acc: [f32; 2], acc_cutoff_hz: f32, acc_weight: f32) -> Self {
+        Complementary {
+            angles: Vec3::new(acc[0], acc[1], 0.0),
+            acc: LowPass::new(acc_cutoff_hz),
+            acc_cutoff_hz,
+            acc_weight,
+            recovering: false,
+        }
+    }
+}
+
+impl AttitudeEstimator for Complementary {
+    fn from_acc(acc: [f32; 2]) -> Self {
+        Complementary::new(acc, ACC_LOWPASS_HZ, ACC_WEIGHT)
+    }
+
+    fn update(&mut self, gyro: Vec3, acc: Option<Vec3>, mag: Option<Vec3>, dt: f32) {
+        // clipped readings stay out of the low-pass as well
+        let (acc, acc_weight) = match (acc, self.recovering) {
+            (None, _) => ([0.0; 2], 0.0),
+            (Some(a), true) => (self.acc.apply(acc_angles(a), dt), ACC_WEIGHT_RECOVERY),
+            (Some(a), false) => (self.acc.apply(acc_angles(a), dt), self.acc_weight),
+        };
+
+        // the accelerometer pitch folds back at +-90, neither angle has a reference up there
+        let vertical = libm::fabsf(self.angles.x) > VERTICAL_PITCH;
+        let (acc_weight, roll_rate) = if vertical {
+            (0.0, gyro.y.clamp(-VERTICAL_MAX_ROLL_RATE, VERTICAL_MAX_ROLL_RATE))
+        } else {
+            (acc_weight, gyro.y)
+        };
+
+        let new_pitch = self.angles.x + gyro.x * dt;
+        let new_roll = self.angles.y + roll_rate * dt;
+
+        self.angles.x = new_pitch * (1.0 - acc_weight) + acc[0] * acc_weight;
+        self.angles.y = new_roll * (1.0 - acc_weight) + acc[1] * acc_weight;
+
+        // blend on the error so the correction doesn't go the long way around at +-pi
+        let new_yaw = self.angles.z + gyro.z * dt;
+        let heading = |m| tilt_compensated_heading(m, self.angles.x, self.angles.y);
+        let mag_error = mag.map(|m| wrap_angle(heading(m) - new_yaw)).unwrap_or(0.0);
+        self.angles.z = wrap_angle(new_yaw + mag_error * MAG_WEIGHT);
+    }
+
+    fn reset(&mut self, acc: [f32; 2]) {
+        self.angles = Vec3::new(acc[0], acc[1], 0.0);
+        self.acc.reset();
+    }
+
+    fn zero_yaw(&mut self) {
+        self.angles.z = 0.0;
+    }
+
+    fn orientation(&self) -> SpatialOrientation {
+        SpatialOrientation { pitch: self.angles.x, roll: self.angles.y, yaw: self.angles.z }
+    }
+
+    fn quaternion(&self) -> [f32; 4] {
+        quaternion_from_acc_angles(self.angles.x, self.angles.y, self.angles.z)
+    }
+
+    fn set_recovering(&mut self, recovering: bool) {
+        self.recovering = recovering;
+    }
+
+    const KIND: u8 = 1;
+
+    fn gain(&self) -> f32 {
+        self.acc_weight
+    }
+
+    fn set_gain(&mut self, gain: f32) {
+        self.acc_weight = gain;
+    }
+
+    fn acc_cutoff_hz(&self) -> Option<f32> {
+        Some(self.acc_cutoff_hz)
+    }
+
+    fn set_acc_cutoff_hz(&mut self, hz: f32) {
+        // the filtered angles start over from the next sample
+        self.acc = LowPass::new(hz);
+        self.acc_cutoff_hz = hz;
+    }
+}
+
+/// Unit quaternion w, x, y, z for [SpatialOrientation] angles: yaw about Z, then roll
+/// about Y, then pitch about X
+pub fn quaternion_from_angles(pitch: f32, roll: f32, yaw: f32) -> [f32; 4] {
+    let (sp, cp) = (libm::sinf(pitch / 2.0), libm::cosf(pitch / 2.0));
+    let (sr, cr) = (libm::sinf(roll / 2.0), libm::cosf(roll / 2.0));
+    let (sy, cy) = (libm::sinf(yaw / 2.0), libm::cosf(yaw / 2.0));
+
+    [
+        cp * cr * cy + sp * sr * sy,
+        sp * cr * cy - cp * sr * sy,
+        cp * sr * cy + sp * cr * sy,
+        cp * cr * sy - sp * sr * cy,
+    ]
+}
+
+/// [quaternion_from_angles] for a pitch measured like [acc_angles] does, against the plane
+/// the board's X axis and gravity span rather than about X after the roll. The two only
+/// agree while one of them is zero.
+pub fn quaternion_from_acc_angles(pitch: f32, roll: f32, yaw: f32) -> [f32; 4] {
+    let up = gravity_from_acc_angles(pitch, roll);
+    quaternion_from_angles(libm::atan2f(up.y, up.z), roll, yaw)
+}
+
+/// Unit accelerometer reading at rest for [acc_angles] `pitch` and `roll`
+fn gravity_from_acc_angles(pitch: f32, roll: f32) -> Vec3 {
+    let (sin_pitch, sin_roll) = (libm::sinf(pitch), libm::sinf(roll));
+    // negative along Z once the integrated pitch went past vertical
+    let z = libm::sqrtf((1.0 - sin_pitch * sin_pitch - sin_roll * sin_roll).max(0.0));
+    let z = if libm::cosf(pitch) < 0.0 { -z } else { z };
+
+    Vec3::new(-sin_roll, sin_pitch, z)
+}
+
+/// Angles in the same order as [acc_angles] for a quaternion whose gravity in sensor axes is
+/// `(2(xz - wy), 2(wx + yz), w² - x² - y² + z²)`, the DMP's convention
+pub fn quaternion_angles(q: [f32; 4]) -> SpatialOrientation {
+    let [w, x, y, z] = q;
+    let sin_roll = (2.0 * (w * y - x * z)).clamp(-1.0, 1.0);
+
+    SpatialOrientation {
+        pitch: libm::atan2f(2.0 * (w * x + y * z), 1.0 - 2.0 * (x * x + y * y)),
+        roll: libm::asinf(sin_roll),
+        yaw: libm::atan2f(2.0 * (w * z + x * y), 1.0 - 2.0 * (y * y + z * z)),
+    }
+}
+
+/// Turns `q` back about the earth Z axis by its yaw, exact at any pitch and roll
+pub fn quaternion_without_yaw(q: [f32; 4]) -> [f32; 4] {
+    let [w, x, y, z] = q;
+    let yaw = libm::atan2f(2.0 * (w * z + x * y), 1.0 - 2.0 * (y * y + z * z));
+    let (s, c) = (libm::sinf(-yaw / 2.0), libm::cosf(-yaw / 2.0));
+
+    // (c, 0, 0, s) * q
+    [c * w - s * z, c * x - s * y, c * y + s * x, c * z + s * w]
+}
+
+/// Frame axes into earth axes for the attitude `q`
+pub fn rotate_to_earth(q: [f32; 4], v: Vec3) -> Vec3 {
+    let [w, x, y, z] = q;
+    Vec3::new(
+        (1.0 - 2.0 * (y * y + z * z)) * v.x + 2.0 * (x * y - w * z) * v.y + 2.0 * (x * z + w * y) * v.z,
+        2.0 * (x * y + w * z) * v.x + (1.0 - 2.0 * (x * x + z * z)) * v.y + 2.0 * (y * z - w * x) * v.z,
+        2.0 * (x * z - w * y) * v.x + 2.0 * (y * z + w * x) * v.y + (1.0 - 2.0 * (x * x + y * y)) * v.z,
+    )
+}
+
+/// Rate of change of `q` turning at `gyro` rad/s, q * (0, gyro) / 2
+pub fn quaternion_rate(q: [f32; 4], gyro: Vec3) -> [f32; 4] {
+    let [q0, q1, q2, q3] = q;
+    [
+        0.5 * (-q1 * gyro.x - q2 * gyro.y - q3 * gyro.z),
+        0.5 * (q0 * gyro.x + q2 * gyro.z - q3 * gyro.y),
+        0.5 * (q0 * gyro.y - q1 * gyro.z + q3 * gyro.x),
+        0.5 * (q0 * gyro.z + q1 * gyro.y - q2 * gyro.x),
+    ]
+}
+
+/// One Euler step of `rate` and back onto unit length, `q` stays as it is should it collapse
+pub fn integrate_quaternion(q: &mut [f32; 4], rate: [f32; 4], dt: f32) {
+    let mut next = [q[0] + rate[0] * dt, q[1] + rate[1] * dt, q[2] + rate[2] * dt, q[3] + rate[3] * dt];
+    let norm = libm::sqrtf(next.iter().map(|v| v * v).sum());
+    if norm > 0.0 {
+        next.iter_mut().for_each(|v| *v /= norm);
+        *q = next;
+    }
+}
+
+pub fn normalized(v: Vec3) -> Option<Vec3> {
+    let norm = libm::sqrtf(v.x * v.x + v.y * v.y + v.z * v.z);
+    if norm > 0.0 { Some(v / norm) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::madgwick::Madgwick;
+    use crate::mahony::Mahony;
+
+    const RANGES: [GyroRange; 4] = [GyroRange::Dps250, GyroRange::Dps500, GyroRange::Dps1000, GyroRange::Dps2000];
+
+    /// Still and tilted by `pitch` about X, in raw counts
+    fn tilted(pitch: f32) -> Vec3 {
+        Vec3::new(0.0, libm::sinf(pitch), libm::cosf(pitch)) * ACCEL_RANGE.sensitivity()
+    }
+
+    /// A second of pitching at `dps`, in the counts `range` reports it in.
+    /// The accelerometer is left out, its low-pass lags behind a rotation.
+    fn pitch_for_a_second(range: GyroRange, dps: f32) -> SpatialOrientation {
+        let mut filter = Complementary::new([0.0, 0.0], ACC_LOWPASS_HZ, 0.0);
+        for i in 1..=GYRO_FREQUENCY_HZ {
+            let counts = Vec3::new(dps * range.sensitivity(), 0.0, 0.0);
+            let acc = tilted((dps * i as f32 * GYRO_DT).to_radians());
+            filter.update(counts * range.rad_per_lsb(), Some(acc), None, GYRO_DT);
+        }
+        filter.orientation()
+    }
+
+    #[test]
+    fn same_rotation_same_angle_at_every_range() {
+        for range in RANGES.iter() {
+            let s = pitch_for_a_second(*range, 60.0);
+            assert!((s.pitch - 60f32.to_radians()).abs() < 1e-3, "{:?} pitch {}", range, s.pitch);
+            assert!(s.roll.abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn counts_scale_with_the_range() {
+        for pair in RANGES.windows(2) {
+            let ratio = pair[1].rad_per_lsb() / pair[0].rad_per_lsb();
+            assert!((ratio - 2.0).abs() < 0.01, "{:?} to {:?} is {}", pair[0], pair[1], ratio);
+        }
+    }
+
+    const MOUNTINGS: [Mounting; 8] = [
+        Mounting::Cw0,
+        Mounting::Cw90,
+        Mounting::Cw180,
+        Mounting::Cw270,
+        Mounting::Cw0Flip,
+        Mounting::Cw90Flip,
+        Mounting::Cw180Flip,
+        Mounting::Cw270Flip,
+    ];
+
+    /// What a sensor mounted as `mounting` reads for `v` in frame axes, the inverse of
+    /// [Mounting::apply] is its transpose
+    fn in_sensor_axes(mounting: Mounting, v: Vec3) -> Vec3 {
+        let x = mounting.apply(Vec3::new(1.0, 0.0, 0.0));
+        let y = mounting.apply(Vec3::new(0.0, 1.0, 0.0));
+        let z = mounting.apply(Vec3::new(0.0, 0.0, 1.0));
+        Vec3::new(x.dot(&v), y.dot(&v), z.dot(&v))
+    }
+
+    #[test]
+    fn mountings_are_rotations() {
+        for mounting in MOUNTINGS.iter() {
+            let x = mounting.apply(Vec3::new(1.0, 0.0, 0.0));
+            let y = mounting.apply(Vec3::new(0.0, 1.0, 0.0));
+            let z = mounting.apply(Vec3::new(0.0, 0.0, 1.0));
+            // right handed, not mirrored
+            assert_eq!(x.cross(&y), z, "{:?}", mounting);
+        }
+        // upside down the sensor sees gravity the other way
+        assert_eq!(Mounting::Cw0Flip.apply(Vec3::new(0.0, 0.0, -1.0)), Vec3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn frame_x_rotation_lands_on_the_same_axis_for_every_mounting() {
+        let range = GyroRange::Dps500;
+        let dps = 30.0;
+
+        for mounting in MOUNTINGS.iter() {
+            let mut filter = Complementary::from_acc([0.0, 0.0]);
+            // a second of rotation, then another one held still for the accelerometer low-pass to catch up
+            for i in 1..=2 * GYRO_FREQUENCY_HZ {
+                let turning = i <= GYRO_FREQUENCY_HZ;
+                let acc = tilted((dps * i.min(GYRO_FREQUENCY_HZ) as f32 * GYRO_DT).to_radians());
+                let gyro = Vec3::new(if turning { dps * range.sensitivity() } else { 0.0 }, 0.0, 0.0);
+                let (raw_acc, raw_gyro) = (in_sensor_axes(*mounting, acc), in_sensor_axes(*mounting, gyro));
+
+                filter.update(mounting.apply(raw_gyro) * range.rad_per_lsb(), Some(mounting.apply(raw_acc)), None, GYRO_DT);
+            }
+
+            // the filter calls the angle about frame X its pitch
+            let s = filter.orientation();
+            assert!((s.pitch - dps.to_radians()).abs() < 1e-3, "{:?} x {}", mounting, s.pitch.to_degrees());
+            assert!(s.roll.abs() < 1e-4, "{:?} y {}", mounting, s.roll.to_degrees());
+            assert!(s.yaw.abs() < 1e-4, "{:?} z {}", mounting, s.yaw.to_degrees());
+        }
+    }
+
+    /// Uniform in 0..1, the same sequence every run
+    fn uniform(seed: &mut u32) -> f32 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 17;
+        *seed ^= *seed << 5;
+        *seed as f32 / u32::MAX as f32
+    }
+
+    /// Intervals between half and three times the nominal one
+    fn irregular_dt(seed: &mut u32) -> f32 {
+        GYRO_DT * (0.5 + 2.5 * uniform(seed))
+    }
+
+    fn variance(values: impl Iterator<Item = f32>) -> f32 {
+        let (mut n, mut sum, mut squares) = (0.0, 0.0, 0.0);
+        for v in values {
+            n += 1.0;
+            sum += v;
+            squares += v * v;
+        }
+        squares / n - (sum / n) * (sum / n)
+    }
+
+    #[test]
+    fn low_pass_cuts_the_variance_of_white_noise() {
+        let mut seed = 42;
+        let noisy: [f32; 20_000] = core::array::from_fn(|_| (uniform(&mut seed) - 0.5) * 0.2);
+
+        let mut low_pass = LowPass::new(ACC_LOWPASS_HZ);
+        let filtered = noisy.map(|x| low_pass.apply([x, -x], GYRO_DT));
+
+        // a first order low-pass leaves alpha / (2 - alpha) of the variance of white noise
+        let rc = 1.0 / (2.0 * core::f32::consts::PI * ACC_LOWPASS_HZ);
+        let alpha = GYRO_DT / (rc + GYRO_DT);
+        let expected = alpha / (2.0 - alpha);
+
+        // past the settling of the first samples
+        let ratio = variance(filtered[500..].iter().map(|v| v[0])) / variance(noisy[500..].iter().copied());
+        assert!((ratio / expected - 1.0).abs() < 0.2, "ratio {} expected {}", ratio, expected);
+        assert!((filtered[1000][1] + filtered[1000][0]).abs() < 1e-6);
+    }
+
+    #[test]
+    fn irregular_intervals_integrate_a_known_rotation() {
+        let (pitch_rate, yaw_rate) = (40f32.to_radians(), -25f32.to_radians());
+        // integration alone, the accelerometer low-pass lags behind a rotation
+        let mut filter = Complementary::new([0.0, 0.0], ACC_LOWPASS_HZ, 0.0);
+        let mut seed = 0x1234_5678;
+        let mut t = 0.0;
+
+        while t < 1.5 {
+            let (dt, clamped) = sample_dt(Some(irregular_dt(&mut seed)));
+            assert!(!clamped, "{}", dt);
+            t += dt;
+            filter.update(Vec3::new(pitch_rate, 0.0, yaw_rate), Some(tilted(pitch_rate * t)), None, dt);
+        }
+
+        let s = filter.orientation();
+        assert!((s.pitch - pitch_rate * t).abs() < 0.01f32.to_radians(), "pitch {}", s.pitch.to_degrees());
+        assert!((s.yaw - yaw_rate * t).abs() < 0.01f32.to_radians(), "yaw {}", s.yaw.to_degrees());
+    }
+
+    #[test]
+    fn pathological_intervals_are_clamped_and_flagged() {
+        assert_eq!(sample_dt(None), (GYRO_DT, false));
+        assert_eq!(sample_dt(Some(GYRO_DT)), (GYRO_DT, false));
+        // a stall of several milliseconds
+        assert_eq!(sample_dt(Some(0.05)), (MAX_DT, true));
+        // two samples in one wakeup, or a wrapped clock
+        assert_eq!(sample_dt(Some(0.0)), (MIN_DT, true));
+        assert_eq!(sample_dt(Some(-0.002)), (MIN_DT, true));
+        assert_eq!(sample_dt(Some(f32::NAN)), (MIN_DT, true));
+    }
+
+    #[test]
+    fn quaternion_and_angles_convert_both_ways() {
+        let (pitch, roll, yaw) = (0.3, -0.5, 2.0);
+        let o = quaternion_angles(quaternion_from_angles(pitch, roll, yaw));
+        assert!((o.pitch - pitch).abs() < 1e-5 && (o.roll - roll).abs() < 1e-5 && (o.yaw - yaw).abs() < 1e-5, "{:?}", o);
+    }
+
+    /// Something like a bench recording: slow swings on every axis, vibration on the
+    /// accelerometer, a gyroscope that is a little off
+    fn recording(seed: &mut u32, i: u32) -> (Vec3, Vec3) {
+        let t = i as f32 * GYRO_DT;
+        let pitch = 0.3 * libm::sinf(1.1 * t);
+        let roll = 0.25 * libm::sinf(0.7 * t + 1.0);
+        let rates = Vec3::new(0.3 * 1.1 * libm::cosf(1.1 * t), 0.25 * 0.7 * libm::cosf(0.7 * t + 1.0), 0.0);
+
+        // gravity for the roll about the pitched Y axis
+        let q = quaternion_from_angles(pitch, roll, 0.0);
+        let [w, x, y, z] = q;
+        let gravity = Vec3::new(2.0 * (x * z - w * y), 2.0 * (w * x + y * z), w * w - x * x - y * y + z * z);
+        let shake = Vec3::new(uniform(seed) - 0.5, uniform(seed) - 0.5, uniform(seed) - 0.5) * 0.1;
+        let drift = Vec3::new(0.004, -0.003, 0.0);
+
+        // body rates of that motion, the small cross terms between the two swings left out
+        let body = Vec3::new(rates.x * libm::cosf(roll), rates.y, rates.x * libm::sinf(roll));
+        (body + drift, (gravity + shake) * ACCEL_RANGE.sensitivity())
+    }
+
+    #[test]
+    fn estimators_agree_on_the_same_recording() {
+        let mut estimators: (Complementary, Madgwick, Mahony) =
+            (AttitudeEstimator::from_acc([0.0, 0.0]), AttitudeEstimator::from_acc([0.0, 0.0]), AttitudeEstimator::from_acc([0.0, 0.0]));
+        let mut seed = 99;
+        let mut worst = 0.0f32;
+
+        for i in 0..30 * GYRO_FREQUENCY_HZ {
+            let (gyro, acc) = recording(&mut seed, i);
+            estimators.0.update(gyro, Some(acc), None, GYRO_DT);
+            estimators.1.update(gyro, Some(acc), None, GYRO_DT);
+            estimators.2.update(gyro, Some(acc), None, GYRO_DT);
+
+            // past the first seconds where they settle from the same start differently
+            if i > 5 * GYRO_FREQUENCY_HZ {
+                let all = [estimators.0.orientation(), estimators.1.orientation(), estimators.2.orientation()];
+                for a in all.iter() {
+                    for b in all.iter() {
+                        worst = worst.max((a.pitch - b.pitch).abs()).max((a.roll - b.roll).abs());
+                    }
+                }
+            }
+        }
+
+        // the accelerometer low-pass of the complementary filter lags the swings by almost 2 degrees
+        assert!(worst < 3f32.to_radians(), "{} degrees apart", worst.to_degrees());
+    }
+
+    #[test]
+    fn tuning_outside_the_stable_range_is_refused() {
+        assert!(valid_gain(0.0) && valid_gain(0.98) && valid_gain(1.0));
+        assert!(!valid_gain(-0.01) && !valid_gain(1.01) && !valid_gain(f32::NAN) && !valid_gain(f32::INFINITY));
+        assert!(valid_acc_cutoff(5.0));
+        let nyquist = GYRO_FREQUENCY_HZ as f32 / 2.0;
+        assert!(!valid_acc_cutoff(0.0) && !valid_acc_cutoff(nyquist) && !valid_acc_cutoff(f32::NAN));
+    }
+
+    #[test]
+    fn yaw_wraps_into_plus_minus_pi() {
+        use core::f32::consts::PI;
+
+        for (angle, wrapped) in [(0.5, 0.5), (PI + 0.25, -PI + 0.25), (-PI - 0.25, PI - 0.25), (3.0 * PI + 0.5, -PI + 0.5), (-PI, PI)] {
+            assert!((wrap_angle(angle) - wrapped).abs() < 1e-5, "{} -> {}", angle, wrap_angle(angle));
+        }
+
+        // turning on past pi comes out on the other side instead of growing
+        let mut filter = Complementary::new([0.0, 0.0], ACC_LOWPASS_HZ, 0.0);
+        for _ in 0..1000 {
+            filter.update(Vec3::new(0.0, 0.0, 2.0), Some(tilted(0.0)), None, 0.002);
+        }
+        let yaw = filter.orientation().yaw;
+        assert!((yaw - wrap_angle(4.0)).abs() < 1e-3, "yaw {}", yaw);
+    }
+
+    #[test]
+    fn zero_yaw_keeps_pitch_and_roll() {
+        fn check<E: AttitudeEstimator>(mut filter: E) {
+            let before = filter.orientation();
+            filter.zero_yaw();
+            let after = filter.orientation();
+            assert!(after.yaw.abs() < 1e-5, "yaw {}", after.yaw);
+            assert!((after.pitch - before.pitch).abs() < 1e-5 && (after.roll - before.roll).abs() < 1e-5, "{:?} {:?}", before, after);
+        }
+
+        fn turned<E: AttitudeEstimator>(mut filter: E) -> E {
+            for _ in 0..500 {
+                filter.update(Vec3::new(0.0, 0.0, 1.5), None, None, 0.002);
+            }
+            filter
+        }
+        let start = [0.4, -0.3];
+
+        check(turned(Complementary::from_acc(start)));
+        check(turned(Madgwick::from_acc(start)));
+        check(turned(Mahony::from_acc(start)));
+    }
+
+    #[test]
+    fn roll_holds_while_pitch_sweeps_through_vertical() {
+        let rate = 20f32.to_radians();
+        let mut filter = Complementary::from_acc([0.0, 0.0]);
+        let mut seed = 7;
+        let mut pitch: f32 = 0.0;
+        let mut worst_roll: f32 = 0.0;
+        let mut top: f32 = 0.0;
+
+        // up to 100 degrees and back at 20 deg/s, the accelerometer noisy and disturbed by the motion
+        for step in 0..(10.0 / GYRO_DT) as u32 {
+            let pitch_rate = if (step as f32 * GYRO_DT) < 5.0 { rate } else { -rate };
+            pitch += pitch_rate * GYRO_DT;
+            let noise = Vec3::new(uniform(&mut seed) - 0.5, uniform(&mut seed) - 0.5, uniform(&mut seed) - 0.5) * 0.1;
+            let acc = tilted(pitch) + noise * ACCEL_RANGE.sensitivity();
+            let gyro = Vec3::new(pitch_rate, (uniform(&mut seed) - 0.5) * 0.05, 0.0);
+
+            filter.update(gyro, Some(acc), None, GYRO_DT);
+            worst_roll = worst_roll.max(libm::fabsf(filter.orientation().roll));
+            top = top.max(filter.orientation().pitch);
+        }
+
+        assert!(worst_roll < 3f32.to_radians(), "roll reached {} degrees", worst_roll.to_degrees());
+        // the folded accelerometer pitch didn't drag it back under 90
+        assert!(top > 95f32.to_radians(), "pitch only reached {} degrees", top.to_degrees());
+        assert!(libm::fabsf(filter.orientation().pitch) < 1f32.to_radians(), "{:?}", filter.orientation());
+    }
+
+    /// Gravity in sensor axes at `pitch` and `roll`, the angles [quaternion_angles] gives back
+    fn gravity(pitch: f32, roll: f32) -> Vec3 {
+        let [w, x, y, z] = quaternion_from_angles(pitch, roll, 0.0);
+        Vec3::new(2.0 * (x * z - w * y), 2.0 * (w * x + y * z), w * w - x * x - y * y + z * z)
+    }
+
+    #[test]
+    fn still_gravity_pulls_every_estimator_to_the_tilt() {
+        fn settled<E: AttitudeEstimator>(acc: Vec3) -> SpatialOrientation {
+            let mut filter = E::from_acc([0.0, 0.0]);
+            // the integral of Mahony overshoots and takes the longest
+            for _ in 0..40 * GYRO_FREQUENCY_HZ {
+                filter.update(Vec3::zeros(), Some(acc), None, GYRO_DT);
+            }
+            filter.orientation()
+        }
+
+        for (pitch, roll) in [(0.5, 0.0), (0.0, -0.7), (-0.3, 0.4), (1.2, 0.2)] {
+            let acc = gravity(pitch, roll) * ACCEL_RANGE.sensitivity();
+
+            // the complementary filter's angles are those of the accelerometer
+            let expected = acc_angles(acc);
+            let s = settled::<Complementary>(acc);
+            assert!((s.pitch - expected[0]).abs() < 1e-3 && (s.roll - expected[1]).abs() < 1e-3, "{:?} {:?}", s, expected);
+
+            for s in [settled::<Madgwick>(acc), settled::<Mahony>(acc)] {
+                assert!((s.pitch - pitch).abs() < 0.5f32.to_radians() && (s.roll - roll).abs() < 0.5f32.to_radians(), "{:?} at {} {}", s, pitch, roll);
+            }
+        }
+    }
+
+    #[test]
+    fn complementary_error_under_a_gyroscope_bias_settles_where_expected() {
+        let bias = Vec3::new(0.05, -0.03, 0.01);
+        let mut filter = Complementary::from_acc([0.0, 0.0]);
+        let seconds = 10;
+
+        for _ in 0..seconds * GYRO_FREQUENCY_HZ {
+            filter.update(bias, Some(tilted(0.0)), None, GYRO_DT);
+        }
+
+        // e = (e + bias dt) (1 - w) each sample settles at bias dt (1 - w) / w
+        let steady = |b: f32| b * GYRO_DT * (1.0 - ACC_WEIGHT) / ACC_WEIGHT;
+        let s = filter.orientation();
+        assert!((s.pitch - steady(bias.x)).abs() < 1e-5, "pitch {} expected {}", s.pitch, steady(bias.x));
+        assert!((s.roll - steady(bias.y)).abs() < 1e-5, "roll {} expected {}", s.roll, steady(bias.y));
+        // nothing holds yaw without a magnetometer
+        assert!((s.yaw - bias.z * seconds as f32).abs() < 1e-3, "yaw {}", s.yaw);
+    }
+
+    #[test]
+    fn linear_acceleration_at_rest_is_zero_at_any_tilt() {
+        fn residual<E: AttitudeEstimator>(acc: Vec3) -> f32 {
+            let mut filter = E::from_acc(acc_angles(acc));
+            // counts for the update, the fixed point filter wants them whole
+            for _ in 0..10 * GYRO_FREQUENCY_HZ {
+                filter.update(Vec3::zeros(), Some(acc * ACCEL_RANGE.sensitivity()), None, GYRO_DT);
+            }
+            let linear = filter.linear_acceleration(acc);
+            libm::sqrtf(linear.dot(&linear))
+        }
+
+        for pitch in (-80..=80).step_by(20) {
+            for roll in (-80..=80).step_by(40) {
+                let acc = gravity((pitch as f32).to_radians(), (roll as f32).to_radians());
+                for (name, r) in [("complementary", residual::<Complementary>(acc)), ("madgwick", residual::<Madgwick>(acc)), ("mahony", residual::<Mahony>(acc)), ("fixed", residual::<crate::fixed::FixedComplementary>(acc))] {
+                    assert!(r < 0.05, "{} at pitch {} roll {}: {} g", name, pitch, roll, r);
+                }
+            }
+        }
+    }
+
+    /// What the magnetometer reads at an attitude, the earth field 50 µT towards +X dipping 60°
+    fn field_at(pitch: f32, roll: f32, yaw: f32) -> Vec3 {
+        let dip = 60f32.to_radians();
+        let earth = Vec3::new(libm::cosf(dip), 0.0, -libm::sinf(dip)) * 50.0;
+        // turned the other way by the conjugate
+        let [w, x, y, z] = quaternion_from_acc_angles(pitch, roll, yaw);
+        rotate_to_earth([w, -x, -y, -z], earth)
+    }
+
+    #[test]
+    fn tilt_compensation_recovers_the_heading_where_the_raw_one_is_far_off() {
+        for (pitch, roll) in [(30.0f32, 30.0f32), (-40.0, 20.0), (20.0, -45.0), (60.0, 10.0)] {
+            let (pitch, roll) = (pitch.to_radians(), roll.to_radians());
+            let mut worst_raw = 0.0f32;
+
+            for step in 0..36 {
+                let yaw = wrap_angle((step as f32 * 10.0).to_radians());
+                let field = field_at(pitch, roll, yaw);
+
+                let error = wrap_angle(tilt_compensated_heading(field, pitch, roll) - yaw);
+                assert!(error.abs() < 0.05f32.to_radians(), "{} off at yaw {}", error.to_degrees(), yaw.to_degrees());
+                worst_raw = worst_raw.max(wrap_angle(mag_heading(field) - yaw).abs());
+            }
+
+            assert!(worst_raw > 90f32.to_radians(), "raw heading only {} off", worst_raw.to_degrees());
+        }
+    }
+
+    #[test]
+    fn level_heading_is_the_raw_one() {
+        for step in 0..36 {
+            let yaw = wrap_angle((step as f32 * 10.0).to_radians());
+            let field = field_at(0.0, 0.0, yaw);
+            assert!(wrap_angle(tilt_compensated_heading(field, 0.0, 0.0) - mag_heading(field)).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn complementary_yaw_follows_the_field_while_tilted() {
+        let (pitch, roll, yaw) = (0.5, -0.4, 1.2);
+        let mut filter = Complementary::from_acc([pitch, roll]);
+        let acc = gravity_from_acc_angles(pitch, roll) * ACCEL_RANGE.sensitivity();
+
+        for i in 0..20 * GYRO_FREQUENCY_HZ {
+            let mag = (i % 10 == 0).then(|| field_at(pitch, roll, yaw));
+            filter.update(Vec3::zeros(), Some(acc), mag, GYRO_DT);
+        }
+
+        assert!(wrap_angle(filter.orientation().yaw - yaw).abs() < 0.1f32.to_radians(), "{:?}", filter.orientation());
+    }
+
+    #[test]
+    fn a_flip_cuts_within_100_ms() {
+        let rate = 360f32.to_radians();
+        let mut filter = Complementary::from_acc([0.0, 0.0]);
+        let mut crash = CrashDetector::new(CRASH_TILT);
+        let mut past_tilt = None;
+
+        // half a second turning onto the back, then lying there
+        for i in 1..=GYRO_FREQUENCY_HZ {
+            let angle = (rate * i as f32 * GYRO_DT).min(core::f32::consts::PI);
+            let gyro = if angle < core::f32::consts::PI { Vec3::new(rate, 0.0, 0.0) } else { Vec3::zeros() };
+            let acc = gravity_from_acc_angles(angle, 0.0);
+            filter.update(gyro, Some(acc * ACCEL_RANGE.sensitivity()), None, GYRO_DT);
+
+            if angle > CRASH_TILT && past_tilt.is_none() {
+                past_tilt = Some(i);
+            }
+            if crash.sample(acc, gyro) | crash.attitude(filter.quaternion(), 1) {
+                let late = (i - past_tilt.unwrap()) as f32 * GYRO_DT;
+                assert!(late < 0.1, "cut {} s after tipping over", late);
+                return;
+            }
+        }
+        panic!("never caught the flip");
+    }
+
+    #[test]
+    fn banking_under_the_crash_tilt_is_flying() {
+        let mut crash = CrashDetector::new(CRASH_TILT);
+        for tilt in [30.0f32, 60.0, 65.0] {
+            let q = quaternion_from_angles(0.0, tilt.to_radians(), 0.0);
+            for _ in 0..2 * GYRO_FREQUENCY_HZ {
+                assert!(!crash.attitude(q, 1), "crash at {}°", tilt);
+            }
+        }
+    }
+
+    #[test]
+    fn an_impact_is_a_crash_only_when_it_goes_still() {
+        let hit = Vec3::new(0.0, 0.0, 5.0);
+        let rest = Vec3::new(0.0, 0.0, 1.0);
+
+        let mut crash = CrashDetector::new(CRASH_TILT);
+        assert!(!crash.sample(hit, Vec3::zeros()));
+        let fired = (0..GYRO_FREQUENCY_HZ).position(|_| crash.sample(rest, Vec3::zeros()));
+        assert_eq!(fired, Some(CRASH_STILL_SAMPLES as usize - 1));
+        // once per crash
+        assert!((0..GYRO_FREQUENCY_HZ).all(|_| !crash.sample(rest, Vec3::zeros())));
+
+        // a hard landing that flies on, then stops long after
+        let turning = Vec3::new(100f32.to_radians(), 0.0, 0.0);
+        assert!(!crash.sample(hit, Vec3::zeros()));
+        assert!((0..GYRO_FREQUENCY_HZ).all(|_| !crash.sample(rest, turning)));
+        assert!((0..GYRO_FREQUENCY_HZ).all(|_| !crash.sample(rest, Vec3::zeros())));
+    }
+
+    #[test]
+    fn crash_tilt_settings_outside_30_to_150_degrees_are_refused() {
+        assert!(valid_crash_tilt(CRASH_TILT));
+        assert!(valid_crash_tilt(30f32.to_radians()) && valid_crash_tilt(150f32.to_radians()));
+        for tilt in [29f32.to_radians(), 151f32.to_radians(), -1.0, f32::NAN, f32::INFINITY] {
+            assert!(!valid_crash_tilt(tilt), "{}", tilt);
+        }
+    }
+}
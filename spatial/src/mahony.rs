@@ -0,0 +1,191 @@
+//! Mahony AHRS (explicit complementary filter) on a quaternion state.
+//!
+//! The error between measured and estimated gravity and field directions feeds back into
+//! the gyroscope rates through a PI controller. The integral converges on whatever bias
+//! the offset captured at boot left, so a stale offset matters less than with the others.
+//! Same quaternion convention as [Madgwick](crate::madgwick::Madgwick).
+
+use common::{SpatialOrientation, Vec3};
+
+use crate::{
+    integrate_quaternion, normalized, quaternion_angles, quaternion_from_angles, quaternion_rate, quaternion_without_yaw,
+    AttitudeEstimator,
+};
+
+/// Proportional gain on the direction error
+pub const MAHONY_KP: f32 = 0.5;
+/// Integral gain, how fast the gyroscope bias estimate follows
+pub const MAHONY_KI: f32 = 0.05;
+/// Proportional gain just out of a gyroscope saturation, see [Saturation](crate::Saturation)
+pub const MAHONY_KP_RECOVERY: f32 = 2.5;
+/// The bias estimate stays within this on every axis, rad/s
+const MAHONY_MAX_BIAS: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Mahony {
+    /// w, x, y, z, unit length
+    q: [f32; 4],
+    pub kp: f32,
+    pub ki: f32,
+    /// Integrated error, rad/s added to the gyroscope
+    bias: Vec3,
+    recovering: bool,
+}
+
+impl Mahony {
+    pub fn new(acc: [f32; 2], kp: f32, ki: f32) -> Self {
+        Mahony { q: quaternion_from_angles(acc[0], acc[1], 0.0), kp, ki, bias: Vec3::zeros(), recovering: false }
+    }
+
+    /// Bias the integral took out of the gyroscope so far, rad/s
+    pub fn bias(&self) -> Vec3 {
+        -self.bias
+    }
+
+    /// Measured cross estimated direction of gravity, plus of the field with `m`
+    fn error(&self, a: Vec3, m: Option<Vec3>) -> Vec3 {
+        let [q0, q1, q2, q3] = self.q;
+        let (q0q0, q0q1, q0q2, q0q3) = (q0 * q0, q0 * q1, q0 * q2, q0 * q3);
+        let (q1q1, q1q2, q1q3) = (q1 * q1, q1 * q2, q1 * q3);
+        let (q2q2, q2q3, q3q3) = (q2 * q2, q2 * q3, q3 * q3);
+
+        // half of gravity in sensor axes
+        let v = Vec3::new(q1q3 - q0q2, q0q1 + q2q3, q0q0 - 0.5 + q3q3);
+        let mut e = a.cross(&v);
+
+        if let Some(m) = m {
+            // field in the earth frame, only its north and down components are a reference
+            let hx = 2.0 * (m.x * (0.5 - q2q2 - q3q3) + m.y * (q1q2 - q0q3) + m.z * (q1q3 + q0q2));
+            let hy = 2.0 * (m.x * (q1q2 + q0q3) + m.y * (0.5 - q1q1 - q3q3) + m.z * (q2q3 - q0q1));
+            let bx = libm::sqrtf(hx * hx + hy * hy);
+            let bz = 2.0 * (m.x * (q1q3 - q0q2) + m.y * (q2q3 + q0q1) + m.z * (0.5 - q1q1 - q2q2));
+
+            // half of that reference back in sensor axes
+            let w = Vec3::new(
+                bx * (0.5 - q2q2 - q3q3) + bz * (q1q3 - q0q2),
+                bx * (q1q2 - q0q3) + bz * (q0q1 + q2q3),
+                bx * (q0q2 + q1q3) + bz * (0.5 - q1q1 - q2q2),
+            );
+            e += m.cross(&w);
+        }
+
+        e
+    }
+}
+
+impl AttitudeEstimator for Mahony {
+    fn from_acc(acc: [f32; 2]) -> Self {
+        Mahony::new(acc, MAHONY_KP, MAHONY_KI)
+    }
+
+    fn update(&mut self, gyro: Vec3, acc: Option<Vec3>, mag: Option<Vec3>, dt: f32) {
+        let mut gyro = gyro;
+
+        // heading alone can't be corrected without knowing which way is down
+        if let Some(a) = acc.and_then(normalized) {
+            let e = self.error(a, mag.and_then(normalized));
+
+            // the bias is left alone while the gyroscope is known to be off
+            if self.ki > 0.0 && !self.recovering {
+                self.bias += e * (2.0 * self.ki * dt);
+                self.bias.iter_mut().for_each(|b| *b = b.clamp(-MAHONY_MAX_BIAS, MAHONY_MAX_BIAS));
+            }
+            let kp = if self.recovering { MAHONY_KP_RECOVERY } else { self.kp };
+            gyro += self.bias + e * (2.0 * kp);
+        } else {
+            gyro += self.bias;
+        }
+
+        let rate = quaternion_rate(self.q, gyro);
+        integrate_quaternion(&mut self.q, rate, dt);
+    }
+
+    fn reset(&mut self, acc: [f32; 2]) {
+        // the bias is still good, only the attitude went stale
+        self.q = quaternion_from_angles(acc[0], acc[1], 0.0);
+    }
+
+    fn reseed(&mut self, acc: [f32; 2]) {
+        self.reset(acc);
+        self.bias = Vec3::zeros();
+    }
+
+    fn zero_yaw(&mut self) {
+        self.q = quaternion_without_yaw(self.q);
+    }
+
+    fn orientation(&self) -> SpatialOrientation {
+        quaternion_angles(self.q)
+    }
+
+    fn quaternion(&self) -> [f32; 4] {
+        self.q
+    }
+
+    fn set_recovering(&mut self, recovering: bool) {
+        self.recovering = recovering;
+    }
+
+    const KIND: u8 = 3;
+
+    /// Kp, Ki stays where it is
+    fn gain(&self) -> f32 {
+        self.kp
+    }
+
+    fn set_gain(&mut self, gain: f32) {
+        self.kp = gain;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.002;
+
+    #[test]
+    fn integral_takes_out_a_gyroscope_bias() {
+        let mut filter = Mahony::new([0.0, 0.0], MAHONY_KP, MAHONY_KI);
+        let bias = Vec3::new(0.02, -0.015, 0.0);
+        let level = Vec3::new(0.0, 0.0, 1.0);
+
+        for _ in 0..(60.0 / DT) as u32 {
+            filter.update(bias, Some(level), None, DT);
+        }
+
+        // heading can't tell a Z bias without the field, the others settle on it
+        let found = filter.bias();
+        assert!((found.x - bias.x).abs() < 1e-3 && (found.y - bias.y).abs() < 1e-3, "{:?}", found);
+        let o = filter.orientation();
+        assert!(o.pitch.abs() < 1e-3 && o.roll.abs() < 1e-3, "{:?}", o);
+    }
+
+    #[test]
+    fn bias_estimate_is_bounded() {
+        let mut filter = Mahony::new([0.0, 0.0], MAHONY_KP, MAHONY_KI);
+        // an accelerometer that insists on a tilt the gyroscope never shows
+        let tilted = Vec3::new(0.0, 0.7, 0.7);
+
+        for _ in 0..(120.0 / DT) as u32 {
+            filter.update(Vec3::zeros(), Some(tilted), None, DT);
+            filter.q = quaternion_from_angles(0.0, 0.0, 0.0);
+        }
+
+        assert!(filter.bias().iter().all(|b| b.abs() <= MAHONY_MAX_BIAS), "{:?}", filter.bias());
+    }
+
+    #[test]
+    fn recovering_leaves_the_bias_alone() {
+        let mut filter = Mahony::new([0.3, 0.0], MAHONY_KP, MAHONY_KI);
+        filter.set_recovering(true);
+
+        for _ in 0..(2.0 / DT) as u32 {
+            filter.update(Vec3::zeros(), Some(Vec3::new(0.0, 0.0, 1.0)), None, DT);
+        }
+
+        assert_eq!(filter.bias(), Vec3::zeros());
+        // the proportional gain alone pulled the attitude in
+        assert!(filter.orientation().pitch.abs() < 0.01, "{:?}", filter.orientation());
+    }
+}
@@ -0,0 +1,135 @@
+//! Altitude and climb rate from the accelerometer, held on to the barometer.
+//!
+//! The acceleration straight up integrates at the IMU rate, every barometer altitude pulls
+//! on the altitude, the speed and an accelerometer bias estimate (a third order
+//! complementary filter). The barometer alone lags and is noisy by a few decimeters,
+//! the accelerometer alone drifts off within seconds.
+
+/// Standard gravity, m/s² per g
+pub const STANDARD_GRAVITY: f32 = 9.80665;
+
+/// Share of the barometer error taken into the altitude with every pressure reading
+pub const VERTICAL_ALTITUDE_GAIN: f32 = 0.03;
+/// Speed correction per meter of barometer error, m/s
+pub const VERTICAL_SPEED_GAIN: f32 = 0.03;
+/// Accelerometer bias correction per meter of barometer error, m/s²
+pub const VERTICAL_BIAS_GAIN: f32 = 0.003;
+
+#[derive(Debug, Clone, Copy)]
+pub struct VerticalFilter {
+    /// Meters over the barometer's ground reference
+    altitude: f32,
+    /// m/s, positive up
+    speed: f32,
+    /// m/s² the accelerometer reads high straight up
+    bias: f32,
+}
+
+impl VerticalFilter {
+    /// Starting at rest at the first barometer altitude
+    pub fn new(altitude: f32) -> Self {
+        VerticalFilter { altitude, speed: 0.0, bias: 0.0 }
+    }
+
+    /// Linear acceleration straight up in g, see
+    /// [linear_acceleration](crate::AttitudeEstimator::linear_acceleration)
+    pub fn predict(&mut self, acc_up: f32, dt: f32) {
+        let a = acc_up * STANDARD_GRAVITY - self.bias;
+        self.altitude += self.speed * dt + 0.5 * a * dt * dt;
+        self.speed += a * dt;
+    }
+
+    /// Barometer altitude in meters
+    pub fn correct(&mut self, altitude: f32) {
+        let error = altitude - self.altitude;
+        self.altitude += error * VERTICAL_ALTITUDE_GAIN;
+        self.speed += error * VERTICAL_SPEED_GAIN;
+        self.bias -= error * VERTICAL_BIAS_GAIN;
+    }
+
+    pub fn altitude(&self) -> f32 {
+        self.altitude
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GYRO_DT, GYRO_FREQUENCY_HZ};
+
+    /// Uniform in 0..1, the same sequence every run
+    fn uniform(seed: &mut u32) -> f32 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 17;
+        *seed ^= *seed << 5;
+        *seed as f32 / u32::MAX as f32
+    }
+
+    /// Acceleration up in m/s² at `t`: still, speeding up to 2 m/s, climbing, slowing down, still
+    fn climb(t: f32) -> f32 {
+        match t {
+            t if t < 10.0 => 0.0,
+            t if t < 12.0 => 1.0,
+            t if t < 17.0 => 0.0,
+            t if t < 19.0 => -1.0,
+            _ => 0.0,
+        }
+    }
+
+    #[test]
+    fn follows_a_climb_with_a_noisy_barometer_and_a_biased_accelerometer() {
+        let mut filter = VerticalFilter::new(0.0);
+        let mut seed = 11;
+        let (mut altitude, mut speed) = (0.0f32, 0.0f32);
+        let (mut worst_speed, mut worst_altitude) = (0.0f32, 0.0f32);
+        // barometer at 12.5 Hz, a pressure reading every fourth of the BMP180's steps
+        let baro_every = GYRO_FREQUENCY_HZ / 12;
+
+        for i in 0..30 * GYRO_FREQUENCY_HZ {
+            let t = i as f32 * GYRO_DT;
+            let a = climb(t);
+            altitude += speed * GYRO_DT + 0.5 * a * GYRO_DT * GYRO_DT;
+            speed += a * GYRO_DT;
+
+            // 0.01 g of bias and 0.02 g of noise on the accelerometer, 0.5 m on the barometer
+            let acc_up = (a / STANDARD_GRAVITY) + 0.01 + (uniform(&mut seed) - 0.5) * 0.04;
+            filter.predict(acc_up, GYRO_DT);
+            if i % baro_every == 0 {
+                filter.correct(altitude + (uniform(&mut seed) - 0.5));
+            }
+
+            // past the time the bias estimate needs to settle
+            if t > 8.0 {
+                worst_speed = worst_speed.max((filter.speed() - speed).abs());
+                worst_altitude = worst_altitude.max((filter.altitude() - altitude).abs());
+            }
+        }
+
+        assert!(worst_speed < 0.25, "speed off by {} m/s", worst_speed);
+        assert!(worst_altitude < 0.3, "altitude off by {} m", worst_altitude);
+        assert!((filter.altitude() - 14.0).abs() < 0.3 && filter.speed().abs() < 0.1, "{} m at {} m/s", filter.altitude(), filter.speed());
+    }
+
+    #[test]
+    fn speed_leads_the_barometer_into_a_climb() {
+        let mut filter = VerticalFilter::new(0.0);
+        let (mut altitude, mut speed) = (0.0f32, 0.0f32);
+
+        // half a second into the acceleration, the barometer has moved about 10 cm
+        for i in 0..(10.5 / GYRO_DT) as u32 {
+            let a = climb(i as f32 * GYRO_DT);
+            altitude += speed * GYRO_DT + 0.5 * a * GYRO_DT * GYRO_DT;
+            speed += a * GYRO_DT;
+            filter.predict(a / STANDARD_GRAVITY, GYRO_DT);
+            if i % 40 == 0 {
+                filter.correct(altitude);
+            }
+        }
+
+        assert!((filter.speed() - 0.5).abs() < 0.05, "{} m/s", filter.speed());
+    }
+}
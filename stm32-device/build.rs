@@ -0,0 +1,47 @@
+//! Commit and date of the build for src/version.rs. Left out when git isn't there, a build
+//! from a source archive reports no commit.
+
+use std::env;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok().filter(|o| o.status.success())?;
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Year, month and day of a day count since 1970, Howard Hinnant's days_from_civil the other
+/// way around
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+fn main() {
+    if let Some(hash) = git(&["rev-parse", "--short=7", "HEAD"]) {
+        println!("cargo:rustc-env=GIT_HASH={}", hash);
+    }
+    if git(&["status", "--porcelain", "--untracked-files=no"]).map_or(false, |s| !s.is_empty()) {
+        println!("cargo:rustc-env=GIT_DIRTY=1");
+    }
+
+    // the same date for the same source when it's set, for a reproducible build
+    let seconds = env::var("SOURCE_DATE_EPOCH").ok().and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs());
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    println!("cargo:rustc-env=BUILD_DATE={:04}{:02}{:02}", year, month, day);
+
+    // a commit or a staged change moves these
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/index");
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
@@ -0,0 +1,380 @@
+//! Barometers sharing the MPU6050 bus.
+//!
+//! Same split as the magnetometers: calibration and setup over the blocking bus,
+//! then one interrupt driven transfer per [BARO_FREQUENCY_HZ] tick.
+
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+pub const BARO_FREQUENCY_HZ: u32 = 25;
+
+/// Both chips answer at 0x77, the BMP280 at 0x76 as well depending on SDO
+pub const ADDRESSES: [u8; 2] = [0x76, 0x77];
+
+const CHIP_ID: u8 = 0xd0;
+
+/// Pressure at sea level of the standard atmosphere
+const SEA_LEVEL_PA: f32 = 101325.0;
+
+/// Bus operation for a tick
+pub enum Step {
+    Read(u8, usize),
+    Write(u8, u8),
+}
+
+pub trait Barometer {
+    fn address(&self) -> u8;
+    /// Reads the calibration and starts measuring
+    fn init<I, E>(&mut self, i2c: &mut I) -> Result<(), E>
+    where
+        I: Write<Error = E> + WriteRead<Error = E>;
+    fn step(&self) -> Step;
+    /// Called once the [Barometer::step] transfer was started
+    fn advance(&mut self);
+    /// Data of a [Step::Read], pressure in pascal once a measurement is complete
+    fn on_read(&mut self, buf: &[u8]) -> Option<f32>;
+}
+
+/// BMP280 (or BME280) in normal mode, it measures on its own so every tick is a read
+pub struct Bmp280 {
+    address: u8,
+    t1: f32,
+    t2: f32,
+    t3: f32,
+    p: [f32; 9],
+}
+
+impl Bmp280 {
+    const IDS: [u8; 4] = [0x56, 0x57, 0x58, 0x60];
+
+    const CALIB: u8 = 0x88;
+    const CTRL_MEAS: u8 = 0xf4;
+    const CONFIG: u8 = 0xf5;
+    const PRESS_MSB: u8 = 0xf7;
+
+    // temperature x2, pressure x16, normal mode: ~26 Hz
+    const CTRL_MEAS_NORMAL: u8 = 0b01010111;
+    // 0.5 ms standby, IIR filter x16
+    const CONFIG_FILTER: u8 = 0b00010000;
+
+    fn new(address: u8) -> Self {
+        Bmp280 { address, t1: 0.0, t2: 0.0, t3: 0.0, p: [0.0; 9] }
+    }
+
+    /// Floating point compensation from the datasheet
+    fn pressure(&self, adc_p: i32, adc_t: i32) -> f32 {
+        let (adc_p, adc_t) = (adc_p as f32, adc_t as f32);
+        let p = &self.p;
+
+        let var1 = (adc_t / 16384.0 - self.t1 / 1024.0) * self.t2;
+        let var2 = (adc_t / 131072.0 - self.t1 / 8192.0) * (adc_t / 131072.0 - self.t1 / 8192.0) * self.t3;
+        let t_fine = var1 + var2;
+
+        let var1 = t_fine / 2.0 - 64000.0;
+        let var2 = var1 * var1 * p[5] / 32768.0;
+        let var2 = var2 + var1 * p[4] * 2.0;
+        let var2 = var2 / 4.0 + p[3] * 65536.0;
+        let var1 = (p[2] * var1 * var1 / 524288.0 + p[1] * var1) / 524288.0;
+        let var1 = (1.0 + var1 / 32768.0) * p[0];
+        if var1 == 0.0 {
+            return 0.0;
+        }
+
+        let pressure = 1048576.0 - adc_p;
+        let pressure = (pressure - var2 / 4096.0) * 6250.0 / var1;
+        let var1 = p[8] * pressure * pressure / 2147483648.0;
+        let var2 = pressure * p[7] / 32768.0;
+
+        pressure + (var1 + var2 + p[6]) / 16.0
+    }
+}
+
+impl Barometer for Bmp280 {
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    fn init<I, E>(&mut self, i2c: &mut I) -> Result<(), E>
+    where
+        I: Write<Error = E> + WriteRead<Error = E>,
+    {
+        let mut calib: [u8; 24] = [0; 24];
+        i2c.write_read(self.address, &[Bmp280::CALIB], &mut calib)?;
+
+        let unsigned = |i: usize| u16::from_le_bytes([calib[i], calib[i + 1]]) as f32;
+        let signed = |i: usize| i16::from_le_bytes([calib[i], calib[i + 1]]) as f32;
+
+        self.t1 = unsigned(0);
+        self.t2 = signed(2);
+        self.t3 = signed(4);
+        self.p[0] = unsigned(6);
+        for (n, p) in self.p.iter_mut().enumerate().skip(1) {
+            *p = signed(6 + 2 * n);
+        }
+
+        i2c.write(self.address, &[Bmp280::CONFIG, Bmp280::CONFIG_FILTER])?;
+        i2c.write(self.address, &[Bmp280::CTRL_MEAS, Bmp280::CTRL_MEAS_NORMAL])
+    }
+
+    fn step(&self) -> Step {
+        // pressure then temperature, 20 bits each
+        Step::Read(Bmp280::PRESS_MSB, 6)
+    }
+
+    fn advance(&mut self) {}
+
+    fn on_read(&mut self, buf: &[u8]) -> Option<f32> {
+        let adc = |b: &[u8]| ((b[0] as i32) << 12) | ((b[1] as i32) << 4) | ((b[2] as i32) >> 4);
+
+        Some(self.pressure(adc(&buf[0..3]), adc(&buf[3..6])))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    StartTemperature,
+    ReadTemperature,
+    StartPressure,
+    ReadPressure,
+}
+
+/// BMP180, conversions are started by hand and take a tick each,
+/// so pressure comes in at a quarter of [BARO_FREQUENCY_HZ]
+pub struct Bmp180 {
+    stage: Stage,
+    ut: i32,
+    ac1: i32,
+    ac2: i32,
+    ac3: i32,
+    ac4: u32,
+    ac5: i32,
+    ac6: i32,
+    b1: i32,
+    b2: i32,
+    mc: i32,
+    md: i32,
+}
+
+impl Bmp180 {
+    const ADDRESS: u8 = 0x77;
+    const ID: u8 = 0x55;
+
+    const CALIB: u8 = 0xaa;
+    const CTRL_MEAS: u8 = 0xf4;
+    const OUT_MSB: u8 = 0xf6;
+
+    const TEMPERATURE: u8 = 0x2e;
+    const PRESSURE: u8 = 0x34;
+    // ultra high resolution, 25.5 ms conversion
+    const OSS: u8 = 3;
+
+    fn new() -> Self {
+        Bmp180 {
+            stage: Stage::StartTemperature,
+            ut: 0,
+            ac1: 0,
+            ac2: 0,
+            ac3: 0,
+            ac4: 0,
+            ac5: 0,
+            ac6: 0,
+            b1: 0,
+            b2: 0,
+            mc: 0,
+            md: 0,
+        }
+    }
+
+    /// Integer compensation from the datasheet
+    fn pressure(&self, up: i32) -> f32 {
+        let oss = Bmp180::OSS as i32;
+
+        let x1 = ((self.ut - self.ac6) * self.ac5) >> 15;
+        let x2 = (self.mc << 11) / (x1 + self.md);
+        let b5 = x1 + x2;
+
+        let b6 = b5 - 4000;
+        let x1 = (self.b2 * ((b6 * b6) >> 12)) >> 11;
+        let x2 = (self.ac2 * b6) >> 11;
+        let x3 = x1 + x2;
+        let b3 = (((self.ac1 * 4 + x3) << oss) + 2) / 4;
+        let x1 = (self.ac3 * b6) >> 13;
+        let x2 = (self.b1 * ((b6 * b6) >> 12)) >> 16;
+        let x3 = (x1 + x2 + 2) >> 2;
+        let b4 = (self.ac4 * (x3 + 32768) as u32) >> 15;
+        let b7 = (up as u32).wrapping_sub(b3 as u32) * (50000 >> oss);
+        let p = if b7 < 0x80000000 { (b7 * 2) / b4 } else { (b7 / b4) * 2 } as i32;
+
+        let x1 = (p >> 8) * (p >> 8);
+        let x1 = (x1 * 3038) >> 16;
+        let x2 = (-7357 * p) >> 16;
+
+        (p + ((x1 + x2 + 3791) >> 4)) as f32
+    }
+}
+
+impl Barometer for Bmp180 {
+    fn address(&self) -> u8 {
+        Bmp180::ADDRESS
+    }
+
+    fn init<I, E>(&mut self, i2c: &mut I) -> Result<(), E>
+    where
+        I: Write<Error = E> + WriteRead<Error = E>,
+    {
+        let mut calib: [u8; 22] = [0; 22];
+        i2c.write_read(Bmp180::ADDRESS, &[Bmp180::CALIB], &mut calib)?;
+
+        let signed = |i: usize| i16::from_be_bytes([calib[i], calib[i + 1]]) as i32;
+        let unsigned = |i: usize| u16::from_be_bytes([calib[i], calib[i + 1]]) as i32;
+
+        self.ac1 = signed(0);
+        self.ac2 = signed(2);
+        self.ac3 = signed(4);
+        self.ac4 = unsigned(6) as u32;
+        self.ac5 = unsigned(8);
+        self.ac6 = unsigned(10);
+        self.b1 = signed(12);
+        self.b2 = signed(14);
+        // MB at 16 is unused
+        self.mc = signed(18);
+        self.md = signed(20);
+        self.stage = Stage::StartTemperature;
+
+        Ok(())
+    }
+
+    fn step(&self) -> Step {
+        match self.stage {
+            Stage::StartTemperature => Step::Write(Bmp180::CTRL_MEAS, Bmp180::TEMPERATURE),
+            Stage::ReadTemperature => Step::Read(Bmp180::OUT_MSB, 2),
+            Stage::StartPressure => Step::Write(Bmp180::CTRL_MEAS, Bmp180::PRESSURE | (Bmp180::OSS << 6)),
+            Stage::ReadPressure => Step::Read(Bmp180::OUT_MSB, 3),
+        }
+    }
+
+    fn advance(&mut self) {
+        self.stage = match self.stage {
+            Stage::StartTemperature => Stage::ReadTemperature,
+            Stage::ReadTemperature => Stage::StartPressure,
+            Stage::StartPressure => Stage::ReadPressure,
+            Stage::ReadPressure => Stage::StartTemperature,
+        }
+    }
+
+    fn on_read(&mut self, buf: &[u8]) -> Option<f32> {
+        match buf.len() {
+            2 => {
+                self.ut = u16::from_be_bytes([buf[0], buf[1]]) as i32;
+                None
+            }
+            _ => {
+                let up = (((buf[0] as i32) << 16) | ((buf[1] as i32) << 8) | buf[2] as i32) >> (8 - Bmp180::OSS);
+                Some(self.pressure(up))
+            }
+        }
+    }
+}
+
+/// Whichever barometer answered at init
+pub enum Baro {
+    Bmp280(Bmp280),
+    Bmp180(Bmp180),
+}
+
+impl Baro {
+    pub fn probe<I, E>(i2c: &mut I) -> Option<Baro>
+    where
+        I: Write<Error = E> + WriteRead<Error = E>,
+    {
+        for address in ADDRESSES.iter() {
+            let mut id: [u8; 1] = [0; 1];
+            if i2c.write_read(*address, &[CHIP_ID], &mut id).is_err() {
+                continue;
+            }
+            if Bmp280::IDS.contains(&id[0]) {
+                return Some(Baro::Bmp280(Bmp280::new(*address)));
+            }
+            if id[0] == Bmp180::ID && *address == Bmp180::ADDRESS {
+                return Some(Baro::Bmp180(Bmp180::new()));
+            }
+        }
+
+        None
+    }
+}
+
+impl Barometer for Baro {
+    fn address(&self) -> u8 {
+        match self {
+            Baro::Bmp280(b) => b.address(),
+            Baro::Bmp180(b) => b.address(),
+        }
+    }
+
+    fn init<I, E>(&mut self, i2c: &mut I) -> Result<(), E>
+    where
+        I: Write<Error = E> + WriteRead<Error = E>,
+    {
+        match self {
+            Baro::Bmp280(b) => b.init(i2c),
+            Baro::Bmp180(b) => b.init(i2c),
+        }
+    }
+
+    fn step(&self) -> Step {
+        match self {
+            Baro::Bmp280(b) => b.step(),
+            Baro::Bmp180(b) => b.step(),
+        }
+    }
+
+    fn advance(&mut self) {
+        match self {
+            Baro::Bmp280(b) => b.advance(),
+            Baro::Bmp180(b) => b.advance(),
+        }
+    }
+
+    fn on_read(&mut self, buf: &[u8]) -> Option<f32> {
+        match self {
+            Baro::Bmp280(b) => b.on_read(buf),
+            Baro::Bmp180(b) => b.on_read(buf),
+        }
+    }
+}
+
+/// Altitude of `pressure` over `ground` in meters, international barometric formula
+pub fn relative_altitude(pressure: f32, ground: f32) -> f32 {
+    altitude(pressure) - altitude(ground)
+}
+
+fn altitude(pressure: f32) -> f32 {
+    44330.0 * (1.0 - libm::powf(pressure / SEA_LEVEL_PA, 1.0 / 5.255))
+}
+
+/// Barometer with the ground reference captured at boot
+pub struct Altimeter {
+    pub baro: Baro,
+    ground: f32,
+    references: u32,
+}
+
+impl Altimeter {
+    /// Readings averaged into the ground reference
+    pub const REFERENCE_SAMPLES: u32 = BARO_FREQUENCY_HZ;
+
+    pub fn new(baro: Baro) -> Self {
+        Altimeter { baro, ground: 0.0, references: 0 }
+    }
+
+    /// Altitude in meters over the ground reference, `None` while it is still being captured
+    pub fn on_pressure(&mut self, pressure: f32) -> Option<f32> {
+        if self.references < Altimeter::REFERENCE_SAMPLES {
+            self.references += 1;
+            self.ground += (pressure - self.ground) / self.references as f32;
+            return None;
+        }
+
+        Some(relative_altitude(pressure, self.ground))
+    }
+}
@@ -0,0 +1,55 @@
+//! Battery voltage on PA0 through a resistor divider and the current sense output of a
+//! power module on PA1, both read on ADC1.
+//!
+//! The ADC counts against the 3.3 V supply, which a cheap regulator has a few percent off.
+//! VREFINT is read with every sample and the pin taken relative to it, that leaves the
+//! 1.20 V nominal of VREFINT as the reference. The F1 has no factory value of it, what is
+//! left goes into [BatteryConfig::divider](common::BatteryConfig::divider).
+
+use common::CurrentConfig;
+use embedded_hal::adc::OneShot;
+use stm32f1xx_hal::adc::Adc;
+use stm32f1xx_hal::gpio::{gpioa::{PA0, PA1}, Analog};
+use stm32f1xx_hal::pac::ADC1;
+
+/// Datasheet typical of VREFINT, 1.16 to 1.24 V
+const VREFINT_MV: f32 = 1200.0;
+/// Less than any pack on the pin, the board runs off USB or the debugger
+const NO_BATTERY_VOLTS: f32 = 1.0;
+/// Weight of every new reading, the load steps of the motors come out within a second
+const SMOOTHING: f32 = 0.3;
+
+pub struct Monitor {
+    adc: Adc<ADC1>,
+    voltage: PA0<Analog>,
+    current: PA1<Analog>,
+    volts: Option<f32>,
+    amps: Option<f32>,
+}
+
+impl Monitor {
+    pub fn new(adc: Adc<ADC1>, voltage: PA0<Analog>, current: PA1<Analog>) -> Self {
+        Monitor { adc, voltage, current, volts: None, amps: None }
+    }
+
+    /// Pack volts by `divider` and the amps `current` makes of its pin, both low-pass
+    /// filtered. No volts without a battery, no amps without a current sensor.
+    pub fn sample(&mut self, divider: f32, current: &CurrentConfig) -> (Option<f32>, Option<f32>) {
+        let vref = self.adc.read_vref().max(1) as f32;
+        let millivolts = |raw: u16| raw as f32 * VREFINT_MV / vref;
+
+        let raw: u16 = nb::block!(self.adc.read(&mut self.voltage)).unwrap_or(0);
+        let volts = millivolts(raw) / 1000.0 * divider;
+        self.volts = if volts < NO_BATTERY_VOLTS { None } else { Some(smooth(self.volts, volts)) };
+
+        // floating without a sensor, nothing comes of the reading then
+        let raw: u16 = nb::block!(self.adc.read(&mut self.current)).unwrap_or(0);
+        self.amps = current.amps(millivolts(raw)).map(|amps| smooth(self.amps, amps));
+        (self.volts, self.amps)
+    }
+}
+
+/// `reading` into what was `filtered` so far, the reading itself for a first one
+fn smooth(filtered: Option<f32>, reading: f32) -> f32 {
+    filtered.map_or(reading, |f| f + (reading - f) * SMOOTHING)
+}
@@ -0,0 +1,61 @@
+//! Jump into the STM32F1 system memory bootloader without the BOOT0 jumper, so stm32flash
+//! can reprogram the device over USART1. The ROM speaks 8E1 and finds the baud rate from
+//! the 0x7f it is sent first: the HC-05 has to be switched to even parity for it, the
+//! firmware's own 8N1 doesn't get through the module then.
+//!
+//! The ROM expects the chip as it comes out of reset. There is no remap on the F1, it runs
+//! from where it is, but everything the firmware started has to be undone first.
+
+use cortex_m::peripheral::{NVIC, SCB, SYST};
+use stm32f1xx_hal::pac::{DMA1, RCC};
+
+/// Stack pointer and reset vector of the ROM
+const SYSTEM_MEMORY: u32 = 0x1fff_f000;
+
+/// Never returns, the device comes back to the firmware on the next reset. The motors have
+/// to be cut before, resetting the GPIO ports leaves their pins floating.
+pub fn jump() -> ! {
+    cortex_m::interrupt::disable();
+
+    unsafe {
+        let syst = &*SYST::ptr();
+        syst.csr.write(0);
+        syst.rvr.write(0);
+        syst.cvr.write(0);
+
+        let nvic = &*NVIC::ptr();
+        for (icer, icpr) in nvic.icer.iter().zip(nvic.icpr.iter()) {
+            icer.write(u32::MAX);
+            icpr.write(u32::MAX);
+        }
+
+        // the AHB has no reset on this part, DMA is stopped by hand
+        let dma = &*DMA1::ptr();
+        for ch in [&dma.ch4, &dma.ch5, &dma.ch6, &dma.ch7] {
+            ch.cr.modify(|_, w| w.en().clear_bit());
+        }
+
+        let rcc = &*RCC::ptr();
+        rcc.ahbenr.modify(|_, w| w.dma1en().clear_bit());
+        rcc.apb1rstr.write(|w| w.bits(u32::MAX));
+        rcc.apb1rstr.write(|w| w.bits(0));
+        rcc.apb2rstr.write(|w| w.bits(u32::MAX));
+        rcc.apb2rstr.write(|w| w.bits(0));
+
+        // back on HSI before the PLL and HSE go off, the reset clock tree
+        rcc.cr.modify(|_, w| w.hsion().set_bit());
+        while rcc.cr.read().hsirdy().bit_is_clear() {}
+        rcc.cfgr.write(|w| w.bits(0));
+        while rcc.cfgr.read().sws().bits() != 0 {}
+        rcc.cr.modify(|_, w| w.pllon().clear_bit().csson().clear_bit().hseon().clear_bit());
+        rcc.cir.write(|w| w.bits(0x009f_0000));
+
+        (*SCB::ptr()).vtor.write(SYSTEM_MEMORY);
+
+        // nothing is enabled in the NVIC any more, the ROM runs with interrupts on
+        cortex_m::interrupt::enable();
+        let sp = core::ptr::read_volatile(SYSTEM_MEMORY as *const u32);
+        let reset = core::ptr::read_volatile((SYSTEM_MEMORY + 4) as *const u32);
+        core::arch::asm!("msr msp, {sp}", "bx {reset}", sp = in(reg) sp, reset = in(reg) reset, options(noreturn));
+    }
+}
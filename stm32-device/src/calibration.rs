@@ -0,0 +1,454 @@
+use common::{Error, SensorError, Vec3};
+
+use crate::mag::MAG_FREQUENCY_HZ;
+use crate::spatial::{AccelCalibration, AccelRange, GyroRange, MagCalibration, GYRO_FREQUENCY_HZ};
+
+/// Gyroscope readings averaged into the offset
+pub const CALIBRATION_SAMPLES: u32 = 500;
+/// Gives up after this long without a still window of [CALIBRATION_SAMPLES]
+pub const CALIBRATION_TIMEOUT_S: u32 = 10;
+/// Noise above this on any axis means the board was moved during calibration
+pub const MOTION_STDDEV_DPS: f32 = 0.5;
+
+/// Accelerometer readings averaged for each of the six positions
+pub const ACCEL_POSITION_SAMPLES: u32 = GYRO_FREQUENCY_HZ;
+/// Share of gravity the axis pointing down has to see for a position to count
+const ACCEL_POSITION_MIN_G: f32 = 0.8;
+/// Accelerometer noise above this means the board was moved during a capture
+const ACCEL_MOTION_STDDEV_G: f32 = 0.05;
+
+/// Readings the background gyro bias estimate averages before deciding, one second
+pub const BIAS_WINDOW_SAMPLES: u32 = GYRO_FREQUENCY_HZ;
+/// Share of the difference a still window moves the offset by
+const BIAS_GAIN: f32 = 0.1;
+/// A still window further off the offset than this is a slow turn rather than drift
+const BIAS_MAX_STEP_DPS: f32 = 1.0;
+
+/// How long the craft is turned around for the magnetometer calibration
+pub const MAG_SWEEP_S: u32 = 30;
+/// Field directions are sorted into this many equal area patches of the sphere,
+/// 4 bands of latitude by 8 of longitude
+const MAG_BINS: u32 = 32;
+/// Patches that need a reading before the sweep can be solved
+const MAG_MIN_BINS: u32 = 24;
+/// Spread along any axis below this means the craft was never turned around it
+const MAG_MIN_SPAN_UT: f32 = 30.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalibrationError {
+    /// Never held still long enough
+    Moving,
+    /// No axis was pointing straight up or down
+    Tilted,
+    /// Not turned through enough orientations
+    Sparse,
+}
+
+impl From<CalibrationError> for Error {
+    fn from(_: CalibrationError) -> Self {
+        Error::Sensor(SensorError::Calibration)
+    }
+}
+
+/// Running per axis mean and variance (Welford), sums of squares of raw counts lose
+/// too much precision in f32
+#[derive(Debug, Clone, Copy)]
+pub struct Accumulator {
+    count: u32,
+    mean: Vec3,
+    m2: Vec3,
+}
+
+impl Accumulator {
+    pub fn new() -> Self {
+        Accumulator { count: 0, mean: Vec3::zeros(), m2: Vec3::zeros() }
+    }
+
+    pub fn add(&mut self, v: Vec3) {
+        self.count += 1;
+        let delta = v - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta.component_mul(&(v - self.mean));
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Vec3 {
+        self.mean
+    }
+
+    pub fn variance(&self) -> Vec3 {
+        if self.count < 2 {
+            return Vec3::zeros();
+        }
+        self.m2 / (self.count - 1) as f32
+    }
+
+    /// Whether the spread is higher than a still board would show, `range` the readings were taken at
+    pub fn moving(&self, range: GyroRange) -> bool {
+        let limit = MOTION_STDDEV_DPS * range.sensitivity();
+        self.variance().iter().any(|v| *v > limit * limit)
+    }
+}
+
+/// Mean of the first still window of [CALIBRATION_SAMPLES] out of `samples`,
+/// collection starts over whenever the board moved during a window
+pub fn gyro_offset(
+    samples: impl Iterator<Item = Vec3>,
+    range: GyroRange,
+    mut on_motion: impl FnMut(&Accumulator),
+) -> Result<Vec3, CalibrationError> {
+    let mut acc = Accumulator::new();
+
+    for sample in samples {
+        acc.add(sample);
+
+        if acc.count() == CALIBRATION_SAMPLES {
+            if !acc.moving(range) {
+                return Ok(acc.mean());
+            }
+            on_motion(&acc);
+            acc = Accumulator::new();
+        }
+    }
+
+    Err(CalibrationError::Moving)
+}
+
+/// Follows the gyroscope offset as it drifts with the die warming up, from windows the
+/// board spent still
+#[derive(Debug, Clone, Copy)]
+pub struct BiasTracker {
+    gyro: Accumulator,
+    acc: Accumulator,
+}
+
+impl BiasTracker {
+    pub fn new() -> Self {
+        BiasTracker { gyro: Accumulator::new(), acc: Accumulator::new() }
+    }
+
+    /// Raw gyroscope and calibrated accelerometer counts, returns the new offset once a
+    /// still window completed
+    pub fn add(
+        &mut self,
+        gyro: Vec3,
+        acc: Vec3,
+        offset: Vec3,
+        gyro_range: GyroRange,
+        accel_range: AccelRange,
+    ) -> Option<Vec3> {
+        self.gyro.add(gyro);
+        self.acc.add(acc);
+        if self.gyro.count() < BIAS_WINDOW_SAMPLES {
+            return None;
+        }
+
+        let (g, a) = (self.gyro, self.acc);
+        *self = BiasTracker::new();
+
+        let limit = ACCEL_MOTION_STDDEV_G * accel_range.sensitivity();
+        let still = !g.moving(gyro_range) && a.variance().iter().all(|v| *v < limit * limit);
+
+        let step = g.mean() - offset;
+        let max_step = BIAS_MAX_STEP_DPS * gyro_range.sensitivity();
+        if !still || step.iter().any(|s| libm::fabsf(*s) > max_step) {
+            return None;
+        }
+
+        Some(offset + step * BIAS_GAIN)
+    }
+}
+
+/// Magnetometer calibration: per axis extremes give the hard iron offset and the ellipsoid
+/// radii a soft iron scale, while the craft is turned through every orientation
+#[derive(Debug, Clone, Copy)]
+pub struct MagSweep {
+    min: Vec3,
+    max: Vec3,
+    readings: u32,
+    /// One bit per patch of the sphere seen, around the center found so far
+    bins: u32,
+}
+
+impl MagSweep {
+    pub fn new() -> Self {
+        MagSweep {
+            min: Vec3::repeat(f32::MAX),
+            max: Vec3::repeat(f32::MIN),
+            readings: 0,
+            bins: 0,
+        }
+    }
+
+    /// Uncalibrated field in microtesla
+    pub fn add(&mut self, field: Vec3) {
+        self.readings += 1;
+        for axis in 0..3 {
+            self.min[axis] = self.min[axis].min(field[axis]);
+            self.max[axis] = self.max[axis].max(field[axis]);
+        }
+
+        let d = field - (self.max + self.min) / 2.0;
+        let norm = libm::sqrtf(d.dot(&d));
+        if norm > 0.0 {
+            // z is uniform over a sphere, equal bands of it are equal areas
+            let band = (((d.z / norm + 1.0) / 2.0 * 4.0) as u32).min(3);
+            let sector = (((libm::atan2f(d.y, d.x) / core::f32::consts::PI + 1.0) / 2.0 * 8.0) as u32).min(7);
+            self.bins |= 1 << (band * 8 + sector);
+        }
+    }
+
+    pub fn readings(&self) -> u32 {
+        self.readings
+    }
+
+    pub fn coverage_percent(&self) -> u8 {
+        (self.bins.count_ones() * 100 / MAG_BINS) as u8
+    }
+
+    /// Enough of the sphere was covered to solve
+    pub fn enough(&self) -> bool {
+        self.bins.count_ones() >= MAG_MIN_BINS
+    }
+
+    pub fn remaining_s(&self) -> u32 {
+        MAG_SWEEP_S.saturating_sub(self.readings / MAG_FREQUENCY_HZ)
+    }
+
+    pub fn done(&self) -> bool {
+        self.readings >= MAG_SWEEP_S * MAG_FREQUENCY_HZ
+    }
+
+    pub fn solve(&self) -> Result<MagCalibration, CalibrationError> {
+        let radius = (self.max - self.min) / 2.0;
+        if !self.enough() || radius.iter().any(|r| *r * 2.0 < MAG_MIN_SPAN_UT) {
+            return Err(CalibrationError::Sparse);
+        }
+
+        let mean = radius.sum() / 3.0;
+        Ok(MagCalibration { offset: (self.max + self.min) / 2.0, scale: radius / mean })
+    }
+}
+
+/// Guided accelerometer calibration: the board rests on each of its six faces in any order,
+/// a capture is started by hand for every one of them
+pub struct SixPosition {
+    /// Averaged reading per face, +X, -X, +Y, -Y, +Z, -Z
+    positions: [Option<Vec3>; 6],
+    capture: Option<Accumulator>,
+}
+
+impl SixPosition {
+    pub fn new() -> Self {
+        SixPosition { positions: [None; 6], capture: None }
+    }
+
+    /// Averages the next [ACCEL_POSITION_SAMPLES] readings
+    pub fn start(&mut self) {
+        self.capture = Some(Accumulator::new());
+    }
+
+    pub fn capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    pub fn captured(&self) -> usize {
+        self.positions.iter().filter(|p| p.is_some()).count()
+    }
+
+    /// Raw reading, returns the index of the face once a capture completes
+    pub fn add(&mut self, acc: Vec3, range: AccelRange) -> Option<Result<usize, CalibrationError>> {
+        let capture = self.capture.as_mut()?;
+        capture.add(acc);
+        if capture.count() < ACCEL_POSITION_SAMPLES {
+            return None;
+        }
+
+        let capture = self.capture.take()?;
+        let one_g = range.sensitivity();
+        let limit = ACCEL_MOTION_STDDEV_G * one_g;
+        let still = capture.variance().iter().all(|v| *v < limit * limit);
+        if !still {
+            return Some(Err(CalibrationError::Moving));
+        }
+
+        let mean = capture.mean();
+        let axis = mean.iamax();
+        if libm::fabsf(mean[axis]) < ACCEL_POSITION_MIN_G * one_g {
+            return Some(Err(CalibrationError::Tilted));
+        }
+
+        let face = axis * 2 + (mean[axis] < 0.0) as usize;
+        self.positions[face] = Some(mean);
+        Some(Ok(face))
+    }
+
+    /// Offset and scale once every face was captured
+    pub fn solve(&self, range: AccelRange) -> Option<AccelCalibration> {
+        let mut calibration = AccelCalibration::identity();
+
+        for axis in 0..3 {
+            let up = self.positions[axis * 2]?[axis];
+            let down = self.positions[axis * 2 + 1]?[axis];
+            calibration.offset[axis] = (up + down) / 2.0;
+            calibration.scale[axis] = (up - down) / 2.0 / range.sensitivity();
+        }
+
+        Some(calibration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RANGE: GyroRange = GyroRange::Dps500;
+    const OFFSET: [f32; 3] = [-21.0, 13.5, 4.0];
+
+    /// Uniform in -1..1, the same sequence every run
+    struct Noise(u32);
+
+    impl Noise {
+        fn next(&mut self) -> f32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0 as f32 / u32::MAX as f32 * 2.0 - 1.0
+        }
+
+        /// Still readings around [OFFSET], `dps` of noise peak to peak either way
+        fn still(&mut self, dps: f32) -> Vec3 {
+            let counts = dps * RANGE.sensitivity();
+            Vec3::new(OFFSET[0] + self.next() * counts, OFFSET[1] + self.next() * counts, OFFSET[2] + self.next() * counts)
+        }
+    }
+
+    #[test]
+    fn offset_is_the_mean_of_noisy_readings() {
+        let mut noise = Noise(1);
+        let readings = (0..CALIBRATION_SAMPLES).map(|_| noise.still(0.5));
+        let offset = gyro_offset(readings, RANGE, |_| panic!("still board rejected")).unwrap();
+
+        for (axis, expected) in OFFSET.iter().enumerate() {
+            // the mean of 500 readings is within a few hundredths of a dps
+            assert!((offset[axis] - expected).abs() < 0.05 * RANGE.sensitivity(), "axis {} {}", axis, offset[axis]);
+        }
+    }
+
+    #[test]
+    fn early_readings_count_as_much_as_late_ones() {
+        let half = CALIBRATION_SAMPLES / 2;
+        let readings = (0..CALIBRATION_SAMPLES).map(|i| Vec3::new(if i < half { 10.0 } else { 20.0 }, 0.0, 0.0));
+        let offset = gyro_offset(readings, RANGE, |_| {}).unwrap();
+
+        assert!((offset.x - 15.0).abs() < 1e-3, "{}", offset.x);
+    }
+
+    #[test]
+    fn motion_threshold() {
+        // alternating readings have a standard deviation of their amplitude
+        let limit = MOTION_STDDEV_DPS * RANGE.sensitivity();
+        for (scale, moving) in [(0.9, false), (1.1, true)].iter() {
+            let mut acc = Accumulator::new();
+            for i in 0..CALIBRATION_SAMPLES {
+                let sign = if i % 2 == 0 { 1.0 } else { -1.0 };
+                acc.add(Vec3::new(0.0, sign * limit * scale, 0.0));
+            }
+            assert_eq!(acc.moving(RANGE), *moving, "{} of the limit", scale);
+        }
+    }
+
+    #[test]
+    fn moving_window_starts_over() {
+        let mut noise = Noise(7);
+        // a slow 20 dps swing, then the board is put down
+        let swing = (0..CALIBRATION_SAMPLES).map(|i| Vec3::new(20.0 * RANGE.sensitivity() * libm::sinf(i as f32 * 0.05), 0.0, 0.0));
+        let still = (0..CALIBRATION_SAMPLES).map(|_| noise.still(0.1));
+
+        let mut restarts = 0;
+        let offset = gyro_offset(swing.chain(still), RANGE, |_| restarts += 1).unwrap();
+
+        assert_eq!(restarts, 1);
+        assert!((offset.x - OFFSET[0]).abs() < 0.05 * RANGE.sensitivity(), "{}", offset.x);
+    }
+
+    #[test]
+    fn never_still_is_an_error() {
+        let mut noise = Noise(3);
+        let readings = (0..3 * CALIBRATION_SAMPLES).map(|_| noise.still(5.0));
+        assert_eq!(gyro_offset(readings, RANGE, |_| {}), Err(CalibrationError::Moving));
+    }
+
+    const ACCEL: AccelRange = AccelRange::G4;
+    const ACCEL_OFFSET: [f32; 3] = [310.0, -145.0, 620.0];
+    const ACCEL_SCALE: [f32; 3] = [1.03, 0.97, 1.015];
+
+    /// What a miscalibrated sensor reads with `g` (in g) along its axes
+    fn miscalibrated(g: Vec3, noise: &mut Noise) -> Vec3 {
+        let one_g = ACCEL.sensitivity();
+        let mut reading = Vec3::zeros();
+        for (axis, r) in reading.iter_mut().enumerate() {
+            *r = ACCEL_OFFSET[axis] + ACCEL_SCALE[axis] * g[axis] * one_g + noise.next() * 0.005 * one_g;
+        }
+        reading
+    }
+
+    fn capture(cal: &mut SixPosition, g: Vec3, noise: &mut Noise) -> Option<Result<usize, CalibrationError>> {
+        cal.start();
+        (0..ACCEL_POSITION_SAMPLES).filter_map(|_| cal.add(miscalibrated(g, noise), ACCEL)).last()
+    }
+
+    #[test]
+    fn six_positions_recover_offset_and_scale() {
+        let mut noise = Noise(11);
+        let mut cal = SixPosition::new();
+        // slightly off level on every face, in any order
+        let faces = [
+            (Vec3::new(0.0, 0.05, -0.998), 5),
+            (Vec3::new(0.998, 0.0, 0.05), 0),
+            (Vec3::new(0.0, -0.998, 0.05), 3),
+            (Vec3::new(-0.05, 0.0, 0.998), 4),
+            (Vec3::new(-0.998, 0.05, 0.0), 1),
+            (Vec3::new(0.05, 0.998, 0.0), 2),
+        ];
+        for (g, face) in faces.iter() {
+            assert!(cal.solve(ACCEL).is_none());
+            assert_eq!(capture(&mut cal, *g, &mut noise), Some(Ok(*face)));
+        }
+        assert_eq!(cal.captured(), 6);
+
+        let solved = cal.solve(ACCEL).unwrap();
+        for axis in 0..3 {
+            // the level error costs 0.2% of g, the noise averages out
+            assert!((solved.offset[axis] - ACCEL_OFFSET[axis]).abs() < 0.005 * ACCEL.sensitivity(), "axis {} {}", axis, solved.offset[axis]);
+            assert!((solved.scale[axis] - ACCEL_SCALE[axis]).abs() < 0.005, "axis {} {}", axis, solved.scale[axis]);
+        }
+
+        // a corrected reading is 1 g at the nominal sensitivity again
+        let corrected = solved.apply(miscalibrated(Vec3::new(0.0, 0.0, 1.0), &mut Noise(5)));
+        let g = libm::sqrtf(corrected.dot(&corrected)) / ACCEL.sensitivity();
+        assert!((g - 1.0).abs() < 0.01, "{}", g);
+    }
+
+    #[test]
+    fn tilted_and_moving_captures_are_refused() {
+        let mut noise = Noise(13);
+        let mut cal = SixPosition::new();
+
+        let edge = Vec3::new(0.707, 0.0, 0.707);
+        assert_eq!(capture(&mut cal, edge, &mut noise), Some(Err(CalibrationError::Tilted)));
+
+        cal.start();
+        let shaken = (0..ACCEL_POSITION_SAMPLES)
+            .map(|i| Vec3::new(0.0, 0.0, if i % 2 == 0 { 0.8 } else { 1.2 }))
+            .filter_map(|g| cal.add(miscalibrated(g, &mut noise), ACCEL))
+            .last();
+        assert_eq!(shaken, Some(Err(CalibrationError::Moving)));
+
+        assert_eq!(cal.captured(), 0);
+        assert!(!cal.capturing());
+    }
+}
@@ -0,0 +1,127 @@
+//! MPU6050 digital motion processor, fuses on chip and queues 6-axis quaternions into the FIFO.
+//!
+//! The firmware image is InvenSense's and can't be redistributed, it is not part of the tree.
+//! With the `dmp` feature `dmp/firmware.bin` has to hold the Motion Driver 6.12 image as it is
+//! in DMP memory after `dmp_enable_feature(DMP_FEATURE_6X_LP_QUAT)` at 200 Hz, so nothing is
+//! left to configure once it is uploaded.
+
+use core::convert::TryInto;
+
+use common::{SpatialOrientation, Vec3};
+
+use crate::spatial::{acc_angles, MOUNTING};
+
+#[cfg(feature = "dmp")]
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use crate::mpu::Mpu6050Error;
+#[cfg(feature = "dmp")]
+use crate::mpu::{Mpu6050, FIFO_EN, FIFO_OFLOW, INT_ENABLE, USER_CTRL, USER_CTRL_FIFO_EN, USER_CTRL_FIFO_RESET};
+#[cfg(feature = "dmp")]
+use crate::spatial::GyroRange;
+
+pub const BANK_SEL: u8 = 0x6d;
+pub const MEM_START_ADDR: u8 = 0x6e;
+pub const MEM_R_W: u8 = 0x6f;
+pub const PRGM_START_H: u8 = 0x70;
+
+pub const USER_CTRL_DMP_EN: u8 = 0b10000000;
+const USER_CTRL_DMP_RESET: u8 = 0b00001000;
+const DMP_INT_EN: u8 = 0b00000010;
+
+/// Where the image starts executing
+pub const PROGRAM_START: u16 = 0x0400;
+
+/// Quaternion packets per second, as set in the image
+pub const DMP_FREQUENCY_HZ: u32 = 200;
+/// The image integrates assuming this range
+#[cfg(feature = "dmp")]
+pub const DMP_GYRO_RANGE: GyroRange = GyroRange::Dps2000;
+
+/// w, x, y, z as big endian q30
+pub const DMP_PACKET_SIZE: usize = 16;
+
+/// Memory writes don't cross a bank as long as this divides 256
+const CHUNK_SIZE: usize = 16;
+
+const Q30: f32 = (1u32 << 30) as f32;
+/// Norm further off than this means the FIFO lost its packet alignment
+const QUATERNION_TOLERANCE: f32 = 0.1;
+
+#[cfg(feature = "dmp")]
+const FIRMWARE: &[u8] = include_bytes!("../dmp/firmware.bin");
+
+#[derive(Debug)]
+pub enum DmpError<E> {
+    Mpu(Mpu6050Error<E>),
+    /// Memory read back differently at this address
+    Verify(u16),
+}
+
+impl<E> From<Mpu6050Error<E>> for DmpError<E> {
+    fn from(e: Mpu6050Error<E>) -> Self {
+        DmpError::Mpu(e)
+    }
+}
+
+/// Uploads and verifies the image, then starts it. Configure the MPU6050 again before going
+/// back to samples if this fails.
+#[cfg(feature = "dmp")]
+pub fn load<I, E>(mpu: &mut Mpu6050<I>) -> Result<(), DmpError<E>>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    for (i, chunk) in FIRMWARE.chunks(CHUNK_SIZE).enumerate() {
+        let address = (i * CHUNK_SIZE) as u16;
+
+        set_address(mpu, address)?;
+        mpu.write_bytes(MEM_R_W, chunk)?;
+
+        let mut readback: [u8; CHUNK_SIZE] = [0; CHUNK_SIZE];
+        set_address(mpu, address)?;
+        mpu.read_bytes(MEM_R_W, &mut readback[..chunk.len()])?;
+        if &readback[..chunk.len()] != chunk {
+            return Err(DmpError::Verify(address));
+        }
+    }
+
+    mpu.write_bytes(PRGM_START_H, &PROGRAM_START.to_be_bytes())?;
+    mpu.set_gyro_full_scale(DMP_GYRO_RANGE)?;
+    mpu.set_sample_rate(DMP_FREQUENCY_HZ)?;
+
+    // the DMP fills the FIFO on its own
+    mpu.write_byte(FIFO_EN, 0)?;
+    mpu.write_byte(INT_ENABLE, DMP_INT_EN | FIFO_OFLOW)?;
+    mpu.write_byte(USER_CTRL, USER_CTRL_DMP_EN | USER_CTRL_FIFO_EN | USER_CTRL_DMP_RESET | USER_CTRL_FIFO_RESET)?;
+    Ok(())
+}
+
+#[cfg(feature = "dmp")]
+fn set_address<I, E>(mpu: &mut Mpu6050<I>, address: u16) -> Result<(), Mpu6050Error<E>>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    mpu.write_byte(BANK_SEL, (address >> 8) as u8)?;
+    mpu.write_byte(MEM_START_ADDR, address as u8)
+}
+
+/// Same angles the software filter produces, None for a packet out of alignment.
+/// Yaw stays in sensor axes, [MOUNTING] only goes into pitch and roll.
+pub fn orientation(packet: &[u8]) -> Option<SpatialOrientation> {
+    if packet.len() != DMP_PACKET_SIZE {
+        return None;
+    }
+
+    let q = |i: usize| i32::from_be_bytes(packet[i * 4..i * 4 + 4].try_into().unwrap()) as f32 / Q30;
+    let (w, x, y, z) = (q(0), q(1), q(2), q(3));
+    if libm::fabsf(w * w + x * x + y * y + z * z - 1.0) > QUATERNION_TOLERANCE {
+        return None;
+    }
+
+    // gravity in sensor axes, what the accelerometer would read at rest
+    let gravity = Vec3::new(2.0 * (x * z - w * y), 2.0 * (w * x + y * z), w * w - x * x - y * y + z * z);
+    let angles = acc_angles(MOUNTING.apply(gravity));
+    let yaw = libm::atan2f(2.0 * (w * z + x * y), 1.0 - 2.0 * (y * y + z * z));
+
+    Some(SpatialOrientation { pitch: angles[0], roll: angles[1], yaw })
+}
@@ -0,0 +1,71 @@
+//! HC-05 setup over its AT commands, for a module powered up with KEY held high. In that
+//! mode it answers at [AT_BAUD] whatever its data rate, keeps what it is told over a power
+//! cycle and only takes the new rate once it is powered up again with KEY low.
+//!
+//! PIN as version 2 modules have it, version 3 ones want it in quotes.
+
+use core::fmt::Write as _;
+
+use cortex_m::peripheral::DWT;
+use embedded_hal::serial::{Read, Write};
+use heapless::String;
+
+/// What the module talks at in AT mode
+pub const AT_BAUD: u32 = 38_400;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AtError {
+    /// Nothing came back in time, the module isn't in AT mode
+    Timeout,
+    /// ERROR or something else than OK
+    Rejected,
+}
+
+/// Sends `command` and waits up to `budget` cycles for the OK, skipping the lines that come
+/// before it
+pub fn command<S: Read<u8> + Write<u8>>(serial: &mut S, command: &str, budget: u32) -> Result<(), AtError> {
+    for byte in command.bytes().chain(*b"\r\n") {
+        nb::block!(serial.write(byte)).ok();
+    }
+
+    let start = DWT::cycle_count();
+    let mut line = [0; 16];
+    let mut len = 0;
+    while DWT::cycle_count().wrapping_sub(start) < budget {
+        match serial.read() {
+            Ok(b'\n') => {
+                match &line[..len] {
+                    b"OK\r" => return Ok(()),
+                    reply if reply.starts_with(b"ERROR") => return Err(AtError::Rejected),
+                    _ => {}
+                }
+                len = 0;
+            }
+            Ok(byte) => {
+                if let Some(b) = line.get_mut(len) {
+                    *b = byte;
+                    len += 1;
+                }
+            }
+            // a byte lost to a line error, the reply it was in won't match
+            Err(_) => {}
+        }
+    }
+    Err(AtError::Timeout)
+}
+
+/// Probes with a bare AT, then sets the name, the PIN and `baud` with 8N1. [AtError::Timeout]
+/// from the probe is a module out of AT mode.
+pub fn configure<S: Read<u8> + Write<u8>>(serial: &mut S, name: &str, pin: &str, baud: u32, budget: u32) -> Result<(), AtError> {
+    command(serial, "AT", budget)?;
+
+    let mut line: String<32> = String::new();
+    write!(line, "AT+NAME={}", name).ok();
+    command(serial, &line, budget)?;
+    line.clear();
+    write!(line, "AT+PSWD={}", pin).ok();
+    command(serial, &line, budget)?;
+    line.clear();
+    write!(line, "AT+UART={},0,0", baud).ok();
+    command(serial, &line, budget)
+}
@@ -0,0 +1,234 @@
+//! Interrupt driven register transfers on I2C2.
+//!
+//! The peripheral is expected to be set up already (by `BlockingI2c`), this only
+//! drives the register read / write sequences from `I2C2_EV` and `I2C2_ER` so
+//! the sampling task doesn't spin on the bus.
+
+use stm32f1xx_hal::pac::I2C2;
+
+/// Longest read, a batch of FIFO samples
+pub const MAX_READ: usize = 8 * crate::mpu::SAMPLE_SIZE;
+
+/// Start attempts a transfer may stay in flight for before it is aborted
+const MAX_BUSY: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Start,
+    Address,
+    Register,
+    Data,
+    Restart,
+    Read,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Read(usize),
+    Write(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    /// Previous transfer is still in flight
+    Busy,
+    Bus,
+    Arbitration,
+    Nack,
+    Overrun,
+    /// Transfer never completed
+    Timeout,
+}
+
+/// A completed transfer
+pub enum Transfer {
+    Read { address: u8, reg: u8, buf: [u8; MAX_READ], len: usize },
+    Write { address: u8, reg: u8 },
+}
+
+pub struct InterruptI2c<PINS> {
+    i2c: I2C2,
+    pins: PINS,
+    address: u8,
+    target: u8,
+    state: State,
+    op: Op,
+    reg: u8,
+    buf: [u8; MAX_READ],
+    idx: usize,
+    busy: u32,
+}
+
+impl<PINS> InterruptI2c<PINS> {
+    pub fn new(i2c: I2C2, pins: PINS, address: u8) -> Self {
+        i2c.cr2.modify(|_, w| w.itevten().set_bit().iterren().set_bit());
+
+        InterruptI2c {
+            i2c,
+            pins,
+            address,
+            target: address,
+            state: State::Idle,
+            op: Op::Read(0),
+            reg: 0,
+            buf: [0; MAX_READ],
+            idx: 0,
+            busy: 0,
+        }
+    }
+
+    pub fn release(self) -> (I2C2, PINS) {
+        self.i2c.cr2.modify(|_, w| w.itevten().clear_bit().iterren().clear_bit().itbufen().clear_bit());
+        if self.state != State::Idle {
+            self.i2c.cr1.modify(|_, w| w.stop().set_bit());
+        }
+
+        (self.i2c, self.pins)
+    }
+
+    /// Starts reading `len` bytes, at least 2 and at most [MAX_READ], from `reg`
+    pub fn read(&mut self, reg: u8, len: usize) -> Result<(), Error> {
+        self.read_from(self.address, reg, len)
+    }
+
+    /// Same as [InterruptI2c::read] for another device on the bus
+    pub fn read_from(&mut self, address: u8, reg: u8, len: usize) -> Result<(), Error> {
+        self.start(address, reg, Op::Read(len.max(2).min(MAX_READ)))
+    }
+
+    pub fn write(&mut self, reg: u8, value: u8) -> Result<(), Error> {
+        self.write_to(self.address, reg, value)
+    }
+
+    /// Same as [InterruptI2c::write] for another device on the bus
+    pub fn write_to(&mut self, address: u8, reg: u8, value: u8) -> Result<(), Error> {
+        self.start(address, reg, Op::Write(value))
+    }
+
+    fn start(&mut self, address: u8, reg: u8, op: Op) -> Result<(), Error> {
+        if self.state != State::Idle {
+            self.busy += 1;
+            if self.busy < MAX_BUSY {
+                return Err(Error::Busy);
+            }
+            self.abort();
+            return Err(Error::Timeout);
+        }
+
+        self.busy = 0;
+        self.target = address;
+        self.reg = reg;
+        self.op = op;
+        self.idx = 0;
+        self.state = State::Start;
+        self.i2c.cr1.modify(|_, w| w.pos().clear_bit().ack().set_bit().start().set_bit());
+
+        Ok(())
+    }
+
+    /// `I2C2_EV` handler, returns the transfer once it is complete
+    pub fn on_event(&mut self) -> Option<Transfer> {
+        let sr1 = self.i2c.sr1.read();
+
+        match (self.state, self.op) {
+            (State::Start, _) if sr1.sb().bit_is_set() => {
+                self.i2c.dr.write(|w| w.dr().bits(self.target << 1));
+                self.state = State::Address;
+            }
+            (State::Address, _) if sr1.addr().bit_is_set() => {
+                // reading SR2 after SR1 clears ADDR
+                self.i2c.sr2.read();
+                self.i2c.dr.write(|w| w.dr().bits(self.reg));
+                self.state = State::Register;
+            }
+            (State::Register, Op::Read(_)) if sr1.btf().bit_is_set() => {
+                self.i2c.cr1.modify(|_, w| w.start().set_bit());
+                self.state = State::Restart;
+            }
+            (State::Register, Op::Write(value)) if sr1.btf().bit_is_set() => {
+                self.i2c.dr.write(|w| w.dr().bits(value));
+                self.state = State::Data;
+            }
+            (State::Data, _) if sr1.btf().bit_is_set() => {
+                self.i2c.cr1.modify(|_, w| w.stop().set_bit());
+                self.state = State::Idle;
+                return Some(Transfer::Write { address: self.target, reg: self.reg });
+            }
+            (State::Restart, _) if sr1.sb().bit_is_set() => {
+                self.i2c.dr.write(|w| w.dr().bits((self.target << 1) | 1));
+                self.state = State::Read;
+            }
+            (State::Read, Op::Read(2)) if sr1.addr().bit_is_set() => {
+                // two bytes: NACK the one in the shift register and pick both up on BTF
+                self.i2c.cr1.modify(|_, w| w.pos().set_bit().ack().clear_bit());
+                self.i2c.sr2.read();
+            }
+            (State::Read, Op::Read(2)) if sr1.btf().bit_is_set() => {
+                self.i2c.cr1.modify(|_, w| w.stop().set_bit());
+                self.buf[0] = self.i2c.dr.read().dr().bits();
+                self.buf[1] = self.i2c.dr.read().dr().bits();
+                self.i2c.cr1.modify(|_, w| w.pos().clear_bit());
+                return self.finish_read(2);
+            }
+            (State::Read, Op::Read(_)) if sr1.addr().bit_is_set() => {
+                self.i2c.sr2.read();
+                self.i2c.cr2.modify(|_, w| w.itbufen().set_bit());
+            }
+            (State::Read, Op::Read(len)) if sr1.rx_ne().bit_is_set() => {
+                self.buf[self.idx] = self.i2c.dr.read().dr().bits();
+                self.idx += 1;
+
+                if self.idx == len - 1 {
+                    // NACK and stop have to be requested right after the second last byte
+                    self.i2c.cr1.modify(|_, w| w.ack().clear_bit().stop().set_bit());
+                } else if self.idx == len {
+                    self.i2c.cr2.modify(|_, w| w.itbufen().clear_bit());
+                    return self.finish_read(len);
+                }
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    fn finish_read(&mut self, len: usize) -> Option<Transfer> {
+        self.state = State::Idle;
+        Some(Transfer::Read { address: self.target, reg: self.reg, buf: self.buf, len })
+    }
+
+    /// `I2C2_ER` handler, aborts the transfer in flight
+    pub fn on_error(&mut self) -> Error {
+        let sr1 = self.i2c.sr1.read();
+        let error = if sr1.af().bit_is_set() {
+            Error::Nack
+        } else if sr1.arlo().bit_is_set() {
+            Error::Arbitration
+        } else if sr1.ovr().bit_is_set() {
+            Error::Overrun
+        } else if sr1.timeout().bit_is_set() {
+            Error::Timeout
+        } else {
+            Error::Bus
+        };
+
+        self.i2c.sr1.modify(|_, w| {
+            w.af().clear_bit()
+                .arlo().clear_bit()
+                .berr().clear_bit()
+                .ovr().clear_bit()
+                .timeout().clear_bit()
+        });
+        self.abort();
+
+        error
+    }
+
+    fn abort(&mut self) {
+        self.i2c.cr2.modify(|_, w| w.itbufen().clear_bit());
+        self.i2c.cr1.modify(|_, w| w.pos().clear_bit().stop().set_bit());
+        self.state = State::Idle;
+        self.busy = 0;
+    }
+}
@@ -0,0 +1,116 @@
+//! ICM-20602 over SPI, register compatible with the MPU6050 for everything used here.
+
+use embedded_hal::blocking::spi::{Transfer, Write};
+use embedded_hal::digital::v2::OutputPin;
+
+use crate::imu::{Imu, ImuSample};
+use crate::mpu::{
+    ACCEL_CONFIG, ACCEL_XOUT_H, CONFIG, DATA_RDY_EN, DLPF_BANDWIDTH, GYRO_CONFIG,
+    INT_ENABLE, INT_PIN_CFG, PWR_MGMT_1, SAMPLE_SIZE, SMPLRT_DIV, WHO_AM_I,
+};
+use crate::spatial::{ACCEL_RANGE, GYRO_FREQUENCY_HZ, GYRO_RANGE};
+
+pub const ID: u8 = 0x12;
+
+const I2C_IF: u8 = 0x70;
+
+const READ: u8 = 0b10000000;
+
+const DEVICE_RESET: u8 = 0b10000000;
+// best available clock source
+const CLKSEL_AUTO: u8 = 0b00000001;
+// SPI only, the I2C interface could pick up noise on shared pins
+const I2C_IF_DIS: u8 = 0b01000000;
+
+// same layout as the MPU6050
+const FS_SEL_SHIFT: u8 = 3;
+
+#[derive(Debug)]
+pub enum Icm20602Error<E> {
+    Spi(E),
+    InvalidChipId(u8),
+}
+
+pub struct Icm20602<SPI, CS> {
+    spi: SPI,
+    cs: CS,
+}
+
+impl<SPI, CS, E> Icm20602<SPI, CS>
+where
+    SPI: Transfer<u8, Error = E> + Write<u8, Error = E>,
+    CS: OutputPin,
+{
+    pub fn new(spi: SPI, cs: CS) -> Self {
+        let mut icm = Icm20602 { spi, cs };
+        icm.cs.set_high().ok();
+        icm
+    }
+
+    /// ACCEL_XOUT_H through GYRO_ZOUT_L in one transaction, see [ImuSample::from_be_bytes]
+    pub fn read_raw(&mut self) -> Result<[u8; SAMPLE_SIZE], Icm20602Error<E>> {
+        let mut buf: [u8; SAMPLE_SIZE + 1] = [0; SAMPLE_SIZE + 1];
+        buf[0] = ACCEL_XOUT_H | READ;
+        self.transfer(&mut buf)?;
+
+        let mut sample: [u8; SAMPLE_SIZE] = [0; SAMPLE_SIZE];
+        sample.copy_from_slice(&buf[1..]);
+        Ok(sample)
+    }
+
+    pub fn write_byte(&mut self, reg: u8, byte: u8) -> Result<(), Icm20602Error<E>> {
+        self.cs.set_low().ok();
+        let result = self.spi.write(&[reg, byte]).map_err(Icm20602Error::Spi);
+        self.cs.set_high().ok();
+        result
+    }
+
+    pub fn read_byte(&mut self, reg: u8) -> Result<u8, Icm20602Error<E>> {
+        let mut buf: [u8; 2] = [reg | READ, 0];
+        self.transfer(&mut buf)?;
+        Ok(buf[1])
+    }
+
+    fn transfer(&mut self, buf: &mut [u8]) -> Result<(), Icm20602Error<E>> {
+        self.cs.set_low().ok();
+        let result = self.spi.transfer(buf).map(|_| ()).map_err(Icm20602Error::Spi);
+        self.cs.set_high().ok();
+        result
+    }
+}
+
+impl<SPI, CS, E> Imu for Icm20602<SPI, CS>
+where
+    SPI: Transfer<u8, Error = E> + Write<u8, Error = E>,
+    CS: OutputPin,
+    E: core::fmt::Debug,
+{
+    type Error = Icm20602Error<E>;
+
+    fn configure(&mut self) -> Result<(), Self::Error> {
+        self.write_byte(PWR_MGMT_1, DEVICE_RESET)?;
+        // 100 ms for the reset, at most 72 MHz
+        cortex_m::asm::delay(7_200_000);
+        self.write_byte(PWR_MGMT_1, CLKSEL_AUTO)?;
+        self.write_byte(I2C_IF, I2C_IF_DIS)?;
+
+        match self.read_byte(WHO_AM_I)? {
+            ID => {}
+            id => return Err(Icm20602Error::InvalidChipId(id)),
+        }
+
+        self.write_byte(CONFIG, DLPF_BANDWIDTH as u8)?;
+        let div = DLPF_BANDWIDTH.gyro_output_rate() / GYRO_FREQUENCY_HZ - 1;
+        self.write_byte(SMPLRT_DIV, div as u8)?;
+        self.write_byte(GYRO_CONFIG, (GYRO_RANGE as u8) << FS_SEL_SHIFT)?;
+        self.write_byte(ACCEL_CONFIG, (ACCEL_RANGE as u8) << FS_SEL_SHIFT)?;
+
+        // active high, push-pull, 50us pulse
+        self.write_byte(INT_PIN_CFG, 0)?;
+        self.write_byte(INT_ENABLE, DATA_RDY_EN)
+    }
+
+    fn read_sample(&mut self) -> Result<ImuSample, Self::Error> {
+        self.read_raw().map(|buf| ImuSample::from_be_bytes(&buf))
+    }
+}
@@ -0,0 +1,46 @@
+//! What the app needs from an inertial sensor regardless of its bus.
+//!
+//! Only setup and calibration go through [Imu], streaming stays with the bus:
+//! the MPU6050 drains its FIFO over interrupt driven I2C, the ICM-20602 is read
+//! over SPI straight from its data ready interrupt.
+
+use common::Vec3;
+use rtt_target::rprintln;
+
+use crate::calibration::{self, CalibrationError, CALIBRATION_TIMEOUT_S};
+use crate::spatial::{GyroRange, GYRO_FREQUENCY_HZ};
+
+pub use crate::mpu::Sample as ImuSample;
+
+/// TEMP_OUT scaling of the sensor the firmware is built for
+#[cfg(not(feature = "icm20602"))]
+pub const TEMP_LSB_PER_C: f32 = 340.0;
+#[cfg(not(feature = "icm20602"))]
+pub const TEMP_OFFSET_C: f32 = 36.53;
+#[cfg(feature = "icm20602")]
+pub const TEMP_LSB_PER_C: f32 = 326.8;
+#[cfg(feature = "icm20602")]
+pub const TEMP_OFFSET_C: f32 = 25.0;
+
+pub trait Imu {
+    type Error: core::fmt::Debug;
+
+    /// Wakes the sensor up, checks its id and sets ranges, DLPF, sample rate and data ready
+    fn configure(&mut self) -> Result<(), Self::Error>;
+
+    /// Accelerometer, temperature and gyroscope in sensor counts
+    fn read_sample(&mut self) -> Result<ImuSample, Self::Error>;
+
+    /// Gyroscope offset in counts at `range`, `wait` has to last about one sample period
+    fn calibrate(&mut self, range: GyroRange, mut wait: impl FnMut()) -> Result<Vec3, CalibrationError> {
+        // faster reads would repeat the same sample
+        let readings = (0..CALIBRATION_TIMEOUT_S * GYRO_FREQUENCY_HZ).flat_map(|_| {
+            wait();
+            self.read_sample().ok().map(|s| s.gyro)
+        });
+
+        calibration::gyro_offset(readings, range, |acc| {
+            rprintln!("moved during gyro calibration, variance {:?}, hold still", acc.variance())
+        })
+    }
+}
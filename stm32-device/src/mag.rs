@@ -0,0 +1,248 @@
+//! Magnetometers sharing the MPU6050 bus.
+//!
+//! Setup and probing go through the blocking bus, samples are read by the
+//! interrupt driven reader so every driver only describes its data registers.
+
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use common::Vec3;
+
+/// Rate the magnetometer is read at, all supported chips update at least this often
+pub const MAG_FREQUENCY_HZ: u32 = 50;
+
+pub trait Magnetometer {
+    fn address(&self) -> u8;
+    /// First data register and the number of bytes to read from it
+    fn data(&self) -> (u8, usize);
+    fn init<I, E>(&mut self, i2c: &mut I) -> Result<(), E>
+    where
+        I: Write<Error = E> + WriteRead<Error = E>;
+    /// Field in microtesla along the MPU6050 axes, `None` for an overflowed reading
+    fn field(&self, buf: &[u8]) -> Option<Vec3>;
+}
+
+/// Standalone HMC5883L, assumed mounted with its axes along the MPU6050's
+pub struct Hmc5883l;
+
+impl Hmc5883l {
+    const ADDRESS: u8 = 0x1e;
+
+    const CONFIG_A: u8 = 0x00;
+    const CONFIG_B: u8 = 0x01;
+    const MODE: u8 = 0x02;
+    const DATA_X_H: u8 = 0x03;
+    const ID_A: u8 = 0x0a;
+
+    // 8 samples averaged, 75 Hz
+    const CONFIG_A_75HZ: u8 = 0b01111000;
+    // +-1.3 Ga
+    const CONFIG_B_GAIN: u8 = 0b00100000;
+    const MODE_CONTINUOUS: u8 = 0b00000000;
+
+    const LSB_PER_GAUSS: f32 = 1090.0;
+    const OVERFLOW: i16 = -4096;
+}
+
+impl Magnetometer for Hmc5883l {
+    fn address(&self) -> u8 {
+        Hmc5883l::ADDRESS
+    }
+
+    fn data(&self) -> (u8, usize) {
+        (Hmc5883l::DATA_X_H, 6)
+    }
+
+    fn init<I, E>(&mut self, i2c: &mut I) -> Result<(), E>
+    where
+        I: Write<Error = E> + WriteRead<Error = E>,
+    {
+        i2c.write(Hmc5883l::ADDRESS, &[Hmc5883l::CONFIG_A, Hmc5883l::CONFIG_A_75HZ])?;
+        i2c.write(Hmc5883l::ADDRESS, &[Hmc5883l::CONFIG_B, Hmc5883l::CONFIG_B_GAIN])?;
+        i2c.write(Hmc5883l::ADDRESS, &[Hmc5883l::MODE, Hmc5883l::MODE_CONTINUOUS])
+    }
+
+    fn field(&self, buf: &[u8]) -> Option<Vec3> {
+        // X, Z, Y big endian
+        let x = i16::from_be_bytes([buf[0], buf[1]]);
+        let z = i16::from_be_bytes([buf[2], buf[3]]);
+        let y = i16::from_be_bytes([buf[4], buf[5]]);
+
+        if [x, y, z].contains(&Hmc5883l::OVERFLOW) {
+            return None;
+        }
+
+        Some(Vec3::new(x as f32, y as f32, z as f32) * (100.0 / Hmc5883l::LSB_PER_GAUSS))
+    }
+}
+
+/// QMC5883L, sold on most boards that claim to carry an HMC5883L.
+/// Assumed mounted with its axes along the MPU6050's.
+pub struct Qmc5883l;
+
+impl Qmc5883l {
+    const ADDRESS: u8 = 0x0d;
+
+    const DATA_X_L: u8 = 0x00;
+    const CONTROL_1: u8 = 0x09;
+    const SET_RESET_PERIOD: u8 = 0x0b;
+    const CHIP_ID: u8 = 0x0d;
+
+    const ID: u8 = 0xff;
+
+    // continuous, 100 Hz, +-8 G, 512 oversampling
+    const CONTROL_1_100HZ: u8 = 0b00011001;
+    const SET_RESET_RECOMMENDED: u8 = 0x01;
+
+    const STATUS_OVERFLOW: u8 = 0b00000010;
+
+    const LSB_PER_GAUSS: f32 = 3000.0;
+}
+
+impl Magnetometer for Qmc5883l {
+    fn address(&self) -> u8 {
+        Qmc5883l::ADDRESS
+    }
+
+    fn data(&self) -> (u8, usize) {
+        // X, Y, Z then the status register
+        (Qmc5883l::DATA_X_L, 7)
+    }
+
+    fn init<I, E>(&mut self, i2c: &mut I) -> Result<(), E>
+    where
+        I: Write<Error = E> + WriteRead<Error = E>,
+    {
+        i2c.write(Qmc5883l::ADDRESS, &[Qmc5883l::SET_RESET_PERIOD, Qmc5883l::SET_RESET_RECOMMENDED])?;
+        i2c.write(Qmc5883l::ADDRESS, &[Qmc5883l::CONTROL_1, Qmc5883l::CONTROL_1_100HZ])
+    }
+
+    fn field(&self, buf: &[u8]) -> Option<Vec3> {
+        if buf[6] & Qmc5883l::STATUS_OVERFLOW != 0 {
+            return None;
+        }
+
+        Some(vector_from_le(buf) * (100.0 / Qmc5883l::LSB_PER_GAUSS))
+    }
+}
+
+/// AK8963 inside the MPU9250, reachable once the MPU bypasses its auxiliary bus
+pub struct Ak8963;
+
+impl Ak8963 {
+    const ADDRESS: u8 = 0x0c;
+
+    const WIA: u8 = 0x00;
+    const HXL: u8 = 0x03;
+    const CNTL1: u8 = 0x0a;
+
+    const ID: u8 = 0x48;
+
+    // 16 bit output, continuous measurement mode 2 (100 Hz)
+    const CNTL1_100HZ: u8 = 0b00010110;
+
+    const ST2_HOFL: u8 = 0b00001000;
+
+    const UT_PER_LSB: f32 = 0.15;
+}
+
+impl Magnetometer for Ak8963 {
+    fn address(&self) -> u8 {
+        Ak8963::ADDRESS
+    }
+
+    fn data(&self) -> (u8, usize) {
+        // X, Y, Z then ST2, reading ST2 releases the data registers
+        (Ak8963::HXL, 7)
+    }
+
+    fn init<I, E>(&mut self, i2c: &mut I) -> Result<(), E>
+    where
+        I: Write<Error = E> + WriteRead<Error = E>,
+    {
+        i2c.write(Ak8963::ADDRESS, &[Ak8963::CNTL1, Ak8963::CNTL1_100HZ])
+    }
+
+    fn field(&self, buf: &[u8]) -> Option<Vec3> {
+        if buf[6] & Ak8963::ST2_HOFL != 0 {
+            return None;
+        }
+
+        // X and Y are swapped against the accelerometer and Z points down
+        let m = vector_from_le(buf) * Ak8963::UT_PER_LSB;
+        Some(Vec3::new(m.y, m.x, -m.z))
+    }
+}
+
+/// Whichever magnetometer answered at init
+pub enum Mag {
+    Hmc5883l(Hmc5883l),
+    Qmc5883l(Qmc5883l),
+    Ak8963(Ak8963),
+}
+
+impl Mag {
+    /// Looks for a known chip id on the bus, the MPU has to bypass its auxiliary bus already
+    pub fn probe<I, E>(i2c: &mut I) -> Option<Mag>
+    where
+        I: Write<Error = E> + WriteRead<Error = E>,
+    {
+        let mut id: [u8; 3] = [0; 3];
+
+        if i2c.write_read(Hmc5883l::ADDRESS, &[Hmc5883l::ID_A], &mut id).is_ok() && &id == b"H43" {
+            return Some(Mag::Hmc5883l(Hmc5883l));
+        }
+        if i2c.write_read(Qmc5883l::ADDRESS, &[Qmc5883l::CHIP_ID], &mut id[..1]).is_ok() && id[0] == Qmc5883l::ID {
+            return Some(Mag::Qmc5883l(Qmc5883l));
+        }
+        if i2c.write_read(Ak8963::ADDRESS, &[Ak8963::WIA], &mut id[..1]).is_ok() && id[0] == Ak8963::ID {
+            return Some(Mag::Ak8963(Ak8963));
+        }
+
+        None
+    }
+}
+
+impl Magnetometer for Mag {
+    fn address(&self) -> u8 {
+        match self {
+            Mag::Hmc5883l(m) => m.address(),
+            Mag::Qmc5883l(m) => m.address(),
+            Mag::Ak8963(m) => m.address(),
+        }
+    }
+
+    fn data(&self) -> (u8, usize) {
+        match self {
+            Mag::Hmc5883l(m) => m.data(),
+            Mag::Qmc5883l(m) => m.data(),
+            Mag::Ak8963(m) => m.data(),
+        }
+    }
+
+    fn init<I, E>(&mut self, i2c: &mut I) -> Result<(), E>
+    where
+        I: Write<Error = E> + WriteRead<Error = E>,
+    {
+        match self {
+            Mag::Hmc5883l(m) => m.init(i2c),
+            Mag::Qmc5883l(m) => m.init(i2c),
+            Mag::Ak8963(m) => m.init(i2c),
+        }
+    }
+
+    fn field(&self, buf: &[u8]) -> Option<Vec3> {
+        match self {
+            Mag::Hmc5883l(m) => m.field(buf),
+            Mag::Qmc5883l(m) => m.field(buf),
+            Mag::Ak8963(m) => m.field(buf),
+        }
+    }
+}
+
+/// Three little endian words
+fn vector_from_le(buf: &[u8]) -> Vec3 {
+    Vec3::new(
+        i16::from_le_bytes([buf[0], buf[1]]) as f32,
+        i16::from_le_bytes([buf[2], buf[3]]) as f32,
+        i16::from_le_bytes([buf[4], buf[5]]) as f32,
+    )
+}
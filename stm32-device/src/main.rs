@@ -2,65 +2,896 @@
 #![no_std]
 #![cfg_attr(not(doc), no_main)]
 
+mod baro;
+mod battery;
+mod bootloader;
+mod calibration;
+mod dmp;
+mod hc05;
+mod i2c_irq;
+mod icm20602;
+mod imu;
+mod mag;
+mod mpu;
+mod params;
+mod settings;
 mod spatial;
+mod version;
+
+use core::sync::atomic::AtomicU32;
 
 use panic_rtt_target as _;
 
+/// Bytes written to USART1, counted where they are written rather than in [app::Shared]:
+/// every task sends, most of them without a lock on the link stats
+static BYTES_SENT: AtomicU32 = AtomicU32::new(0);
+/// Frames that found no room in the transmit buffer, counted along with [BYTES_SENT]
+static FRAMES_SKIPPED: AtomicU32 = AtomicU32::new(0);
+
+#[cfg(all(feature = "crsf", feature = "sbus"))]
+compile_error!("crsf and sbus both take USART2, pick one");
+#[cfg(all(feature = "ppm", any(feature = "crsf", feature = "sbus")))]
+compile_error!("one RC receiver at a time, ppm or a serial one");
+#[cfg(all(feature = "ppm", feature = "icm20602"))]
+compile_error!("ppm takes PA6, the ICM-20602's MISO");
+#[cfg(all(feature = "wired-link", any(feature = "crsf", feature = "sbus")))]
+compile_error!("wired-link takes USART2, the serial receivers' port");
+#[cfg(all(feature = "wired-link", feature = "msp"))]
+compile_error!("wired-link speaks the native commands only");
+
 #[rtic::app(device = stm32f1xx_hal::pac, dispatchers = [WWDG])]
 mod app {
+    use core::convert::TryInto;
+    use core::sync::atomic::{compiler_fence, Ordering};
     use nb;
-    use nalgebra::Vector3;
+    use cortex_m::peripheral::DWT;
+    use common::Vec3;
     use rtt_target::{rprintln, rtt_init_print, UpChannel, rprint};
 
-    use stm32f1xx_hal::device::USART1;
+    use stm32f1xx_hal::device::{dma1::CH, usart1::{sr, RegisterBlock}, DMA1, USART1, USART2};
     use stm32f1xx_hal::dma::CircBuffer;
     use stm32f1xx_hal::timer::{Tim2NoRemap, Timer, Tim4NoRemap, Event as TEvent, CountDownTimer};
     use stm32f1xx_hal::{
+        adc::Adc,
+        flash,
         gpio::{
-            gpiob::{PB4, PB6, PB7, PB8, PB9, PB10, PB11}, CRH,
-            Alternate, OpenDrain, Pin, PushPull, Output
+            gpioa::{PA4, PA5, PA6, PA7},
+            gpiob::{PB4, PB6, PB7, PB8, PB9, PB10, PB11, PB12}, Cr, CRH,
+            Alternate, OpenDrain, Pin, PushPull, Output, Input, Floating,
+            Edge, ExtiPin,
         },
-        i2c::{BlockingI2c, DutyCycle, Mode},
-        pac::{I2C2, TIM1, TIM2, TIM3, TIM4},
+        i2c::{self, BlockingI2c, DutyCycle, Mode},
+        pac::{I2C2, SPI1, TIM1, TIM2, TIM3, TIM4},
         prelude::*,
-        pwm::{C3, Channel, Pwm},
-        serial::{Config, Serial, Tx, Event, RxDma1},
+        rcc::Clocks,
+        pwm::{C1, C2, C3, C4, Channel, Pwm},
+        serial::{Config, Serial, StopBits, Tx, Event, RxDma1, RxDma2},
+        spi::{Spi, Spi1NoRemap},
     };
 
+    use embedded_hal::spi::MODE_3;
     use systick_monotonic::*;
 
-    use crate::spatial::SpatialOrientationDevice;
-    use common::{SpatialOrientation, Command};
-    use common::EOT;
-    use common::COMMAND_SIZE;
-
-    use mpu6050::Mpu6050;
+    use crate::baro::{Altimeter, Baro, Barometer, Step, BARO_FREQUENCY_HZ};
+    use crate::battery;
+    use crate::calibration::{BiasTracker, CalibrationError, MagSweep, SixPosition, MAG_SWEEP_S};
+    use crate::dmp::{self, DMP_FREQUENCY_HZ, DMP_PACKET_SIZE, USER_CTRL_DMP_EN};
+    use crate::i2c_irq::{self, InterruptI2c, Transfer, MAX_READ};
+    use crate::icm20602::Icm20602;
+    use crate::imu::Imu as ImuDevice;
+    use crate::mag::{Mag, Magnetometer, MAG_FREQUENCY_HZ};
+    use crate::mpu::{self, Mpu6050, Mpu6050Error, Sample, SelfTestDeviation, SAMPLE_SIZE};
+    use crate::params;
+    use crate::{BYTES_SENT, FRAMES_SKIPPED};
+    use crate::settings::{FilterSettings, Settings, FLASH_SIZE, SECTOR_SIZE};
+    use crate::spatial::vertical::VerticalFilter;
+    use crate::spatial::{
+        acc_angles, acc_saturated, quaternion_from_acc_angles, sample_dt, REARM_STILL_DPS, AccelCalibration, AttitudeEstimator, Estimator, MagCalibration, MOUNTING, Notch, Saturation, GyroRange,
+        tilt_compensated_heading, valid_acc_cutoff, valid_crash_tilt, valid_gain, wrap_angle, CrashDetector, CRASH_TILT,
+        GYRO_FREQUENCY_HZ, GYRO_RANGE, ACCEL_RANGE, TELEMETRY_FREQUENCY_HZ,
+    };
+    use common::{SpatialOrientation, Command, Msg, Temperature, Altitude, Vertical, Status, SelfTest, GyroDebug, BusScan};
+    use common::{AttitudeQuaternion, CompactOrientation, CycleStats, Cycles, FilterConfig, Ack, AckStatus, FrameError, Arm, ArmState, ARM_MAGIC, MAX_ARM_THROTTLE, Heading, LinearAcceleration, LinkStats, MotorOutputs, ProtocolInfo, Setpoint, Axis, AxisPidGains, PidGains, PROTOCOL_VERSION, TelemetryConfig, FailsafeConfig, FlightMode, Trim, Pong, Rates, MagCalibrationProgress, Param, ParamValue, Value, RawSample, SampleStats, SequenceTracker, BUS_SCAN_FIRST, BUS_SCAN_LAST, RAW_SAMPLE_SIZE};
+    use common::{Battery, BatteryAlarm, BatteryConfig, CurrentConfig, MAX_CELL_VOLTS, MIN_CELL_VOLTS};
+    use common::{ExtendedStatus, DEFAULT_STATUS_PERIOD_MS, MAX_STATUS_PERIOD_MS, MIN_STATUS_PERIOD_MS};
+    use common::{ring_pending, Announce, AppliedState, LinkRate, CommandLogEntry, EmergencyStop, BOOTLOADER_MAGIC, LockState, SlewConfig, ThrottleOutput, CLEAR_STOP_MAGIC, EOT, UNLOCK_CODE};
+    use common::{cobs, crsf, mavlink, msp, ppm, sbus, telemetry, Error, SensorError, RcInput, RcMap, COMMAND_FRAME_SIZE, COMMAND_SIZE};
+    use heapless::spsc::{Consumer, Producer, Queue};
+    use heapless::Deque;
 
     #[monotonic(binds = SysTick, default = true)]
     type MyMono = Systick<100>;
 
-    type MPU = Mpu6050<BlockingI2c<I2C2, (PB10<Alternate<OpenDrain>>, PB11<Alternate<OpenDrain>>)>>;
-    type MFR = Pwm<TIM4, Tim4NoRemap, C3, PB8<Alternate<PushPull>>>;
+    type I2cPins = (PB10<Alternate<OpenDrain>>, PB11<Alternate<OpenDrain>>);
+    type MPU = Mpu6050<BlockingI2c<I2C2, I2cPins>>;
+    type MpuError = Mpu6050Error<nb::Error<i2c::Error>>;
+    type Reader = InterruptI2c<I2cPins>;
+    type SpiPins = (PA5<Alternate<PushPull>>, PA6<Input<Floating>>, PA7<Alternate<PushPull>>);
+    type SpiImu = Icm20602<Spi<SPI1, Spi1NoRemap, SpiPins, u8>, PA4<Output<PushPull>>>;
+    type MotorPins = (PB6<Alternate<PushPull>>, PB7<Alternate<PushPull>>, PB8<Alternate<PushPull>>, PB9<Alternate<PushPull>>);
+    type MFR = Pwm<TIM4, Tim4NoRemap, (C1, C2, C3, C4), MotorPins>;
+    /// Enables the ESCs of all four motors at once
+    type EN = PB4<Output<PushPull>>;
+
+    /// TIM4 channel of every motor, PB6 to PB9
+    const MOTORS: [Channel; 4] = [Channel::C1, Channel::C2, Channel::C3, Channel::C4];
+
+    const I2C_RETRIES: u32 = 3;
+    const I2C_FREQUENCY_HZ: u32 = 400_000;
+    const USART1_BAUD: u32 = 9600;
+    /// What the HC-05 is set to with the hc05-setup feature, and taken to run at
+    const HC05_BAUD: u32 = 115_200;
+    const HC05_NAME: &str = "stm32-quad";
+    const HC05_PIN: &str = "1234";
+    /// For each AT command to answer
+    const HC05_TIMEOUT_MS: u32 = 200;
+    /// For the ack of [Msg::SetBaud] to go out at the old rate
+    const BAUD_SWITCH_DELAY_MS: u64 = 100;
+    /// The new rate has this long to bring a command before the old one is back
+    const BAUD_REVERT_MS: u64 = 3000;
+    /// A link at [HC05_BAUD] that brought nothing but line errors this long goes back to
+    /// [USART1_BAUD], the module was never set up
+    const BAUD_FALLBACK_MS: u64 = 5000;
+    /// Raw frames the link carries per second, 10 bits a byte including the length, CRC,
+    /// COBS code and EOT
+    const RAW_STREAM_MAX_HZ: u32 = USART1_BAUD / 10 / telemetry::max_wire_len(RAW_SAMPLE_SIZE) as u32;
+    /// Every this many samples is streamed, without any anti-aliasing. A faster link
+    /// gets the full rate.
+    const RAW_STREAM_DECIMATION: u32 = (GYRO_FREQUENCY_HZ + RAW_STREAM_MAX_HZ - 1) / RAW_STREAM_MAX_HZ;
+
+    /// Replies waiting for [send_telemetry], two per command. A burst of commands past this
+    /// loses the oldest.
+    const REPLY_QUEUE: usize = 8;
+    /// A few commands back to back before the oldest gets overwritten
+    const RX_RING_SIZE: usize = 4 * COMMAND_FRAME_SIZE;
+    /// Each half of the transmit buffer, a round of telemetry with the replies in it.
+    /// Some 270 ms of sending at 9600 baud.
+    const TX_BUFFER_SIZE: usize = 256;
+    /// Bytes [on_rx] hands to [process_commands], a ring's worth with one slot the queue
+    /// keeps empty
+    const COMMAND_QUEUE_SIZE: usize = RX_RING_SIZE + 1;
+    /// Two frames of either receiver, one comes in per idle line. As much as USART1 has for
+    /// the wired link.
+    const RECEIVER_RING_SIZE: usize = if cfg!(feature = "wired-link") { RX_RING_SIZE } else { 2 * crsf::MAX_FRAME };
+    /// USB serial adapters have no trouble with it
+    const WIRED_BAUD: u32 = 115_200;
+    /// RC frames per attitude frame back, the receiver only sends down a few per second
+    const CRSF_TELEMETRY_DIVISOR: u32 = 10;
+    /// The first autopilot of the first vehicle, what ground stations expect with only one
+    const MAVLINK_SYSTEM_ID: u8 = 1;
+    const MAVLINK_COMPONENT_ID: u8 = 1;
+
+    /// Port a command came in on
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Link {
+        /// HC-05 on USART1
+        Bluetooth,
+        /// USART2 with the wired-link feature, on PA2/PA3
+        Wired,
+    }
+
+    /// What the receiver on USART2 speaks
+    const RECEIVER: RcSource = if cfg!(feature = "sbus") { RcSource::Sbus } else { RcSource::Crsf };
+    /// Where throttle, setpoint and arming come from, see [RcSource]
+    const RC_SOURCE: RcSource = if cfg!(any(feature = "crsf", feature = "sbus")) {
+        RECEIVER
+    } else if cfg!(feature = "ppm") {
+        RcSource::Ppm
+    } else if cfg!(feature = "msp") {
+        RcSource::Msp
+    } else {
+        RcSource::Commands
+    };
+
+    /// After boot and the sensors, for a ground that connected before and missed it
+    const ANNOUNCE_DELAY_MS: u64 = 3000;
+    /// What [Announce] says the firmware was built with
+    const FEATURES: u32 = Announce::FEATURE_MSP * cfg!(feature = "msp") as u32
+        | Announce::FEATURE_CRSF * cfg!(feature = "crsf") as u32
+        | Announce::FEATURE_SBUS * cfg!(feature = "sbus") as u32
+        | Announce::FEATURE_PPM * cfg!(feature = "ppm") as u32
+        | Announce::FEATURE_MAVLINK * cfg!(feature = "mavlink") as u32
+        | Announce::FEATURE_ICM20602 * cfg!(feature = "icm20602") as u32
+        | Announce::FEATURE_DMP * cfg!(feature = "dmp") as u32
+        | Announce::FEATURE_POLLED * cfg!(feature = "polled") as u32
+        | Announce::FEATURE_WIRED_LINK * cfg!(feature = "wired-link") as u32
+        | Announce::FEATURE_RX_IRQ * cfg!(feature = "rx-irq") as u32;
+
+    /// Time for the ack of [Msg::RebootToBootloader] to go out before the UART is reset
+    const BOOTLOADER_DELAY_MS: u64 = 200;
+
+    /// Boot waits no longer than this for the bus scan
+    const SCAN_TIMEOUT_MS: u32 = 100;
+
+    // draining the FIFO, 9 clocks a byte, leaves at least half of the bus to the
+    // magnetometer, the barometer and transaction overhead
+    const _: () = assert!(GYRO_FREQUENCY_HZ * SAMPLE_SIZE as u32 * 9 * 2 < I2C_FREQUENCY_HZ);
+
+    /// Sampling is checked for stalls this often
+    const HEALTH_PERIOD_MS: u64 = 100;
+    /// Periods without a single FIFO batch before the MPU6050 counts as lost
+    const STALLED_PERIODS: u32 = 2;
+    /// Periods between attempts to reach a lost MPU6050
+    const REPROBE_PERIODS: u32 = 10;
+    /// How often [link_watchdog] looks for commands that stopped coming
+    const LINK_WATCHDOG_PERIOD_MS: u64 = 50;
+    /// How often [output] moves the motors towards the commanded throttle, one scheduler tick
+    const OUTPUT_PERIOD_MS: u64 = 10;
+    /// How often [battery_monitor] samples the battery
+    const BATTERY_PERIOD_MS: u64 = 200;
+    /// Between two parameters of a listing, each takes some 25 bytes
+    const PARAM_LIST_PERIOD_MS: u64 = 50;
+    /// Commands kept for [Msg::DumpCommandLog], some 30 bytes each
+    const COMMAND_LOG: usize = 32;
+    /// Between two entries of a dump, each takes up to 36 bytes
+    const COMMAND_LOG_PERIOD_MS: u64 = 50;
+
+    /// What it takes to re-create the bus after a stuck transaction
+    pub struct I2cBus {
+        crh: Cr<CRH, 'B'>,
+        clocks: Clocks,
+        errors: u32,
+        recoveries: u32,
+    }
+
+    /// Fusion state, the bus itself lives in `reader` while samples stream in
+    pub struct Imu {
+        bus: I2cBus,
+        gyro_range: GyroRange,
+        offset: Vec3,
+        accel: AccelCalibration,
+        /// Six position accelerometer calibration in progress
+        six_position: Option<SixPosition>,
+        /// None for sensors without one
+        self_test: Option<SelfTest>,
+        orientation: SpatialOrientation,
+        mag: Option<Mag>,
+        mag_cal: MagCalibration,
+        /// Magnetometer calibration in progress
+        mag_sweep: Option<MagSweep>,
+        /// Latest magnetometer reading, waiting for the next sample
+        field: Option<Vec3>,
+        /// Tilt compensated heading of the latest magnetometer reading
+        heading: Option<f32>,
+        /// Heading captured by [Msg::HoldHeading]
+        heading_hold: Option<f32>,
+        /// FIFO batches processed, wraps
+        batches: u32,
+        /// DWT cycle count when the previous batch came in
+        last_batch: Option<u32>,
+        /// Motor vibration on the gyroscope, keyed to throttle
+        notch: Notch,
+        saturation: Saturation,
+        /// Software fusion, idle while the DMP runs
+        estimator: Estimator,
+        /// Cuts the motor once the craft is down, whatever the commands say
+        crash: CrashDetector,
+        /// DMP yaw taken as zero, the chip has no way to re-reference it
+        dmp_yaw_zero: f32,
+        /// From the first barometer altitude on, not while the DMP runs
+        vertical: Option<VerticalFilter>,
+        /// Drift of the offset while warming up
+        bias: BiasTracker,
+        /// The FIFO holds DMP quaternions instead of samples
+        dmp: bool,
+        /// Samples went missing, re-seed the orientation from the next one
+        resync: bool,
+        /// Just armed, re-seed the orientation from the next sample if the craft is still
+        rearm: bool,
+        lost: bool,
+        /// Latest gyro and accelerometer reading, none while the DMP runs
+        last_sample: Option<Sample>,
+        /// Rates of the latest sample the estimator took in rad/s, frame axes
+        rates: Option<Vec3>,
+        /// Raw and notch filtered gyro of the latest sample, sensor axes
+        debug: Option<(Vec3, Vec3)>,
+    }
+
+    /// What [send_telemetry] sends of the IMU, copied out under one short lock so the frames of a
+    /// round all come from the same batch
+    struct Snapshot {
+        orientation: SpatialOrientation,
+        /// The DMP's own quaternion is in sensor axes, this one is from its mounted angles
+        quaternion: [f32; 4],
+        rates: Option<Vec3>,
+        /// [Imu::debug] in counts less the offset
+        debug: Option<([i16; 3], [i16; 3])>,
+        /// In g, of the latest sample
+        linear_accel: Option<Vec3>,
+        /// Heading and how far off the one held it is
+        heading: Option<(f32, f32)>,
+    }
+
+    /// Attitude controller gains by [Axis], the ones in use and the ones [persist] writes
+    #[derive(Debug, Clone, Copy)]
+    pub struct Pid {
+        gains: [PidGains; 3],
+        stored: [PidGains; 3],
+    }
+
+    /// Roll and pitch trim, the one in use and the one [persist] writes. A controller holds
+    /// the [Setpoint] trimmed by it.
+    #[derive(Debug, Clone, Copy)]
+    pub struct Trims {
+        trim: Trim,
+        stored: Trim,
+    }
+
+    type Instant = <MyMono as rtic::Monotonic>::Instant;
+
+    /// Milliseconds from `since` to `now`, [MyMono] ticks every 10 ms
+    fn millis_since(since: Instant, now: Instant) -> u64 {
+        (now - since).ticks() * 10
+    }
+
+    /// Milliseconds since boot, in the same 10 ms steps
+    fn uptime_ms() -> u64 {
+        monotonics::now().duration_since_epoch().ticks() * 10
+    }
+
+    /// Waiting to go out after the frame being written, see [write_replies]
+    #[derive(Debug, Clone, Copy)]
+    pub enum Reply {
+        Ack(Ack),
+        Applied(AppliedState),
+        Pong(Pong),
+    }
+
+    /// Whoever flies the craft. Only the one the firmware is built for moves the motors and
+    /// keeps the link failsafe off, every other command still goes through.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum RcSource {
+        /// [Msg::Throttle], [Msg::Setpoint] and [Msg::Arm] over the command link
+        Commands,
+        /// MSP_SET_RAW_RC, with the msp feature
+        Msp,
+        /// A CRSF receiver on USART2, with the crsf feature
+        Crsf,
+        /// An SBUS receiver on USART2, with the sbus feature
+        Sbus,
+        /// A PPM sum on PA6, with the ppm feature
+        Ppm,
+    }
+
+    /// Throttle the motors are headed for, see [output]
+    pub struct Slew {
+        /// Last throttle command while armed, zero otherwise
+        commanded: f32,
+        config: SlewConfig,
+    }
+
+    /// Link-loss failsafe, see [link_watchdog]
+    pub struct Failsafe {
+        config: FailsafeConfig,
+        /// When the last command that decoded came in, over the [Failsafe::controller] link
+        last_command: Option<Instant>,
+        /// Link that last sent throttle, a setpoint or arming. Its timer is the one that
+        /// counts, the other link keeps talking without holding off the failsafe.
+        controller: Link,
+        /// Duty the motors ramp down from and since when
+        ramp: Option<([u16; 4], Instant)>,
+    }
+
+    /// Battery voltage, current and their alarms, see [battery_monitor]
+    pub struct Power {
+        config: BatteryConfig,
+        current: CurrentConfig,
+        /// Filtered, none without a battery
+        volts: Option<f32>,
+        /// Filtered, none without a current sensor
+        amps: Option<f32>,
+        alarm: BatteryAlarm,
+        /// Since boot or [Msg::ResetConsumed]
+        consumed_mah: f32,
+        /// Since the last arming
+        armed_mah: f32,
+        /// Latched until [Msg::ResetConsumed]
+        over_current: bool,
+    }
+
+    impl Power {
+        fn cell_volts(&self) -> Option<f32> {
+            self.volts.map(|v| v / self.config.cells as f32)
+        }
+
+        fn frame(&self) -> Option<Battery> {
+            let (volts, cell) = (self.volts?, self.cell_volts()?);
+            Some(Battery {
+                millivolts: (volts * 1000.0) as u16,
+                cell_millivolts: (cell * 1000.0) as u16,
+                alarm: self.alarm,
+                centiamps: self.amps.map(|a| (a * 100.0) as u16),
+                consumed_mah: self.consumed_mah as u32,
+                armed_mah: self.armed_mah as u32,
+                over_current: self.over_current,
+            })
+        }
+
+        /// Straight from an empty to a full LiPo cell, no more than a rough idea
+        fn remaining_percent(&self) -> u8 {
+            self.cell_volts().map_or(0, |v| ((v - MIN_CELL_VOLTS) / (MAX_CELL_VOLTS - MIN_CELL_VOLTS) * 100.0).max(0.0).min(100.0) as u8)
+        }
+    }
+
+    /// Arm state machine and the reasons to refuse arming. Only [Arm::Disarm] leaves
+    /// [ArmState::Failsafe], so a stale arm request can't arm again once the IMU is back.
+    /// [ArmState::Killed] outranks every other state, only [Arming::clear_stop] leaves it.
+    #[derive(Debug)]
+    pub struct Arming {
+        state: ArmState,
+        /// Sequence of the command that started [ArmState::Arming], its ack going out arms
+        arm_sequence: u16,
+        self_test_passed: bool,
+        imu_lost: bool,
+        /// Raw samples stream in place of the orientation
+        raw_stream: bool,
+        /// Tipped over or hit the ground, cleared by a disarm command
+        crashed: bool,
+        /// See [LockState::Locked]
+        locked: bool,
+    }
+
+    /// DWT cycles one stage of the gyro task, the telemetry or the receive interrupt took,
+    /// between two reports
+    #[derive(Debug, Clone, Copy)]
+    pub struct Stage {
+        min: u32,
+        max: u32,
+        sum: u64,
+        runs: u32,
+    }
+
+    impl Stage {
+        const fn new() -> Self {
+            Stage { min: u32::MAX, max: 0, sum: 0, runs: 0 }
+        }
+
+        fn add(&mut self, cycles: u32) {
+            self.min = self.min.min(cycles);
+            self.max = self.max.max(cycles);
+            self.sum += cycles as u64;
+            self.runs += 1;
+        }
+
+        /// Starts over for the next report
+        fn take(&mut self) -> Cycles {
+            let cycles = match self.runs {
+                0 => Cycles::default(),
+                runs => Cycles { min: self.min, mean: (self.sum / runs as u64) as u32, max: self.max },
+            };
+            *self = Stage::new();
+            cycles
+        }
+    }
+
+    /// Where the gyro task spends its time, see [CycleStats]
+    pub struct Stats {
+        i2c: Stage,
+        fusion: Stage,
+        total: Stage,
+        /// Cycle count when the FIFO batch was asked for
+        read_started: Option<u32>,
+    }
+
+    impl Stats {
+        const fn new() -> Self {
+            Stats { i2c: Stage::new(), fusion: Stage::new(), total: Stage::new(), read_started: None }
+        }
+
+        /// `receive` is kept by [on_rx] and `telemetry` by [send_telemetry], they report along with
+        /// the rest
+        fn take(&mut self, receive: Cycles, telemetry: Cycles) -> CycleStats {
+            CycleStats { i2c: self.i2c.take(), fusion: self.fusion.take(), telemetry, total: self.total.take(), receive }
+        }
+    }
+
+    impl Arming {
+        const fn new() -> Self {
+            Arming { state: ArmState::Disarmed, arm_sequence: 0, self_test_passed: false, imu_lost: false, raw_stream: false, crashed: false, locked: true }
+        }
+
+        fn allowed(&self) -> bool {
+            !matches!(self.state, ArmState::Failsafe | ArmState::Killed) && self.self_test_passed && !self.imu_lost && !self.raw_stream && !self.crashed
+        }
+
+        /// Motors may be enabled, or will be once the ack of the arm request is out
+        fn engaged(&self) -> bool {
+            matches!(self.state, ArmState::Arming | ArmState::Armed)
+        }
+
+        /// [Arm::Arm] from the command `sequence`, nothing changes once armed
+        fn request(&mut self, magic: u32, throttle: f32, sequence: u16) -> AckStatus {
+            if self.state == ArmState::Armed {
+                return AckStatus::Applied;
+            }
+            if self.locked {
+                rprintln!("arming refused, locked");
+                return AckStatus::Locked;
+            }
+            if magic != ARM_MAGIC || !(throttle <= MAX_ARM_THROTTLE) || !self.allowed() {
+                rprintln!("arming refused {:?}, throttle {}", self, throttle);
+                return AckStatus::NotArmed;
+            }
+            self.state = ArmState::Arming;
+            self.arm_sequence = sequence;
+            AckStatus::Applied
+        }
+
+        /// The ack for `sequence` went out, true when that completes arming
+        fn acked(&mut self, sequence: u16) -> bool {
+            if self.state == ArmState::Arming && self.arm_sequence == sequence && self.allowed() {
+                self.state = ArmState::Armed;
+                true
+            } else {
+                false
+            }
+        }
+
+        fn disarm(&mut self) {
+            if self.state != ArmState::Killed {
+                self.state = ArmState::Disarmed;
+            }
+            self.crashed = false;
+        }
+
+        fn failsafe(&mut self) {
+            if self.state != ArmState::Killed {
+                self.state = ArmState::Failsafe;
+            }
+        }
+
+        /// [Msg::Unlock], anything but [UNLOCK_CODE] stays locked
+        fn unlock(&mut self, code: u32) -> AckStatus {
+            if code != UNLOCK_CODE {
+                return AckStatus::Locked;
+            }
+            self.locked = false;
+            AckStatus::Applied
+        }
+
+        fn lock_state(&self) -> LockState {
+            if self.locked { LockState::Locked } else { LockState::Unlocked }
+        }
+
+        fn kill(&mut self) {
+            self.state = ArmState::Killed;
+        }
+
+        /// [Msg::ClearEmergencyStop], anything but [CLEAR_STOP_MAGIC] stays killed
+        fn clear_stop(&mut self, magic: u32) -> AckStatus {
+            if magic != CLEAR_STOP_MAGIC {
+                return AckStatus::NotArmed;
+            }
+            if self.state == ArmState::Killed {
+                self.state = ArmState::Disarmed;
+            }
+            AckStatus::Applied
+        }
+    }
+
+    /// Receive buffer the DMA writes round and round, two halves of `HALF` bytes. Never
+    /// stopped so nothing that comes in while the interrupt runs gets lost, each idle line
+    /// reads back what came since the last one.
+    pub struct RxRing<T, const HALF: usize> {
+        /// Keeps the transfer going, the bytes are read through `ring`
+        _transfer: T,
+        ring: *const u8,
+        /// Bytes the DMA has left before it starts over, the transfer keeps the channel so
+        /// its counter is read off the registers
+        remaining: fn() -> usize,
+        /// Same for starting it over at the beginning
+        restart: fn(),
+        read_at: usize,
+        /// Port the transfer reads, for its flags
+        usart: *const RegisterBlock,
+    }
+
+    // the pointer goes to the buffer the transfer owns, nothing but the receive interrupt reads it
+    unsafe impl<T, const HALF: usize> Send for RxRing<T, HALF> {}
+
+    impl<T, const HALF: usize> RxRing<T, HALF> {
+        fn new(
+            buf: &'static mut [[u8; HALF]; 2],
+            start: impl FnOnce(&'static mut [[u8; HALF]; 2]) -> T,
+            remaining: fn() -> usize,
+            restart: fn(),
+            usart: *const RegisterBlock,
+        ) -> Self {
+            let ring = buf.as_ptr() as *const u8;
+            RxRing { _transfer: start(buf), ring, remaining, restart, read_at: 0, usart }
+        }
+
+        /// Back to the start of the ring with nothing received, whatever was is dropped
+        fn restart(&mut self) {
+            (self.restart)();
+            self.read_at = 0;
+        }
+
+        /// Bytes in the order they came since the last call. More than a whole ring in
+        /// between overwrites the oldest, the decoder drops the frame they belonged to.
+        fn received(&mut self) -> impl Iterator<Item = u8> + Clone {
+            let size = 2 * HALF;
+            let (write_at, len) = ring_pending(self.read_at, (self.remaining)(), size);
+            let (ring, from) = (self.ring, self.read_at);
+            self.read_at = write_at;
+
+            (0..len).map(move |i| unsafe { core::ptr::read_volatile(ring.add((from + i) % size)) })
+        }
+    }
+
+    /// Where [take_in] gets the bytes of a command port from
+    pub trait CommandSource {
+        /// Every byte since the last call in the order they came, then the flags of the port
+        /// as they were, see [clear_idle]
+        fn take(&mut self, f: impl FnMut(u8)) -> sr::R;
+        /// Drops whatever is half received, after a line error
+        fn restart(&mut self);
+    }
+
+    impl<T, const HALF: usize> CommandSource for RxRing<T, HALF> {
+        fn take(&mut self, f: impl FnMut(u8)) -> sr::R {
+            let status = clear_idle(unsafe { &*self.usart });
+            self.received().for_each(f);
+            status
+        }
+
+        fn restart(&mut self) {
+            RxRing::restart(self)
+        }
+    }
+
+    /// USART1 without the DMA, an interrupt a byte, with the rx-irq feature
+    #[cfg(feature = "rx-irq")]
+    pub struct IrqSource {
+        /// Owned so nothing else reads the port, it is read through the registers
+        _rx: stm32f1xx_hal::serial::Rx<USART1>,
+    }
+
+    #[cfg(feature = "rx-irq")]
+    impl CommandSource for IrqSource {
+        fn take(&mut self, mut f: impl FnMut(u8)) -> sr::R {
+            let usart = unsafe { &*USART1::ptr() };
+            // the data register read clears the idle and error flags along with the byte
+            let status = usart.sr.read();
+            if status.rxne().bit_is_set() || status.idle().bit_is_set() {
+                let byte = usart.dr.read().bits() as u8;
+                if status.rxne().bit_is_set() {
+                    f(byte);
+                }
+            }
+            status
+        }
+
+        fn restart(&mut self) {}
+    }
+
+    /// A DMA1 transmit channel and the two halves it sends from: frames go into one while the
+    /// other is on its way out
+    pub struct TxDma {
+        bufs: &'static mut [[u8; TX_BUFFER_SIZE]; 2],
+        /// Half taking frames, the other one may be with the channel
+        filling: usize,
+        len: usize,
+        channel: fn() -> &'static CH,
+    }
+
+    impl TxDma {
+        fn new(bufs: &'static mut [[u8; TX_BUFFER_SIZE]; 2], channel: fn() -> &'static CH, usart: *const RegisterBlock) -> Self {
+            let usart = unsafe { &*usart };
+            let ch = channel();
+            ch.par.write(|w| unsafe { w.pa().bits(&usart.dr as *const _ as u32) });
+            // memory to the data register a byte at a time, done raises the interrupt
+            ch.cr.write(|w| w.minc().set_bit().dir().set_bit().tcie().set_bit());
+            usart.cr3.modify(|_, w| w.dmat().set_bit());
+            TxDma { bufs, filling: 0, len: 0, channel }
+        }
+
+        /// Copies `parts` in one after the other, all of them or none when the half has no room
+        fn push(&mut self, parts: &[&[u8]]) -> bool {
+            let len: usize = parts.iter().map(|p| p.len()).sum();
+            let buf = &mut self.bufs[self.filling];
+            if self.len + len > buf.len() {
+                return false;
+            }
+            for part in parts {
+                buf[self.len..self.len + part.len()].copy_from_slice(part);
+                self.len += part.len();
+            }
+            self.kick();
+            true
+        }
+
+        /// Hands the filled half to the channel, unless it is still sending the other one
+        fn kick(&mut self) {
+            let ch = (self.channel)();
+            if self.len == 0 || ch.ndtr.read().bits() != 0 {
+                return;
+            }
+            ch.cr.modify(|_, w| w.en().clear_bit());
+            ch.mar.write(|w| unsafe { w.ma().bits(self.bufs[self.filling].as_ptr() as u32) });
+            ch.ndtr.write(|w| w.ndt().bits(self.len as u16));
+            // the copy into the half lands before the channel reads it
+            compiler_fence(Ordering::Release);
+            ch.cr.modify(|_, w| w.en().set_bit());
+            self.filling ^= 1;
+            self.len = 0;
+        }
+
+        fn idle(&self) -> bool {
+            self.len == 0 && (self.channel)().ndtr.read().bits() == 0
+        }
+    }
+
+    /// Telemetry, replies and events out on USART1, and on USART2 as well with the wired-link
+    /// feature. A writer only copies its frame in, the DMA sends it, so the time a task takes
+    /// doesn't go with the baud rate.
+    pub struct TxLink {
+        bluetooth: TxDma,
+        /// The wired link, gets everything USART1 does. Not in [BYTES_SENT].
+        mirror: Option<TxDma>,
+        /// Owned so nothing writes to the port behind the DMA
+        _tx: Tx<USART1>,
+    }
+
+    impl TxLink {
+        /// Queues one frame from `parts`. Without room for all of it the frame is skipped and
+        /// counted in [FRAMES_SKIPPED], a frame cut short would spoil the next one too.
+        fn write(&mut self, parts: &[&[u8]]) {
+            if self.bluetooth.push(parts) {
+                BYTES_SENT.fetch_add(parts.iter().map(|p| p.len() as u32).sum(), Ordering::Relaxed);
+            } else {
+                FRAMES_SKIPPED.fetch_add(1, Ordering::Relaxed);
+            }
+            if let Some(mirror) = &mut self.mirror {
+                mirror.push(parts);
+            }
+        }
+
+        /// For the transfer complete interrupts, the next half goes if it has anything
+        fn kick(&mut self) {
+            self.bluetooth.kick();
+            if let Some(mirror) = &mut self.mirror {
+                mirror.kick();
+            }
+        }
+
+        /// Waits for everything queued on USART1 to be with the UART, before a baud rate
+        /// change. Up to a whole buffer of sending, there is only one while disarmed.
+        fn flush(&mut self) {
+            while !self.bluetooth.idle() {
+                self.bluetooth.kick();
+            }
+        }
+    }
+
+    #[cfg(not(feature = "rx-irq"))]
+    type CommandPort = RxRing<CircBuffer<[u8; RX_RING_SIZE / 2], RxDma1>, { RX_RING_SIZE / 2 }>;
+    #[cfg(feature = "rx-irq")]
+    type CommandPort = IrqSource;
+    type ReceiverRing = RxRing<CircBuffer<[u8; RECEIVER_RING_SIZE / 2], RxDma2>, { RECEIVER_RING_SIZE / 2 }>;
+
+    /// Status then data register, the way the reference manual clears the idle line flag.
+    /// The overrun, framing and noise flags go with it, the status it returns tells of them.
+    fn clear_idle(usart: &RegisterBlock) -> sr::R {
+        let status = usart.sr.read();
+        usart.dr.read();
+        status
+    }
+
+    /// USART1 rate and the bus clock it divides
+    pub struct Baud {
+        rate: u32,
+        pclk2: u32,
+    }
+
+    /// USART1 over to `rate` as it is, the DMA and the HAL halves don't notice. Waits for the
+    /// byte on the way out.
+    fn set_baud(baud: &mut Baud, rate: u32) {
+        let usart = unsafe { &*USART1::ptr() };
+        while usart.sr.read().tc().bit_is_clear() {}
+        usart.cr1.modify(|_, w| w.ue().clear_bit());
+        usart.brr.write(|w| unsafe { w.bits((baud.pclk2 + rate / 2) / rate) });
+        usart.cr1.modify(|_, w| w.ue().set_bit());
+        baud.rate = rate;
+    }
+
+    /// Takes the channel round from the start of its buffer, `len` bytes of it
+    fn restart_channel(channel: &CH, len: usize) {
+        channel.cr.modify(|_, w| w.en().clear_bit());
+        channel.ndtr.write(|w| w.ndt().bits(len as u16));
+        channel.cr.modify(|_, w| w.en().set_bit());
+    }
 
     #[shared]
-    struct Shared {}
+    struct Shared {
+        imu: Option<Imu>,
+        reader: Option<Reader>,
+        /// Only with the icm20602 feature, sampled right from the data ready interrupt
+        spi_imu: Option<SpiImu>,
+        altimeter: Option<Altimeter>,
+        usart1_tx: TxLink,
+        /// Telemetry for the RC receiver to send down
+        usart2_tx: Tx<USART2>,
+        arming: Arming,
+        /// Throttle on the motors, zero while disarmed
+        throttle: f32,
+        slew: Slew,
+        /// Receiver channels the controls are on, the one in flash from boot
+        rc_map: RcMap,
+        /// Raw and filtered gyro telemetry was asked for
+        gyro_debug: bool,
+        /// Quaternion frames were asked for in place of the angles
+        quaternion: bool,
+        /// Linear acceleration frames were asked for
+        linear_accel: bool,
+        /// Rate frames were asked for
+        rates: bool,
+        /// Compact orientation frames were asked for in place of the full precision ones
+        compact: bool,
+        /// Latest valid attitude setpoint, held for the controller and echoed in the meantime
+        setpoint: Setpoint,
+        pid: Pid,
+        trim: Trims,
+        /// Only [FlightMode::Passthrough] until there is a controller for the others
+        mode: FlightMode,
+        /// What [send_telemetry] sends and how often, the one in flash from boot
+        telemetry: TelemetryConfig,
+        failsafe: Failsafe,
+        power: Power,
+        /// Counted by [on_rx], [process_commands] and [link_watchdog], the bytes sent are in
+        /// [BYTES_SENT] and the frames skipped in [FRAMES_SKIPPED]
+        link: LinkStats,
+        /// Time [on_rx] takes, see [CycleStats::receive]
+        rx_cycles: Stage,
+        /// Time [send_telemetry] takes, see [CycleStats::telemetry]
+        tx_cycles: Stage,
+        /// Time the gyro task takes, the same as [CycleStats::total] but taken by
+        /// [extended_status] on its own period
+        loop_cycles: Stage,
+        /// How often [extended_status] reports, the one in flash from boot
+        status_period_ms: u16,
+        baud: Baud,
+        /// Replies to commands, written out by [send_telemetry] ahead of telemetry so they
+        /// don't cut into a frame
+        replies: Deque<Reply, REPLY_QUEUE>,
+        /// MAVLink numbering of [send_telemetry] and [repeat], one sequence for the link
+        mavlink: mavlink::Encoder,
+        /// Last commands [process_commands] took in, oldest first, for what the craft was told
+        /// before a failsafe or crash
+        command_log: Deque<CommandLogEntry, COMMAND_LOG>,
+        pwm: MFR,
+        en: EN,
+    }
 
     #[local]
     struct Local {
-        recv: Option<CircBuffer<[u8; COMMAND_SIZE], RxDma1>>,
-        usart1_tx: Tx<USART1>,
-        pwm: MFR,
+        recv: CommandPort,
+        /// Bytes [on_rx] copied off the ring
+        commands_in: Producer<'static, u8, COMMAND_QUEUE_SIZE>,
+        commands_out: Consumer<'static, u8, COMMAND_QUEUE_SIZE>,
+        /// Same for [receiver] with the wired link
+        wired_in: Producer<'static, u8, COMMAND_QUEUE_SIZE>,
+        wired_out: Consumer<'static, u8, COMMAND_QUEUE_SIZE>,
+        receiver_recv: ReceiverRing,
         count: u32,
         pwm_tim: CountDownTimer<TIM2>,
-        en: PB4<Output<PushPull>>,
+        mpu_int: PB12<Input<Floating>>,
+        flash: flash::Parts,
+        battery: battery::Monitor,
+        /// For [process_commands] to turn the cycle count of the interrupt into time
+        sysclk: u32,
     }
 
-    #[init]
+    #[init(local = [commands: Queue<u8, COMMAND_QUEUE_SIZE> = Queue::new(), wired: Queue<u8, COMMAND_QUEUE_SIZE> = Queue::new()])]
     fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
         rtt_init_print!();
 
         let dp = cx.device;
-        let cp = cx.core;
+        let mut cp = cx.core;
+
+        // cycle counter for timing the sampling path
+        cp.DCB.enable_trace();
+        cp.DWT.enable_cycle_counter();
 
         let mut flash = dp.FLASH.constrain();
 
@@ -69,6 +900,39 @@ mod app {
 
         let clocks = rcc.cfgr.freeze(&mut flash.acr);
 
+        let settings = match Settings::load(&flash.writer(SECTOR_SIZE, FLASH_SIZE)) {
+            Ok(s) => Some(s),
+            Err(e) => {
+                rprintln!("no settings loaded {:?}, code {:#06x}", e, e.code());
+                None
+            }
+        };
+        let gains = settings.map(|s| s.pid).filter(|p| p.iter().all(PidGains::is_valid)).unwrap_or_default();
+        let trim = settings.map(|s| s.trim).filter(Trim::is_valid).unwrap_or_default();
+        let telemetry = settings.map(|s| s.telemetry.clamped().0).unwrap_or_default();
+        let failsafe = Failsafe {
+            config: settings.map(|s| s.failsafe).filter(FailsafeConfig::is_valid).unwrap_or_default(),
+            last_command: None,
+            controller: Link::Bluetooth,
+            ramp: None,
+        };
+        let slew = Slew { commanded: 0.0, config: settings.map(|s| s.slew).filter(SlewConfig::is_valid).unwrap_or_default() };
+        let rc_map = settings.map(|s| s.rc_map).filter(RcMap::is_valid).unwrap_or_default();
+        let status_period_ms = settings
+            .map(|s| s.status_period_ms)
+            .filter(|ms| (MIN_STATUS_PERIOD_MS..=MAX_STATUS_PERIOD_MS).contains(ms))
+            .unwrap_or(DEFAULT_STATUS_PERIOD_MS);
+        let power = Power {
+            config: settings.map(|s| s.battery).filter(BatteryConfig::is_valid).unwrap_or_default(),
+            current: settings.map(|s| s.current).filter(CurrentConfig::is_valid).unwrap_or_default(),
+            volts: None,
+            amps: None,
+            alarm: BatteryAlarm::Ok,
+            consumed_mah: 0.0,
+            armed_mah: 0.0,
+            over_current: false,
+        };
+
         let mono: MyMono = Systick::new(cp.SYST, clocks.sysclk().0);
 
         // BLUETOOTH
@@ -82,17 +946,116 @@ mod app {
             dp.USART1,
             usart1_pins,
             &mut afio.mapr,
-            Config::default().baudrate(9600.bps()),
+            Config::default().baudrate(USART1_BAUD.bps()),
             clocks,
         );
         usart1.listen(Event::Idle);
+        if cfg!(feature = "rx-irq") {
+            usart1.listen(Event::Rxne);
+        }
+
+        let mut baud = Baud { rate: USART1_BAUD, pclk2: clocks.pclk2().0 };
+        if cfg!(feature = "hc05-setup") {
+            set_baud(&mut baud, crate::hc05::AT_BAUD);
+            let budget = clocks.sysclk().0 / 1000 * HC05_TIMEOUT_MS;
+            let rate = match crate::hc05::configure(&mut usart1, HC05_NAME, HC05_PIN, HC05_BAUD, budget) {
+                Ok(()) => {
+                    rprintln!("HC-05 set up as {}, power it up again with KEY low", HC05_NAME);
+                    HC05_BAUD
+                }
+                Err(crate::hc05::AtError::Timeout) => {
+                    rprintln!("HC-05 not in AT mode, taken as set up");
+                    HC05_BAUD
+                }
+                Err(e) => {
+                    rprintln!("HC-05 setup failed {:?}", e);
+                    USART1_BAUD
+                }
+            };
+            set_baud(&mut baud, rate);
+            if rate != USART1_BAUD {
+                baud_fallback::spawn_after(BAUD_FALLBACK_MS.millis()).ok();
+            }
+        }
+        rprintln!("link at {} baud", baud.rate);
 
         let dma1 = dp.DMA1.split();
         let (usart1_tx, rx) = usart1.split();
-        let rrx = rx.with_dma(dma1.5);
+        rprintln!("firmware {}", crate::version::BUILD_INFO);
+        build_info::spawn().ok();
+        announce::spawn_after(ANNOUNCE_DELAY_MS.millis()).ok();
+        // DMA1 channel 5 left to something else, see [IrqSource]
+        #[cfg(feature = "rx-irq")]
+        let command_port = IrqSource { _rx: rx };
+        #[cfg(not(feature = "rx-irq"))]
+        let command_port = {
+            let rrx = rx.with_dma(dma1.5);
+            let buf = cortex_m::singleton!(: [[u8; RX_RING_SIZE / 2]; 2] = [[0; RX_RING_SIZE / 2]; 2]).unwrap();
+            RxRing::new(
+                buf,
+                |buf| rrx.circ_read(buf),
+                || unsafe { (*DMA1::ptr()).ch5.ndtr.read().bits() } as usize,
+                || restart_channel(unsafe { &(*DMA1::ptr()).ch5 }, RX_RING_SIZE),
+                USART1::ptr(),
+            )
+        };
+        // overruns, framing and noise errors raise the interrupt as well, with the DMA on
+        unsafe { (*USART1::ptr()).cr3.modify(|_, w| w.eie().set_bit()) };
+        let (commands_in, commands_out) = cx.local.commands.split();
+        let (wired_in, wired_out) = cx.local.wired.split();
+
+        // RC RECEIVER
+        let usart2_pins = (
+            gpioa.pa2.into_alternate_push_pull(&mut gpioa.crl),
+            gpioa.pa3,
+        );
+
+        // SBUS is 8E2, needs an inverter in front of PA3 too, see common/src/sbus.rs
+        let receiver_config = if cfg!(feature = "wired-link") {
+            Config::default().baudrate(WIRED_BAUD.bps())
+        } else if RECEIVER == RcSource::Sbus {
+            Config::default().baudrate(sbus::BAUD.bps()).parity_even().stopbits(StopBits::STOP2)
+        } else {
+            Config::default().baudrate(crsf::BAUD.bps())
+        };
+        let mut usart2 = Serial::usart2(
+            dp.USART2,
+            usart2_pins,
+            &mut afio.mapr,
+            receiver_config,
+            clocks,
+        );
+        usart2.listen(Event::Idle);
+
+        let (usart2_tx, rx) = usart2.split();
+        let crx = rx.with_dma(dma1.6);
+
+        let buf = cortex_m::singleton!(: [[u8; RECEIVER_RING_SIZE / 2]; 2] = [[0; RECEIVER_RING_SIZE / 2]; 2]).unwrap();
+        let receiver_ring = RxRing::new(
+            buf,
+            |buf| crx.circ_read(buf),
+            || unsafe { (*DMA1::ptr()).ch6.ndtr.read().bits() } as usize,
+            || restart_channel(unsafe { &(*DMA1::ptr()).ch6 }, RECEIVER_RING_SIZE),
+            USART2::ptr(),
+        );
+        if cfg!(feature = "wired-link") {
+            unsafe { (*USART2::ptr()).cr3.modify(|_, w| w.eie().set_bit()) };
+        }
 
-        let buf = cortex_m::singleton!(: [[u8; COMMAND_SIZE]; 2] = [[0; COMMAND_SIZE]; 2]).unwrap();
-        let rx_transfer = rrx.circ_read(buf);
+        // BATTERY, through a divider on PA0 and the current sensor on PA1
+        let adc1 = Adc::adc1(dp.ADC1, clocks);
+        let battery = battery::Monitor::new(adc1, gpioa.pa0.into_analog(&mut gpioa.crl), gpioa.pa1.into_analog(&mut gpioa.crl));
+
+        let buf = cortex_m::singleton!(: [[u8; TX_BUFFER_SIZE]; 2] = [[0; TX_BUFFER_SIZE]; 2]).unwrap();
+        let bluetooth = TxDma::new(buf, || unsafe { &(*DMA1::ptr()).ch4 }, USART1::ptr());
+        #[cfg(feature = "wired-link")]
+        let mirror = {
+            let buf = cortex_m::singleton!(: [[u8; TX_BUFFER_SIZE]; 2] = [[0; TX_BUFFER_SIZE]; 2]).unwrap();
+            Some(TxDma::new(buf, || unsafe { &(*DMA1::ptr()).ch7 }, USART2::ptr()))
+        };
+        #[cfg(not(feature = "wired-link"))]
+        let mirror = None;
+        let usart1_tx = TxLink { bluetooth, mirror, _tx: usart1_tx };
 
         // GYRO
         let mut gpiob = dp.GPIOB.split();
@@ -101,47 +1064,94 @@ mod app {
             gpiob.pb11.into_alternate_open_drain(&mut gpiob.crh)
         );
 
-        let i2c2 = BlockingI2c::i2c2(
-            dp.I2C2,
-            i2c_pins,
-            Mode::Fast {
-                frequency: 400_000.hz(),
-                duty_cycle: DutyCycle::Ratio16to9,
-            },
-            clocks,
-            1000,
-            10,
-            1000,
-            1000,
-        );
+        let mut i2c2 = i2c2(dp.I2C2, i2c_pins, clocks);
+
+        let scan = scan_bus(&mut i2c2, clocks);
+        rprint!("i2c devices:");
+        scan.addresses().for_each(|a| rprint!(" {:#x}", a));
+        rprintln!("{}", if scan.dead { ", bus dead" } else { "" });
+        #[cfg(not(feature = "icm20602"))]
+        if !scan.found(mpu::ADDRESS) {
+            rprintln!("no device at {:#x}", mpu::ADDRESS);
+        }
+        boot_status::spawn(scan).ok();
+        link_watchdog::spawn().ok();
+        output::spawn().ok();
+        send_telemetry::spawn().ok();
+        repeat::spawn().ok();
+        battery_monitor::spawn().ok();
+        extended_status::spawn().ok();
+
+        // MPU6050 INT, rising edge on every new sample
+        let mut mpu_int = gpiob.pb12.into_floating_input(&mut gpiob.crh);
+        mpu_int.make_interrupt_source(&mut afio);
+        mpu_int.trigger_on_edge(&dp.EXTI, Edge::Rising);
+        #[cfg(not(feature = "polled"))]
+        mpu_int.enable_interrupt(&dp.EXTI);
+
+        if RC_SOURCE == RcSource::Ppm {
+            start_ppm(dp.TIM3, clocks);
+        }
 
         // PWM
         let (_, _, pb4) = afio.mapr.disable_jtag(gpioa.pa15, gpiob.pb3, gpiob.pb4);
         let mut en = pb4.into_push_pull_output(&mut gpiob.crl);
         en.set_low();
 
-        let mot1 = gpiob.pb8.into_alternate_push_pull(&mut gpiob.crh);
+        let motors = (
+            gpiob.pb6.into_alternate_push_pull(&mut gpiob.crl),
+            gpiob.pb7.into_alternate_push_pull(&mut gpiob.crl),
+            gpiob.pb8.into_alternate_push_pull(&mut gpiob.crh),
+            gpiob.pb9.into_alternate_push_pull(&mut gpiob.crh),
+        );
 
         let mut pwm_tim = Timer::tim2(dp.TIM2, &clocks)
             .start_count_down(1.hz());
         pwm_tim.listen(TEvent::Update);
 
-        let mut pwm = Timer::tim4(dp.TIM4, &clocks).pwm::<Tim4NoRemap, _, _, _>(mot1, &mut afio.mapr, 1.khz());
-        pwm.enable(Channel::C3);
+        let mut pwm = Timer::tim4(dp.TIM4, &clocks).pwm::<Tim4NoRemap, _, _, _>(motors, &mut afio.mapr, 1.khz());
+        for motor in MOTORS {
+            pwm.set_duty(motor, 0);
+            pwm.enable(motor);
+        }
 
         //
         let mpu = Mpu6050::new(i2c2);
-        mpu_init::spawn_after(1.secs(), mpu);
+        let bus = I2cBus { crh: gpiob.crh, clocks, errors: 0, recoveries: 0 };
+        #[cfg(not(feature = "icm20602"))]
+        mpu_init::spawn_after(1.secs(), mpu, bus, settings).ok();
+
+        #[cfg(feature = "icm20602")]
+        {
+            // the I2C bus stays idle
+            drop(mpu);
+
+            let spi_pins = (
+                gpioa.pa5.into_alternate_push_pull(&mut gpioa.crl),
+                gpioa.pa6.into_floating_input(&mut gpioa.crl),
+                gpioa.pa7.into_alternate_push_pull(&mut gpioa.crl),
+            );
+            let cs = gpioa.pa4.into_push_pull_output(&mut gpioa.crl);
+            // well below the 10 MHz the ICM-20602 takes, a sample is still read in ~150us
+            let spi = Spi::spi1(dp.SPI1, spi_pins, &mut afio.mapr, MODE_3, 1.mhz(), clocks);
+            icm_init::spawn_after(1.secs(), Icm20602::new(spi, cs), bus, settings).ok();
+        }
 
         (
-            Shared {},
+            Shared { imu: None, reader: None, spi_imu: None, altimeter: None, usart1_tx, usart2_tx, arming: Arming::new(), throttle: 0.0, slew, rc_map, gyro_debug: false, quaternion: false, linear_accel: false, rates: false, compact: false, setpoint: Setpoint::default(), pid: Pid { gains, stored: gains }, trim: Trims { trim, stored: trim }, mode: FlightMode::Passthrough, telemetry, failsafe, power, link: LinkStats::new(), rx_cycles: Stage::new(), tx_cycles: Stage::new(), loop_cycles: Stage::new(), status_period_ms, baud, replies: Deque::new(), mavlink: mavlink::Encoder::new(MAVLINK_SYSTEM_ID, MAVLINK_COMPONENT_ID), command_log: Deque::new(), pwm, en },
             Local {
-                recv: Some(rx_transfer),
-                usart1_tx,
-                pwm,
+                recv: command_port,
+                commands_in,
+                commands_out,
+                wired_in,
+                wired_out,
+                receiver_recv: receiver_ring,
                 count: 0,
                 pwm_tim,
-                en,
+                mpu_int,
+                flash,
+                battery,
+                sysclk: clocks.sysclk().0,
             },
             init::Monotonics(mono),
         )
@@ -164,67 +1174,2662 @@ mod app {
     //     rprintln!("INTERRUPT CLEAR");
     // }
 
-    #[task]
-    fn mpu_init(_: mpu_init::Context, mut mpu: MPU) {
-        mpu.init().expect("unable to init MPU6050");
+    fn i2c2(i2c: I2C2, pins: I2cPins, clocks: Clocks) -> BlockingI2c<I2C2, I2cPins> {
+        BlockingI2c::i2c2(
+            i2c,
+            pins,
+            Mode::Fast {
+                frequency: I2C_FREQUENCY_HZ.hz(),
+                duty_cycle: DutyCycle::Ratio16to9,
+            },
+            clocks,
+            1000,
+            10,
+            1000,
+            1000,
+        )
+    }
+
+    /// Empty write to every address, stops at anything other than a NACK or once it ran
+    /// out of time so a dead bus can't hold up boot
+    fn scan_bus(i2c: &mut BlockingI2c<I2C2, I2cPins>, clocks: Clocks) -> BusScan {
+        let mut scan = BusScan::new();
+        let budget = clocks.sysclk().0 / 1000 * SCAN_TIMEOUT_MS;
+        let start = DWT::cycle_count();
 
-        let offset = (0..2000)
-            .flat_map(|_| mpu.get_gyro().ok())
-            .reduce(|l, r| (l + r) / 2.0)
-            .expect("no calibration measurements");
-        let angles = mpu.get_acc_angles().expect("unable to get acc angles");
+        for address in BUS_SCAN_FIRST..=BUS_SCAN_LAST {
+            match i2c.write(address, &[]) {
+                Ok(()) => scan.set(address),
+                Err(nb::Error::Other(i2c::Error::Acknowledge)) => {}
+                Err(e) => {
+                    rprintln!("i2c scan stopped at {:#x} {:?}", address, e);
+                    scan.dead = true;
+                    break;
+                }
+            }
+            if DWT::cycle_count().wrapping_sub(start) > budget {
+                rprintln!("i2c scan out of time at {:#x}", address);
+                scan.dead = true;
+                break;
+            }
+        }
+        scan
+    }
 
-        let spatial_orientation = SpatialOrientation::new(angles);
+    /// Repeats the bus scan for a ground station connecting late, it matters most when
+    /// the IMU never came up
+    #[task(shared = [usart1_tx])]
+    fn boot_status(mut cx: boot_status::Context, scan: BusScan) {
+        cx.shared.usart1_tx.lock(|tx| write_frame(tx, &scan.to_byte_array()));
+        boot_status::spawn_after(1.secs(), scan).ok();
+    }
 
-        gyro::spawn(mpu, offset, spatial_orientation);
+    /// HAL 0.8 has no way back out of a `BlockingI2c`. The peripheral and the pins are zero
+    /// sized tokens, so once the driver is forgotten they can be taken up again
+    fn release(i2c: BlockingI2c<I2C2, I2cPins>) -> (I2C2, I2cPins) {
+        const _: () = assert!(core::mem::size_of::<I2cPins>() == 0);
+        core::mem::forget(i2c);
+        // the forgotten driver was their only owner
+        unsafe { (stm32f1xx_hal::pac::Peripherals::steal().I2C2, core::mem::zeroed()) }
     }
 
-    #[task(local = [usart1_tx], capacity = 1)]
-    fn gyro(cx: gyro::Context, mut mpu: MPU, offset: Vector3<f32>, mut s: SpatialOrientation) {
-        let tx: &mut Tx<USART1> = cx.local.usart1_tx;
-        let spawn_next_at = monotonics::now() + 4.micros();
+    /// Clocks out whatever a slave still holds SDA low for and re-creates the peripheral
+    fn recover_bus(i2c: I2C2, (scl, sda): I2cPins, bus: &mut I2cBus) -> MPU {
+        bus.recoveries += 1;
+        rprintln!("i2c recovery {}", bus.recoveries);
 
-        let raw_gyro = mpu.get_gyro().expect("unable to get gyro");
-        let angles = mpu.get_acc_angles().expect("unable to get acc angles");
+        let half_period = bus.clocks.sysclk().0 / 200_000;
 
-        s.adjust(raw_gyro - offset, angles);
+        let mut scl = scl.into_open_drain_output(&mut bus.crh);
+        let mut sda = sda.into_open_drain_output(&mut bus.crh);
+        sda.set_high();
+        for _ in 0..9 {
+            scl.set_low();
+            cortex_m::asm::delay(half_period);
+            scl.set_high();
+            cortex_m::asm::delay(half_period);
+        }
+        // stop condition while SCL is high
+        sda.set_low();
+        cortex_m::asm::delay(half_period);
+        sda.set_high();
+        cortex_m::asm::delay(half_period);
 
-        // rprintln!("{:?}", s);
-        IntoIterator::into_iter(s.to_byte_array()).for_each(|byt| { nb::block!(tx.write(byt)).unwrap() });
-        nb::block!(tx.write(EOT)).unwrap();
+        let pins = (
+            scl.into_alternate_open_drain(&mut bus.crh),
+            sda.into_alternate_open_drain(&mut bus.crh),
+        );
+        Mpu6050::new(i2c2(i2c, pins, bus.clocks))
+    }
 
-        gyro::spawn_at(spawn_next_at, mpu, offset, s);
+    fn with_retries<T>(mpu: &mut MPU, bus: &mut I2cBus, f: impl Fn(&mut MPU) -> Result<T, MpuError>) -> Result<T, MpuError> {
+        let mut attempt = 0;
+        loop {
+            match f(mpu) {
+                Ok(v) => return Ok(v),
+                Err(e) => {
+                    bus.errors += 1;
+                    rprintln!("i2c error {:?}, {} total", e, bus.errors);
+                    if attempt == I2C_RETRIES {
+                        return Err(e);
+                    }
+                    attempt += 1;
+                }
+            }
+        }
     }
 
-    #[task(binds = USART1, local = [recv, pwm, en], priority = 2)]
-    fn on_rx(cx: on_rx::Context) {
-        if let Some(rx) = cx.local.recv.take() {
-            let (buf, mut rx) = rx.stop();
-            let len = (buf[0].len() as u32 * 2) - rx.channel.ch().ndtr.read().bits();
+    /// Retries `f`, then recovers the bus once and retries again
+    fn with_recovery<T>(mpu: MPU, bus: &mut I2cBus, f: impl Fn(&mut MPU) -> Result<T, MpuError>) -> (MPU, Result<T, MpuError>) {
+        let mut mpu = mpu;
+        match with_retries(&mut mpu, bus, &f) {
+            Ok(v) => (mpu, Ok(v)),
+            Err(_) => {
+                let (i2c, pins) = release(mpu.release());
+                let mut mpu = recover_bus(i2c, pins, bus);
+                // the device may have been reset along with the bus
+                let result = with_retries(&mut mpu, bus, MPU::configure)
+                    .and_then(|_| with_retries(&mut mpu, bus, &f));
+                (mpu, result)
+            }
+        }
+    }
+
+    /// Hands the bus over to the interrupt driven reader
+    fn stream(mpu: MPU) -> Reader {
+        let (i2c, pins) = release(mpu.release());
+        InterruptI2c::new(i2c, pins, mpu::ADDRESS)
+    }
 
-            let command = Command::from_byte_slice(&buf[0]);
-            rprintln!("got {:?}", command);
+    fn disarm(pwm: &mut MFR, en: &mut EN) {
+        en.set_low();
+        set_motors(pwm, [0; 4]);
+    }
 
-            // todo: find a better way
-            // workaround malformed packet
-            if command.throttle_on {
-                cx.local.en.set_high();
-            } else {
-                cx.local.en.set_low();
+    fn set_motors(pwm: &mut MFR, duty: [u16; 4]) {
+        for (motor, duty) in MOTORS.iter().zip(duty) {
+            pwm.set_duty(*motor, duty);
+        }
+    }
+
+    fn calibrate_gyro(imu: &mut impl ImuDevice, clocks: Clocks, range: GyroRange) -> Result<Vec3, CalibrationError> {
+        let period = clocks.sysclk().0 / GYRO_FREQUENCY_HZ;
+        imu.calibrate(range, || cortex_m::asm::delay(period))
+    }
+
+    /// Self-test, then the configuration it clobbered
+    fn self_test(mpu: &mut MPU, clocks: Clocks) -> Result<SelfTestDeviation, MpuError> {
+        let period = clocks.sysclk().0 / GYRO_FREQUENCY_HZ;
+        let deviation = mpu.self_test(|| cortex_m::asm::delay(period))?;
+        mpu.configure()?;
+        Ok(deviation)
+    }
+
+    #[task(shared = [imu, reader, altimeter, usart1_tx, arming, pwm, en])]
+    fn mpu_init(cx: mpu_init::Context, mpu: MPU, mut bus: I2cBus, settings: Option<Settings>) {
+        let (mut imu, mut reader, mut pwm, mut en) = (cx.shared.imu, cx.shared.reader, cx.shared.pwm, cx.shared.en);
+        let (mut altimeter, mut tx, mut allowed) = (cx.shared.altimeter, cx.shared.usart1_tx, cx.shared.arming);
+
+        let clocks = bus.clocks;
+        let (mpu, configured) = with_recovery(mpu, &mut bus, MPU::configure);
+        let (mut mpu, first) = match configured {
+            Ok(()) => with_recovery(mpu, &mut bus, |mpu| Ok((self_test(mpu, clocks)?, mpu.read_sample()?))),
+            Err(e) => (mpu, Err(e)),
+        };
+        let (deviation, angles) = match first {
+            Ok((deviation, sample)) => (deviation, acc_angles(MOUNTING.apply(sample.acc))),
+            Err(e) => {
+                rprintln!("unable to init MPU6050 {:?}", Error::from(e));
+                (&mut pwm, &mut en).lock(|pwm, en| disarm(pwm, en));
+                return;
             }
+        };
 
-            if command.throttle <= 1.0 && command.throttle >= 0.0 {
-                let max_duty = cx.local.pwm.get_max_duty();
-                let duty = (max_duty as f32 * command.throttle) as u16;
-                cx.local.pwm.set_duty(Channel::C3, duty);
-                rprintln!("duty {}", duty);
+        // the link and telemetry keep working either way, only arming is refused
+        let self_test = deviation.check();
+        rprintln!("self-test gyro {:?} accel {:?}", deviation.gyro, deviation.accel);
+        if self_test.passed() {
+            rprintln!("self-test passed");
+        } else {
+            rprintln!("{:?} {:?}, arming disabled", Error::Sensor(SensorError::SelfTest), self_test);
+        }
+        allowed.lock(|arming| arming.self_test_passed = self_test.passed());
+        tx.lock(|tx| write_frame(tx, &self_test.to_byte_array()));
+
+        match mpu.get_dlpf() {
+            Ok(Some(dlpf)) => rprintln!("DLPF {} Hz", dlpf.hz()),
+            Ok(None) => rprintln!("DLPF reserved"),
+            Err(e) => rprintln!("unable to read DLPF {:?}", e),
+        }
+        match mpu.get_sample_rate() {
+            Ok(hz) if hz == GYRO_FREQUENCY_HZ => rprintln!("sample rate {} Hz, telemetry {} Hz", hz, TELEMETRY_FREQUENCY_HZ),
+            Ok(hz) => rprintln!("sample rate {} Hz instead of {} Hz", hz, GYRO_FREQUENCY_HZ),
+            Err(e) => rprintln!("unable to read sample rate {:?}", e),
+        }
+
+        // a blocking read of one sample costs more than draining it from the FIFO,
+        // it should still leave half of the period to everything else
+        let period = clocks.sysclk().0 / GYRO_FREQUENCY_HZ;
+        let start = DWT::cycle_count();
+        if mpu.read_sample().is_ok() {
+            let cycles = DWT::cycle_count().wrapping_sub(start);
+            if cycles > period / 2 {
+                rprintln!("one sample takes {} of {} cycles, {} Hz is not sustainable", cycles, period, GYRO_FREQUENCY_HZ);
+            }
+        }
+
+        let gyro_range = GYRO_RANGE;
+        rprintln!("gyro range +-{} dps", gyro_range.dps());
+        rprintln!("accel range +-{} g", ACCEL_RANGE.g());
+
+        let saturation = Saturation::new();
+        let (offset, accel, mag_cal) = match settings {
+            Some(s) => {
+                rprintln!("loaded calibration {:?}", s);
+                (s.gyro_offset, s.accel, s.mag)
             }
+            None => match tx.lock(|tx| {
+                write_frame(tx, &allowed.lock(|a| status(false, &saturation, a, true)).to_byte_array());
+                let offset = calibrate_gyro(&mut mpu, bus.clocks, gyro_range);
+                write_frame(tx, &allowed.lock(|a| status(false, &saturation, a, false)).to_byte_array());
+                offset
+            }) {
+                Ok(offset) => {
+                    let (accel, mag) = (AccelCalibration::identity(), MagCalibration::identity());
+                    persist::spawn(Settings::calibration(offset, accel, mag, filter_settings(&Estimator::from_acc(angles)), CRASH_TILT)).ok();
+                    (offset, accel, mag)
+                }
+                Err(e) => {
+                    rprintln!("unable to calibrate gyro {:?}", Error::from(e));
+                    (&mut pwm, &mut en).lock(|pwm, en| disarm(pwm, en));
+                    return;
+                }
+            },
+        };
+
+        let mut mag = Mag::probe(mpu.bus());
+        match &mut mag {
+            Some(m) => match m.init(mpu.bus()) {
+                Ok(()) => rprintln!("magnetometer at {:#x}", m.address()),
+                Err(e) => {
+                    rprintln!("unable to init magnetometer {:?}", e);
+                    mag = None;
+                }
+            },
+            None => rprintln!("no magnetometer"),
+        }
 
-            let (rx, channel) = rx.release();
-            rx.clear_idle_interrupt();
-            let rx = rx.with_dma(channel);
+        let mut baro = Baro::probe(mpu.bus());
+        match &mut baro {
+            Some(b) => match b.init(mpu.bus()) {
+                Ok(()) => rprintln!("barometer at {:#x}", b.address()),
+                Err(e) => {
+                    rprintln!("unable to init barometer {:?}", e);
+                    baro = None;
+                }
+            },
+            None => rprintln!("no barometer"),
+        }
 
-            cx.local.recv.replace(rx.circ_read(buf));
+        let mut estimator = Estimator::from_acc(angles);
+        if let Some(s) = settings {
+            apply_filter(&mut estimator, s.filter);
+        }
+        let orientation = estimator.orientation();
+        let crash = CrashDetector::new(settings.map(|s| s.crash_tilt).filter(|t| valid_crash_tilt(*t)).unwrap_or(CRASH_TILT));
+
+        #[cfg(not(feature = "dmp"))]
+        let dmp = false;
+        #[cfg(feature = "dmp")]
+        let (dmp, gyro_range, offset) = match dmp::load(&mut mpu) {
+            // the offset only serves saturation detection and a fallback from here on
+            Ok(()) => {
+                rprintln!("DMP running, {} Hz", DMP_FREQUENCY_HZ);
+                (true, dmp::DMP_GYRO_RANGE, gyro_range.rescale(offset, dmp::DMP_GYRO_RANGE))
+            }
+            Err(e) => {
+                rprintln!("unable to load DMP {:?}, using the software filter", e);
+                if let Err(e) = mpu.configure() {
+                    rprintln!("unable to configure MPU6050 {:?}", e);
+                }
+                (false, gyro_range, offset)
+            }
+        };
+
+        imu.lock(|imu| *imu = Some(Imu { bus, gyro_range, offset, accel, six_position: None, self_test: Some(self_test), orientation, mag, mag_cal, mag_sweep: None, field: None, heading: None, heading_hold: None, batches: 0, last_batch: None, notch: Notch::new(), saturation, estimator, crash, dmp_yaw_zero: 0.0, vertical: None, bias: BiasTracker::new(), dmp, resync: false, rearm: false, lost: false, last_sample: None, rates: None, debug: None }));
+        reader.lock(|reader| *reader = Some(stream(mpu)));
+
+        if let Some(b) = baro {
+            altimeter.lock(|altimeter| *altimeter = Some(Altimeter::new(b)));
+            altitude::spawn().ok();
+        }
+
+        health::spawn().ok();
+
+        #[cfg(feature = "polled")]
+        poll::spawn().ok();
+    }
+
+    /// Brings up the ICM-20602, it has no factory trims to self-test against. Only the
+    /// MPU6050 on I2C is reconnected when lost, recalibrated or joined by a magnetometer
+    /// and barometer.
+    #[task(shared = [imu, spi_imu, usart1_tx, arming, pwm, en])]
+    fn icm_init(cx: icm_init::Context, mut icm: SpiImu, bus: I2cBus, settings: Option<Settings>) {
+        let (mut imu, mut spi_imu, mut pwm, mut en) = (cx.shared.imu, cx.shared.spi_imu, cx.shared.pwm, cx.shared.en);
+        let (mut tx, mut allowed) = (cx.shared.usart1_tx, cx.shared.arming);
+
+        // same budget as an I2C transaction gets
+        let mut configured = icm.configure();
+        for _ in 0..I2C_RETRIES {
+            if configured.is_ok() {
+                break;
+            }
+            configured = icm.configure();
+        }
+        let angles = match configured.and_then(|_| icm.read_sample()) {
+            Ok(sample) => acc_angles(MOUNTING.apply(sample.acc)),
+            Err(e) => {
+                rprintln!("unable to init ICM-20602 {:?}", e);
+                (&mut pwm, &mut en).lock(|pwm, en| disarm(pwm, en));
+                return;
+            }
+        };
+        allowed.lock(|arming| arming.self_test_passed = true);
+
+        rprintln!("sample rate {} Hz, telemetry {} Hz", GYRO_FREQUENCY_HZ, TELEMETRY_FREQUENCY_HZ);
+        let gyro_range = GYRO_RANGE;
+        rprintln!("gyro range +-{} dps", gyro_range.dps());
+        rprintln!("accel range +-{} g", ACCEL_RANGE.g());
+
+        let saturation = Saturation::new();
+        let (offset, accel, mag_cal) = match settings {
+            Some(s) => {
+                rprintln!("loaded calibration {:?}", s);
+                (s.gyro_offset, s.accel, s.mag)
+            }
+            None => match tx.lock(|tx| {
+                write_frame(tx, &allowed.lock(|a| status(false, &saturation, a, true)).to_byte_array());
+                let offset = calibrate_gyro(&mut icm, bus.clocks, gyro_range);
+                write_frame(tx, &allowed.lock(|a| status(false, &saturation, a, false)).to_byte_array());
+                offset
+            }) {
+                Ok(offset) => {
+                    let (accel, mag) = (AccelCalibration::identity(), MagCalibration::identity());
+                    persist::spawn(Settings::calibration(offset, accel, mag, filter_settings(&Estimator::from_acc(angles)), CRASH_TILT)).ok();
+                    (offset, accel, mag)
+                }
+                Err(e) => {
+                    rprintln!("unable to calibrate gyro {:?}", Error::from(e));
+                    (&mut pwm, &mut en).lock(|pwm, en| disarm(pwm, en));
+                    return;
+                }
+            },
+        };
+
+        let mut estimator = Estimator::from_acc(angles);
+        if let Some(s) = settings {
+            apply_filter(&mut estimator, s.filter);
+        }
+        let orientation = estimator.orientation();
+        let crash = CrashDetector::new(settings.map(|s| s.crash_tilt).filter(|t| valid_crash_tilt(*t)).unwrap_or(CRASH_TILT));
+
+        imu.lock(|imu| *imu = Some(Imu { bus, gyro_range, offset, accel, six_position: None, self_test: None, orientation, mag: None, mag_cal, mag_sweep: None, field: None, heading: None, heading_hold: None, batches: 0, last_batch: None, notch: Notch::new(), saturation, estimator, crash, dmp_yaw_zero: 0.0, vertical: None, bias: BiasTracker::new(), dmp: false, resync: false, rearm: false, lost: false, last_sample: None, rates: None, debug: None }));
+        spi_imu.lock(|spi_imu| *spi_imu = Some(icm));
+
+        health::spawn().ok();
+
+        #[cfg(feature = "polled")]
+        poll::spawn().ok();
+    }
+
+    /// Re-creates the bus and configures the MPU6050, the DMP and magnetometer again
+    fn reconnect(bus: &mut I2cBus, mag: &mut Option<Mag>, dmp: &mut bool, r: Reader) -> (Reader, Result<(), MpuError>) {
+        let (i2c, pins) = r.release();
+        let mut mpu = recover_bus(i2c, pins, bus);
+
+        let result = with_retries(&mut mpu, bus, MPU::configure);
+        #[cfg(feature = "dmp")]
+        if let (Ok(()), true) = (&result, *dmp) {
+            if let Err(e) = dmp::load(&mut mpu) {
+                // the gyro range stays at what the DMP needed, offset and fusion go along with it
+                rprintln!("unable to reload DMP {:?}, using the software filter", e);
+                *dmp = false;
+                let _ = with_retries(&mut mpu, bus, |mpu| mpu.configure().and_then(|_| mpu.set_gyro_full_scale(dmp::DMP_GYRO_RANGE)));
+            }
+        }
+        #[cfg(not(feature = "dmp"))]
+        let _ = dmp;
+        if let (Ok(()), Some(m)) = (&result, mag) {
+            if let Err(e) = m.init(mpu.bus()) {
+                rprintln!("unable to init magnetometer {:?}", e);
+            }
+        }
+
+        (stream(mpu), result)
+    }
+
+    /// The [Status] frame as it stands, with `lost` for the IMU. The crash stays latched
+    /// until a disarm and saturation until the accelerometer pulled the angles back, both
+    /// go out as they are through a calibration too.
+    fn status(lost: bool, saturation: &Saturation, arming: &Arming, calibrating: bool) -> Status {
+        Status { calibrating, imu_lost: lost, gyro_saturated: saturation.saturated(), reset_skipped: false, crashed: arming.crashed }
+    }
+
+    /// Cuts the motors and fails safe when the IMU is lost, reports changes either way.
+    /// Never arms again on its own.
+    fn set_imu_lost(imu: &mut Imu, lost: bool, arming: &mut Arming, pwm: &mut MFR, en: &mut EN, tx: &mut TxLink) {
+        if lost {
+            disarm(pwm, en);
+            if !matches!(arming.state, ArmState::Failsafe | ArmState::Killed) {
+                arming.failsafe();
+                report_arm_state::spawn().ok();
+                print_command_log::spawn().ok();
+            }
+        }
+        arming.imu_lost = lost;
+
+        if imu.lost != lost {
+            imu.lost = lost;
+            imu.resync = true;
+            if lost {
+                rprintln!("{:?}, disarmed", Error::Sensor(SensorError::Lost));
+            } else {
+                rprintln!("IMU back, arm again to continue");
+            }
+            write_frame(tx, &status(lost, &imu.saturation, arming, false).to_byte_array());
+        }
+    }
+
+    /// Catches sampling that stalls without any bus error, as it does once the MPU6050
+    /// loses power or its INT line, and keeps trying to reach a lost one
+    #[task(local = [last: u32 = 0, stalled: u32 = 0, periods: u32 = 0], shared = [imu, reader, usart1_tx, arming, pwm, en])]
+    fn health(cx: health::Context) {
+        let spawn_next_at = monotonics::now() + HEALTH_PERIOD_MS.millis();
+
+        let (last, stalled, periods) = (cx.local.last, cx.local.stalled, cx.local.periods);
+        let (mut imu, mut reader, mut tx) = (cx.shared.imu, cx.shared.reader, cx.shared.usart1_tx);
+        let (mut arming, mut pwm, mut en) = (cx.shared.arming, cx.shared.pwm, cx.shared.en);
+
+        (&mut imu, &mut tx).lock(|imu, tx| {
+            if let Some(imu) = imu {
+                *stalled = if imu.batches == *last { *stalled + 1 } else { 0 };
+                *last = imu.batches;
+
+                if !imu.lost && *stalled >= STALLED_PERIODS {
+                    rprintln!("sampling stalled");
+                    (&mut arming, &mut pwm, &mut en).lock(|arming, pwm, en| set_imu_lost(imu, true, arming, pwm, en, tx));
+                }
+
+                if imu.lost {
+                    *periods += 1;
+                    if *periods % REPROBE_PERIODS != 0 {
+                        return;
+                    }
+
+                    if let Some(r) = reader.lock(|reader| reader.take()) {
+                        let (r, result) = reconnect(&mut imu.bus, &mut imu.mag, &mut imu.dmp, r);
+                        reader.lock(|reader| *reader = Some(r));
+
+                        if result.is_ok() {
+                            *stalled = 0;
+                            (&mut arming, &mut pwm, &mut en).lock(|arming, pwm, en| set_imu_lost(imu, false, arming, pwm, en, tx));
+                        }
+                    }
+                }
+            }
+        });
+
+        health::spawn_at(spawn_next_at).ok();
+    }
+
+    /// One barometer transfer per tick, squeezed in between the FIFO reads
+    #[task(shared = [reader, altimeter])]
+    fn altitude(cx: altitude::Context) {
+        let spawn_next_at = monotonics::now() + (1000 / BARO_FREQUENCY_HZ as u64).millis();
+
+        (cx.shared.reader, cx.shared.altimeter).lock(|reader, altimeter| {
+            if let (Some(r), Some(a)) = (reader, altimeter) {
+                let address = a.baro.address();
+                let started = match a.baro.step() {
+                    Step::Read(reg, len) => r.read_from(address, reg, len),
+                    Step::Write(reg, value) => r.write_to(address, reg, value),
+                };
+                match started {
+                    Ok(()) => a.baro.advance(),
+                    // the bus is busy with samples, try again next tick
+                    Err(i2c_irq::Error::Busy) => {}
+                    Err(e) => {
+                        gyro::spawn(Err(e)).ok();
+                    }
+                }
+            }
+        });
+
+        altitude::spawn_at(spawn_next_at).ok();
+    }
+
+    /// Takes the bus from the reader for a fresh gyro offset capture, sampling
+    /// stops meanwhile and starts over from the accelerometer angles
+    #[task(shared = [imu, reader, usart1_tx, arming])]
+    fn recalibrate(cx: recalibrate::Context) {
+        let (mut imu, mut reader, mut tx, mut arming) = (cx.shared.imu, cx.shared.reader, cx.shared.usart1_tx, cx.shared.arming);
+
+        (&mut imu, &mut tx).lock(|imu, tx| {
+            if let Some(Imu { bus, gyro_range, offset, accel, mag_cal, orientation, estimator, crash, saturation, lost, .. }) = imu {
+                if let Some(r) = reader.lock(|reader| reader.take()) {
+                    write_frame(tx, &arming.lock(|a| status(*lost, saturation, a, true)).to_byte_array());
+
+                    let (i2c, pins) = r.release();
+                    let mut mpu = Mpu6050::new(i2c2(i2c, pins, bus.clocks));
+
+                    match calibrate_gyro(&mut mpu, bus.clocks, *gyro_range) {
+                        Ok(o) => {
+                            *offset = o;
+                            persist::spawn(stored_settings(*gyro_range, o, *accel, *mag_cal, estimator, crash)).ok();
+                        }
+                        Err(e) => rprintln!("unable to calibrate gyro {:?}, keeping the old offset", Error::from(e)),
+                    }
+
+                    // the craft was picked up for this, integrated angles are stale
+                    match mpu.read_sample() {
+                        Ok(sample) => {
+                            estimator.reset(acc_angles(MOUNTING.apply(accel.apply(sample.acc))));
+                            *orientation = estimator.orientation();
+                        }
+                        Err(e) => rprintln!("unable to read MPU6050 {:?}", Error::from(e)),
+                    }
+
+                    reader.lock(|reader| *reader = Some(stream(mpu)));
+                    write_frame(tx, &arming.lock(|a| status(*lost, saturation, a, false)).to_byte_array());
+                }
+            }
+        });
+    }
+
+    /// Current heading becomes yaw zero, for the software estimator and the DMP alike
+    #[task(shared = [imu])]
+    fn zero_yaw(mut cx: zero_yaw::Context) {
+        cx.shared.imu.lock(|imu| {
+            if let Some(Imu { orientation, estimator, dmp, dmp_yaw_zero, .. }) = imu {
+                if *dmp {
+                    *dmp_yaw_zero = wrap_angle(*dmp_yaw_zero + orientation.yaw);
+                } else {
+                    estimator.zero_yaw();
+                }
+                orientation.yaw = 0.0;
+                rprintln!("yaw zeroed");
+            }
+        });
+    }
+
+    /// Latest magnetic heading becomes the reference of [Heading] frames
+    #[task(shared = [imu])]
+    fn hold_heading(mut cx: hold_heading::Context) {
+        cx.shared.imu.lock(|imu| {
+            if let Some(Imu { heading, heading_hold, .. }) = imu {
+                match heading {
+                    Some(h) => {
+                        *heading_hold = Some(*h);
+                        rprintln!("holding heading {}", h.to_degrees());
+                    }
+                    None => rprintln!("no magnetic heading to hold"),
+                }
+            }
+        });
+    }
+
+    /// Re-seeds the attitude on arming, see [Imu::rearm]
+    #[task(shared = [imu])]
+    fn rearm(mut cx: rearm::Context) {
+        cx.shared.imu.lock(|imu| {
+            if let Some(imu) = imu {
+                imu.rearm = true;
+            }
+        });
+    }
+
+    /// Next face of the six position calibration, started over once all of them are in
+    #[task(shared = [imu, usart1_tx, arming])]
+    fn accel_capture(cx: accel_capture::Context) {
+        let mut arming = cx.shared.arming;
+        (cx.shared.imu, cx.shared.usart1_tx).lock(|imu, tx| {
+            if let Some(Imu { six_position, saturation, lost, .. }) = imu {
+                let cal = six_position.get_or_insert_with(SixPosition::new);
+                if !cal.capturing() {
+                    cal.start();
+                    write_frame(tx, &arming.lock(|a| status(*lost, saturation, a, true)).to_byte_array());
+                }
+            }
+        });
+    }
+
+    /// Starts the magnetometer sweep, the craft has to be turned through every orientation until it ends
+    #[task(shared = [imu])]
+    fn mag_capture(mut cx: mag_capture::Context) {
+        cx.shared.imu.lock(|imu| {
+            if let Some(Imu { mag, mag_sweep, .. }) = imu {
+                if mag.is_none() {
+                    rprintln!("no magnetometer to calibrate");
+                } else if mag_sweep.is_none() {
+                    *mag_sweep = Some(MagSweep::new());
+                    rprintln!("turn the craft through every orientation for {} s", MAG_SWEEP_S);
+                }
+            }
+        });
+    }
+
+    /// Feeds an uncalibrated field to the sweep, reports progress every second and solves and
+    /// stores once it ended
+    fn sweep_field(sweep: &mut MagSweep, field: Vec3, mag_cal: &mut MagCalibration, settings: Settings, tx: &mut TxLink) -> bool {
+        sweep.add(field);
+        let done = sweep.done();
+        if !sweep.readings().is_multiple_of(MAG_FREQUENCY_HZ) && !done {
+            return false;
+        }
+
+        let progress = MagCalibrationProgress {
+            coverage_percent: sweep.coverage_percent(),
+            remaining_s: sweep.remaining_s() as u8,
+            enough: sweep.enough(),
+        };
+        write_frame(tx, &progress.to_byte_array());
+        if !done {
+            return false;
+        }
+
+        match sweep.solve() {
+            Ok(solved) => {
+                rprintln!("mag calibration {:?}", solved);
+                *mag_cal = solved;
+                persist::spawn(Settings { mag: solved, ..settings }).ok();
+            }
+            Err(e) => rprintln!("mag calibration failed {:?}, {}% covered, keeping the old one", e, progress.coverage_percent),
+        }
+        true
+    }
+
+    /// Feeds a raw reading to the capture in progress, solves and stores once every face is in.
+    /// `status` goes out once a face was captured or failed.
+    fn capture_face(cal: &mut SixPosition, acc: Vec3, accel: &mut AccelCalibration, settings: Settings, status: Status, tx: &mut TxLink) -> bool {
+        match cal.add(acc, ACCEL_RANGE) {
+            None => return false,
+            Some(Ok(face)) => rprintln!("accel face {} captured, {} of 6", face, cal.captured()),
+            Some(Err(e)) => rprintln!("accel capture failed {:?}, try again", e),
+        }
+        write_frame(tx, &status.to_byte_array());
+        if let Some(solved) = cal.solve(ACCEL_RANGE) {
+            rprintln!("accel calibration {:?}", solved);
+            *accel = solved;
+            persist::spawn(Settings { accel: solved, ..settings }).ok();
+            return true;
+        }
+        false
+    }
+
+    /// The calibration [persist] writes, `offset` at `gyro_range`. The rest is left to it.
+    fn stored_settings(gyro_range: GyroRange, offset: Vec3, accel: AccelCalibration, mag: MagCalibration, estimator: &Estimator, crash: &CrashDetector) -> Settings {
+        Settings::calibration(gyro_range.rescale(offset, GYRO_RANGE), accel, mag, filter_settings(estimator), crash.tilt())
+    }
+
+    fn filter_settings(estimator: &Estimator) -> FilterSettings {
+        FilterSettings { kind: Estimator::KIND, gain: estimator.gain(), acc_cutoff_hz: estimator.acc_cutoff_hz().unwrap_or(0.0) }
+    }
+
+    fn filter_config(estimator: &Estimator) -> FilterConfig {
+        FilterConfig { gain: estimator.gain(), acc_cutoff_hz: estimator.acc_cutoff_hz().unwrap_or(0.0) }
+    }
+
+    /// Stored tuning, unless it was stored for another estimator or is out of range
+    fn apply_filter(estimator: &mut Estimator, filter: FilterSettings) {
+        if filter.kind != Estimator::KIND {
+            return;
+        }
+        if valid_gain(filter.gain) {
+            estimator.set_gain(filter.gain);
+        }
+        if estimator.acc_cutoff_hz().is_some() && valid_acc_cutoff(filter.acc_cutoff_hz) {
+            estimator.set_acc_cutoff_hz(filter.acc_cutoff_hz);
+        }
+    }
+
+    /// Applies a parameter [params] accepted and echoes what is in use either way, the
+    /// estimator and crash detector can still turn a value down. Stored on [Msg::SaveParams].
+    #[task(shared = [imu, failsafe, telemetry, trim, slew, rc_map, power, status_period_ms, gyro_debug, rates, compact], capacity = 2)]
+    fn tune(cx: tune::Context, param: Param, value: Value) {
+        let tune::SharedResources { mut imu, mut failsafe, mut telemetry, mut trim, mut slew, mut rc_map, mut power, mut status_period_ms, mut gyro_debug, mut rates, mut compact } = cx.shared;
+
+        // within the registry's range already
+        let applied = match (param, value) {
+            (Param::FilterGain | Param::AccCutoff | Param::CrashTilt, Value::F32(v)) => imu.lock(|imu| {
+                let Imu { estimator, crash, .. } = match imu {
+                    Some(imu) => imu,
+                    None => return false,
+                };
+                match param {
+                    Param::FilterGain if valid_gain(v) => estimator.set_gain(v),
+                    Param::AccCutoff if estimator.acc_cutoff_hz().is_some() && valid_acc_cutoff(v) => estimator.set_acc_cutoff_hz(v),
+                    Param::CrashTilt if valid_crash_tilt(v.to_radians()) => crash.set_tilt(v.to_radians()),
+                    _ => return false,
+                }
+                true
+            }),
+            (Param::LinkTimeout, Value::U32(ms)) => {
+                failsafe.lock(|f| f.config.timeout_ms = ms as u16);
+                true
+            }
+            (Param::LinkRamp, Value::U32(ms)) => {
+                failsafe.lock(|f| f.config.ramp_ms = ms as u16);
+                true
+            }
+            (Param::TelemetryDivisor, Value::U32(divisor)) => {
+                telemetry.lock(|t| t.divisor = divisor as u16);
+                true
+            }
+            (Param::TelemetryStreams, Value::U32(streams)) => {
+                telemetry.lock(|t| t.streams = streams as u8);
+                true
+            }
+            (Param::TrimRoll, Value::F32(degrees)) => {
+                trim.lock(|t| t.trim.roll = degrees.to_radians());
+                true
+            }
+            (Param::TrimPitch, Value::F32(degrees)) => {
+                trim.lock(|t| t.trim.pitch = degrees.to_radians());
+                true
+            }
+            (Param::ThrottleSlewUp, Value::F32(rate)) => {
+                slew.lock(|s| s.config.up_per_s = rate);
+                true
+            }
+            (Param::ThrottleSlewDown, Value::F32(rate)) => {
+                slew.lock(|s| s.config.down_per_s = rate);
+                true
+            }
+            (Param::RcRoll | Param::RcPitch | Param::RcThrottle | Param::RcYaw | Param::RcArm, Value::U32(channel)) => rc_map.lock(|m| match rc_channel(m, param) {
+                Some(c) => {
+                    *c = channel as u8 - 1;
+                    true
+                }
+                None => false,
+            }),
+            (Param::BatteryDivider, Value::F32(divider)) => {
+                power.lock(|p| p.config.divider = divider);
+                true
+            }
+            (Param::BatteryCells, Value::U32(cells)) => {
+                power.lock(|p| p.config.cells = cells as u8);
+                true
+            }
+            (Param::BatteryWarning, Value::F32(volts)) => {
+                power.lock(|p| p.config.warning_cell_volts = volts);
+                true
+            }
+            (Param::BatteryCritical, Value::F32(volts)) => {
+                power.lock(|p| p.config.critical_cell_volts = volts);
+                true
+            }
+            (Param::BatteryFailsafe, Value::Bool(on)) => {
+                power.lock(|p| p.config.failsafe = on);
+                true
+            }
+            (Param::CurrentScale, Value::F32(scale)) => {
+                power.lock(|p| p.current.mv_per_amp = scale);
+                true
+            }
+            (Param::CurrentOffset, Value::F32(millivolts)) => {
+                power.lock(|p| p.current.offset_mv = millivolts);
+                true
+            }
+            (Param::CurrentLimit, Value::F32(amps)) => {
+                power.lock(|p| p.current.max_amps = amps);
+                true
+            }
+            (Param::StatusPeriod, Value::U32(ms)) => {
+                status_period_ms.lock(|p| *p = ms as u16);
+                true
+            }
+            (Param::GyroDebug, Value::Bool(on)) => {
+                gyro_debug.lock(|d| *d = on);
+                true
+            }
+            (Param::Rates, Value::Bool(on)) => {
+                rates.lock(|r| *r = on);
+                true
+            }
+            (Param::Compact, Value::Bool(on)) => {
+                compact.lock(|c| *c = on);
+                true
+            }
+            _ => false,
+        };
+
+        if applied {
+            rprintln!("{:?} set to {:?}", param, value);
+        } else {
+            rprintln!("{:?} {:?} rejected", param, value);
+        }
+        report_param::spawn(param).ok();
+    }
+
+    /// Field of the [RcMap] a parameter sets, counting from zero where the parameter counts
+    /// from one
+    fn rc_channel(map: &mut RcMap, param: Param) -> Option<&mut u8> {
+        match param {
+            Param::RcRoll => Some(&mut map.roll),
+            Param::RcPitch => Some(&mut map.pitch),
+            Param::RcThrottle => Some(&mut map.throttle),
+            Param::RcYaw => Some(&mut map.yaw),
+            Param::RcArm => Some(&mut map.arm),
+            _ => None,
+        }
+    }
+
+    /// Echoes a parameter as it is in use, nothing for the IMU's ones before it is up
+    #[task(shared = [imu, failsafe, telemetry, trim, slew, rc_map, power, status_period_ms, gyro_debug, rates, compact, usart1_tx], capacity = 4)]
+    fn report_param(cx: report_param::Context, param: Param) {
+        let report_param::SharedResources { mut imu, mut failsafe, mut telemetry, mut trim, mut slew, mut rc_map, mut power, mut status_period_ms, mut gyro_debug, mut rates, mut compact, mut usart1_tx } = cx.shared;
+        let value = match param {
+            Param::FilterGain => imu.lock(|imu| imu.as_ref().map(|imu| Value::F32(imu.estimator.gain()))),
+            Param::AccCutoff => imu.lock(|imu| imu.as_ref().map(|imu| Value::F32(imu.estimator.acc_cutoff_hz().unwrap_or(0.0)))),
+            Param::CrashTilt => imu.lock(|imu| imu.as_ref().map(|imu| Value::F32(imu.crash.tilt().to_degrees()))),
+            Param::LinkTimeout => Some(Value::U32(failsafe.lock(|f| f.config.timeout_ms) as u32)),
+            Param::LinkRamp => Some(Value::U32(failsafe.lock(|f| f.config.ramp_ms) as u32)),
+            Param::TelemetryDivisor => Some(Value::U32(telemetry.lock(|t| t.divisor) as u32)),
+            Param::TelemetryStreams => Some(Value::U32(telemetry.lock(|t| t.streams) as u32)),
+            Param::TrimRoll => Some(Value::F32(trim.lock(|t| t.trim.roll).to_degrees())),
+            Param::TrimPitch => Some(Value::F32(trim.lock(|t| t.trim.pitch).to_degrees())),
+            Param::ThrottleSlewUp => Some(Value::F32(slew.lock(|s| s.config.up_per_s))),
+            Param::ThrottleSlewDown => Some(Value::F32(slew.lock(|s| s.config.down_per_s))),
+            Param::RcRoll | Param::RcPitch | Param::RcThrottle | Param::RcYaw | Param::RcArm => {
+                rc_map.lock(|m| rc_channel(m, param).map(|c| Value::U32(*c as u32 + 1)))
+            }
+            Param::BatteryDivider => Some(Value::F32(power.lock(|p| p.config.divider))),
+            Param::BatteryCells => Some(Value::U32(power.lock(|p| p.config.cells) as u32)),
+            Param::BatteryWarning => Some(Value::F32(power.lock(|p| p.config.warning_cell_volts))),
+            Param::BatteryCritical => Some(Value::F32(power.lock(|p| p.config.critical_cell_volts))),
+            Param::BatteryFailsafe => Some(Value::Bool(power.lock(|p| p.config.failsafe))),
+            Param::CurrentScale => Some(Value::F32(power.lock(|p| p.current.mv_per_amp))),
+            Param::CurrentOffset => Some(Value::F32(power.lock(|p| p.current.offset_mv))),
+            Param::CurrentLimit => Some(Value::F32(power.lock(|p| p.current.max_amps))),
+            Param::StatusPeriod => Some(Value::U32(status_period_ms.lock(|p| *p) as u32)),
+            Param::GyroDebug => Some(Value::Bool(gyro_debug.lock(|d| *d))),
+            Param::Rates => Some(Value::Bool(rates.lock(|r| *r))),
+            Param::Compact => Some(Value::Bool(compact.lock(|c| *c))),
+        };
+
+        match value {
+            Some(value) => usart1_tx.lock(|tx| write_frame(tx, &ParamValue { id: param.id(), value }.to_byte_array())),
+            None => rprintln!("no IMU, {:?} not reported", param),
+        }
+    }
+
+    /// The [ParamInfo] and value of [Param::ALL] from `index` on, one every
+    /// [PARAM_LIST_PERIOD_MS] to leave the link to telemetry in between
+    #[task(shared = [usart1_tx])]
+    fn list_params(mut cx: list_params::Context, index: usize) {
+        let param = match Param::ALL.get(index) {
+            Some(param) => *param,
+            None => return,
+        };
+        cx.shared.usart1_tx.lock(|tx| write_frame(tx, &params::info(param).to_byte_array()));
+        report_param::spawn(param).ok();
+        list_params::spawn_after(PARAM_LIST_PERIOD_MS.millis(), index + 1).ok();
+    }
+
+    /// [CommandLogEntry] `index` from the oldest on, one every [COMMAND_LOG_PERIOD_MS]. Commands
+    /// coming in meanwhile push the oldest out, an entry may come twice or not at all.
+    #[task(shared = [command_log, usart1_tx])]
+    fn dump_command_log(mut cx: dump_command_log::Context, index: usize) {
+        let entry = match cx.shared.command_log.lock(|log| log.iter().nth(index).copied()) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let mut tx = cx.shared.usart1_tx;
+        tx.lock(|tx| write_frame(tx, &entry.to_byte_array()));
+        dump_command_log::spawn_after(COMMAND_LOG_PERIOD_MS.millis(), index + 1).ok();
+    }
+
+    /// The whole command log over RTT, for when the motors got cut with no ground listening
+    #[task(shared = [command_log])]
+    fn print_command_log(mut cx: print_command_log::Context) {
+        cx.shared.command_log.lock(|log| {
+            rprintln!("last {} commands:", log.len());
+            for entry in log.iter() {
+                rprintln!("{} ms #{} {:?} {:?}", entry.at_ms, entry.sequence, entry.status, entry.msg);
+            }
+        });
+    }
+
+    /// Puts the arming on the motors: enables them once armed and moves them from the
+    /// throttle they are on towards the commanded one, as fast as the [SlewConfig] lets them.
+    /// Takes them down over the ramp in [ArmState::Failsafe] and cuts them in every other
+    /// state. Commands only change the arming and the throttle they want, the crash, a lost
+    /// IMU and the emergency stop still cut on the spot and this finds the motors off.
+    #[task(shared = [arming, slew, throttle, failsafe, pwm, en])]
+    fn output(cx: output::Context) {
+        let now = monotonics::now();
+        let spawn_next_at = now + OUTPUT_PERIOD_MS.millis();
+
+        let mut shared = (cx.shared.arming, cx.shared.slew, cx.shared.throttle, cx.shared.failsafe, cx.shared.pwm, cx.shared.en);
+        shared.lock(|arming, slew, throttle, failsafe, pwm, en| match arming.state {
+            ArmState::Armed => {
+                if en.is_set_low() {
+                    // on zero duty until the next throttle
+                    en.set_high();
+                    rearm::spawn().ok();
+                }
+                let next = slew.config.step(*throttle, slew.commanded, OUTPUT_PERIOD_MS as f32 / 1000.0);
+                if next != *throttle {
+                    *throttle = next;
+                    let duty = (pwm.get_max_duty() as f32 * next) as u16;
+                    // the same on every motor until a controller mixes the setpoint in
+                    set_motors(pwm, [duty; 4]);
+                }
+            }
+            ArmState::Failsafe if failsafe.config.ramp_ms > 0 && en.is_set_high() => {
+                *throttle = 0.0;
+                slew.commanded = 0.0;
+                let (from, started) = *failsafe.ramp.get_or_insert_with(|| (MOTORS.map(|m| pwm.get_duty(m)), now));
+                let ramp = failsafe.config.ramp_ms as u32;
+                let elapsed = (millis_since(started, now) as u32).min(ramp);
+                if elapsed == ramp {
+                    failsafe.ramp = None;
+                    disarm(pwm, en);
+                } else {
+                    set_motors(pwm, from.map(|d| (d as u32 * (ramp - elapsed) / ramp) as u16));
+                }
+            }
+            _ => {
+                // a command from before disarming doesn't carry over to the next arming
+                slew.commanded = 0.0;
+                *throttle = 0.0;
+                failsafe.ramp = None;
+                if en.is_set_high() {
+                    disarm(pwm, en);
+                }
+            }
+        });
+
+        output::spawn_at(spawn_next_at).ok();
+    }
+
+    /// Back to [USART1_BAUD] for a link that only ever brought line errors, looks again later
+    /// while it brought nothing at all. Done once a command came through.
+    #[task(shared = [baud, link, usart1_tx])]
+    fn baud_fallback(cx: baud_fallback::Context) {
+        let (mut baud, mut link, mut tx) = (cx.shared.baud, cx.shared.link, cx.shared.usart1_tx);
+        let (accepted, errors) = link.lock(|l| (l.accepted, l.framing.wrapping_add(l.noise)));
+        match (accepted, errors) {
+            (0, 0) => {
+                baud_fallback::spawn_after(BAUD_FALLBACK_MS.millis()).ok();
+            }
+            (0, _) => {
+                tx.lock(|tx| tx.flush());
+                baud.lock(|b| set_baud(b, USART1_BAUD));
+                rprintln!("nothing but line errors, link back at {} baud", USART1_BAUD);
+            }
+            _ => {}
+        }
+    }
+
+    /// Over to the rate of a [Msg::SetBaud], the ack went out at the old one
+    #[task(shared = [baud, link, usart1_tx])]
+    fn switch_baud(cx: switch_baud::Context, rate: u32) {
+        let (mut baud, mut link, mut tx) = (cx.shared.baud, cx.shared.link, cx.shared.usart1_tx);
+        tx.lock(|tx| tx.flush());
+        let previous = baud.lock(|b| {
+            let previous = b.rate;
+            set_baud(b, rate);
+            previous
+        });
+        let accepted = link.lock(|l| l.accepted);
+        rprintln!("link at {} baud, {} if nothing comes", rate, previous);
+        revert_baud::spawn_after(BAUD_REVERT_MS.millis(), previous, accepted).ok();
+    }
+
+    /// Back to `previous` when not a command came through since the switch, `accepted` being
+    /// the count then
+    #[task(shared = [baud, link, usart1_tx])]
+    fn revert_baud(cx: revert_baud::Context, previous: u32, accepted: u32) {
+        let (mut baud, mut link, mut tx) = (cx.shared.baud, cx.shared.link, cx.shared.usart1_tx);
+        if link.lock(|l| l.accepted) == accepted {
+            tx.lock(|tx| tx.flush());
+            baud.lock(|b| set_baud(b, previous));
+            rprintln!("nothing at the new rate, link back at {} baud", previous);
+        }
+    }
+
+    /// Fails safe once no command came for the configured timeout while armed, [output]
+    /// cuts the motors or takes them down over the ramp. Arming again takes a disarm first.
+    /// Locks after the same timeout armed or not, see [LockState::Locked].
+    #[task(shared = [failsafe, arming, link])]
+    fn link_watchdog(cx: link_watchdog::Context) {
+        let now = monotonics::now();
+        let spawn_next_at = now + LINK_WATCHDOG_PERIOD_MS.millis();
+
+        let mut shared = (cx.shared.failsafe, cx.shared.arming, cx.shared.link);
+        let (failed, locked) = shared.lock(|failsafe, arming, link| {
+            let timeout = failsafe.config.timeout_ms as u64;
+            let quiet = failsafe.last_command.map_or(true, |t| millis_since(t, now) >= timeout);
+            let failed = arming.engaged() && quiet;
+            let locked = quiet && !arming.locked;
+            if locked {
+                rprintln!("no command for {} ms, locked", timeout);
+                arming.locked = true;
+            }
+            if failed {
+                rprintln!("no command for {} ms, failsafe", timeout);
+                arming.failsafe();
+                link.failsafes = link.failsafes.wrapping_add(1);
+            }
+            (failed, locked)
+        });
+        if failed || locked {
+            report_arm_state::spawn().ok();
+        }
+        if failed {
+            print_command_log::spawn().ok();
+        }
+
+        link_watchdog::spawn_at(spawn_next_at).ok();
+    }
+
+    /// Echoes the gains in use for `axis`, or for every axis
+    #[task(shared = [pid, usart1_tx], capacity = 2)]
+    fn report_pid_gains(cx: report_pid_gains::Context, axis: Option<Axis>) {
+        (cx.shared.pid, cx.shared.usart1_tx).lock(|pid, tx| {
+            for a in Axis::ALL.iter().copied().filter(|a| axis.map_or(true, |axis| axis == *a)) {
+                write_frame(tx, &AxisPidGains { axis: a, gains: pid.gains[a as usize] }.to_byte_array());
+            }
+        });
+    }
+
+    /// Makes the gains in use the stored ones and writes them along with the calibration,
+    /// which takes a connected IMU
+    #[task(shared = [imu, pid, en])]
+    fn save_pid_gains(cx: save_pid_gains::Context) {
+        (cx.shared.imu, cx.shared.pid, cx.shared.en).lock(|imu, pid, en| {
+            if en.is_set_high() {
+                rprintln!("armed, PID gains not stored");
+                return;
+            }
+            match imu {
+                Some(Imu { gyro_range, offset, accel, mag_cal, estimator, crash, .. }) => {
+                    pid.stored = pid.gains;
+                    persist::spawn(stored_settings(*gyro_range, *offset, *accel, *mag_cal, estimator, crash)).ok();
+                }
+                None => rprintln!("no IMU, PID gains not stored"),
+            }
+        });
+    }
+
+    /// Echoes the trim in use
+    #[task(shared = [trim, usart1_tx], capacity = 2)]
+    fn report_trim(cx: report_trim::Context) {
+        (cx.shared.trim, cx.shared.usart1_tx).lock(|trim, tx| write_frame(tx, &trim.trim.to_byte_array()));
+    }
+
+    /// Latest roll and pitch become the trim, as long as they are within its range. The
+    /// orientation is taken as it is, keep the craft still while it settles.
+    #[task(shared = [imu, trim])]
+    fn capture_trim(cx: capture_trim::Context) {
+        (cx.shared.imu, cx.shared.trim).lock(|imu, trims| match imu {
+            Some(Imu { orientation, .. }) => {
+                let trim = Trim { roll: orientation.roll, pitch: orientation.pitch };
+                if trim.is_valid() {
+                    trims.trim = trim;
+                    rprintln!("trim captured {:?}", trim);
+                } else {
+                    rprintln!("too far off level for a trim {:?}", trim);
+                }
+            }
+            None => rprintln!("no IMU, trim not captured"),
+        });
+        report_trim::spawn().ok();
+    }
+
+    /// Makes the trim in use the stored one and writes it along with the calibration and
+    /// every other parameter in use, which takes a connected IMU
+    #[task(shared = [imu, trim, en])]
+    fn save_params(cx: save_params::Context) {
+        (cx.shared.imu, cx.shared.trim, cx.shared.en).lock(|imu, trim, en| {
+            if en.is_set_high() {
+                rprintln!("armed, parameters not stored");
+                return;
+            }
+            match imu {
+                Some(Imu { gyro_range, offset, accel, mag_cal, estimator, crash, .. }) => {
+                    trim.stored = trim.trim;
+                    persist::spawn(stored_settings(*gyro_range, *offset, *accel, *mag_cal, estimator, crash)).ok();
+                }
+                None => rprintln!("no IMU, parameters not stored"),
+            }
+        });
+    }
+
+    /// Second step of arming, once the ack of the arm request for `sequence` went out. Only
+    /// now [output] enables the motors.
+    #[task(shared = [arming])]
+    fn armed(mut cx: armed::Context, sequence: u16) {
+        if cx.shared.arming.lock(|arming| arming.acked(sequence)) {
+            rprintln!("armed");
+            report_arm_state::spawn().ok();
+        }
+    }
+
+    #[task(shared = [arming, usart1_tx], capacity = 2)]
+    fn report_arm_state(cx: report_arm_state::Context) {
+        (cx.shared.arming, cx.shared.usart1_tx).lock(|arming, tx| {
+            write_frame(tx, &arming.state.to_byte_array());
+            write_frame(tx, &arming.lock_state().to_byte_array());
+        });
+    }
+
+    #[task(shared = [mode, usart1_tx])]
+    fn report_mode(cx: report_mode::Context) {
+        (cx.shared.mode, cx.shared.usart1_tx).lock(|mode, tx| write_frame(tx, &mode.to_byte_array()));
+    }
+
+    /// Echoes the telemetry config in use
+    #[task(shared = [telemetry, usart1_tx])]
+    fn report_telemetry(cx: report_telemetry::Context) {
+        (cx.shared.telemetry, cx.shared.usart1_tx).lock(|telemetry, tx| write_frame(tx, &telemetry.to_byte_array()));
+    }
+
+    /// Writes the calibration again for [persist] to pick up the telemetry and failsafe
+    /// config in use, without an IMU they are kept until the next write
+    #[task(shared = [imu])]
+    fn save_settings(mut cx: save_settings::Context) {
+        cx.shared.imu.lock(|imu| match imu {
+            Some(Imu { gyro_range, offset, accel, mag_cal, estimator, crash, .. }) => {
+                persist::spawn(stored_settings(*gyro_range, *offset, *accel, *mag_cal, estimator, crash)).ok();
+            }
+            None => rprintln!("no IMU, settings not stored"),
+        });
+    }
+
+    /// Writes calibration to flash, the erase stalls everything so it is skipped while armed.
+    /// The PID gains and trim are always the stored ones, what is being tried out stays in
+    /// RAM. The telemetry, failsafe, slew, battery and current config, the RC channels and
+    /// the status period are the ones in use.
+    #[task(local = [flash], shared = [pid, trim, telemetry, failsafe, slew, rc_map, power, status_period_ms, en])]
+    fn persist(mut cx: persist::Context, settings: Settings) {
+        if cx.shared.en.lock(|en| en.is_set_high()) {
+            rprintln!("armed, calibration not stored");
+            return;
+        }
+        let settings = Settings {
+            pid: cx.shared.pid.lock(|p| p.stored),
+            trim: cx.shared.trim.lock(|t| t.stored),
+            telemetry: cx.shared.telemetry.lock(|t| *t),
+            failsafe: cx.shared.failsafe.lock(|f| f.config),
+            slew: cx.shared.slew.lock(|s| s.config),
+            rc_map: cx.shared.rc_map.lock(|m| *m),
+            battery: cx.shared.power.lock(|p| p.config),
+            current: cx.shared.power.lock(|p| p.current),
+            status_period_ms: cx.shared.status_period_ms.lock(|p| *p),
+            ..settings
+        };
+
+        let mut writer = cx.local.flash.writer(SECTOR_SIZE, FLASH_SIZE);
+        match settings.store(&mut writer) {
+            Ok(()) => rprintln!("calibration stored"),
+            Err(e) => rprintln!("unable to store calibration {:?}", e),
+        }
+    }
+
+    /// Asks how much the FIFO holds, a transfer still in flight means this wakeup is skipped
+    /// and the samples are picked up with the next one. An SPI sensor is read right away.
+    fn start_sample(reader: &mut Option<Reader>, spi_imu: &mut Option<SpiImu>, skipped: &mut u32) {
+        if let Some(icm) = spi_imu {
+            let transfer = icm.read_raw()
+                .map(|raw| {
+                    let mut buf = [0; MAX_READ];
+                    buf[..SAMPLE_SIZE].copy_from_slice(&raw);
+                    // same path as a single sample drained from the MPU6050 FIFO
+                    Transfer::Read { address: mpu::ADDRESS, reg: mpu::ACCEL_XOUT_H, buf, len: SAMPLE_SIZE }
+                })
+                .map_err(|_| i2c_irq::Error::Bus);
+            if gyro::spawn(transfer).is_err() {
+                rprintln!("transfer not processed in time");
+            }
+        } else if let Some(reader) = reader {
+            // overflow first, the FIFO count is meaningless after one
+            match reader.read(mpu::INT_STATUS, 1) {
+                Ok(()) => {}
+                Err(i2c_irq::Error::Busy) => {
+                    *skipped += 1;
+                    rprintln!("skipped wakeups {}", skipped);
+                }
+                Err(e) => {
+                    gyro::spawn(Err(e)).ok();
+                }
+            }
+        }
+    }
+
+    #[task(binds = EXTI15_10, local = [mpu_int, skipped: u32 = 0], shared = [reader, spi_imu], priority = 3)]
+    fn mpu_data_ready(cx: mpu_data_ready::Context) {
+        cx.local.mpu_int.clear_interrupt_pending_bit();
+
+        let skipped = cx.local.skipped;
+        (cx.shared.reader, cx.shared.spi_imu).lock(|reader, spi_imu| start_sample(reader, spi_imu, skipped));
+    }
+
+    /// Fixed period sampling when the INT pin is not wired. Systick can't keep up with the
+    /// sample rate, the FIFO holds the samples in between.
+    #[task(local = [skipped: u32 = 0], shared = [reader, spi_imu])]
+    fn poll(cx: poll::Context) {
+        let spawn_next_at = monotonics::now() + (1000 / TELEMETRY_FREQUENCY_HZ as u64).millis();
+
+        let skipped = cx.local.skipped;
+        (cx.shared.reader, cx.shared.spi_imu).lock(|reader, spi_imu| start_sample(reader, spi_imu, skipped));
+
+        poll::spawn_at(spawn_next_at).ok();
+    }
+
+    #[task(binds = I2C2_EV, shared = [reader], priority = 3)]
+    fn i2c2_ev(mut cx: i2c2_ev::Context) {
+        let transfer = cx.shared.reader.lock(|reader| reader.as_mut().and_then(|r| r.on_event()));
+
+        if let Some(t) = transfer {
+            if gyro::spawn(Ok(t)).is_err() {
+                rprintln!("transfer not processed in time");
+            }
+        }
+    }
+
+    #[task(binds = I2C2_ER, shared = [reader], priority = 3)]
+    fn i2c2_er(mut cx: i2c2_er::Context) {
+        let error = cx.shared.reader.lock(|reader| reader.as_mut().map(|r| r.on_error()));
+
+        if let Some(e) = error {
+            gyro::spawn(Err(e)).ok();
+        }
+    }
+
+    /// Answers an MSP request for `command`, `$M!` when `ok` is false or for one the device
+    /// has no answer to. The magnetometer counts of MSP_RAW_IMU stay zero, the reading is
+    /// used up by the next sample.
+    #[task(shared = [imu, arming, pid, usart1_tx], capacity = 4)]
+    fn msp_reply(mut cx: msp_reply::Context, command: u8, ok: bool) {
+        let mut shared = (cx.shared.imu, cx.shared.arming, cx.shared.pid);
+        let response = shared.lock(|imu, arming, pid| {
+            let counts = |v: Vec3| [v.x as i16, v.y as i16, v.z as i16];
+            match (command, imu.as_ref()) {
+                (_, _) if !ok => msp::Response::error(command),
+                (msp::STATUS, imu) => {
+                    let (errors, sensors) = imu.map_or((0, 0), |imu| {
+                        let mag = if imu.mag.is_some() { msp::SENSOR_MAG } else { 0 };
+                        (imu.bus.errors.min(u16::MAX as u32) as u16, msp::SENSOR_ACC | mag)
+                    });
+                    let cycle_us = (1_000_000 / GYRO_FREQUENCY_HZ) as u16;
+                    msp::Response::new(command, &msp::status(cycle_us, errors, sensors, arming.state == ArmState::Armed))
+                }
+                (msp::RAW_IMU, Some(Imu { last_sample: Some(sample), .. })) => {
+                    msp::Response::new(command, &msp::raw_imu(counts(sample.acc), counts(sample.gyro), [0; 3]))
+                }
+                (msp::ATTITUDE, Some(imu)) => msp::Response::new(command, &msp::attitude(&imu.orientation)),
+                (msp::PID, _) => msp::Response::new(command, &msp::pid(&pid.gains)),
+                (msp::SET_RAW_RC | msp::SET_PID, _) => msp::Response::new(command, &[]),
+                _ => msp::Response::error(command),
+            }
+        });
+        cx.shared.usart1_tx.lock(|tx| tx.write(&[&response]));
+    }
+
+    /// Sticks and arm switch from `source`, ignored unless it is the [RC_SOURCE]. Arming
+    /// goes by the switch edges. Locked, the switch has to be seen down before an edge up
+    /// counts, which is what unlocks the device for a transmitter: a receiver that boots or
+    /// comes back with the switch up arms nothing. It starts out taken for up, and one held
+    /// up through a refused arming has to come down first as well.
+    #[task(local = [switch: bool = true], shared = [failsafe, arming, setpoint, slew, throttle, pwm, en])]
+    fn rc_input(cx: rc_input::Context, source: RcSource, input: RcInput) {
+        if source != RC_SOURCE {
+            return;
+        }
+        let edge = core::mem::replace(cx.local.switch, input.arm) != input.arm;
+
+        let mut shared = (cx.shared.failsafe, cx.shared.arming, cx.shared.setpoint, cx.shared.slew, cx.shared.throttle, cx.shared.pwm, cx.shared.en);
+        let changed = shared.lock(|failsafe, arming, setpoint, slew, throttle, pwm, en| {
+            failsafe.last_command = Some(monotonics::now());
+            *setpoint = input.setpoint;
+            let state = (arming.state, arming.locked);
+            if arming.locked && !input.arm {
+                rprintln!("arm switch down, unlocked");
+                arming.locked = false;
+            }
+            if edge && input.arm {
+                if arming.request(ARM_MAGIC, input.throttle, 0) == AckStatus::Applied && arming.state == ArmState::Arming {
+                    // nothing to ack, armed right away
+                    armed::spawn(0).ok();
+                }
+            } else if edge {
+                arming.disarm();
+            }
+            if edge && !arming.engaged() {
+                disarm(pwm, en);
+                *throttle = 0.0;
+            }
+            if arming.state == ArmState::Armed {
+                slew.commanded = input.throttle;
+            }
+            (arming.state, arming.locked) != state
+        });
+        if changed {
+            report_arm_state::spawn().ok();
+        }
+    }
+
+    /// Frames from the RC receiver, the [RcMap] makes sticks for [rc_input] of their channels.
+    /// Every [CRSF_TELEMETRY_DIVISOR]th CRSF one gets an attitude frame back, link statistics
+    /// and the rest are skipped. An SBUS frame the receiver marks lost is skipped, one in
+    /// failsafe fails the link safe right away rather than after the timeout. With the wired
+    /// link the port brings commands instead, queued the way [on_rx] queues them.
+    #[task(
+        binds = USART2,
+        local = [
+            receiver_recv,
+            wired_in,
+            wired_stop: EmergencyStop = EmergencyStop::new(),
+            crsf_parser: crsf::Parser = crsf::Parser::new(),
+            sbus_parser: sbus::Parser = sbus::Parser::new(),
+            frames: u32 = 0,
+            dropped: u32 = 0,
+        ],
+        shared = [rc_map, failsafe, arming, throttle, link, pwm, en],
+        priority = 2
+    )]
+    fn receiver(mut cx: receiver::Context) {
+        let receiver::LocalResources { receiver_recv, wired_in, wired_stop, crsf_parser, sbus_parser, frames, dropped } = cx.local;
+        let map = cx.shared.rc_map.lock(|m| *m);
+        let mut failsafe = cx.shared.failsafe;
+
+        if cfg!(feature = "wired-link") {
+            let (mut arming, mut throttle, mut link, mut pwm, mut en) = (cx.shared.arming, cx.shared.throttle, cx.shared.link, cx.shared.pwm, cx.shared.en);
+            if take_in(receiver_recv, wired_in, wired_stop, &mut link) {
+                emergency_stop(Link::Wired, &mut arming, &mut pwm, &mut en, &mut throttle);
+            }
+            process_commands::spawn(DWT::cycle_count()).ok();
+            return;
+        }
+        clear_idle(unsafe { &*USART2::ptr() });
+        sbus_parser.idle();
+        for byte in receiver_recv.received() {
+            let received = match RECEIVER {
+                RcSource::Sbus => match sbus_parser.push(byte) {
+                    Some(Ok(frame)) if frame.failsafe => {
+                        if RC_SOURCE == RECEIVER && failsafe.lock(|f| f.last_command.take().is_some()) {
+                            rprintln!("SBUS receiver in failsafe");
+                        }
+                        None
+                    }
+                    // the channels are the ones of the frame before
+                    Some(Ok(frame)) if frame.frame_lost => None,
+                    frame => frame.map(|f| f.map(|f| f.channels)),
+                },
+                _ => match crsf_parser.push(byte) {
+                    Some(Ok(frame)) if frame.kind == crsf::RC_CHANNELS_PACKED => crsf::Channels::from_payload(frame.payload()).map(|c| Ok(c.0)),
+                    Some(Ok(_)) | None => None,
+                    Some(Err(e)) => Some(Err(e)),
+                },
+            };
+
+            match received {
+                Some(Ok(channels)) => {
+                    rc_input::spawn(RECEIVER, map.input(&channels)).ok();
+                    *frames = frames.wrapping_add(1);
+                    if RECEIVER == RcSource::Crsf && *frames % CRSF_TELEMETRY_DIVISOR == 0 {
+                        crsf_telemetry::spawn().ok();
+                    }
+                }
+                Some(Err(e)) => {
+                    *dropped += 1;
+                    rprintln!("{:?} frame dropped {:?}, {} so far", RECEIVER, e, dropped);
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Attitude and the battery for the transmitter, zero amps and mAh without a current
+    /// sensor
+    #[task(shared = [imu, power, usart2_tx])]
+    fn crsf_telemetry(cx: crsf_telemetry::Context) {
+        let (mut imu, mut power, mut tx) = (cx.shared.imu, cx.shared.power, cx.shared.usart2_tx);
+        let attitude = imu.lock(|imu| imu.as_ref().map(|imu| crsf::Response::attitude(&imu.orientation)));
+        let battery = power.lock(|p| p.volts.map(|v| crsf::Response::battery(v, p.amps.unwrap_or(0.0), p.consumed_mah as u32, p.remaining_percent())));
+        tx.lock(|tx| {
+            for frame in attitude.iter().chain(battery.iter()) {
+                write_bytes(tx, frame);
+            }
+        });
+    }
+
+    /// PPM capture on PA6, which is a floating input out of reset already. TIM3 channel 1
+    /// counts at 1 MHz in reset mode: every rising edge captures the time since the one
+    /// before and starts the count over, an update only comes after [ppm::TIMEOUT_US] without
+    /// one. The HAL has no input capture, the registers are set up by hand.
+    fn start_ppm(tim: TIM3, clocks: Clocks) {
+        let rcc = unsafe { &*stm32f1xx_hal::pac::RCC::ptr() };
+        rcc.apb1enr.modify(|_, w| w.tim3en().set_bit());
+
+        tim.psc.write(|w| w.psc().bits((clocks.pclk1_tim().0 / 1_000_000 - 1) as u16));
+        tim.arr.write(|w| w.arr().bits(ppm::TIMEOUT_US as u16));
+        // CC1 on TI1, filtered over eight clocks
+        tim.ccmr1_input().write(|w| unsafe { w.bits(0b0011 << 4 | 0b01) });
+        tim.ccer.write(|w| w.cc1e().set_bit());
+        // reset on TI1FP1
+        tim.smcr.write(|w| unsafe { w.bits(0b101 << 4 | 0b100) });
+        tim.egr.write(|w| w.ug().set_bit());
+        tim.sr.write(|w| unsafe { w.bits(0) });
+        tim.dier.write(|w| w.cc1ie().set_bit().uie().set_bit());
+        // the resets don't count as updates, only running out does
+        tim.cr1.write(|w| w.urs().set_bit().cen().set_bit());
+    }
+
+    /// Edges of the PPM stream, see [start_ppm]. Frames go on to [rc_input] through the
+    /// [RcMap], [ppm::LOST_US] without an edge fail the link safe right away rather than
+    /// after the timeout.
+    #[task(binds = TIM3, local = [decoder: ppm::Decoder = ppm::Decoder::new(), dropped: u32 = 0], shared = [rc_map, failsafe], priority = 2)]
+    fn ppm_edge(cx: ppm_edge::Context) {
+        let ppm_edge::LocalResources { decoder, dropped } = cx.local;
+        let ppm_edge::SharedResources { mut rc_map, mut failsafe } = cx.shared;
+        let tim = unsafe { &*TIM3::ptr() };
+
+        let sr = tim.sr.read();
+        // reading the capture clears its flag
+        if sr.cc1if().bit_is_set() {
+            match decoder.push(tim.ccr1.read().bits()) {
+                Some(Ok(channels)) => {
+                    rc_input::spawn(RcSource::Ppm, rc_map.lock(|m| m.input(&channels))).ok();
+                }
+                Some(Err(e)) => {
+                    *dropped += 1;
+                    rprintln!("PPM frame dropped {:?}, {} so far", e, dropped);
+                }
+                None => {}
+            }
+        }
+        if sr.uif().bit_is_set() {
+            tim.sr.modify(|_, w| w.uif().clear_bit());
+            if decoder.timeout() {
+                rprintln!("no PPM edge for {} ms", ppm::LOST_US / 1000);
+                failsafe.lock(|f| f.last_command = None);
+            }
+        }
+    }
+
+    /// Bytes as they are, for the protocols that bring their own framing
+    fn write_bytes<U>(tx: &mut Tx<U>, bytes: &[u8]) where Tx<U>: embedded_hal::serial::Write<u8, Error = core::convert::Infallible> {
+        bytes.iter().for_each(|b| nb::block!(tx.write(*b)).unwrap());
+    }
+
+    /// MAVLink on the command link. The EOT after each one ends it as a broken frame for a
+    /// native decoder listening as well, and a MAVLink parser skips it looking for the next
+    /// start byte.
+    fn write_mavlink(tx: &mut TxLink, frame: &[u8]) {
+        tx.write(&[frame, &[EOT]]);
+    }
+
+    /// Every native frame goes out through here, whatever task it comes from
+    fn write_frame(tx: &mut TxLink, frame: &[u8]) {
+        let mut wire = [0; telemetry::MAX_WIRE_SIZE];
+        let len = telemetry::encode_frame(frame, &mut wire).unwrap();
+        tx.write(&[&wire[..len]]);
+    }
+
+    /// The half that was on its way out is done, see [TxLink::kick]
+    #[task(binds = DMA1_CHANNEL4, shared = [usart1_tx])]
+    fn tx_done(mut cx: tx_done::Context) {
+        unsafe { (*DMA1::ptr()).ifcr.write(|w| w.ctcif4().set_bit()) };
+        cx.shared.usart1_tx.lock(|tx| tx.kick());
+    }
+
+    /// Same for the wired link, never raised without the wired-link feature
+    #[task(binds = DMA1_CHANNEL7, shared = [usart1_tx])]
+    fn mirror_done(mut cx: mirror_done::Context) {
+        unsafe { (*DMA1::ptr()).ifcr.write(|w| w.ctcif7().set_bit()) };
+        cx.shared.usart1_tx.lock(|tx| tx.kick());
+    }
+
+    /// Empties the FIFO, keeping the DMP running if it is
+    fn reset_fifo(reader: &mut Option<Reader>, dmp: bool) {
+        let dmp_en = if dmp { USER_CTRL_DMP_EN } else { 0 };
+        if let Some(r) = reader {
+            r.write(mpu::USER_CTRL, dmp_en | mpu::USER_CTRL_FIFO_EN | mpu::USER_CTRL_FIFO_RESET).ok();
+        }
+    }
+
+    #[task(
+        local = [
+            samples: u32 = 0,
+            mag_samples: u32 = 0,
+            failures: u32 = 0,
+            stats: Stats = Stats::new(),
+            overflows: u32 = 0,
+            clamped: u32 = 0,
+            raw_counter: u16 = 0,
+            read: u32 = 0,
+            missed: u32 = 0,
+            max_gap: u32 = 0,
+            drained_at: Option<u32> = None,
+            since_drained: u32 = 0,
+        ],
+        shared = [imu, reader, altimeter, usart1_tx, arming, throttle, rx_cycles, tx_cycles, loop_cycles, pwm, en],
+        capacity = 4
+    )]
+    fn gyro(cx: gyro::Context, transfer: Result<Transfer, i2c_irq::Error>) {
+        let start = DWT::cycle_count();
+
+        let samples: &mut u32 = cx.local.samples;
+        let mag_samples: &mut u32 = cx.local.mag_samples;
+        let failures: &mut u32 = cx.local.failures;
+        let stats: &mut Stats = cx.local.stats;
+        let overflows: &mut u32 = cx.local.overflows;
+        let clamped: &mut u32 = cx.local.clamped;
+        let raw_counter: &mut u16 = cx.local.raw_counter;
+        let read: &mut u32 = cx.local.read;
+        let missed: &mut u32 = cx.local.missed;
+        let max_gap: &mut u32 = cx.local.max_gap;
+        let drained_at: &mut Option<u32> = cx.local.drained_at;
+        let since_drained: &mut u32 = cx.local.since_drained;
+        let (mut imu, mut reader, mut pwm, mut en) = (cx.shared.imu, cx.shared.reader, cx.shared.pwm, cx.shared.en);
+        let (mut altimeter, mut tx, mut arming) = (cx.shared.altimeter, cx.shared.usart1_tx, cx.shared.arming);
+        let (mut throttle, mut rx_cycles, mut tx_cycles, mut loop_cycles) = (cx.shared.throttle, cx.shared.rx_cycles, cx.shared.tx_cycles, cx.shared.loop_cycles);
+
+        (&mut imu, &mut tx).lock(|imu, tx| {
+            if let Some(imu) = imu {
+                let Imu { bus, gyro_range, offset, accel, six_position, orientation: s, mag, mag_cal, mag_sweep, field, heading, batches, last_batch, notch, saturation, estimator, crash, dmp_yaw_zero, vertical, bias, dmp: dmp_running, resync, rearm, lost, last_sample, rates, debug, .. } = imu;
+                let (packet, rate) = if *dmp_running { (DMP_PACKET_SIZE, DMP_FREQUENCY_HZ) } else { (SAMPLE_SIZE, GYRO_FREQUENCY_HZ) };
+                match transfer {
+                    Ok(Transfer::Read { address: mpu::ADDRESS, reg: mpu::INT_STATUS, buf, .. }) => {
+                        *failures = 0;
+
+                        if buf[0] & mpu::FIFO_OFLOW != 0 {
+                            // samples were overwritten since the last read
+                            *overflows += 1;
+                            *resync = true;
+                            rprintln!("FIFO overflows {}", overflows);
+                            reader.lock(|r| reset_fifo(r, *dmp_running));
+                        } else {
+                            reader.lock(|r| r.as_mut().map(|r| r.read(mpu::FIFO_COUNT_H, 2)));
+                        }
+                    }
+                    Ok(Transfer::Read { address: mpu::ADDRESS, reg: mpu::FIFO_COUNT_H, buf, .. }) => {
+                        *failures = 0;
+
+                        let count = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+                        if count > mpu::FIFO_SIZE - packet {
+                            // full FIFO drops samples and loses frame alignment, start over
+                            *overflows += 1;
+                            *resync = true;
+                            rprintln!("FIFO overflows {}", overflows);
+                            reader.lock(|r| reset_fifo(r, *dmp_running));
+                        } else {
+                            let batch = (count / packet).min(MAX_READ / packet);
+                            if batch > 0 {
+                                stats.read_started = Some(DWT::cycle_count());
+                                reader.lock(|r| r.as_mut().map(|r| r.read(mpu::FIFO_R_W, batch * packet)));
+                            }
+                        }
+                    }
+                    Ok(Transfer::Read { address: mpu::ADDRESS, buf, len, .. }) => {
+                        *failures = 0;
+                        if let Some(t) = stats.read_started.take() {
+                            stats.i2c.add(start.wrapping_sub(t));
+                        }
+
+                        *batches = batches.wrapping_add(1);
+
+                        // Systick ticks are far too coarse for this. A batch covers the time since the
+                        // previous one however late it is processed, one wrap of the counter is fine.
+                        let count = (len / packet) as u32;
+                        let sysclk = bus.clocks.sysclk().0 as f32;
+                        if let Some(t) = last_batch {
+                            *max_gap = (*max_gap).max(start.wrapping_sub(*t));
+                        }
+                        let measured = last_batch.map(|t| start.wrapping_sub(t) as f32 / sysclk / count as f32);
+                        *last_batch = Some(start);
+
+                        // whatever the sensor produced since the FIFO was last drained and never got
+                        // here, one sample of slack for where in a period the reads land
+                        *read = read.wrapping_add(count);
+                        *since_drained += count;
+                        let drained = len < (MAX_READ / packet) * packet;
+                        if drained {
+                            if let Some(t) = drained_at {
+                                let expected = libm::roundf(start.wrapping_sub(*t) as f32 / sysclk * rate as f32) as u32;
+                                *missed += expected.saturating_sub(*since_drained + 1);
+                            }
+                            *drained_at = Some(start);
+                            *since_drained = 0;
+                        }
+                        let (dt, out_of_range) = sample_dt(measured);
+                        if out_of_range {
+                            *clamped += 1;
+                        }
+
+                        notch.retune(throttle.lock(|t| *t));
+                        *debug = None;
+                        let raw_stream = arming.lock(|a| a.raw_stream);
+
+                        let mut last = None;
+                        *rates = None;
+                        let mut crashed = false;
+                        if *dmp_running {
+                            // fusion already happened on the chip
+                            let mut misaligned = false;
+                            for p in buf[..len].chunks_exact(DMP_PACKET_SIZE) {
+                                match dmp::orientation(p) {
+                                    Some(o) => *s = SpatialOrientation { yaw: wrap_angle(o.yaw - *dmp_yaw_zero), ..o },
+                                    None => misaligned = true,
+                                }
+                            }
+                            if misaligned {
+                                rprintln!("DMP packets out of alignment");
+                                reader.lock(|r| reset_fifo(r, true));
+                            }
+                            *resync = false;
+                            *rearm = false;
+                        } else {
+                            for frame in buf[..len].chunks_exact(SAMPLE_SIZE) {
+                                let mut sample = Sample::from_be_bytes(frame.try_into().unwrap());
+
+                                *raw_counter = raw_counter.wrapping_add(1);
+                                if raw_stream && *raw_counter as u32 % RAW_STREAM_DECIMATION == 0 {
+                                    let words = |v: Vec3| [v.x as i16, v.y as i16, v.z as i16];
+                                    write_frame(tx, &RawSample { counter: *raw_counter, acc: words(sample.acc), gyro: words(sample.gyro) }.to_byte_array());
+                                }
+                                if let Some(cal) = six_position {
+                                    let settings = stored_settings(*gyro_range, *offset, *accel, *mag_cal, estimator, crash);
+                                    if capture_face(cal, sample.acc, accel, settings, arming.lock(|a| status(*lost, saturation, a, false)), tx) {                                        *six_position = None;
+                                    }
+                                }
+                                sample.acc = accel.apply(sample.acc);
+                                let raw = sample.gyro;
+                                sample.gyro = notch.apply(raw);
+
+                                // the integrated angles can't be trusted until the accelerometer pulled them back
+                                let was_saturated = saturation.saturated();
+                                saturation.update(raw, *offset, *gyro_range);
+                                if saturation.saturated() != was_saturated {
+                                    if saturation.saturated() {
+                                        rprintln!("gyro saturated, {} times", saturation.count);
+                                    }
+                                    write_frame(tx, &arming.lock(|a| status(*lost, saturation, a, false)).to_byte_array());
+                                }
+                                *debug = Some((raw, sample.gyro));
+
+                                if let Some(o) = bias.add(raw, sample.acc, *offset, *gyro_range, ACCEL_RANGE) {
+                                    *offset = o;
+                                }
+
+                                // everything above works per sensor axis, fusion in frame axes
+                                let framed = sample.mounted(MOUNTING);
+
+                                // samples were lost, the integrated part is stale
+                                if *resync {
+                                    estimator.reset(acc_angles(framed.acc));
+                                    notch.reset();
+                                    *resync = false;
+                                } else if *rearm {
+                                    // carried around or diverged while disarmed, only trusted at rest
+                                    *rearm = false;
+                                    let limit = REARM_STILL_DPS * gyro_range.sensitivity();
+                                    if (framed.gyro - MOUNTING.apply(*offset)).iter().all(|g| libm::fabsf(*g) < limit) {
+                                        estimator.reseed(acc_angles(framed.acc));
+                                    } else {
+                                        rprintln!("moving when armed, attitude kept");
+                                        write_frame(tx, &Status { reset_skipped: true, ..arming.lock(|a| status(*lost, saturation, a, false)) }.to_byte_array());
+                                    }
+                                } else {
+                                    let fusion_start = DWT::cycle_count();
+                                    let gyro = (framed.gyro - MOUNTING.apply(*offset)) * gyro_range.rad_per_lsb();
+                                    // a saturated accelerometer drops its correction for this sample
+                                    let acc = Some(framed.acc).filter(|a| !acc_saturated(*a));
+                                    let mag = field.take().map(|f| MOUNTING.apply(f));
+                                    estimator.set_recovering(saturation.recovering());
+                                    estimator.update(gyro, acc, mag, dt);
+                                    *rates = Some(gyro);
+                                    crashed |= crash.sample(framed.acc / ACCEL_RANGE.sensitivity(), gyro);
+                                    if let (Some(v), Some(a)) = (vertical.as_mut(), acc) {
+                                        v.predict(estimator.linear_acceleration(a / ACCEL_RANGE.sensitivity()).z, dt);
+                                    }
+                                    stats.fusion.add(DWT::cycle_count().wrapping_sub(fusion_start));                                }
+                                last = Some(sample);
+                            }
+
+                            // Euler angles once a batch, for the quaternion filters they cost about as
+                            // much as an update
+                            *s = estimator.orientation();
+                            if last.is_some() {
+                                *last_sample = last;
+                            }
+                        }
+
+                        let q = if *dmp_running { quaternion_from_acc_angles(s.pitch, s.roll, s.yaw) } else { estimator.quaternion() };
+                        crashed |= crash.attitude(q, count);
+                        if crashed && en.lock(|en| en.is_set_high()) {
+                            (&mut arming, &mut pwm, &mut en).lock(|arming, pwm, en| {
+                                disarm(pwm, en);
+                                arming.crashed = true;
+                                arming.failsafe();
+                            });
+                            report_arm_state::spawn().ok();
+                            throttle.lock(|t| *t = 0.0);
+                            rprintln!("crashed, disarmed");
+                            print_command_log::spawn().ok();
+                            write_frame(tx, &arming.lock(|a| status(*lost, saturation, a, false)).to_byte_array());
+                        }
+
+                        // more than one batch was queued, the magnetometer waits for the FIFO to drain
+                        *mag_samples += count;
+                        if !drained {
+                            reader.lock(|r| r.as_mut().map(|r| r.read(mpu::FIFO_COUNT_H, 2)));
+                        } else if let Some(m) = mag {
+                            if *mag_samples >= rate / MAG_FREQUENCY_HZ {
+                                *mag_samples = 0;
+                                let (reg, len) = m.data();
+                                reader.lock(|r| r.as_mut().map(|r| r.read_from(m.address(), reg, len)));
+                            }
+                        }
+
+                        let total = DWT::cycle_count().wrapping_sub(start);
+                        stats.total.add(total);
+                        loop_cycles.lock(|c| c.add(total));
+
+                        // roughly once per second
+                        *samples += count;
+                        if *samples >= rate {
+                            *samples -= rate;
+
+                            let sample_stats = SampleStats {
+                                read: *read,
+                                missed: *missed,
+                                max_gap_us: (*max_gap as u64 * 1_000_000 / bus.clocks.sysclk().0 as u64) as u32,
+                                overflows: (*overflows).min(u16::MAX as u32) as u16,
+                            };
+                            let cycles = stats.take(rx_cycles.lock(|s| s.take()), tx_cycles.lock(|s| s.take()));
+                            statistics::spawn(sample_stats, cycles).ok();
+
+                            rprintln!("i2c {:?}, fusion {:?}", cycles.i2c, cycles.fusion);
+                            rprintln!("telemetry {:?}, total {:?}, {} intervals clamped", cycles.telemetry, cycles.total, clamped);
+                            rprintln!("receive {:?}", cycles.receive);
+                            if cycles.fusion.max > 0 {
+                                rprintln!("fusion {} Hz at most", bus.clocks.sysclk().0 / cycles.fusion.max);
+                            }
+                            rprintln!("{} samples read, {} missed, max gap {} us", sample_stats.read, sample_stats.missed, sample_stats.max_gap_us);
+                            *max_gap = 0;
+                        }
+                    }
+                    Ok(Transfer::Read { address, buf, len, .. }) => {
+                        *failures = 0;
+
+                        if mag.as_ref().map(|m| m.address()) == Some(address) {
+                            let raw = mag.as_ref().and_then(|m| m.field(&buf[..len]));
+                            if let (Some(sweep), Some(f)) = (mag_sweep.as_mut(), raw) {
+                                let settings = stored_settings(*gyro_range, *offset, *accel, *mag_cal, estimator, crash);
+                                if sweep_field(sweep, f, mag_cal, settings, tx) {
+                                    *mag_sweep = None;
+                                }
+                            }
+                            *field = raw.map(|f| mag_cal.apply(f));
+                            *heading = field.map(|f| tilt_compensated_heading(MOUNTING.apply(f), s.pitch, s.roll));
+                        } else {
+                            let altitude = altimeter.lock(|altimeter| {
+                                altimeter.as_mut()
+                                    .and_then(|a| a.baro.on_read(&buf[..len]).and_then(|p| a.on_pressure(p)))
+                            });
+                            match (altitude, *dmp_running) {
+                                (Some(meters), false) => {
+                                    let v = vertical.get_or_insert_with(|| VerticalFilter::new(meters));
+                                    v.correct(meters);
+                                    let frame = Vertical {
+                                        centimeters: (v.altitude() * 100.0) as i32,
+                                        centimeters_per_s: (v.speed() * 100.0) as i16,
+                                    };
+                                    write_frame(tx, &frame.to_byte_array());
+                                }
+                                (Some(meters), true) => {
+                                    write_frame(tx, &Altitude { centimeters: (meters * 100.0) as i32 }.to_byte_array());
+                                }
+                                (None, _) => {}
+                            }
+                        }
+                    }
+                    Ok(Transfer::Write { .. }) => {}
+                    Err(e) => {
+                        imu.bus.errors += 1;
+                        *failures += 1;
+                        rprintln!("i2c error {:?}, {} total", e, imu.bus.errors);
+
+                        // following samples act as the retries, recover once they failed too.
+                        // A lost IMU is left to `health` so errors don't keep the bus in recovery.
+                        if *failures > I2C_RETRIES && !imu.lost {
+                            *failures = 0;
+
+                            (&mut arming, &mut pwm, &mut en).lock(|arming, pwm, en| set_imu_lost(imu, true, arming, pwm, en, tx));
+
+                            if let Some(r) = reader.lock(|reader| reader.take()) {
+                                let (r, result) = reconnect(&mut imu.bus, &mut imu.mag, &mut imu.dmp, r);
+                                reader.lock(|reader| *reader = Some(r));
+
+                                match result {
+                                    Ok(()) => (&mut arming, &mut pwm, &mut en).lock(|arming, pwm, en| set_imu_lost(imu, false, arming, pwm, en, tx)),
+                                    Err(e) => rprintln!("MPU6050 not responding {:?}", Error::from(e)),
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /// Tells the ground which protocol this device speaks, once a command came on another
+    #[task(shared = [usart1_tx])]
+    fn protocol_info(mut cx: protocol_info::Context) {
+        cx.shared.usart1_tx.lock(|tx| write_frame(tx, &ProtocolInfo { version: PROTOCOL_VERSION }.to_byte_array()));
+    }
+
+    /// Cuts the motors and leaves for the ROM, see [bootloader]. Nothing comes back from it
+    /// but a reset.
+    #[task(shared = [arming, pwm, en])]
+    fn reboot_to_bootloader(cx: reboot_to_bootloader::Context) {
+        let (mut arming, mut pwm, mut en) = (cx.shared.arming, cx.shared.pwm, cx.shared.en);
+        (&mut pwm, &mut en).lock(|pwm, en| disarm(pwm, en));
+        arming.lock(|a| a.disarm());
+        rprintln!("jumping to the bootloader");
+        crate::bootloader::jump();
+    }
+
+    /// Answers [Msg::Hello], the sensors as far as boot found them
+    #[task(shared = [imu, altimeter, usart1_tx], capacity = 2)]
+    fn announce(cx: announce::Context) {
+        let (mut imu, mut altimeter, mut tx) = (cx.shared.imu, cx.shared.altimeter, cx.shared.usart1_tx);
+        let (found, mag) = imu.lock(|imu| imu.as_ref().map_or((false, false), |imu| (true, imu.mag.is_some())));
+        let baro = altimeter.lock(|a| a.is_some());
+        let sensors = Announce::SENSOR_IMU * found as u8 | Announce::SENSOR_MAG * mag as u8 | Announce::SENSOR_BARO * baro as u8;
+        let announce = Announce { protocol: PROTOCOL_VERSION, features: FEATURES, motors: MOTORS.len() as u8, sensors, params: Param::ALL.len() as u8 };
+        tx.lock(|tx| write_frame(tx, &announce.to_byte_array()));
+    }
+
+    /// Tells the ground what it is talking to, see [version]
+    #[task(shared = [usart1_tx])]
+    fn build_info(mut cx: build_info::Context) {
+        cx.shared.usart1_tx.lock(|tx| write_frame(tx, &crate::version::BUILD_INFO.to_byte_array()));
+    }
+
+    /// Tells the ground a command didn't make it, the count since boot
+    #[task(shared = [link, usart1_tx])]
+    fn link_stats(cx: link_stats::Context) {
+        let (mut link, mut tx) = (cx.shared.link, cx.shared.usart1_tx);
+        let stats = link.lock(|l| with_bytes_sent(l));
+        tx.lock(|tx| write_frame(tx, &stats.to_byte_array()));
+    }
+
+    /// [Msg::ResetConsumed]
+    #[task(shared = [power, usart1_tx])]
+    fn reset_consumed(cx: reset_consumed::Context) {
+        let (mut power, mut tx) = (cx.shared.power, cx.shared.usart1_tx);
+        let frame = power.lock(|p| {
+            p.consumed_mah = 0.0;
+            p.armed_mah = 0.0;
+            p.over_current = false;
+            p.frame()
+        });
+        if let Some(frame) = frame {
+            tx.lock(|tx| write_frame(tx, &frame.to_byte_array()));
+        }
+    }
+
+    /// [Msg::ResetLinkStats], the command that asked for it is already counted
+    #[task(shared = [link])]
+    fn reset_link_stats(mut cx: reset_link_stats::Context) {
+        cx.shared.link.lock(|l| *l = LinkStats::new());
+        BYTES_SENT.store(0, Ordering::Relaxed);
+        FRAMES_SKIPPED.store(0, Ordering::Relaxed);
+        link_stats::spawn().ok();
+    }
+
+    fn with_bytes_sent(link: &LinkStats) -> LinkStats {
+        LinkStats { bytes_sent: BYTES_SENT.load(Ordering::Relaxed), skipped: FRAMES_SKIPPED.load(Ordering::Relaxed), ..*link }
+    }
+
+    /// Telemetry on a timer, every [TelemetryConfig::divisor] gyro periods however fast the
+    /// IMU samples. Queued replies go first, command feedback doesn't wait behind the stream on
+    /// a slow link. Only the replies while the raw samples stream.
+    #[task(
+        local = [rounds: u32 = 0],
+        shared = [imu, usart1_tx, arming, throttle, slew, gyro_debug, quaternion, linear_accel, rates, compact, telemetry, replies, tx_cycles, mavlink],
+    )]
+    fn send_telemetry(cx: send_telemetry::Context) {
+        let start = DWT::cycle_count();
+        let rounds: &mut u32 = cx.local.rounds;
+        let send_telemetry::SharedResources { mut imu, mut usart1_tx, mut arming, mut throttle, mut slew, mut gyro_debug, mut quaternion, mut linear_accel, mut rates, mut compact, mut telemetry, mut replies, mut tx_cycles, mut mavlink } = cx.shared;
+
+        let config = telemetry.lock(|t| *t);
+        let spawn_next_at = monotonics::now() + (config.divisor as u64 * 1000 / GYRO_FREQUENCY_HZ as u64).millis();
+
+        let snapshot = imu.lock(|imu| {
+            imu.as_ref().map(|imu| {
+                let s = imu.orientation;
+                let counts = |v: Vec3| {
+                    let v = v - imu.offset;
+                    [v.x as i16, v.y as i16, v.z as i16]
+                };
+                Snapshot {
+                    orientation: s,
+                    quaternion: if imu.dmp { quaternion_from_acc_angles(s.pitch, s.roll, s.yaw) } else { imu.estimator.quaternion() },
+                    rates: imu.rates,
+                    debug: imu.debug.map(|(raw, filtered)| (counts(raw), counts(filtered))),
+                    linear_accel: imu.last_sample.as_ref().map(|sample| imu.estimator.linear_acceleration(MOUNTING.apply(sample.acc) / ACCEL_RANGE.sensitivity())),
+                    heading: imu.heading.zip(imu.heading_hold).map(|(h, reference)| (h, wrap_angle(h - reference))),
+                }
+            })
+        });
+        let raw_stream = arming.lock(|a| a.raw_stream);
+        let armed = arming.lock(|a| a.state == ArmState::Armed);
+
+        usart1_tx.lock(|tx| {
+            replies.lock(|replies| write_replies(tx, replies, &mut arming));
+            let snapshot = match snapshot {
+                Some(snapshot) if !raw_stream => snapshot,
+                _ => return,
+            };
+            let s = &snapshot.orientation;
+
+            if cfg!(feature = "mavlink") {
+                if config.streams(TelemetryConfig::ATTITUDE) {
+                    let time_boot_ms = uptime_ms() as u32;
+                    let g = snapshot.rates.unwrap_or_default();
+                    let frame = mavlink.lock(|m| m.attitude(time_boot_ms, s, [g.x, g.y, g.z]));
+                    write_mavlink(tx, &frame);
+                }
+                return;
+            }
+
+            if config.streams(TelemetryConfig::ATTITUDE) {
+                if quaternion.lock(|q| *q) {
+                    let [w, x, y, z] = snapshot.quaternion;
+                    write_frame(tx, &AttitudeQuaternion { w, x, y, z }.to_byte_array());
+                } else if compact.lock(|c| *c) {
+                    write_frame(tx, &CompactOrientation::from_orientation(s).to_byte_array());
+                } else {
+                    write_frame(tx, &s.to_byte_array());
+                }
+            }
+
+            if armed {
+                let output = throttle.lock(|t| *t);
+                write_frame(tx, &ThrottleOutput { commanded: slew.lock(|s| s.commanded), output }.to_byte_array());
+            }
+
+            if let (true, Some((raw, filtered))) = (gyro_debug.lock(|d| *d), snapshot.debug) {
+                write_frame(tx, &GyroDebug { raw, filtered }.to_byte_array());
+            }
+
+            if let (true, Some(g)) = (rates.lock(|r| *r) && config.streams(TelemetryConfig::RATES), snapshot.rates) {
+                let milli = |v: f32| (v * 1000.0) as i16;
+                write_frame(tx, &Rates { milli_rad_s: [milli(g.x), milli(g.y), milli(g.z)] }.to_byte_array());
+            }
+
+            // every other round, 9600 baud doesn't carry both at the full rate
+            *rounds = rounds.wrapping_add(1);
+            if let (true, true, Some(a)) = (linear_accel.lock(|l| *l), *rounds % 2 == 0, snapshot.linear_accel) {
+                let milli_g = |v: f32| (v * 1000.0) as i16;
+                write_frame(tx, &LinearAcceleration { milli_g: [milli_g(a.x), milli_g(a.y), milli_g(a.z)] }.to_byte_array());
+            }
+            if let (true, Some((h, error))) = (*rounds % 2 == 1, snapshot.heading) {
+                let centi = |rad: f32| (rad.to_degrees() * 100.0) as i16;
+                write_frame(tx, &Heading { centi_degrees: centi(h), error_centi_degrees: centi(error) }.to_byte_array());
+            }
+        });
+        tx_cycles.lock(|c| c.add(DWT::cycle_count().wrapping_sub(start)));
+
+        send_telemetry::spawn_at(spawn_next_at).ok();
+    }
+
+    /// Once a second, for a ground station connecting after boot: even with nothing else
+    /// streamed it finds out why. With the status stream, what changes seldom comes along.
+    #[task(shared = [imu, altimeter, usart1_tx, arming, mode, telemetry, failsafe, power, setpoint, trim, baud, pwm, mavlink])]
+    fn repeat(cx: repeat::Context) {
+        let spawn_next_at = monotonics::now() + 1.secs();
+        let repeat::SharedResources { mut imu, mut altimeter, mut usart1_tx, mut arming, mut mode, mut telemetry, mut failsafe, mut power, mut setpoint, mut trim, mut baud, mut pwm, mut mavlink } = cx.shared;
+
+        let config = telemetry.lock(|t| *t);
+        // lost with no IMU at all, the MAVLink state goes critical the same
+        let (lost, mag, errors, temperature, self_test, filter) = imu.lock(|imu| match imu {
+            Some(imu) => (imu.lost, imu.mag.is_some(), imu.bus.errors, imu.last_sample.as_ref().map(|s| s.temp), imu.self_test, Some(filter_config(&imu.estimator))),
+            None => (true, false, 0, None, None, None),
+        });
+
+        usart1_tx.lock(|tx| {
+            if cfg!(feature = "mavlink") {
+                let (armed, crashed) = arming.lock(|a| (a.state == ArmState::Armed, a.crashed));
+                let mut sensors = mavlink::SENSOR_GYRO | mavlink::SENSOR_ACCEL;
+                if mag {
+                    sensors |= mavlink::SENSOR_MAG;
+                }
+                if altimeter.lock(|a| a.is_some()) {
+                    sensors |= mavlink::SENSOR_BARO;
+                }
+                let (heartbeat, status) = mavlink.lock(|m| (m.heartbeat(armed, crashed || lost), m.sys_status(sensors, power.lock(|p| p.volts), power.lock(|p| p.amps), errors.min(u16::MAX as u32) as u16)));
+                write_mavlink(tx, &heartbeat);
+                write_mavlink(tx, &status);
+                return;
+            }
+
+            write_frame(tx, &config.to_byte_array());
+            write_frame(tx, &arming.lock(|a| a.state).to_byte_array());
+            write_frame(tx, &arming.lock(|a| a.lock_state()).to_byte_array());
+            write_frame(tx, &mode.lock(|m| *m).to_byte_array());
+            write_frame(tx, &crate::version::BUILD_INFO.to_byte_array());
+            write_frame(tx, &LinkRate { baud: baud.lock(|b| b.rate) }.to_byte_array());
+            if config.streams(TelemetryConfig::STATUS) {
+                if let Some(celsius) = temperature {
+                    write_frame(tx, &Temperature { celsius }.to_byte_array());
+                }
+                if let Some(t) = self_test {
+                    write_frame(tx, &t.to_byte_array());
+                }
+                if let Some(filter) = filter {
+                    write_frame(tx, &filter.to_byte_array());
+                }
+                write_frame(tx, &failsafe.lock(|f| f.config).to_byte_array());
+                write_frame(tx, &setpoint.lock(|s| *s).to_byte_array());
+                write_frame(tx, &trim.lock(|t| t.trim).to_byte_array());
+                let outputs = pwm.lock(|pwm| MotorOutputs { duty: MOTORS.map(|m| pwm.get_duty(m)), max_duty: pwm.get_max_duty() });
+                write_frame(tx, &outputs.to_byte_array());
+                if let Some(battery) = power.lock(|p| p.frame()) {
+                    write_frame(tx, &battery.to_byte_array());
+                }
+            }
+        });
+
+        repeat::spawn_at(spawn_next_at).ok();
+    }
+
+    /// Samples the battery and writes its frame the moment the alarm changes or the current
+    /// goes over the limit. Armed at [BatteryAlarm::Critical] with [BatteryConfig::failsafe],
+    /// the craft fails safe as on a lost link and [output] takes the motors down over the ramp.
+    ///
+    /// The mAh add up over the time since the last sample as the monotonic has it, a run
+    /// held up behind the other tasks still counts in full.
+    #[task(local = [battery, last: Option<Instant> = None, armed: bool = false], shared = [power, arming, usart1_tx])]
+    fn battery_monitor(cx: battery_monitor::Context) {
+        let now = monotonics::now();
+        let spawn_next_at = now + BATTERY_PERIOD_MS.millis();
+        let (mut power, mut arming, mut tx) = (cx.shared.power, cx.shared.arming, cx.shared.usart1_tx);
+
+        let (divider, current) = power.lock(|p| (p.config.divider, p.current));
+        let (volts, amps) = cx.local.battery.sample(divider, &current);
+        let hours = cx.local.last.replace(now).map_or(0.0, |last| millis_since(last, now) as f32 / 3_600_000.0);
+        let armed = arming.lock(|a| a.state == ArmState::Armed);
+        let rearmed = armed && !*cx.local.armed;
+        *cx.local.armed = armed;
+
+        let (changed, frame, critical) = power.lock(|p| {
+            let previous = (p.alarm, p.over_current);
+            p.volts = volts;
+            p.amps = amps;
+            p.alarm = p.cell_volts().map_or(BatteryAlarm::Ok, |v| p.config.alarm(v, previous.0));
+            if rearmed {
+                p.armed_mah = 0.0;
+            }
+            if let Some(amps) = amps {
+                p.consumed_mah += amps * hours * 1000.0;
+                p.armed_mah += amps * hours * 1000.0;
+                p.over_current |= current.over_current(amps);
+            }
+            ((p.alarm, p.over_current) != previous, p.frame(), p.config.failsafe && p.alarm == BatteryAlarm::Critical)
+        });
+        if let (true, Some(frame)) = (changed, frame) {
+            rprintln!("battery {:?} at {} mV, over current {}", frame.alarm, frame.millivolts, frame.over_current);
+            tx.lock(|tx| write_frame(tx, &frame.to_byte_array()));
+        }
+
+        let failed = critical && arming.lock(|a| {
+            let engaged = a.engaged();
+            if engaged {
+                a.failsafe();
+            }
+            engaged
+        });
+        if failed {
+            rprintln!("battery critical, failsafe");
+            report_arm_state::spawn().ok();
+            print_command_log::spawn().ok();
+        }
+
+        battery_monitor::spawn_at(spawn_next_at).ok();
+    }
+
+    /// The gyro task's numbers for the last second, with the link's
+    #[task(shared = [telemetry, link, usart1_tx])]
+    fn statistics(cx: statistics::Context, sample_stats: SampleStats, cycles: CycleStats) {
+        let (mut telemetry, mut link, mut tx) = (cx.shared.telemetry, cx.shared.link, cx.shared.usart1_tx);
+        if !telemetry.lock(|t| t.streams(TelemetryConfig::STATISTICS)) || cfg!(feature = "mavlink") {
+            return;
+        }
+        let stats = link.lock(|l| with_bytes_sent(l));
+        tx.lock(|tx| {
+            write_frame(tx, &sample_stats.to_byte_array());
+            write_frame(tx, &cycles.to_byte_array());
+            write_frame(tx, &stats.to_byte_array());
+        });
+    }
+
+    /// The [ExtendedStatus] with its stream, every [Param::StatusPeriod]. The loop times
+    /// start over either way, the next frame only has the ones since this one.
+    #[task(shared = [imu, arming, failsafe, power, loop_cycles, telemetry, status_period_ms, pwm, usart1_tx])]
+    fn extended_status(cx: extended_status::Context) {
+        let now = monotonics::now();
+        let extended_status::SharedResources { mut imu, mut arming, mut failsafe, mut power, mut loop_cycles, mut telemetry, mut status_period_ms, mut pwm, mut usart1_tx } = cx.shared;
+        let spawn_next_at = now + (status_period_ms.lock(|p| *p) as u64).millis();
+
+        let cycles = loop_cycles.lock(|c| c.take());
+        if !telemetry.lock(|t| t.streams(TelemetryConfig::EXTENDED)) || cfg!(feature = "mavlink") {
+            extended_status::spawn_at(spawn_next_at).ok();
+            return;
+        }
+
+        let (imu_warnings, i2c_errors, cycles_per_us) = imu.lock(|imu| match imu {
+            Some(imu) => {
+                let calibrating = imu.six_position.is_some() || imu.mag_sweep.is_some();
+                let warnings = ExtendedStatus::WARNING_IMU_LOST * imu.lost as u8
+                    | ExtendedStatus::WARNING_GYRO_SATURATED * imu.saturation.saturated() as u8
+                    | ExtendedStatus::WARNING_CALIBRATING * calibrating as u8;
+                (warnings, imu.bus.errors, imu.bus.clocks.sysclk().0 / 1_000_000)
+            }
+            None => (ExtendedStatus::WARNING_IMU_LOST, 0, 1),
+        });
+        let power_warnings = power.lock(|p| {
+            ExtendedStatus::WARNING_LOW_BATTERY * (p.alarm != BatteryAlarm::Ok) as u8 | ExtendedStatus::WARNING_OVER_CURRENT * p.over_current as u8
+        });
+        let status = ExtendedStatus {
+            arm_state: arming.lock(|a| a.state),
+            outputs: pwm.lock(|pwm| MotorOutputs { duty: MOTORS.map(|m| pwm.get_duty(m)), max_duty: pwm.get_max_duty() }),
+            command_age_ms: failsafe.lock(|f| f.last_command.map(|t| millis_since(t, now) as u32)),
+            i2c_errors,
+            loop_min_us: cycles.min / cycles_per_us,
+            loop_max_us: cycles.max / cycles_per_us,
+            warnings: imu_warnings | power_warnings,
+        };
+        usart1_tx.lock(|tx| write_frame(tx, &status.to_byte_array()));
+
+        extended_status::spawn_at(spawn_next_at).ok();
+    }
+
+    /// Answers [Msg::RequestStatus] with what the once a second report has, less what
+    /// takes the IMU
+    #[task(shared = [arming, mode, telemetry, failsafe, power, setpoint, trim, pwm, link, baud, usart1_tx])]
+    fn report_status(cx: report_status::Context) {
+        let (mut arming, mut mode, mut telemetry, mut failsafe) = (cx.shared.arming, cx.shared.mode, cx.shared.telemetry, cx.shared.failsafe);
+        let (mut setpoint, mut trim, mut pwm, mut tx) = (cx.shared.setpoint, cx.shared.trim, cx.shared.pwm, cx.shared.usart1_tx);
+        let (mut link, mut baud, mut power) = (cx.shared.link, cx.shared.baud, cx.shared.power);
+        let stats = link.lock(|l| with_bytes_sent(l));
+        let rate = LinkRate { baud: baud.lock(|b| b.rate) };
+        let battery = power.lock(|p| p.frame());
+        tx.lock(|tx| {
+            write_frame(tx, &arming.lock(|a| a.state).to_byte_array());
+            write_frame(tx, &arming.lock(|a| a.lock_state()).to_byte_array());
+            write_frame(tx, &mode.lock(|m| *m).to_byte_array());
+            write_frame(tx, &telemetry.lock(|t| *t).to_byte_array());
+            write_frame(tx, &failsafe.lock(|f| f.config).to_byte_array());
+            write_frame(tx, &setpoint.lock(|s| *s).to_byte_array());
+            write_frame(tx, &trim.lock(|t| t.trim).to_byte_array());
+            let outputs = pwm.lock(|pwm| MotorOutputs { duty: MOTORS.map(|m| pwm.get_duty(m)), max_duty: pwm.get_max_duty() });
+            write_frame(tx, &outputs.to_byte_array());
+            write_frame(tx, &stats.to_byte_array());
+            write_frame(tx, &rate.to_byte_array());
+            if let Some(battery) = battery {
+                write_frame(tx, &battery.to_byte_array());
+            }
+        });
+    }
+
+    /// Writes out every queued reply. An ack going out while arming completes it, see
+    /// [armed].
+    fn write_replies(tx: &mut TxLink, replies: &mut Deque<Reply, REPLY_QUEUE>, arming: &mut impl rtic::Mutex<T = Arming>) {
+        while let Some(reply) = replies.pop_front() {
+            match reply {
+                Reply::Ack(ack) => {
+                    write_frame(tx, &ack.to_byte_array());
+                    if arming.lock(|a| a.state == ArmState::Arming) {
+                        armed::spawn(ack.sequence).ok();
+                    }
+                }
+                Reply::Applied(applied) => write_frame(tx, &applied.to_byte_array()),
+                Reply::Pong(pong) => write_frame(tx, &pong.to_byte_array()),
+            }
+        }
+    }
+
+    /// Answers a ping without waiting for the next telemetry frame, along with whatever
+    /// acks queued up before it
+    #[task(shared = [replies, arming, usart1_tx])]
+    fn flush_replies(cx: flush_replies::Context) {
+        let mut arming = cx.shared.arming;
+        (cx.shared.replies, cx.shared.usart1_tx).lock(|replies, tx| write_replies(tx, replies, &mut arming));
+    }
+
+    /// What a command port brought since the last interrupt, queued for [process_commands].
+    /// True when it had the [EmergencyStop] in it.
+    fn take_in(source: &mut impl CommandSource, queue: &mut Producer<'static, u8, COMMAND_QUEUE_SIZE>, stop: &mut EmergencyStop, link: &mut impl rtic::Mutex<T = LinkStats>) -> bool {
+        let (mut received, mut stopped, mut dropped) = (0, false, 0);
+        // a full queue is the task falling behind, the bytes are lost the way an overrun loses them
+        let status = source.take(|byte| {
+            received += 1;
+            stopped |= stop.push(byte);
+            dropped += queue.enqueue(byte).is_err() as u32;
+        });
+        link.lock(|l| l.bytes_received = l.bytes_received.wrapping_add(received));
+
+        // cleared along with the idle line, the ring starts over clean and a delimiter ends the
+        // frame the error spoiled, it fails to decode. Shorting RX to ground for a moment on the
+        // bench makes framing errors, the frame after them has to come through.
+        let (overrun, framing, noise) = (status.ore().bit_is_set(), status.fe().bit_is_set(), status.ne().bit_is_set());
+        if overrun || framing || noise {
+            source.restart();
+            queue.enqueue(EOT).ok();
+        }
+        link.lock(|l| {
+            l.overruns = l.overruns.wrapping_add((overrun || dropped > 0) as u32);
+            l.framing = l.framing.wrapping_add(framing as u32);
+            l.noise = l.noise.wrapping_add(noise as u32);
+        });
+        stopped
+    }
+
+    /// Kills from the interrupt, ahead of anything else in the burst: a command queued before
+    /// it doesn't get to run
+    fn emergency_stop(
+        from: Link,
+        arming: &mut impl rtic::Mutex<T = Arming>,
+        pwm: &mut impl rtic::Mutex<T = MFR>,
+        en: &mut impl rtic::Mutex<T = EN>,
+        throttle: &mut impl rtic::Mutex<T = f32>,
+    ) {
+        // one inside another, a tuple of them only locks inside a task
+        arming.lock(|arming| {
+            pwm.lock(|pwm| en.lock(|en| disarm(pwm, en)));
+            arming.kill();
+            throttle.lock(|throttle| *throttle = 0.0);
+        });
+        rprintln!("emergency stop over {:?}, killed until a power cycle or clear", from);
+        report_arm_state::spawn().ok();
+        print_command_log::spawn().ok();
+    }
+
+    /// Copies what came off the ring for [process_commands], short so it doesn't hold up the
+    /// sampling. Only the emergency stop acts here.
+    #[task(binds = USART1, local = [recv, commands_in, stop: EmergencyStop = EmergencyStop::new()], shared = [arming, throttle, link, rx_cycles, pwm, en], priority = 2)]
+    fn on_rx(cx: on_rx::Context) {
+        let entered = DWT::cycle_count();
+        let on_rx::LocalResources { recv, commands_in, stop } = cx.local;
+        let on_rx::SharedResources { mut arming, mut throttle, mut link, mut rx_cycles, mut pwm, mut en } = cx.shared;
+
+        if take_in(recv, commands_in, stop, &mut link) {
+            emergency_stop(Link::Bluetooth, &mut arming, &mut pwm, &mut en, &mut throttle);
+        }
+
+        process_commands::spawn(entered).ok();
+        rx_cycles.lock(|s| s.add(DWT::cycle_count().wrapping_sub(entered)));
+    }
+
+    /// Parses and applies the commands [on_rx] and the wired link queued, at the priority of
+    /// the telemetry. `entered` is the cycle count of the interrupt that queued the first of
+    /// them. Each link has a decoder and a sequence of its own, throttle and setpoints go by
+    /// whichever sent the last, either can arm, disarm or kill.
+    #[task(
+        local = [
+            commands_out,
+            wired_out,
+            sysclk,
+            decoders: [cobs::Decoder<COMMAND_FRAME_SIZE>; 2] = [cobs::Decoder::new(), cobs::Decoder::new()],
+            sequences: [SequenceTracker; 2] = [SequenceTracker::new(), SequenceTracker::new()],
+            announced: bool = false,
+            commanded: f32 = 0.0,
+            msp: msp::Parser = msp::Parser::new(),
+        ],
+        shared = [arming, gyro_debug, quaternion, linear_accel, rates, compact, setpoint, pid, trim, mode, telemetry, failsafe, link, replies, command_log, slew, pwm, en]
+    )]
+    fn process_commands(cx: process_commands::Context, entered: u32) {
+        let process_commands::LocalResources { commands_out, wired_out, sysclk, decoders, sequences: trackers, announced, commanded, .. } = cx.local;
+        let process_commands::SharedResources { mut arming, mut gyro_debug, mut quaternion, mut linear_accel, mut rates, mut compact, mut setpoint, mut pid, mut trim, mut mode, mut telemetry, mut failsafe, mut link, mut replies, mut command_log, mut slew, mut pwm, mut en } = cx.shared;
+        let received = core::iter::from_fn(|| {
+            let bluetooth = commands_out.dequeue().map(|byte| (Link::Bluetooth, byte));
+            bluetooth.or_else(|| wired_out.dequeue().map(|byte| (Link::Wired, byte)))
+        });
+
+        let mut reply = |reply: Reply| {
+            replies.lock(|replies| {
+                if replies.is_full() {
+                    replies.pop_front();
+                }
+                replies.push_back(reply).ok();
+            })
+        };
+
+        let mut log = |sequence: u16, msg: Option<Msg>, status: AckStatus| {
+            let at_ms = uptime_ms() as u32;
+            command_log.lock(|entries| {
+                if entries.is_full() {
+                    entries.pop_front();
+                }
+                entries.push_back(CommandLogEntry { at_ms, sequence, msg, status }).ok();
+            })
+        };
+
+        // MSP_SET_PID carries no feed-forward, the gains it starts from are read before `apply`
+        // holds the table. Only this port writes them, so they stay current through the burst.
+        #[cfg(feature = "msp")]
+        let gains = pid.lock(|p| p.gains);
+
+        let mut apply = |command: &Command, from: Link| -> (AckStatus, AppliedState) {
+            if RC_SOURCE == RcSource::Commands {
+                let control = matches!(command.msg, Msg::Throttle(_) | Msg::Setpoint(_) | Msg::Arm(Arm::Arm(_)));
+                let taken = failsafe.lock(|f| {
+                    let taken = control && f.controller != from;
+                    if control {
+                        f.controller = from;
+                    }
+                    if f.controller == from {
+                        f.last_command = Some(monotonics::now());
+                    }
+                    taken
+                });
+                if taken {
+                    rprintln!("flown over {:?} now", from);
+                }
+            }
+            let state = arming.lock(|a| (a.state, a.locked));
+
+            let status = match command.msg {
+                // disarming is always a command away
+                Msg::Throttle(_) | Msg::Setpoint(_) | Msg::Arm(Arm::Arm(_)) if RC_SOURCE != RcSource::Commands => {
+                    rprintln!("{:?} ignored, flown over {:?}", command.msg, RC_SOURCE);
+                    AckStatus::Unsupported
+                }
+                Msg::Throttle(t) => {
+                    // what arming checks against, whatever the state
+                    *commanded = t;
+                    (&mut arming, &mut slew).lock(|arming, slew| {
+                        if arming.locked {
+                            return AckStatus::Locked;
+                        }
+                        if arming.state == ArmState::Armed {
+                            // output takes the motors there
+                            slew.commanded = t;
+                        }
+                        AckStatus::Applied
+                    })
+                }
+                // output cuts the motors once this leaves them disarmed
+                Msg::Arm(request) => arming.lock(|arming| match request {
+                    Arm::Disarm => {
+                        arming.disarm();
+                        AckStatus::Applied
+                    }
+                    Arm::Arm(magic) => arming.request(magic, *commanded, command.sequence),
+                }),
+                Msg::Setpoint(s) => {
+                    setpoint.lock(|setpoint| *setpoint = s);
+                    AckStatus::Applied
+                }
+                Msg::Streams(streams) => {
+                    gyro_debug.lock(|d| *d = streams.gyro_debug);
+                    quaternion.lock(|q| *q = streams.quaternion);
+                    linear_accel.lock(|l| *l = streams.linear_accel);
+                    rates.lock(|r| *r = streams.rates);
+                    compact.lock(|c| *c = streams.compact);
+                    arming.lock(|arming| {
+                        arming.raw_stream = streams.raw_stream;
+                        if arming.engaged() && !arming.allowed() {
+                            rprintln!("disarmed, arming no longer allowed {:?}", arming);
+                            arming.disarm();
+                            AckStatus::NotArmed
+                        } else {
+                            AckStatus::Applied
+                        }
+                    })
+                }
+                Msg::SetParam(id, value) => match Param::from_id(id) {
+                    Some(param) if params::info(param).accepts(value) => {
+                        tune::spawn(param, value).ok();
+                        AckStatus::Applied
+                    }
+                    Some(param) => {
+                        rprintln!("{:?} {:?} out of range", param, value);
+                        report_param::spawn(param).ok();
+                        AckStatus::Range
+                    }
+                    None => AckStatus::Unknown,
+                },
+                Msg::GetParam(id) => match Param::from_id(id) {
+                    Some(param) => {
+                        report_param::spawn(param).ok();
+                        AckStatus::Applied
+                    }
+                    None => AckStatus::Unknown,
+                },
+                Msg::ListParams => {
+                    list_params::spawn(0).ok();
+                    AckStatus::Applied
+                }
+                Msg::SetMode(FlightMode::Passthrough) => {
+                    mode.lock(|m| *m = FlightMode::Passthrough);
+                    report_mode::spawn().ok();
+                    AckStatus::Applied
+                }
+                Msg::SetMode(m) => {
+                    rprintln!("no controller for {:?}", m);
+                    AckStatus::Unsupported
+                }
+                Msg::SetPidGains(axis, gains) => {
+                    // the whole table at once, a controller never reads half an update
+                    pid.lock(|p| {
+                        let mut table = p.gains;
+                        table[axis as usize] = gains;
+                        p.gains = table;
+                    });
+                    report_pid_gains::spawn(Some(axis)).ok();
+                    AckStatus::Applied
+                }
+                Msg::GetPidGains => {
+                    report_pid_gains::spawn(None).ok();
+                    AckStatus::Applied
+                }
+                Msg::SavePidGains => {
+                    save_pid_gains::spawn().ok();
+                    AckStatus::Applied
+                }
+                Msg::Telemetry(config) => {
+                    let (config, clamped) = config.clamped();
+                    if clamped {
+                        rprintln!("telemetry divisor clamped to {}", config.divisor);
+                    }
+                    let changed = telemetry.lock(|t| core::mem::replace(t, config) != config);
+                    report_telemetry::spawn().ok();
+                    if changed {
+                        save_settings::spawn().ok();
+                    }
+                    if clamped { AckStatus::Clamped } else { AckStatus::Applied }
+                }
+                Msg::SetTrim(t) => {
+                    trim.lock(|trim| trim.trim = t);
+                    report_trim::spawn().ok();
+                    AckStatus::Applied
+                }
+                Msg::SaveTrim | Msg::SaveParams => {
+                    save_params::spawn().ok();
+                    AckStatus::Applied
+                }
+                Msg::Calibrate | Msg::CalibrateAccel | Msg::CalibrateMag | Msg::CaptureTrim if (&mut arming, &mut en).lock(|a, en| en.is_set_high() || a.engaged()) => {
+                    rprintln!("calibration rejected while armed");
+                    AckStatus::Armed
+                }
+                Msg::RebootToBootloader(magic) if magic != BOOTLOADER_MAGIC => AckStatus::Range,
+                Msg::RebootToBootloader(_) if (&mut arming, &mut en).lock(|a, en| en.is_set_high() || a.engaged()) => {
+                    rprintln!("bootloader rejected while armed");
+                    AckStatus::Armed
+                }
+                Msg::RebootToBootloader(_) => {
+                    reboot_to_bootloader::spawn_after(BOOTLOADER_DELAY_MS.millis()).ok();
+                    AckStatus::Applied
+                }
+                Msg::SetBaud(_) if (&mut arming, &mut en).lock(|a, en| en.is_set_high() || a.engaged()) => {
+                    rprintln!("baud change rejected while armed");
+                    AckStatus::Armed
+                }
+                Msg::SetBaud(rate) => {
+                    switch_baud::spawn_after(BAUD_SWITCH_DELAY_MS.millis(), rate).ok();
+                    flush_replies::spawn().ok();
+                    AckStatus::Applied
+                }
+                Msg::Calibrate => {
+                    recalibrate::spawn().ok();
+                    AckStatus::Applied
+                }
+                Msg::CalibrateAccel => {
+                    accel_capture::spawn().ok();
+                    AckStatus::Applied
+                }
+                Msg::CalibrateMag => {
+                    mag_capture::spawn().ok();
+                    AckStatus::Applied
+                }
+                Msg::CaptureTrim => {
+                    capture_trim::spawn().ok();
+                    AckStatus::Applied
+                }
+                Msg::ZeroYaw => {
+                    zero_yaw::spawn().ok();
+                    AckStatus::Applied
+                }
+                Msg::HoldHeading => {
+                    hold_heading::spawn().ok();
+                    AckStatus::Applied
+                }
+                Msg::RequestStatus => {
+                    report_status::spawn().ok();
+                    AckStatus::Applied
+                }
+                // answered once acked, see below
+                Msg::Ping(_) => AckStatus::Applied,
+                Msg::ClearEmergencyStop(magic) => arming.lock(|a| a.clear_stop(magic)),
+                Msg::Unlock(code) => arming.lock(|a| a.unlock(code)),
+                Msg::DumpCommandLog => {
+                    dump_command_log::spawn(0).ok();
+                    AckStatus::Applied
+                }
+                Msg::RequestVersion => {
+                    build_info::spawn().ok();
+                    AckStatus::Applied
+                }
+                Msg::ResetLinkStats => {
+                    reset_link_stats::spawn().ok();
+                    AckStatus::Applied
+                }
+                Msg::ResetConsumed => {
+                    reset_consumed::spawn().ok();
+                    AckStatus::Applied
+                }
+                Msg::Hello => {
+                    announce::spawn().ok();
+                    AckStatus::Applied
+                }
+            };
+
+            if arming.lock(|a| (a.state, a.locked)) != state {
+                report_arm_state::spawn().ok();
+            }
+            let applied = (&mut arming, &mut pwm, &mut en, &mut mode).lock(|arming, pwm, en, mode| AppliedState {
+                sequence: command.sequence,
+                outputs: MotorOutputs { duty: MOTORS.map(|m| pwm.get_duty(m)), max_duty: pwm.get_max_duty() },
+                arm_state: arming.state,
+                enabled: en.is_set_high(),
+                mode: *mode,
+            });
+            (status, applied)
+        };
+
+        // MSP has the port, requests go through the same checks and apply as commands would
+        #[cfg(feature = "msp")]
+        {
+            let _ = (decoders, trackers, announced, reply);
+            for (_, byte) in received {
+                let request = match cx.local.msp.push(byte) {
+                    Some(Ok(request)) => request,
+                    Some(Err(e)) => {
+                        let rejected = link.lock(|l| {
+                            l.rejected = l.rejected.wrapping_add(1);
+                            l.crc = l.crc.wrapping_add((e == FrameError::Crc) as u32);
+                            l.rejected
+                        });
+                        rprintln!("MSP request rejected {:?}, {} so far", e, rejected);
+                        continue;
+                    }
+                    None => continue,
+                };
+                link.lock(|l| l.accepted = l.accepted.wrapping_add(1));
+
+                let mut run = |msg: Msg| -> AckStatus {
+                    let status = match msg.check() {
+                        Ok(()) => apply(&Command { sequence: 0, msg }, Link::Bluetooth).0,
+                        Err(_) => AckStatus::Range,
+                    };
+                    log(0, Some(msg), status);
+                    status
+                };
+                let ok = match request.command {
+                    msp::SET_RAW_RC => match msp::RawRc::from_payload(request.payload()) {
+                        Some(rc) => rc_input::spawn(RcSource::Msp, rc.input()).is_ok(),
+                        None => false,
+                    },
+                    msp::SET_PID => match msp::set_pid(request.payload(), &gains) {
+                        Some(gains) => Axis::ALL.iter().fold(true, |ok, a| run(Msg::SetPidGains(*a, gains[*a as usize])) == AckStatus::Applied && ok),
+                        None => false,
+                    },
+                    _ => true,
+                };
+                msp_reply::spawn(request.command, ok).ok();
+            }
+        }
+
+        // a frame can straddle two idle lines, or come after the tail of a broken one. Every
+        // command of a burst applies in order, each one asks for something else.
+        #[cfg(not(feature = "msp"))]
+        let mut decoded = [0; COMMAND_SIZE];
+        #[cfg(not(feature = "msp"))]
+        for (from, byte) in received {
+            let tracker = &mut trackers[from as usize];
+            let frame = match decoders[from as usize].push(byte, &mut decoded) {
+                Some(frame) => frame,
+                None => continue,
+            };
+            let received = match frame {
+                Ok(n) => Command::from_byte_slice(&decoded[..n]).map_err(|e| (e, Command::sequence_of(&decoded[..n]))),
+                Err(e) => Err((e, None)),
+            };
+            let skipped = received.as_ref().map_or(0, |c| tracker.skipped(c.sequence));
+            match received {
+                Ok(c) if tracker.accept(c.sequence) => {
+                    link.lock(|l| {
+                        l.accepted = l.accepted.wrapping_add(1);
+                        l.gaps = l.gaps.wrapping_add(skipped);
+                    });
+                    if skipped > 0 {
+                        rprintln!("{} commands missing before {}", skipped, c.sequence);
+                    }
+                    rprintln!("got {:?} over {:?}", c, from);
+                    let (status, applied) = apply(&c, from);
+                    log(c.sequence, Some(c.msg), status);
+                    reply(Reply::Ack(Ack { sequence: c.sequence, status }));
+                    reply(Reply::Applied(applied));
+                    if let Msg::Ping(nonce) = c.msg {
+                        let processing_us = DWT::cycle_count().wrapping_sub(entered) / (*sysclk / 1_000_000);
+                        let uptime_ms = uptime_ms() as u32;
+                        rprintln!("ping {} answered {} us after the interrupt", nonce, processing_us);
+                        reply(Reply::Pong(Pong { nonce, uptime_ms, processing_us }));
+                        flush_replies::spawn().ok();
+                    }
+                }
+                Ok(c) => {
+                    let stale = link.lock(|l| {
+                        l.stale = l.stale.wrapping_add(1);
+                        l.stale
+                    });
+                    rprintln!("stale command {}, {} so far", c.sequence, stale);
+                    link_stats::spawn().ok();
+                    log(c.sequence, Some(c.msg), AckStatus::Stale);
+                    reply(Reply::Ack(Ack { sequence: c.sequence, status: AckStatus::Stale }));
+                }
+                Err((e, sequence)) => {
+                    let rejected = link.lock(|l| {
+                        l.rejected = l.rejected.wrapping_add(1);
+                        l.crc = l.crc.wrapping_add((e == FrameError::Crc) as u32);
+                        l.rejected
+                    });
+                    rprintln!("command rejected {:?}, {} so far", e, rejected);
+                    link_stats::spawn().ok();
+                    let status = match e {
+                        FrameError::Crc => AckStatus::Crc,
+                        FrameError::Version(_) => AckStatus::Version,
+                        FrameError::Unknown(_) => AckStatus::Unknown,
+                        FrameError::NotFinite | FrameError::Range => AckStatus::Range,
+                        _ => AckStatus::Malformed,
+                    };
+                    log(sequence.unwrap_or(0), None, status);
+                    reply(Reply::Ack(Ack { sequence: sequence.unwrap_or(0), status }));
+                    if let (FrameError::Version(_), false) = (e, *announced) {
+                        *announced = true;
+                        protocol_info::spawn().ok();
+                    }
+                }
+            }
         }
     }
 }
@@ -0,0 +1,428 @@
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use common::{Error, SelfTest, SensorError, Vec3};
+
+use crate::imu::{Imu, TEMP_LSB_PER_C, TEMP_OFFSET_C};
+use crate::spatial::{AccelRange, GyroRange, Mounting, ACCEL_RANGE, GYRO_FREQUENCY_HZ, GYRO_RANGE};
+
+pub const ADDRESS: u8 = 0x68;
+
+pub const SELF_TEST_X: u8 = 0x0d;
+pub const SELF_TEST_A: u8 = 0x10;
+pub const SMPLRT_DIV: u8 = 0x19;
+pub const CONFIG: u8 = 0x1a;
+pub const GYRO_CONFIG: u8 = 0x1b;
+pub const ACCEL_CONFIG: u8 = 0x1c;
+pub const FIFO_EN: u8 = 0x23;
+pub const INT_PIN_CFG: u8 = 0x37;
+pub const INT_ENABLE: u8 = 0x38;
+pub const INT_STATUS: u8 = 0x3a;
+pub const ACCEL_XOUT_H: u8 = 0x3b;
+pub const GYRO_XOUT_H: u8 = 0x43;
+pub const USER_CTRL: u8 = 0x6a;
+pub const PWR_MGMT_1: u8 = 0x6b;
+pub const FIFO_COUNT_H: u8 = 0x72;
+pub const FIFO_R_W: u8 = 0x74;
+pub const WHO_AM_I: u8 = 0x75;
+
+pub const DATA_RDY_EN: u8 = 0b00000001;
+/// Same bit in INT_ENABLE and INT_STATUS, reading INT_STATUS clears it
+pub const FIFO_OFLOW: u8 = 0b00010000;
+
+/// WHO_AM_I of the MPU9250, register compatible for everything used here
+pub const MPU9250_ID: u8 = 0x71;
+
+// auxiliary bus joined to the main one, exposes the AK8963 of the MPU9250
+const I2C_BYPASS_EN: u8 = 0b00000010;
+
+// TEMP, XG, YG, ZG and ACCEL, FIFO frames then have the same layout as a burst read
+const FIFO_SAMPLE: u8 = 0b11111000;
+
+pub const USER_CTRL_FIFO_EN: u8 = 0b01000000;
+pub const USER_CTRL_FIFO_RESET: u8 = 0b00000100;
+
+pub const FIFO_SIZE: usize = 1024;
+
+// out of sleep, PLL with X axis gyroscope reference
+const CLKSEL_PLL_X: u8 = 0b00000001;
+
+// INT_LEVEL, INT_OPEN, LATCH_INT_EN, INT_RD_CLEAR
+const INT_PIN_MODE_MASK: u8 = 0b11110000;
+
+const DLPF_CFG_MASK: u8 = 0b00000111;
+
+// same layout for FS_SEL and AFS_SEL
+const FS_SEL_SHIFT: u8 = 3;
+const FS_SEL_MASK: u8 = 0b00011000;
+
+// XG_ST, YG_ST, ZG_ST and XA_ST, YA_ST, ZA_ST
+const SELF_TEST_EN: u8 = 0b11100000;
+
+/// Readings thrown away after switching self-test, then averaged
+const SELF_TEST_SETTLE: u32 = 50;
+const SELF_TEST_SAMPLES: u32 = 50;
+/// Allowed deviation of the self-test response from factory trim
+pub const SELF_TEST_TOLERANCE: f32 = 0.14;
+
+/// Gyroscope output rate with the DLPF disabled
+pub const GYRO_OUTPUT_RATE_HZ: u32 = 8000;
+/// Gyroscope output rate with the DLPF enabled
+pub const GYRO_OUTPUT_RATE_DLPF_HZ: u32 = 1000;
+
+pub const DLPF_BANDWIDTH: DlpfBandwidth = DlpfBandwidth::Hz42;
+
+// SMPLRT_DIV only divides the output rate by whole numbers
+const _: () = assert!(GYRO_FREQUENCY_HZ <= GYRO_OUTPUT_RATE_DLPF_HZ);
+const _: () = assert!(GYRO_OUTPUT_RATE_DLPF_HZ.is_multiple_of(GYRO_FREQUENCY_HZ));
+
+/// Digital low pass filter bandwidth of the gyroscope, DLPF_CFG field of CONFIG
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DlpfBandwidth {
+    Hz256 = 0,
+    Hz188 = 1,
+    Hz98 = 2,
+    Hz42 = 3,
+    Hz20 = 4,
+    Hz10 = 5,
+    Hz5 = 6,
+}
+
+impl DlpfBandwidth {
+    pub fn from_bits(bits: u8) -> Option<DlpfBandwidth> {
+        match bits & DLPF_CFG_MASK {
+            0 => Some(DlpfBandwidth::Hz256),
+            1 => Some(DlpfBandwidth::Hz188),
+            2 => Some(DlpfBandwidth::Hz98),
+            3 => Some(DlpfBandwidth::Hz42),
+            4 => Some(DlpfBandwidth::Hz20),
+            5 => Some(DlpfBandwidth::Hz10),
+            6 => Some(DlpfBandwidth::Hz5),
+            _ => None,
+        }
+    }
+
+    pub fn hz(&self) -> u32 {
+        match self {
+            DlpfBandwidth::Hz256 => 256,
+            DlpfBandwidth::Hz188 => 188,
+            DlpfBandwidth::Hz98 => 98,
+            DlpfBandwidth::Hz42 => 42,
+            DlpfBandwidth::Hz20 => 20,
+            DlpfBandwidth::Hz10 => 10,
+            DlpfBandwidth::Hz5 => 5,
+        }
+    }
+
+    pub fn gyro_output_rate(&self) -> u32 {
+        match self {
+            DlpfBandwidth::Hz256 => GYRO_OUTPUT_RATE_HZ,
+            _ => GYRO_OUTPUT_RATE_DLPF_HZ,
+        }
+    }
+}
+
+/// ACCEL_XOUT_H through GYRO_ZOUT_L
+pub const SAMPLE_SIZE: usize = 14;
+
+/// Accelerometer and gyroscope in sensor counts from a single burst read
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub acc: Vec3,
+    pub temp: f32,
+    pub gyro: Vec3,
+}
+
+impl Sample {
+    pub fn from_be_bytes(buf: &[u8; SAMPLE_SIZE]) -> Sample {
+        Sample {
+            acc: vector_from_be(&buf[0..6]),
+            temp: temp_from_be(&buf[6..8]),
+            gyro: vector_from_be(&buf[8..14]),
+        }
+    }
+
+    /// Both vectors in frame axes, the temperature stays
+    pub fn mounted(&self, mounting: Mounting) -> Sample {
+        Sample { acc: mounting.apply(self.acc), temp: self.temp, gyro: mounting.apply(self.gyro) }
+    }
+}
+
+/// Three big endian words as sensor counts
+pub fn vector_from_be(buf: &[u8]) -> Vec3 {
+    Vec3::new(
+        i16::from_be_bytes([buf[0], buf[1]]) as f32,
+        i16::from_be_bytes([buf[2], buf[3]]) as f32,
+        i16::from_be_bytes([buf[4], buf[5]]) as f32,
+    )
+}
+
+/// TEMP_OUT in degrees Celsius
+pub fn temp_from_be(buf: &[u8]) -> f32 {
+    i16::from_be_bytes([buf[0], buf[1]]) as f32 / TEMP_LSB_PER_C + TEMP_OFFSET_C
+}
+
+/// Self-test response against factory trim per axis, `(response - trim) / trim`
+#[derive(Debug, Clone, Copy)]
+pub struct SelfTestDeviation {
+    pub gyro: Vec3,
+    pub accel: Vec3,
+}
+
+impl SelfTestDeviation {
+    pub fn check(&self) -> SelfTest {
+        let pass = |d: f32| libm::fabsf(d) <= SELF_TEST_TOLERANCE;
+
+        SelfTest {
+            gyro: [pass(self.gyro.x), pass(self.gyro.y), pass(self.gyro.z)],
+            accel: [pass(self.accel.x), pass(self.accel.y), pass(self.accel.z)],
+        }
+    }
+}
+
+/// Factory trim from the register map, zero trim can't be checked against and fails
+fn deviation(response: f32, trim: f32) -> f32 {
+    if trim == 0.0 {
+        return f32::INFINITY;
+    }
+    (response - trim) / trim
+}
+
+#[derive(Debug)]
+pub enum Mpu6050Error<E> {
+    I2c(E),
+    InvalidChipId(u8),
+}
+
+/// The bus error itself is left behind, the HAL's has nothing to match on
+impl<E> From<Mpu6050Error<E>> for Error {
+    fn from(e: Mpu6050Error<E>) -> Self {
+        match e {
+            Mpu6050Error::I2c(_) => Error::Sensor(SensorError::Bus),
+            Mpu6050Error::InvalidChipId(id) => Error::Sensor(SensorError::Identity(id)),
+        }
+    }
+}
+
+/// Register level MPU6050 driver, owns the bus so it can be handed back for recovery
+pub struct Mpu6050<I> {
+    i2c: I,
+}
+
+impl<I, E> Mpu6050<I>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    pub fn new(i2c: I) -> Self {
+        Mpu6050 { i2c }
+    }
+
+    pub fn release(self) -> I {
+        self.i2c
+    }
+
+    /// Wakes the device up and checks WHO_AM_I
+    pub fn init(&mut self) -> Result<(), Mpu6050Error<E>> {
+        self.write_byte(PWR_MGMT_1, CLKSEL_PLL_X)?;
+
+        match self.read_byte(WHO_AM_I)? {
+            ADDRESS | MPU9250_ID => Ok(()),
+            id => Err(Mpu6050Error::InvalidChipId(id)),
+        }
+    }
+
+    pub fn set_dlpf(&mut self, bandwidth: DlpfBandwidth) -> Result<(), Mpu6050Error<E>> {
+        let config = self.read_byte(CONFIG)?;
+        self.write_byte(CONFIG, (config & !DLPF_CFG_MASK) | bandwidth as u8)
+    }
+
+    /// None when DLPF_CFG is the reserved value 7
+    pub fn get_dlpf(&mut self) -> Result<Option<DlpfBandwidth>, Mpu6050Error<E>> {
+        self.read_byte(CONFIG).map(DlpfBandwidth::from_bits)
+    }
+
+    pub fn set_sample_rate(&mut self, hz: u32) -> Result<(), Mpu6050Error<E>> {
+        // the output rate depends on whether DLPF is on, so set it first
+        let output_rate = self.get_dlpf()?
+            .map(|b| b.gyro_output_rate())
+            .unwrap_or(GYRO_OUTPUT_RATE_HZ);
+        let div = output_rate / hz - 1;
+        self.write_byte(SMPLRT_DIV, div as u8)
+    }
+
+    /// Output rate over SMPLRT_DIV as configured on the device
+    pub fn get_sample_rate(&mut self) -> Result<u32, Mpu6050Error<E>> {
+        let output_rate = self.get_dlpf()?
+            .map(|b| b.gyro_output_rate())
+            .unwrap_or(GYRO_OUTPUT_RATE_HZ);
+        Ok(output_rate / (self.read_byte(SMPLRT_DIV)? as u32 + 1))
+    }
+
+    pub fn set_gyro_full_scale(&mut self, range: GyroRange) -> Result<(), Mpu6050Error<E>> {
+        let config = self.read_byte(GYRO_CONFIG)?;
+        self.write_byte(GYRO_CONFIG, (config & !FS_SEL_MASK) | ((range as u8) << FS_SEL_SHIFT))
+    }
+
+    /// Gyroscope reading in sensor counts, see [GyroRange::rad_per_lsb]
+    pub fn get_gyro_raw(&mut self) -> Result<Vec3, Mpu6050Error<E>> {
+        self.read_vector(GYRO_XOUT_H)
+    }
+
+    pub fn set_accel_full_scale(&mut self, range: AccelRange) -> Result<(), Mpu6050Error<E>> {
+        let config = self.read_byte(ACCEL_CONFIG)?;
+        self.write_byte(ACCEL_CONFIG, (config & !FS_SEL_MASK) | ((range as u8) << FS_SEL_SHIFT))
+    }
+
+    /// Accelerometer, temperature and gyroscope in one transaction
+    pub fn read_sample(&mut self) -> Result<Sample, Mpu6050Error<E>> {
+        let mut buf: [u8; SAMPLE_SIZE] = [0; SAMPLE_SIZE];
+        self.read_bytes(ACCEL_XOUT_H, &mut buf)?;
+
+        Ok(Sample::from_be_bytes(&buf))
+    }
+
+    /// Built-in self-test at +-250 dps and +-8 g, `wait` has to last about one sample period.
+    /// Leaves both ranges changed, configure again afterwards.
+    pub fn self_test(&mut self, mut wait: impl FnMut()) -> Result<SelfTestDeviation, Mpu6050Error<E>> {
+        let gyro_fs = (GyroRange::Dps250 as u8) << FS_SEL_SHIFT;
+        let accel_fs = (AccelRange::G8 as u8) << FS_SEL_SHIFT;
+
+        self.write_byte(GYRO_CONFIG, gyro_fs)?;
+        self.write_byte(ACCEL_CONFIG, accel_fs)?;
+        let normal = self.average_sample(&mut wait)?;
+
+        self.write_byte(GYRO_CONFIG, gyro_fs | SELF_TEST_EN)?;
+        self.write_byte(ACCEL_CONFIG, accel_fs | SELF_TEST_EN)?;
+        let test = self.average_sample(&mut wait)?;
+
+        self.write_byte(GYRO_CONFIG, gyro_fs)?;
+        self.write_byte(ACCEL_CONFIG, accel_fs)?;
+
+        let mut trim: [u8; 4] = [0; 4];
+        self.read_bytes(SELF_TEST_X, &mut trim)?;
+
+        let gyro_trim = |i: usize| {
+            let g_test = (trim[i] & 0b00011111) as i32;
+            if g_test == 0 { 0.0 } else { 25.0 * 131.0 * libm::powf(1.046, (g_test - 1) as f32) }
+        };
+        let accel_trim = |i: usize| {
+            let a_test = ((trim[i] >> 3) & 0b00011100) | ((trim[3] >> (4 - 2 * i)) & 0b00000011);
+            if a_test == 0 { 0.0 } else { 4096.0 * 0.34 * libm::powf(0.92 / 0.34, (a_test as f32 - 1.0) / 30.0) }
+        };
+
+        let gyro = test.gyro - normal.gyro;
+        let accel = test.acc - normal.acc;
+
+        Ok(SelfTestDeviation {
+            // Y trim is negative
+            gyro: Vec3::new(
+                deviation(gyro.x, gyro_trim(0)),
+                deviation(gyro.y, -gyro_trim(1)),
+                deviation(gyro.z, gyro_trim(2)),
+            ),
+            accel: Vec3::new(
+                deviation(accel.x, accel_trim(0)),
+                deviation(accel.y, accel_trim(1)),
+                deviation(accel.z, accel_trim(2)),
+            ),
+        })
+    }
+
+    fn average_sample(&mut self, wait: &mut impl FnMut()) -> Result<Sample, Mpu6050Error<E>> {
+        for _ in 0..SELF_TEST_SETTLE {
+            wait();
+        }
+
+        let mut sum = Sample { acc: Vec3::zeros(), temp: 0.0, gyro: Vec3::zeros() };
+        for _ in 0..SELF_TEST_SAMPLES {
+            wait();
+            let sample = self.read_sample()?;
+            sum.acc += sample.acc;
+            sum.temp += sample.temp;
+            sum.gyro += sample.gyro;
+        }
+
+        let n = SELF_TEST_SAMPLES as f32;
+        Ok(Sample { acc: sum.acc / n, temp: sum.temp / n, gyro: sum.gyro / n })
+    }
+
+    /// Queues every sample into the FIFO, see [Sample::from_be_bytes].
+    /// Overflows show up in INT_STATUS.
+    pub fn enable_fifo(&mut self) -> Result<(), Mpu6050Error<E>> {
+        self.write_byte(FIFO_EN, FIFO_SAMPLE)?;
+        self.write_byte(INT_ENABLE, FIFO_OFLOW)?;
+        self.write_byte(USER_CTRL, USER_CTRL_FIFO_EN | USER_CTRL_FIFO_RESET)
+    }
+
+    pub fn enable_data_ready(&mut self) -> Result<(), Mpu6050Error<E>> {
+        // active high, push-pull, 50us pulse: a rising edge per sample
+        let pin_cfg = self.read_byte(INT_PIN_CFG)?;
+        self.write_byte(INT_PIN_CFG, pin_cfg & !INT_PIN_MODE_MASK)?;
+        let enabled = self.read_byte(INT_ENABLE)?;
+        self.write_byte(INT_ENABLE, enabled | DATA_RDY_EN)
+    }
+
+    /// Makes devices on the auxiliary bus reachable on the main one
+    pub fn enable_bypass(&mut self) -> Result<(), Mpu6050Error<E>> {
+        let pin_cfg = self.read_byte(INT_PIN_CFG)?;
+        self.write_byte(INT_PIN_CFG, pin_cfg | I2C_BYPASS_EN)
+    }
+
+    /// The bus, for other devices sharing it
+    pub fn bus(&mut self) -> &mut I {
+        &mut self.i2c
+    }
+
+    fn read_vector(&mut self, reg: u8) -> Result<Vec3, Mpu6050Error<E>> {
+        let mut buf: [u8; 6] = [0; 6];
+        self.read_bytes(reg, &mut buf)?;
+
+        Ok(vector_from_be(&buf))
+    }
+
+    pub fn write_byte(&mut self, reg: u8, byte: u8) -> Result<(), Mpu6050Error<E>> {
+        self.i2c.write(ADDRESS, &[reg, byte]).map_err(Mpu6050Error::I2c)
+    }
+
+    /// Up to 16 bytes starting at `reg`
+    pub fn write_bytes(&mut self, reg: u8, bytes: &[u8]) -> Result<(), Mpu6050Error<E>> {
+        let mut buf: [u8; 17] = [0; 17];
+        buf[0] = reg;
+        buf[1..=bytes.len()].copy_from_slice(bytes);
+        self.i2c.write(ADDRESS, &buf[..=bytes.len()]).map_err(Mpu6050Error::I2c)
+    }
+
+    pub fn read_byte(&mut self, reg: u8) -> Result<u8, Mpu6050Error<E>> {
+        let mut byte: [u8; 1] = [0; 1];
+        self.read_bytes(reg, &mut byte)?;
+        Ok(byte[0])
+    }
+
+    pub fn read_bytes(&mut self, reg: u8, buf: &mut [u8]) -> Result<(), Mpu6050Error<E>> {
+        self.i2c.write_read(ADDRESS, &[reg], buf).map_err(Mpu6050Error::I2c)
+    }
+}
+
+impl<I, E> Imu for Mpu6050<I>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+    E: core::fmt::Debug,
+{
+    type Error = Mpu6050Error<E>;
+
+    /// Samples go to the FIFO, the auxiliary bus is bridged for the magnetometer
+    fn configure(&mut self) -> Result<(), Self::Error> {
+        self.init()?;
+        self.set_dlpf(DLPF_BANDWIDTH)?;
+        self.set_sample_rate(GYRO_FREQUENCY_HZ)?;
+        self.set_gyro_full_scale(GYRO_RANGE)?;
+        self.set_accel_full_scale(ACCEL_RANGE)?;
+        self.enable_fifo()?;
+        self.enable_bypass()?;
+        #[cfg(not(feature = "polled"))]
+        self.enable_data_ready()?;
+        Ok(())
+    }
+
+    fn read_sample(&mut self) -> Result<Sample, Self::Error> {
+        Mpu6050::read_sample(self)
+    }
+}
@@ -0,0 +1,60 @@
+//! Type, default and range of every [Param]. A [Msg::SetParam](common::Msg::SetParam) is
+//! checked against these before anything applies it, [Msg::ListParams](common::Msg::ListParams)
+//! sends them for the ground to build its controls from.
+
+use common::{BatteryConfig, FailsafeConfig, Param, ParamInfo, RcMap, SlewConfig, TelemetryConfig, Value, MAX_LINK_RAMP_MS, MAX_LINK_TIMEOUT_MS, RC_CHANNELS};
+use common::{CurrentConfig, MAX_BATTERY_CELLS, MAX_BATTERY_DIVIDER, MAX_CELL_VOLTS, MIN_BATTERY_DIVIDER, MIN_CELL_VOLTS};
+use common::{MAX_CURRENT_LIMIT, MAX_CURRENT_OFFSET_MV, MAX_CURRENT_SCALE};
+use common::{DEFAULT_STATUS_PERIOD_MS, MAX_STATUS_PERIOD_MS, MIN_STATUS_PERIOD_MS};
+use common::{MAX_TELEMETRY_DIVISOR, MAX_THROTTLE_SLEW, MAX_TRIM_ANGLE, MIN_LINK_TIMEOUT_MS, MIN_TELEMETRY_DIVISOR};
+
+use crate::spatial::{AttitudeEstimator, Estimator, CRASH_TILT, GYRO_FREQUENCY_HZ};
+
+/// Every stream bit [TelemetryConfig] has
+const ALL_STREAMS: u8 = TelemetryConfig::ATTITUDE | TelemetryConfig::RATES | TelemetryConfig::STATUS | TelemetryConfig::STATISTICS | TelemetryConfig::EXTENDED;
+
+pub fn info(param: Param) -> ParamInfo {
+    let failsafe = FailsafeConfig::DEFAULT;
+    let telemetry = TelemetryConfig::DEFAULT;
+    let slew = SlewConfig::DEFAULT;
+    let rc = RcMap::DEFAULT;
+    let battery = BatteryConfig::DEFAULT;
+    let current = CurrentConfig::DEFAULT;
+    // counting from one
+    let channel = |c: u8| (Value::U32(c as u32 + 1), 1.0, RC_CHANNELS as f32);
+    let max_trim = MAX_TRIM_ANGLE.to_degrees();
+
+    let (default, min, max) = match param {
+        // whatever the estimator the firmware is built with starts out at
+        Param::FilterGain => (Value::F32(Estimator::from_acc([0.0; 2]).gain()), 0.0, 1.0),
+        Param::AccCutoff => {
+            let default = Estimator::from_acc([0.0; 2]).acc_cutoff_hz().unwrap_or(0.0);
+            (Value::F32(default), 0.1, GYRO_FREQUENCY_HZ as f32 / 2.0)
+        }
+        Param::CrashTilt => (Value::F32(CRASH_TILT.to_degrees()), 30.0, 150.0),
+        Param::LinkTimeout => (Value::U32(failsafe.timeout_ms as u32), MIN_LINK_TIMEOUT_MS as f32, MAX_LINK_TIMEOUT_MS as f32),
+        Param::LinkRamp => (Value::U32(failsafe.ramp_ms as u32), 0.0, MAX_LINK_RAMP_MS as f32),
+        Param::TelemetryDivisor => (Value::U32(telemetry.divisor as u32), MIN_TELEMETRY_DIVISOR as f32, MAX_TELEMETRY_DIVISOR as f32),
+        Param::TelemetryStreams => (Value::U32(telemetry.streams as u32), 0.0, ALL_STREAMS as f32),
+        Param::TrimRoll | Param::TrimPitch => (Value::F32(0.0), -max_trim, max_trim),
+        Param::ThrottleSlewUp => (Value::F32(slew.up_per_s), 0.0, MAX_THROTTLE_SLEW),
+        Param::ThrottleSlewDown => (Value::F32(slew.down_per_s), 0.0, MAX_THROTTLE_SLEW),
+        Param::RcRoll => channel(rc.roll),
+        Param::RcPitch => channel(rc.pitch),
+        Param::RcThrottle => channel(rc.throttle),
+        Param::RcYaw => channel(rc.yaw),
+        Param::RcArm => channel(rc.arm),
+        Param::BatteryDivider => (Value::F32(battery.divider), MIN_BATTERY_DIVIDER, MAX_BATTERY_DIVIDER),
+        Param::BatteryCells => (Value::U32(battery.cells as u32), 1.0, MAX_BATTERY_CELLS as f32),
+        Param::BatteryWarning => (Value::F32(battery.warning_cell_volts), MIN_CELL_VOLTS, MAX_CELL_VOLTS),
+        Param::BatteryCritical => (Value::F32(battery.critical_cell_volts), MIN_CELL_VOLTS, MAX_CELL_VOLTS),
+        Param::BatteryFailsafe => (Value::Bool(battery.failsafe), 0.0, 1.0),
+        Param::CurrentScale => (Value::F32(current.mv_per_amp), 0.0, MAX_CURRENT_SCALE),
+        Param::CurrentOffset => (Value::F32(current.offset_mv), 0.0, MAX_CURRENT_OFFSET_MV),
+        Param::CurrentLimit => (Value::F32(current.max_amps), 0.0, MAX_CURRENT_LIMIT),
+        Param::StatusPeriod => (Value::U32(DEFAULT_STATUS_PERIOD_MS as u32), MIN_STATUS_PERIOD_MS as f32, MAX_STATUS_PERIOD_MS as f32),
+        Param::GyroDebug | Param::Rates | Param::Compact => (Value::Bool(false), 0.0, 1.0),
+    };
+
+    ParamInfo { id: param.id(), default, min, max }
+}
@@ -0,0 +1,230 @@
+//! Calibration kept in the last flash page, which memory.x leaves out of the image.
+
+use core::convert::TryInto;
+
+use common::{BatteryConfig, CurrentConfig, Error, FailsafeConfig, PidGains, RcMap, SlewConfig, StorageError, TelemetryConfig, Trim, Vec3, DEFAULT_STATUS_PERIOD_MS};
+use stm32f1xx_hal::flash::{FlashSize, FlashWriter, SectorSize};
+
+use crate::spatial::{AccelCalibration, MagCalibration};
+
+pub const SECTOR_SIZE: SectorSize = SectorSize::Sz1K;
+pub const FLASH_SIZE: FlashSize = FlashSize::Sz64K;
+
+/// Offset of the last 1K page from the start of flash
+pub const SETTINGS_OFFSET: u32 = 63 * 1024;
+
+const MAGIC: u32 = 0x5354_4d32;
+const VERSION: u16 = 14;
+
+/// Magic, version, padding, gyro offset, accelerometer offset and scale, magnetometer
+/// offset and scale, estimator kind, padding, gain and cutoff, crash tilt, roll, pitch and yaw
+/// PID gains, telemetry divisor and streams, padding, link timeout and ramp, roll and pitch
+/// trim, throttle slew up and down, RC channel of roll, pitch, throttle, yaw and arming,
+/// padding, battery divider, warning and critical cell volts, cell count and failsafe,
+/// padding, current scale, offset and limit, extended status period, padding, CRC
+pub const SETTINGS_SIZE: usize = 4 + 2 + 2 + 12 + 12 + 12 + 12 + 12 + 1 + 3 + 4 + 4 + 4 + 3 * 16 + 2 + 1 + 1 + 2 + 2 + 8 + 8 + 5 + 3 + 12 + 1 + 1 + 2 + 12 + 2 + 2 + 4;
+
+/// Estimator tuning, see `AttitudeEstimator`
+#[derive(Debug, Clone, Copy)]
+pub struct FilterSettings {
+    /// `AttitudeEstimator::KIND` it was stored for, another build keeps its defaults
+    pub kind: u8,
+    pub gain: f32,
+    /// Zero for estimators without the low-pass
+    pub acc_cutoff_hz: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    /// Gyroscope counts at rest, at [GYRO_RANGE](crate::spatial::GYRO_RANGE) whatever the DMP runs at
+    pub gyro_offset: Vec3,
+    pub accel: AccelCalibration,
+    pub mag: MagCalibration,
+    pub filter: FilterSettings,
+    /// Radians, see `CrashDetector`
+    pub crash_tilt: f32,
+    /// By `Axis`, only what [Msg::SavePidGains](common::Msg::SavePidGains) saved
+    pub pid: [PidGains; 3],
+    /// Whatever a command last set, clamped when loaded
+    pub telemetry: TelemetryConfig,
+    pub failsafe: FailsafeConfig,
+    /// Only what [Msg::SaveTrim](common::Msg::SaveTrim) saved
+    pub trim: Trim,
+    pub slew: SlewConfig,
+    pub rc_map: RcMap,
+    pub battery: BatteryConfig,
+    pub current: CurrentConfig,
+    /// Clamped when loaded, like the telemetry
+    pub status_period_ms: u16,
+}
+
+impl Settings {
+    /// Calibration with everything else at its defaults, `persist` fills in what is in use
+    pub fn calibration(gyro_offset: Vec3, accel: AccelCalibration, mag: MagCalibration, filter: FilterSettings, crash_tilt: f32) -> Settings {
+        Settings {
+            gyro_offset,
+            accel,
+            mag,
+            filter,
+            crash_tilt,
+            pid: [PidGains::default(); 3],
+            telemetry: TelemetryConfig::DEFAULT,
+            failsafe: FailsafeConfig::DEFAULT,
+            trim: Trim::default(),
+            slew: SlewConfig::DEFAULT,
+            rc_map: RcMap::DEFAULT,
+            battery: BatteryConfig::DEFAULT,
+            current: CurrentConfig::DEFAULT,
+            status_period_ms: DEFAULT_STATUS_PERIOD_MS,
+        }
+    }
+
+    pub fn to_byte_array(&self) -> [u8; SETTINGS_SIZE] {
+        let mut result: [u8; SETTINGS_SIZE] = [0; SETTINGS_SIZE];
+        result[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        result[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        write_vector(&mut result[8..20], self.gyro_offset);
+        write_vector(&mut result[20..32], self.accel.offset);
+        write_vector(&mut result[32..44], self.accel.scale);
+        write_vector(&mut result[44..56], self.mag.offset);
+        write_vector(&mut result[56..68], self.mag.scale);
+        result[68] = self.filter.kind;
+        result[72..76].copy_from_slice(&self.filter.gain.to_le_bytes());
+        result[76..80].copy_from_slice(&self.filter.acc_cutoff_hz.to_le_bytes());
+        result[80..84].copy_from_slice(&self.crash_tilt.to_le_bytes());
+        for (chunk, gains) in result[84..132].chunks_exact_mut(16).zip(self.pid.iter()) {
+            write_floats(chunk, &[gains.p, gains.i, gains.d, gains.ff]);
+        }
+        result[132..134].copy_from_slice(&self.telemetry.divisor.to_le_bytes());
+        result[134] = self.telemetry.streams;
+        result[136..138].copy_from_slice(&self.failsafe.timeout_ms.to_le_bytes());
+        result[138..140].copy_from_slice(&self.failsafe.ramp_ms.to_le_bytes());
+        write_floats(&mut result[140..148], &[self.trim.roll, self.trim.pitch]);
+        write_floats(&mut result[148..156], &[self.slew.up_per_s, self.slew.down_per_s]);
+        let rc = &self.rc_map;
+        result[156..161].copy_from_slice(&[rc.roll, rc.pitch, rc.throttle, rc.yaw, rc.arm]);
+        let battery = &self.battery;
+        write_floats(&mut result[164..176], &[battery.divider, battery.warning_cell_volts, battery.critical_cell_volts]);
+        result[176] = battery.cells;
+        result[177] = battery.failsafe as u8;
+        let current = &self.current;
+        write_floats(&mut result[180..192], &[current.mv_per_amp, current.offset_mv, current.max_amps]);
+        result[192..194].copy_from_slice(&self.status_period_ms.to_le_bytes());
+
+        let crc = crc32(&result[..SETTINGS_SIZE - 4]);
+        result[SETTINGS_SIZE - 4..].copy_from_slice(&crc.to_le_bytes());
+        result
+    }
+
+    /// [StorageError::Blank] for an erased page, [StorageError::Layout] for another layout
+    /// and [StorageError::Crc] for a corrupted write
+    pub fn from_byte_slice(buf: &[u8]) -> Result<Settings, StorageError> {
+        if buf.len() != SETTINGS_SIZE {
+            return Err(StorageError::Flash);
+        }
+
+        let magic = u32::from_le_bytes(buf[0..4].try_into().unwrap());
+        let version = u16::from_le_bytes(buf[4..6].try_into().unwrap());
+        let crc = u32::from_le_bytes(buf[SETTINGS_SIZE - 4..].try_into().unwrap());
+        match (magic == MAGIC, version == VERSION, crc == crc32(&buf[..SETTINGS_SIZE - 4])) {
+            (false, _, _) => return Err(StorageError::Blank),
+            (true, false, _) => return Err(StorageError::Layout(version)),
+            (true, true, false) => return Err(StorageError::Crc),
+            (true, true, true) => {}
+        }
+
+        Ok(Settings {
+            gyro_offset: read_vector(&buf[8..20]),
+            accel: AccelCalibration {
+                offset: read_vector(&buf[20..32]),
+                scale: read_vector(&buf[32..44]),
+            },
+            mag: MagCalibration {
+                offset: read_vector(&buf[44..56]),
+                scale: read_vector(&buf[56..68]),
+            },
+            filter: FilterSettings {
+                kind: buf[68],
+                gain: f32::from_le_bytes(buf[72..76].try_into().unwrap()),
+                acc_cutoff_hz: f32::from_le_bytes(buf[76..80].try_into().unwrap()),
+            },
+            crash_tilt: f32::from_le_bytes(buf[80..84].try_into().unwrap()),
+            pid: [read_gains(&buf[84..100]), read_gains(&buf[100..116]), read_gains(&buf[116..132])],
+            telemetry: TelemetryConfig {
+                divisor: u16::from_le_bytes([buf[132], buf[133]]),
+                streams: buf[134],
+            },
+            failsafe: FailsafeConfig {
+                timeout_ms: u16::from_le_bytes([buf[136], buf[137]]),
+                ramp_ms: u16::from_le_bytes([buf[138], buf[139]]),
+            },
+            trim: Trim {
+                roll: f32::from_le_bytes(buf[140..144].try_into().unwrap()),
+                pitch: f32::from_le_bytes(buf[144..148].try_into().unwrap()),
+            },
+            slew: SlewConfig {
+                up_per_s: f32::from_le_bytes(buf[148..152].try_into().unwrap()),
+                down_per_s: f32::from_le_bytes(buf[152..156].try_into().unwrap()),
+            },
+            rc_map: RcMap { roll: buf[156], pitch: buf[157], throttle: buf[158], yaw: buf[159], arm: buf[160] },
+            battery: BatteryConfig {
+                divider: f32::from_le_bytes(buf[164..168].try_into().unwrap()),
+                cells: buf[176],
+                warning_cell_volts: f32::from_le_bytes(buf[168..172].try_into().unwrap()),
+                critical_cell_volts: f32::from_le_bytes(buf[172..176].try_into().unwrap()),
+                failsafe: buf[177] != 0,
+            },
+            current: CurrentConfig {
+                mv_per_amp: f32::from_le_bytes(buf[180..184].try_into().unwrap()),
+                offset_mv: f32::from_le_bytes(buf[184..188].try_into().unwrap()),
+                max_amps: f32::from_le_bytes(buf[188..192].try_into().unwrap()),
+            },
+            status_period_ms: u16::from_le_bytes([buf[192], buf[193]]),
+        })
+    }
+
+    pub fn load(flash: &FlashWriter) -> Result<Settings, Error> {
+        let buf = flash.read(SETTINGS_OFFSET, SETTINGS_SIZE).map_err(|_| StorageError::Flash)?;
+        Ok(Settings::from_byte_slice(buf)?)
+    }
+
+    /// Stalls the CPU for the page erase, only call this while disarmed
+    pub fn store(&self, flash: &mut FlashWriter) -> Result<(), Error> {
+        flash.erase(SETTINGS_OFFSET, 1024).map_err(|_| StorageError::Flash)?;
+        flash.write(SETTINGS_OFFSET, &self.to_byte_array()).map_err(|_| StorageError::Flash)?;
+        Ok(())
+    }
+}
+
+fn write_vector(buf: &mut [u8], v: Vec3) {
+    write_floats(buf, v.as_array());
+}
+
+fn write_floats(buf: &mut [u8], floats: &[f32]) {
+    for (chunk, f) in buf.chunks_exact_mut(4).zip(floats) {
+        chunk.copy_from_slice(&f.to_le_bytes());
+    }
+}
+
+fn read_vector(buf: &[u8]) -> Vec3 {
+    let f = |i: usize| f32::from_le_bytes(buf[i..i + 4].try_into().unwrap());
+    Vec3::new(f(0), f(4), f(8))
+}
+
+fn read_gains(buf: &[u8]) -> PidGains {
+    let f = |i: usize| f32::from_le_bytes(buf[i..i + 4].try_into().unwrap());
+    PidGains { p: f(0), i: f(4), d: f(8), ff: f(12) }
+}
+
+/// CRC-32 (IEEE), bitwise to stay clear of a 1K table
+pub fn crc32(buf: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in buf {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
@@ -0,0 +1,42 @@
+//! What was flashed, for [Msg::RequestVersion](common::Msg::RequestVersion), the once a
+//! second report and the boot log. build.rs fills in the commit and the date.
+
+use common::{BuildInfo, COMMIT_LEN, PROTOCOL_VERSION};
+
+pub const BUILD_INFO: BuildInfo = BuildInfo {
+    version: [
+        number(env!("CARGO_PKG_VERSION_MAJOR")) as u8,
+        number(env!("CARGO_PKG_VERSION_MINOR")) as u8,
+        number(env!("CARGO_PKG_VERSION_PATCH")) as u8,
+    ],
+    commit: commit(option_env!("GIT_HASH")),
+    dirty: option_env!("GIT_DIRTY").is_some(),
+    date: number(env!("BUILD_DATE")),
+    protocol: PROTOCOL_VERSION,
+};
+
+/// Decimal digits, a const `parse`
+const fn number(digits: &str) -> u32 {
+    let digits = digits.as_bytes();
+    let mut n = 0;
+    let mut i = 0;
+    while i < digits.len() {
+        n = n * 10 + (digits[i] - b'0') as u32;
+        i += 1;
+    }
+    n
+}
+
+/// Zeros for a build outside of git
+const fn commit(hash: Option<&str>) -> [u8; COMMIT_LEN] {
+    let mut commit = [0; COMMIT_LEN];
+    if let Some(hash) = hash {
+        let hash = hash.as_bytes();
+        let mut i = 0;
+        while i < hash.len() && i < COMMIT_LEN {
+            commit[i] = hash[i];
+            i += 1;
+        }
+    }
+    commit
+}
@@ -4,14 +4,36 @@ use hex::FromHexError;
 use std::io::Read;
 use std::io::Write;
 
+use std::collections::BTreeMap;
 use std::panic;
+use std::time::Instant;
 
-use common::EOT;
+use common::{cobs, telemetry, EOT, COMMAND_FRAME_SIZE, MAX_FRAME_SIZE};
 use common::SpatialOrientation;
-use common::Command;
+use common::{Command, Msg, Streams};
+use common::Temperature;
+use common::Altitude;
+use common::Status;
+use common::SelfTest;
+use common::GyroDebug;
+use common::BusScan;
+use common::RawSample;
+use common::SampleStats;
+use common::CycleStats;
+use common::LinkStats;
+use common::{Ack, AckStatus, Announce, AppliedState, Battery, BuildInfo, ExtendedStatus, LinkRate, CommandLogEntry, MotorOutputs, ThrottleOutput, Pong, ProtocolInfo, Setpoint, Trim, PROTOCOL_VERSION};
+use common::{Arm, ArmState, EmergencyStop, FailsafeConfig, FlightMode, LockState, Axis, AxisPidGains, PidGains, TelemetryConfig, ARM_MAGIC, BOOTLOADER_MAGIC, CLEAR_STOP_MAGIC, UNLOCK_CODE};
+use common::MagCalibrationProgress;
+use common::FilterConfig;
+use common::{Param, ParamInfo, ParamValue, Value};
+use common::AttitudeQuaternion;
+use common::LinearAcceleration;
+use common::Vertical;
+use common::Rates;
+use common::CompactOrientation;
+use common::Heading;
 
 pub const CHUNK_SIZE: usize = 16;
-pub const BUF_SIZE: usize = 40;
 
 enum Stm32Error {
     BtConnection(String),
@@ -45,10 +67,78 @@ impl From<FromHexError> for Stm32Error {
 #[inherit(Node)]
 pub struct Sensor {
     socket: Option<BtSocket>,
-    buf: [u8; BUF_SIZE],
     chunk: [u8; CHUNK_SIZE],
-    idx: usize,
+    decoder: telemetry::Decoder,
+    /// Frames that came in broken, too long or failing their CRC
+    frame_errors: u32,
     last_read: (f32, f32, f32),
+    last_temperature: f32,
+    last_altitude: f32,
+    /// m/s, only with the software fusion on the device
+    last_climb_rate: f32,
+    calibrating: bool,
+    imu_lost: bool,
+    gyro_saturated: bool,
+    reset_skipped: bool,
+    crashed: bool,
+    self_test: Option<SelfTest>,
+    /// What the device was last asked to stream
+    streams: Streams,
+    last_gyro: Option<GyroDebug>,
+    bus_scan: Option<BusScan>,
+    /// CSV lines not yet taken by [Sensor::take_raw_samples]
+    raw_samples: String,
+    /// CSV lines of the log the device dumped, see [Sensor::dump_command_log]
+    command_log: String,
+    sample_stats: Option<SampleStats>,
+    cycle_stats: Option<CycleStats>,
+    /// Device side count of the commands it took and dropped
+    link_stats: Option<LinkStats>,
+    /// Last [Command::sequence] sent
+    sequence: u16,
+    /// Reply to the last command the device answered, not necessarily the last one sent
+    last_ack: Option<Ack>,
+    /// What the device did with the last command it answered
+    last_applied: Option<AppliedState>,
+    /// Announced by a device on another [PROTOCOL_VERSION]
+    device_protocol: Option<u8>,
+    /// Firmware of the device, it repeats it once a second
+    device_build: Option<BuildInfo>,
+    /// What the device can do, answers the hello sent on connect
+    announce: Option<Announce>,
+    /// Baud rate of the device's link, with its status
+    device_baud: Option<u32>,
+    /// Last battery report, none until one came or with no battery on the device
+    battery: Option<Battery>,
+    /// Last extended status, comes with its stream on
+    extended_status: Option<ExtendedStatus>,
+    mag_calibration: Option<MagCalibrationProgress>,
+    filter_config: Option<FilterConfig>,
+    failsafe_config: Option<FailsafeConfig>,
+    arm_state: Option<ArmState>,
+    lock_state: Option<LockState>,
+    mode: Option<FlightMode>,
+    last_quaternion: Option<AttitudeQuaternion>,
+    last_linear_accel: Option<LinearAcceleration>,
+    last_rates: Option<Rates>,
+    last_heading: Option<Heading>,
+    /// The one the device holds
+    setpoint_echo: Option<Setpoint>,
+    /// The one the device uses, not necessarily the stored one
+    trim: Option<Trim>,
+    motor_outputs: Option<MotorOutputs>,
+    throttle_output: Option<ThrottleOutput>,
+    /// By [Axis], as the device last echoed them
+    pid_gains: [Option<PidGains>; 3],
+    telemetry: Option<TelemetryConfig>,
+    /// What a [Pong] is timed against and its clock compared to
+    started: Instant,
+    /// Nonce of the last ping and when it went out, answers to earlier ones are ignored
+    ping_sent: Option<(u32, Instant)>,
+    /// Round trip and device clock offset in ms, device processing in us
+    ping: Option<(f32, f32, u32)>,
+    /// By [Param::id], what the device listed and last echoed
+    params: BTreeMap<u8, (Option<ParamInfo>, Option<Value>)>,
 }
 
 impl Drop for Sensor {
@@ -62,21 +152,467 @@ impl Sensor {
     fn new(_owner: &Node) -> Self {
         Sensor {
             socket: None,
-            buf: [0; BUF_SIZE],
             chunk: [0; CHUNK_SIZE],
-            idx: 0,
+            decoder: telemetry::Decoder::new(),
+            frame_errors: 0,
             last_read: (0.0, 0.0, 0.0),
+            last_temperature: 0.0,
+            last_altitude: 0.0,
+            last_climb_rate: 0.0,
+            calibrating: false,
+            imu_lost: false,
+            gyro_saturated: false,
+            reset_skipped: false,
+            crashed: false,
+            self_test: None,
+            streams: Streams::default(),
+            last_gyro: None,
+            bus_scan: None,
+            raw_samples: String::new(),
+            command_log: String::new(),
+            sample_stats: None,
+            cycle_stats: None,
+            link_stats: None,
+            // a restarted app starting where the last one did would be taken for a replay
+            sequence: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.subsec_nanos() as u16).unwrap_or(0),
+            last_ack: None,
+            last_applied: None,
+            device_protocol: None,
+            device_build: None,
+            announce: None,
+            device_baud: None,
+            battery: None,
+            extended_status: None,
+            mag_calibration: None,
+            filter_config: None,
+            arm_state: None,
+            lock_state: None,
+            mode: None,
+            failsafe_config: None,
+            last_quaternion: None,
+            last_linear_accel: None,
+            last_rates: None,
+            last_heading: None,
+            setpoint_echo: None,
+            trim: None,
+            motor_outputs: None,
+            throttle_output: None,
+            pid_gains: [None; 3],
+            telemetry: None,
+            started: Instant::now(),
+            ping_sent: None,
+            ping: None,
+            params: BTreeMap::new(),
         }
     }
 
+    /// 0 to 1, only runs the motors once armed, see [Sensor::arm]
     #[export]
-    fn send_throttle(&mut self, _owner: &Node, throttle_on: bool, throttle: f32) -> Result<(), Stm32Error> {
-        let command = Command { throttle_on, throttle };
+    fn send_throttle(&mut self, _owner: &Node, throttle: f32) -> Result<(), Stm32Error> {
+        self.send(Msg::Throttle(throttle), "throttle")
+    }
+
+    /// Lets the device take throttle and arm commands, needed after connecting and after
+    /// any gap in commands longer than the link timeout, see [Sensor::is_locked]
+    #[export]
+    fn unlock(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.send(Msg::Unlock(UNLOCK_CODE), "unlock")
+    }
+
+    /// Asks to arm, refused while the last throttle sent is over 5%. The motors are enabled
+    /// once the device acked it, see [Sensor::get_arm_state].
+    #[export]
+    fn arm(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.send(Msg::Arm(Arm::Arm(ARM_MAGIC)), "arm")
+    }
+
+    #[export]
+    fn disarm(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.send(Msg::Arm(Arm::Disarm), "disarm")
+    }
+
+    /// Cuts the motors for good, not a command but a few raw bytes the device looks for
+    /// even when it can't make sense of anything else. It stays killed until it is power
+    /// cycled or [Sensor::clear_emergency_stop] is called.
+    #[export]
+    fn emergency_stop(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        match &mut self.socket {
+            Some(s) if s.write(&EmergencyStop::FRAME).is_ok() => Ok(()),
+            Some(_) => Err(Stm32Error::Command(format!("emergency stop"))),
+            None => Err(Stm32Error::BtConnection(format!("not connected"))),
+        }
+    }
+
+    /// Lets the device arm again after [Sensor::emergency_stop], arming still takes
+    /// [Sensor::arm]
+    #[export]
+    fn clear_emergency_stop(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.send(Msg::ClearEmergencyStop(CLEAR_STOP_MAGIC), "clear emergency stop")
+    }
+
+    /// Device has to be disarmed, keep it still until telemetry resumes
+    #[export]
+    fn calibrate(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.send(Msg::Calibrate, "calibrate")
+    }
+
+    /// Call once per face with the device resting on it, any order
+    #[export]
+    fn calibrate_accel(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.send(Msg::CalibrateAccel, "calibrate accel")
+    }
+
+    /// Starts the magnetometer calibration, see [Sensor::get_mag_calibration] for its progress
+    #[export]
+    fn calibrate_mag(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.mag_calibration = None;
+        self.send(Msg::CalibrateMag, "calibrate mag")
+    }
+
+    /// Accelerometer pull of the attitude estimator, 0 to 1. The device rejects anything else
+    /// and echoes what it uses, see [Sensor::get_params]. Like every parameter it is lost at
+    /// reset unless saved, see [Sensor::save_params].
+    #[export]
+    fn set_filter_gain(&mut self, _owner: &Node, gain: f32) -> Result<(), Stm32Error> {
+        self.send(Msg::SetParam(Param::FilterGain.id(), Value::F32(gain)), "filter gain")
+    }
+
+    /// Accelerometer low-pass cutoff in Hz, only the complementary filter has one
+    #[export]
+    fn set_acc_cutoff(&mut self, _owner: &Node, hz: f32) -> Result<(), Stm32Error> {
+        self.send(Msg::SetParam(Param::AccCutoff.id(), Value::F32(hz)), "accel cutoff")
+    }
+
+    /// Tilt in degrees past which the device takes the craft for crashed and cuts the motor
+    #[export]
+    fn set_crash_tilt(&mut self, _owner: &Node, degrees: f32) -> Result<(), Stm32Error> {
+        self.send(Msg::SetParam(Param::CrashTilt.id(), Value::F32(degrees)), "crash tilt")
+    }
+
+    /// Command gap in ms after which the armed device fails safe, 100 to 5000
+    #[export]
+    fn set_link_timeout(&mut self, _owner: &Node, ms: u32) -> Result<(), Stm32Error> {
+        self.send(Msg::SetParam(Param::LinkTimeout.id(), Value::U32(ms)), "link timeout")
+    }
+
+    /// Time in ms the motors take to go down once it failed safe, up to 2000, zero cuts them
+    #[export]
+    fn set_link_ramp(&mut self, _owner: &Node, ms: u32) -> Result<(), Stm32Error> {
+        self.send(Msg::SetParam(Param::LinkRamp.id(), Value::U32(ms)), "link ramp")
+    }
+
+    /// Full throttle per second the motors may speed up and slow down at, up to 100 and zero
+    /// for no limit
+    #[export]
+    fn set_throttle_slew(&mut self, _owner: &Node, up: f32, down: f32) -> Result<(), Stm32Error> {
+        self.send(Msg::SetParam(Param::ThrottleSlewUp.id(), Value::F32(up)), "throttle slew up")?;
+        self.send(Msg::SetParam(Param::ThrottleSlewDown.id(), Value::F32(down)), "throttle slew down")
+    }
+
+    /// Receiver channels, counting from one, of roll, pitch, throttle, yaw and the arm switch
+    #[export]
+    fn set_rc_channels(&mut self, _owner: &Node, roll: u32, pitch: u32, throttle: u32, yaw: u32, arm: u32) -> Result<(), Stm32Error> {
+        self.send(Msg::SetParam(Param::RcRoll.id(), Value::U32(roll)), "roll channel")?;
+        self.send(Msg::SetParam(Param::RcPitch.id(), Value::U32(pitch)), "pitch channel")?;
+        self.send(Msg::SetParam(Param::RcThrottle.id(), Value::U32(throttle)), "throttle channel")?;
+        self.send(Msg::SetParam(Param::RcYaw.id(), Value::U32(yaw)), "yaw channel")?;
+        self.send(Msg::SetParam(Param::RcArm.id(), Value::U32(arm)), "arm channel")
+    }
+
+    /// Pack volts per volt on the device's pin, cells in series, the per cell volts of the
+    /// warning and the critical alarm and whether the critical one fails safe
+    #[export]
+    fn set_battery(&mut self, _owner: &Node, divider: f32, cells: u32, warning: f32, critical: f32, failsafe: bool) -> Result<(), Stm32Error> {
+        self.send(Msg::SetParam(Param::BatteryDivider.id(), Value::F32(divider)), "battery divider")?;
+        self.send(Msg::SetParam(Param::BatteryCells.id(), Value::U32(cells)), "battery cells")?;
+        self.send(Msg::SetParam(Param::BatteryWarning.id(), Value::F32(warning)), "battery warning")?;
+        self.send(Msg::SetParam(Param::BatteryCritical.id(), Value::F32(critical)), "battery critical")?;
+        self.send(Msg::SetParam(Param::BatteryFailsafe.id(), Value::Bool(failsafe)), "battery failsafe")
+    }
+
+    /// Millivolts per amp and millivolts at zero amps of the power module's current output,
+    /// a zero scale for none, and the amps that set the over current flag, zero for no limit
+    #[export]
+    fn set_current_sensor(&mut self, _owner: &Node, mv_per_amp: f32, offset_mv: f32, max_amps: f32) -> Result<(), Stm32Error> {
+        self.send(Msg::SetParam(Param::CurrentScale.id(), Value::F32(mv_per_amp)), "current scale")?;
+        self.send(Msg::SetParam(Param::CurrentOffset.id(), Value::F32(offset_mv)), "current offset")?;
+        self.send(Msg::SetParam(Param::CurrentLimit.id(), Value::F32(max_amps)), "current limit")
+    }
+
+    /// Milliseconds between two extended status frames, 100 to 10000
+    #[export]
+    fn set_status_period(&mut self, _owner: &Node, ms: u32) -> Result<(), Stm32Error> {
+        self.send(Msg::SetParam(Param::StatusPeriod.id(), Value::U32(ms)), "status period")
+    }
+
+    /// Starts the mAh drawn over and clears the over current flag
+    #[export]
+    fn reset_consumed(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.send(Msg::ResetConsumed, "reset consumed")
+    }
+
+    /// Asks for the range and value of every parameter, they come in over the next second,
+    /// see [Sensor::get_params]
+    #[export]
+    fn list_params(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.send(Msg::ListParams, "list params")
+    }
+
+    /// Asks for the value of the parameter `id` alone
+    #[export]
+    fn request_param(&mut self, _owner: &Node, id: u32) -> Result<(), Stm32Error> {
+        self.send(Msg::GetParam(id as u8), "get param")
+    }
+
+    /// Sets the parameter `id` of [Sensor::get_params], taking `value` as the type it
+    /// listed, a bool for anything but zero. Only listed parameters can be set.
+    #[export]
+    fn set_param(&mut self, _owner: &Node, id: u32, value: f32) -> Result<(), Stm32Error> {
+        let info = self.params.get(&(id as u8)).and_then(|(info, _)| *info);
+        let value = match info.map(|i| i.default) {
+            Some(Value::F32(_)) => Value::F32(value),
+            Some(Value::U32(_)) => Value::U32(value as u32),
+            Some(Value::Bool(_)) => Value::Bool(value != 0.0),
+            None => return Err(Stm32Error::Command(format!("param {}, not listed", id))),
+        };
+        self.send(Msg::SetParam(id as u8, value), "param")
+    }
+
+    /// Stores every parameter and the trim in use to flash, the device has to be disarmed
+    #[export]
+    fn save_params(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.send(Msg::SaveParams, "save params")
+    }
+
+    /// Current heading becomes yaw zero, for a device without a magnetometer to re-reference
+    #[export]
+    fn zero_yaw(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.send(Msg::ZeroYaw, "zero yaw")
+    }
+
+    /// Current magnetic heading becomes the reference [Heading] frames report the error to
+    #[export]
+    fn hold_heading(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.send(Msg::HoldHeading, "hold heading")
+    }
+
+    /// Roll and pitch in degrees, up to 45 either way, and yaw rate in degrees per second, up
+    /// to 360. Anything outside isn't sent, the device keeps its last one.
+    #[export]
+    fn set_setpoint(&mut self, _owner: &Node, roll: f32, pitch: f32, yaw_rate: f32) -> Result<(), Stm32Error> {
+        let setpoint = Setpoint { roll: roll.to_radians(), pitch: pitch.to_radians(), yaw_rate: yaw_rate.to_radians() };
+        self.send(Msg::Setpoint(setpoint), "setpoint")
+    }
+
+    /// Roll and pitch in degrees the frame counts as level at, up to 10 either way. In use
+    /// right away, lost when the device resets unless saved.
+    #[export]
+    fn set_trim(&mut self, _owner: &Node, roll: f32, pitch: f32) -> Result<(), Stm32Error> {
+        let trim = Trim { roll: roll.to_radians(), pitch: pitch.to_radians() };
+        self.send(Msg::SetTrim(trim), "trim")
+    }
+
+    /// Takes the attitude the device rests at as the trim, put it on a level surface first.
+    /// It has to be disarmed.
+    #[export]
+    fn capture_trim(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.send(Msg::CaptureTrim, "capture trim")
+    }
+
+    /// Stores the trim in use to flash, the device has to be disarmed
+    #[export]
+    fn save_trim(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.send(Msg::SaveTrim, "save trim")
+    }
+
+    /// Gains for the roll, pitch or yaw `axis`, 0 to 2, each within 0 to 10. In use right
+    /// away, lost when the device resets unless saved.
+    #[export]
+    fn set_pid_gains(&mut self, _owner: &Node, axis: u32, p: f32, i: f32, d: f32, ff: f32) -> Result<(), Stm32Error> {
+        let axis = *Axis::ALL.get(axis as usize).ok_or_else(|| Stm32Error::Command(format!("no axis {}", axis)))?;
+        self.send(Msg::SetPidGains(axis, PidGains { p, i, d, ff }), "pid gains")
+    }
+
+    /// Asks for the gains of every axis, see [Sensor::get_pid_gains]
+    #[export]
+    fn request_pid_gains(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.send(Msg::GetPidGains, "get pid gains")
+    }
+
+    /// Stores the gains in use to flash, the device has to be disarmed
+    #[export]
+    fn save_pid_gains(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.send(Msg::SavePidGains, "save pid gains")
+    }
+
+    /// Telemetry every `divisor` gyro samples, 5 to 5000, with the streams `streams` has
+    /// bits of [TelemetryConfig::ATTITUDE] and the rest for. A divisor out of range is
+    /// clamped, the command is acked [AckStatus::Clamped] then.
+    #[export]
+    fn set_telemetry(&mut self, _owner: &Node, divisor: u32, streams: u32) -> Result<(), Stm32Error> {
+        let telemetry = TelemetryConfig { divisor: divisor.min(u16::MAX as u32) as u16, streams: streams as u8 };
+        self.send(Msg::Telemetry(telemetry), "telemetry")
+    }
+
+    /// Times a round trip to the device, see [Sensor::get_ping]
+    #[export]
+    fn ping(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        // the sequence it goes out under, unique enough
+        let nonce = self.sequence.wrapping_add(1) as u32;
+        self.ping_sent = Some((nonce, Instant::now()));
+        self.send(Msg::Ping(nonce), "ping")
+    }
+
+    /// Passthrough, Rate or Angle, armed or not. The device only takes modes it has a
+    /// controller for, see [Sensor::get_mode] for the one it went with.
+    #[export]
+    fn set_mode(&mut self, _owner: &Node, mode: String) -> Result<(), Stm32Error> {
+        let mode = match mode.as_str() {
+            "Passthrough" => FlightMode::Passthrough,
+            "Rate" => FlightMode::Rate,
+            "Angle" => FlightMode::Angle,
+            _ => return Err(Stm32Error::Command(format!("no mode {}", mode))),
+        };
+        self.send(Msg::SetMode(mode), "mode")
+    }
+
+    /// Asks for the arm state, flight mode, telemetry and failsafe config, setpoint, trim,
+    /// motor outputs and link stats without waiting for the once a second report
+    #[export]
+    fn request_status(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.send(Msg::RequestStatus, "request status")
+    }
+
+    /// Hands the device over to its ROM bootloader for stm32flash, refused while armed. The
+    /// link goes quiet until the device is reset.
+    #[export]
+    fn reboot_to_bootloader(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.send(Msg::RebootToBootloader(BOOTLOADER_MAGIC), "reboot to bootloader")
+    }
+
+    /// Moves the device's link to `baud`, one of common's BAUD_RATES, refused while armed. It
+    /// goes back to the old rate unless a command comes through at the new one within a few
+    /// seconds, the other end of the serial link has to follow.
+    #[export]
+    fn set_baud(&mut self, _owner: &Node, baud: u32) -> Result<(), Stm32Error> {
+        self.send(Msg::SetBaud(baud), "set baud")
+    }
+
+    /// Asks for the firmware version without waiting for the once a second report
+    #[export]
+    fn request_version(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.send(Msg::RequestVersion, "request version")
+    }
+
+    /// Raw and notch filtered gyro telemetry
+    #[export]
+    fn set_gyro_debug(&mut self, _owner: &Node, enabled: bool) -> Result<(), Stm32Error> {
+        if !enabled {
+            self.last_gyro = None;
+        }
+        self.send_streams(Streams { gyro_debug: enabled, ..self.streams })
+    }
+
+    /// Raw sensor words instead of angles for bench analysis. The device won't arm while
+    /// this is on.
+    #[export]
+    fn set_raw_stream(&mut self, _owner: &Node, enabled: bool) -> Result<(), Stm32Error> {
+        self.send_streams(Streams { raw_stream: enabled, ..self.streams })
+    }
+
+    /// Attitude quaternion frames instead of angles. [Sensor::get_angles] keeps returning
+    /// the last angles received meanwhile.
+    #[export]
+    fn set_quaternion(&mut self, _owner: &Node, enabled: bool) -> Result<(), Stm32Error> {
+        if !enabled {
+            self.last_quaternion = None;
+        }
+        self.send_streams(Streams { quaternion: enabled, ..self.streams })
+    }
+
+    /// Acceleration with gravity taken out
+    #[export]
+    fn set_linear_accel(&mut self, _owner: &Node, enabled: bool) -> Result<(), Stm32Error> {
+        if !enabled {
+            self.last_linear_accel = None;
+        }
+        self.send_streams(Streams { linear_accel: enabled, ..self.streams })
+    }
+
+    /// Gyroscope rates along with the angles
+    #[export]
+    fn set_rates(&mut self, _owner: &Node, enabled: bool) -> Result<(), Stm32Error> {
+        if !enabled {
+            self.last_rates = None;
+        }
+        self.send_streams(Streams { rates: enabled, ..self.streams })
+    }
+
+    /// Centi-degree angles with a checksum in place of the full precision ones, for a link
+    /// too slow for 12 byte frames
+    #[export]
+    fn set_compact(&mut self, _owner: &Node, enabled: bool) -> Result<(), Stm32Error> {
+        self.send_streams(Streams { compact: enabled, ..self.streams })
+    }
+
+    /// Kept for the next change whether or not it made it, the device holds on to the last
+    /// ones it got
+    fn send_streams(&mut self, streams: Streams) -> Result<(), Stm32Error> {
+        self.streams = streams;
+        self.send(Msg::Streams(streams), "streams")
+    }
+
+    /// `counter,ax,ay,az,gx,gy,gz` lines received since the last call
+    #[export]
+    fn take_raw_samples(&mut self, _owner: &Node) -> String {
+        std::mem::take(&mut self.raw_samples)
+    }
+
+    /// Asks for the last commands the device took in, they come in over the next second,
+    /// see [Sensor::get_command_log]
+    #[export]
+    fn dump_command_log(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.command_log.clear();
+        self.send(Msg::DumpCommandLog, "dump command log")
+    }
+
+    /// `at_ms,sequence,status,command` lines of the last dump, oldest first. The command is
+    /// empty for a frame the device couldn't decode.
+    #[export]
+    fn get_command_log(&mut self, _owner: &Node) -> String {
+        self.command_log.clone()
+    }
+
+    fn on_pong(&mut self, pong: Pong) {
+        let sent = match self.ping_sent {
+            Some((nonce, sent)) if nonce == pong.nonce => sent,
+            _ => return,
+        };
+        let rtt = sent.elapsed();
+        // taking the answer for halfway through the round trip
+        let midpoint = (sent + rtt / 2).duration_since(self.started);
+        let offset = pong.uptime_ms as f32 - midpoint.as_secs_f32() * 1000.0;
+        self.ping = Some((rtt.as_secs_f32() * 1000.0, offset, pong.processing_us));
+        self.ping_sent = None;
+    }
+
+    /// Sequence for the next command, commands only get theirs here
+    fn next_sequence(&mut self) -> u16 {
+        self.sequence = self.sequence.wrapping_add(1);
+        self.sequence
+    }
+
+    /// Sends `msg` under the next sequence, unless the device would reject it anyway
+    fn send(&mut self, msg: Msg, name: &str) -> Result<(), Stm32Error> {
+        msg.check().map_err(|e| Stm32Error::Command(format!("{}, {:?}", name, e)))?;
+        let command = Command { sequence: self.next_sequence(), msg };
         if let Some(s) = &mut self.socket {
-            let buf = command.to_byte_array();
+            let mut buf = [0; COMMAND_FRAME_SIZE];
+            let len = cobs::encode(&command.to_byte_array(), &mut buf).unwrap();
+            buf[len] = EOT;
 
-            if !s.write(&buf).is_ok() {
-                Err(Stm32Error::Command(format!("throttle")))
+            if !s.write(&buf[..len + 1]).is_ok() {
+                Err(Stm32Error::Command(format!("{}", name)))
             } else {
                 Ok(())
             }
@@ -94,38 +630,499 @@ impl Sensor {
         let mut socket = BtSocket::new(BtProtocol::RFCOMM)?;
         socket.connect(BtAddr(mac))?;
         self.socket = Some(socket);
+        self.announce = None;
 
-        Ok(())
+        self.send(Msg::Hello, "hello")
     }
 
     #[export]
     fn get_angles(&mut self, _owner: &Node) -> (f32, f32, f32) {
-        if let Some(s) = &mut self.socket {
-            let read_len = s.read(&mut self.chunk).expect("failed to read from channel");
+        let read_len = match &mut self.socket {
+            Some(s) => s.read(&mut self.chunk).expect("failed to read from channel"),
+            None => return self.last_read,
+        };
 
-            if self.idx + CHUNK_SIZE >= BUF_SIZE {
-                self.idx = 0;
+        let mut frame = [0; MAX_FRAME_SIZE];
+        for i in 0..read_len {
+            match self.decoder.push(self.chunk[i], &mut frame) {
+                Some(Ok(len)) => self.on_frame(&frame[..len]),
+                Some(Err(_)) => self.frame_errors += 1,
+                None => {}
             }
+        }
+
+        self.last_read
+    }
+
+    fn on_frame(&mut self, payload: &[u8]) {
+        if let Some(so) = SpatialOrientation::from_byte_slice(payload) {
+            self.last_read = (so.pitch, so.roll, so.yaw);
+        } else if let Some(c) = CompactOrientation::from_byte_slice(payload) {
+            let so = c.to_orientation();
+            self.last_read = (so.pitch, so.roll, so.yaw);
+        } else if let Some(q) = AttitudeQuaternion::from_byte_slice(payload) {
+            self.last_quaternion = Some(q);
+        } else if let Some(l) = LinearAcceleration::from_byte_slice(payload) {
+            self.last_linear_accel = Some(l);
+        } else if let Some(h) = Heading::from_byte_slice(payload) {
+            self.last_heading = Some(h);
+        } else if let Some(r) = Rates::from_byte_slice(payload) {
+            self.last_rates = Some(r);
+        } else if let Some(t) = Temperature::from_byte_slice(payload) {
+            self.last_temperature = t.celsius;
+        } else if let Some(a) = Altitude::from_byte_slice(payload) {
+            self.last_altitude = a.centimeters as f32 / 100.0;
+        } else if let Some(v) = Vertical::from_byte_slice(payload) {
+            self.last_altitude = v.centimeters as f32 / 100.0;
+            self.last_climb_rate = v.centimeters_per_s as f32 / 100.0;
+        } else if let Some(s) = Status::from_byte_slice(payload) {
+            self.calibrating = s.calibrating;
+            self.imu_lost = s.imu_lost;
+            self.gyro_saturated = s.gyro_saturated;
+            self.reset_skipped = s.reset_skipped;
+            self.crashed = s.crashed;
+        } else if let Some(t) = SelfTest::from_byte_slice(payload) {
+            self.self_test = Some(t);
+        } else if let Some(g) = GyroDebug::from_byte_slice(payload) {
+            self.last_gyro = Some(g);
+        } else if let Some(b) = BusScan::from_byte_slice(payload) {
+            self.bus_scan = Some(b);
+        } else if let Some(f) = FilterConfig::from_byte_slice(payload) {
+            self.filter_config = Some(f);
+        } else if let Some(m) = MagCalibrationProgress::from_byte_slice(payload) {
+            self.mag_calibration = Some(m);
+        } else if let Some(g) = AxisPidGains::from_byte_slice(payload) {
+            self.pid_gains[g.axis as usize] = Some(g.gains);
+        } else if let Some(f) = FailsafeConfig::from_byte_slice(payload) {
+            self.failsafe_config = Some(f);
+        } else if let Some(a) = ArmState::from_byte_slice(payload) {
+            self.arm_state = Some(a);
+        } else if let Some(l) = LockState::from_byte_slice(payload) {
+            self.lock_state = Some(l);
+        } else if let Some(m) = FlightMode::from_byte_slice(payload) {
+            self.mode = Some(m);
+        } else if let Some(t) = TelemetryConfig::from_byte_slice(payload) {
+            self.telemetry = Some(t);
+        } else if let Some(m) = MotorOutputs::from_byte_slice(payload) {
+            self.motor_outputs = Some(m);
+        } else if let Some(t) = ThrottleOutput::from_byte_slice(payload) {
+            self.throttle_output = Some(t);
+        } else if let Some(s) = Setpoint::from_byte_slice(payload) {
+            self.setpoint_echo = Some(s);
+        } else if let Some(t) = Trim::from_byte_slice(payload) {
+            self.trim = Some(t);
+        } else if let Some(p) = Pong::from_byte_slice(payload) {
+            self.on_pong(p);
+        } else if let Some(e) = CommandLogEntry::from_byte_slice(payload) {
+            let msg = e.msg.map(|m| format!("{:?}", m)).unwrap_or_default();
+            self.command_log += &format!("{},{},{:?},\"{}\"\n", e.at_ms, e.sequence, e.status, msg);
+        } else if let Some(i) = ParamInfo::from_byte_slice(payload) {
+            self.params.entry(i.id).or_default().0 = Some(i);
+        } else if let Some(v) = ParamValue::from_byte_slice(payload) {
+            self.params.entry(v.id).or_default().1 = Some(v.value);
+        } else if let Some(p) = ProtocolInfo::from_byte_slice(payload) {
+            self.device_protocol = Some(p.version);
+        } else if let Some(b) = BuildInfo::from_byte_slice(payload) {
+            self.device_build = Some(b);
+        } else if let Some(a) = Announce::from_byte_slice(payload) {
+            self.announce = Some(a);
+        } else if let Some(r) = LinkRate::from_byte_slice(payload) {
+            self.device_baud = Some(r.baud);
+        } else if let Some(b) = Battery::from_byte_slice(payload) {
+            self.battery = Some(b);
+        } else if let Some(s) = ExtendedStatus::from_byte_slice(payload) {
+            self.motor_outputs = Some(s.outputs);
+            self.arm_state = Some(s.arm_state);
+            self.extended_status = Some(s);
+        } else if let Some(a) = Ack::from_byte_slice(payload) {
+            self.last_ack = Some(a);
+        } else if let Some(a) = AppliedState::from_byte_slice(payload) {
+            self.motor_outputs = Some(a.outputs);
+            self.arm_state = Some(a.arm_state);
+            self.mode = Some(a.mode);
+            self.last_applied = Some(a);
+        } else if let Some(l) = LinkStats::from_byte_slice(payload) {
+            self.link_stats = Some(l);
+        } else if let Some(c) = CycleStats::from_byte_slice(payload) {
+            self.cycle_stats = Some(c);
+        } else if let Some(s) = SampleStats::from_byte_slice(payload) {
+            self.sample_stats = Some(s);
+        } else if let Some(r) = RawSample::from_byte_slice(payload) {
+            let [ax, ay, az] = r.acc;
+            let [gx, gy, gz] = r.gyro;
+            self.raw_samples += &format!("{},{},{},{},{},{},{}\n", r.counter, ax, ay, az, gx, gy, gz);
+        }
+    }
+
+    #[export]
+    fn get_temperature(&mut self, _owner: &Node) -> f32 {
+        self.last_temperature
+    }
+
+    /// Telemetry frames dropped as broken, not counting ones lost whole
+    #[export]
+    fn get_frame_errors(&mut self, _owner: &Node) -> u32 {
+        self.frame_errors
+    }
+
+    /// Device asks to be held still
+    #[export]
+    fn is_calibrating(&mut self, _owner: &Node) -> bool {
+        self.calibrating
+    }
+
+    /// Device lost its IMU and cut the motors
+    #[export]
+    fn is_imu_lost(&mut self, _owner: &Node) -> bool {
+        self.imu_lost
+    }
 
-            self.buf[self.idx..self.idx + CHUNK_SIZE].clone_from_slice(&self.chunk);
-            self.idx += read_len;
-
-            let m = self.buf[..self.idx]
-                .split(|w| *w == EOT )
-                .collect::<Vec<&[u8]>>()
-                .into_iter()
-                .rev()
-                .next();
-            
-            if let Some(payload) = m {
-                if payload.len() == common::BUFF_SIZE {
-                    let so = SpatialOrientation::from_byte_slice(payload);
-                    self.last_read = (so.pitch, so.roll, 0.0);
-                }
+    /// Gyroscope hit its full scale, attitude is unreliable for a moment
+    #[export]
+    fn is_gyro_saturated(&mut self, _owner: &Node) -> bool {
+        self.gyro_saturated
+    }
+
+    /// Armed while moving, the attitude wasn't re-seeded and may be off
+    #[export]
+    fn is_reset_skipped(&mut self, _owner: &Node) -> bool {
+        self.reset_skipped
+    }
+
+    /// Tipped over or hit the ground, disarm before arming again
+    #[export]
+    fn is_crashed(&mut self, _owner: &Node) -> bool {
+        self.crashed
+    }
+
+    /// False until the device reported a passed self-test, it refuses to arm otherwise
+    #[export]
+    fn self_test_passed(&mut self, _owner: &Node) -> bool {
+        self.self_test.map(|t| t.passed()).unwrap_or(false)
+    }
+
+    /// Gyroscope counts before the notch filter, zeros until gyro debug is on
+    #[export]
+    fn get_gyro_raw(&mut self, _owner: &Node) -> (f32, f32, f32) {
+        let raw = self.last_gyro.map(|g| g.raw).unwrap_or([0; 3]);
+        (raw[0] as f32, raw[1] as f32, raw[2] as f32)
+    }
+
+    /// Gyroscope counts after the notch filter
+    #[export]
+    fn get_gyro_filtered(&mut self, _owner: &Node) -> (f32, f32, f32) {
+        let filtered = self.last_gyro.map(|g| g.filtered).unwrap_or([0; 3]);
+        (filtered[0] as f32, filtered[1] as f32, filtered[2] as f32)
+    }
+
+    /// Sensor bus addresses that answered at boot, empty until the device reported them
+    #[export]
+    fn get_bus_scan(&mut self, _owner: &Node) -> String {
+        match &self.bus_scan {
+            Some(scan) => {
+                let found: Vec<String> = scan.addresses().map(|a| format!("{:#04x}", a)).collect();
+                let dead = if scan.dead { " (bus dead)" } else { "" };
+                format!("{}{}", found.join(" "), dead)
             }
+            None => String::new(),
         }
+    }
 
-        self.last_read
+    /// Samples read, samples missed, longest gap in microseconds and FIFO overflows
+    #[export]
+    fn get_sample_stats(&mut self, _owner: &Node) -> (u32, u32, u32, u32) {
+        self.sample_stats
+            .map(|s| (s.read, s.missed, s.max_gap_us, s.overflows as u32))
+            .unwrap_or((0, 0, 0, 0))
+    }
+
+    /// Commands the device dropped as corrupted since it booted
+    #[export]
+    fn get_rejected_commands(&mut self, _owner: &Node) -> u32 {
+        self.link_stats.map(|l| l.rejected).unwrap_or(0)
+    }
+
+    /// Commands the device took, dropped as corrupted and dropped as stale, the rejected
+    /// ones that failed the CRC, sequences missing, link failsafes and bytes received and
+    /// sent, since boot or the last reset. As of the last report, once a second with the
+    /// statistics stream.
+    #[export]
+    fn get_link_stats(&mut self, _owner: &Node) -> (u32, u32, u32, u32, u32, u32, u32, u32) {
+        self.link_stats
+            .map(|l| (l.accepted, l.rejected, l.stale, l.crc, l.gaps, l.failsafes, l.bytes_received, l.bytes_sent))
+            .unwrap_or_default()
+    }
+
+    /// Bursts the device's UART lost to overruns, framing errors and noise, as of the last
+    /// report the same as get_link_stats
+    #[export]
+    fn get_line_errors(&mut self, _owner: &Node) -> (u32, u32, u32) {
+        self.link_stats.map(|l| (l.overruns, l.framing, l.noise)).unwrap_or_default()
+    }
+
+    /// Frames the device dropped for want of room to send them, the telemetry asking more
+    /// than the baud rate carries. As of the last report the same as get_link_stats.
+    #[export]
+    fn get_frames_skipped(&mut self, _owner: &Node) -> u32 {
+        self.link_stats.map(|l| l.skipped).unwrap_or(0)
+    }
+
+    /// Starts the device's link counts over, for a measurement over a given stretch
+    #[export]
+    fn reset_link_stats(&mut self, _owner: &Node) -> Result<(), Stm32Error> {
+        self.send(Msg::ResetLinkStats, "reset link stats")
+    }
+
+    /// Roll, pitch and yaw rate the device holds in degrees and degrees per second, zeros
+    /// until it echoed them
+    #[export]
+    fn get_setpoint(&mut self, _owner: &Node) -> (f32, f32, f32) {
+        self.setpoint_echo
+            .map(|s| (s.roll.to_degrees(), s.pitch.to_degrees(), s.yaw_rate.to_degrees()))
+            .unwrap_or((0.0, 0.0, 0.0))
+    }
+
+    /// Last round trip in ms, device clock ahead of this one in ms and the time the device
+    /// took to answer in us, zeros until a ping was answered. The round trip includes
+    /// however long the answer waited for [Sensor::get_angles] to read it.
+    #[export]
+    fn get_ping(&mut self, _owner: &Node) -> (f32, f32, u32) {
+        self.ping.unwrap_or((0.0, 0.0, 0))
+    }
+
+    /// `id,name,value,default,min,max` lines of the parameters the device listed or echoed,
+    /// by id. Fields it didn't send yet are empty, a bool is `true` or `false`.
+    #[export]
+    fn get_params(&mut self, _owner: &Node) -> String {
+        let show = |v: Option<Value>| match v {
+            Some(Value::F32(v)) => format!("{}", v),
+            Some(Value::U32(v)) => format!("{}", v),
+            Some(Value::Bool(v)) => format!("{}", v),
+            None => String::new(),
+        };
+        let mut lines = String::new();
+        for (id, (info, value)) in &self.params {
+            let name = Param::from_id(*id).map(|p| format!("{:?}", p)).unwrap_or_default();
+            let (min, max) = info.map(|i| (i.min.to_string(), i.max.to_string())).unwrap_or_default();
+            lines += &format!("{},{},{},{},{},{}\n", id, name, show(*value), show(info.map(|i| i.default)), min, max);
+        }
+        lines
+    }
+
+    /// Roll and pitch trim the device uses in degrees, zeros until it echoed them
+    #[export]
+    fn get_trim(&mut self, _owner: &Node) -> (f32, f32) {
+        self.trim.map(|t| (t.roll.to_degrees(), t.pitch.to_degrees())).unwrap_or((0.0, 0.0))
+    }
+
+    /// P, I, D and feed forward of the roll, pitch or yaw `axis`, zeros until the device
+    /// echoed them
+    #[export]
+    fn get_pid_gains(&mut self, _owner: &Node, axis: u32) -> (f32, f32, f32, f32) {
+        match self.pid_gains.get(axis as usize).copied().flatten() {
+            Some(g) => (g.p, g.i, g.d, g.ff),
+            None => (0.0, 0.0, 0.0, 0.0),
+        }
+    }
+
+    /// Disarmed, Arming, Armed, Failsafe or Killed, empty until the device reported it
+    #[export]
+    fn get_arm_state(&mut self, _owner: &Node) -> String {
+        self.arm_state.map(|a| format!("{:?}", a)).unwrap_or_default()
+    }
+
+    /// Throttle and arming are ignored until [Sensor::unlock], true until the device
+    /// reported otherwise
+    #[export]
+    fn is_locked(&mut self, _owner: &Node) -> bool {
+        self.lock_state != Some(LockState::Unlocked)
+    }
+
+    /// Passthrough, Rate or Angle, empty until the device reported it
+    #[export]
+    fn get_mode(&mut self, _owner: &Node) -> String {
+        self.mode.map(|m| format!("{:?}", m)).unwrap_or_default()
+    }
+
+    /// Link timeout and ramp-down in ms, zeros until the device reported them
+    #[export]
+    fn get_failsafe_config(&mut self, _owner: &Node) -> (u32, u32) {
+        self.failsafe_config.map(|f| (f.timeout_ms as u32, f.ramp_ms as u32)).unwrap_or((0, 0))
+    }
+
+    /// Divisor and streams the device sends telemetry with, zeros until it echoed them
+    #[export]
+    fn get_telemetry(&mut self, _owner: &Node) -> (u32, u32) {
+        self.telemetry.map(|t| (t.divisor as u32, t.streams as u32)).unwrap_or((0, 0))
+    }
+
+    /// Duty of motor 1 to 4 as a fraction of full throttle, zeros until the device reported them
+    #[export]
+    fn get_motor_outputs(&mut self, _owner: &Node) -> (f32, f32, f32, f32) {
+        match self.motor_outputs {
+            Some(MotorOutputs { duty: [m1, m2, m3, m4], max_duty }) if max_duty > 0 => {
+                let f = |d: u16| d as f32 / max_duty as f32;
+                (f(m1), f(m2), f(m3), f(m4))
+            }
+            _ => (0.0, 0.0, 0.0, 0.0),
+        }
+    }
+
+    /// Throttle as commanded and as the motors get it after the slew limit, zeros until the
+    /// armed device reported them
+    #[export]
+    fn get_throttle_output(&mut self, _owner: &Node) -> (f32, f32) {
+        self.throttle_output.map(|t| (t.commanded, t.output)).unwrap_or((0.0, 0.0))
+    }
+
+    /// Sequence and status of the last reply to a command, empty until the device sent one
+    #[export]
+    fn get_last_ack(&mut self, _owner: &Node) -> String {
+        self.last_ack.map(|a| format!("{} {:?}", a.sequence, a.status)).unwrap_or_default()
+    }
+
+    /// Sequence, arm state, enable line, mode and the four duties the device ended up with
+    /// after the last command it answered, empty until it sent one
+    #[export]
+    fn get_last_applied(&mut self, _owner: &Node) -> String {
+        self.last_applied
+            .map(|a| {
+                let [m1, m2, m3, m4] = a.outputs.duty;
+                format!("{} {:?} {} {:?} {} {} {} {}", a.sequence, a.arm_state, a.enabled, a.mode, m1, m2, m3, m4)
+            })
+            .unwrap_or_default()
+    }
+
+    /// Protocol version of a device that doesn't speak this one, zero until one announced
+    /// itself. Such a device ignores every command.
+    #[export]
+    fn get_device_protocol_mismatch(&mut self, _owner: &Node) -> u32 {
+        self.device_protocol.filter(|v| *v != PROTOCOL_VERSION).unwrap_or(0) as u32
+    }
+
+    /// Protocol version, `FEATURE_` bits, motor count, `SENSOR_` bits and parameter count of
+    /// the device, see common's Announce. Zeros until it answered the hello.
+    #[export]
+    fn get_announce(&mut self, _owner: &Node) -> (u32, u32, u32, u32, u32) {
+        self.announce
+            .map(|a| (a.protocol as u32, a.features, a.motors as u32, a.sensors as u32, a.params as u32))
+            .unwrap_or_default()
+    }
+
+    /// Baud rate between the device and its Bluetooth module, zero until it reported it
+    #[export]
+    fn get_device_baud(&mut self, _owner: &Node) -> u32 {
+        self.device_baud.unwrap_or(0)
+    }
+
+    /// Pack and cell volts and the alarm, 0 for none, 1 for the warning and 2 for critical.
+    /// Zeros until the device reported a battery.
+    #[export]
+    fn get_battery(&mut self, _owner: &Node) -> (f32, f32, u32) {
+        self.battery
+            .map(|b| (b.millivolts as f32 / 1000.0, b.cell_millivolts as f32 / 1000.0, b.alarm as u32))
+            .unwrap_or_default()
+    }
+
+    /// Amps, -1 without a current sensor, mAh drawn since boot and since arming, and whether
+    /// the current went over its limit. Zeros until the device reported a battery.
+    #[export]
+    fn get_current(&mut self, _owner: &Node) -> (f32, u32, u32, bool) {
+        self.battery
+            .map(|b| (b.centiamps.map_or(-1.0, |c| c as f32 / 100.0), b.consumed_mah, b.armed_mah, b.over_current))
+            .unwrap_or_default()
+    }
+
+    /// Milliseconds since the last command, -1 for none yet, I2C errors since boot, fewest
+    /// and most microseconds of the control loop over the period and the warning bits of
+    /// [ExtendedStatus]. Zeros until the device sent one.
+    #[export]
+    fn get_extended_status(&mut self, _owner: &Node) -> (i64, u32, u32, u32, u32) {
+        self.extended_status
+            .map(|s| (s.command_age_ms.map_or(-1, i64::from), s.i2c_errors, s.loop_min_us, s.loop_max_us, s.warnings as u32))
+            .unwrap_or_default()
+    }
+
+    /// Version, commit and build date of the firmware, empty until the device sent them
+    #[export]
+    fn get_device_version(&mut self, _owner: &Node) -> String {
+        self.device_build.map(|b| b.to_string()).unwrap_or_default()
+    }
+
+    /// The device answered the last command sent and applied it in full
+    #[export]
+    fn is_last_command_applied(&mut self, _owner: &Node) -> bool {
+        self.last_ack == Some(Ack { sequence: self.sequence, status: AckStatus::Applied })
+    }
+
+    /// Min/mean/max cycles of the device's sampling stages and its receive interrupt, empty
+    /// until the device reported them
+    #[export]
+    fn get_cycle_stats(&mut self, _owner: &Node) -> String {
+        match &self.cycle_stats {
+            Some(c) => {
+                let stages = [("i2c", c.i2c), ("fusion", c.fusion), ("telemetry", c.telemetry), ("total", c.total), ("receive", c.receive)];
+                let lines: Vec<String> = stages.iter().map(|(name, s)| format!("{} {}/{}/{}", name, s.min, s.mean, s.max)).collect();
+                lines.join(", ")
+            }
+            None => String::new(),
+        }
+    }
+
+    /// Sphere coverage in percent, seconds left and whether the coverage is enough to solve,
+    /// zeros until a magnetometer calibration was started
+    #[export]
+    fn get_mag_calibration(&mut self, _owner: &Node) -> (u32, u32, bool) {
+        self.mag_calibration
+            .map(|m| (m.coverage_percent as u32, m.remaining_s as u32, m.enough))
+            .unwrap_or((0, 0, false))
+    }
+
+    /// Estimator gain and accelerometer cutoff the device uses, the cutoff is zero when it has none
+    #[export]
+    fn get_filter_config(&mut self, _owner: &Node) -> (f32, f32) {
+        self.filter_config.map(|f| (f.gain, f.acc_cutoff_hz)).unwrap_or((0.0, 0.0))
+    }
+
+    /// Attitude w, x, y, z, identity until quaternion frames are on and one arrived
+    #[export]
+    fn get_quaternion(&mut self, _owner: &Node) -> (f32, f32, f32, f32) {
+        self.last_quaternion.map(|q| (q.w, q.x, q.y, q.z)).unwrap_or((1.0, 0.0, 0.0, 0.0))
+    }
+
+    /// Acceleration in g in earth axes with Z up, gravity taken out. Zeros until linear
+    /// acceleration frames are on and one arrived.
+    #[export]
+    fn get_linear_accel(&mut self, _owner: &Node) -> (f32, f32, f32) {
+        let [x, y, z] = self.last_linear_accel.map(|l| l.milli_g).unwrap_or([0; 3]);
+        (x as f32 / 1000.0, y as f32 / 1000.0, z as f32 / 1000.0)
+    }
+
+    /// Tilt compensated heading and its error to the held one in radians. Zeros until a heading
+    /// was held and a frame arrived.
+    #[export]
+    fn get_heading(&mut self, _owner: &Node) -> (f32, f32) {
+        let rad = |centi: i16| (centi as f32 / 100.0).to_radians();
+        self.last_heading.map(|h| (rad(h.centi_degrees), rad(h.error_centi_degrees))).unwrap_or((0.0, 0.0))
+    }
+
+    /// Rates about pitch, roll and yaw in rad/s, zeros until rate frames are on and one arrived
+    #[export]
+    fn get_rates(&mut self, _owner: &Node) -> (f32, f32, f32) {
+        let [x, y, z] = self.last_rates.map(|r| r.milli_rad_s).unwrap_or([0; 3]);
+        (x as f32 / 1000.0, y as f32 / 1000.0, z as f32 / 1000.0)
+    }
+
+    /// Meters above where the device was powered on
+    #[export]
+    fn get_altitude(&mut self, _owner: &Node) -> f32 {
+        self.last_altitude
+    }
+
+    /// Meters per second up, zero while the device fuses on the DMP or has no barometer
+    #[export]
+    fn get_climb_rate(&mut self, _owner: &Node) -> f32 {
+        self.last_climb_rate
     }
 }
 